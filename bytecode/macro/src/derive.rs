@@ -38,8 +38,12 @@ pub(crate) fn attr_enum(input: DeriveInput) -> Result<TokenStream2> {
             .find(|v| v.attrs.iter().any(|a| a.path.to_token_stream().to_string() == "raw_variant"))
             .ok_or_else(|| Error::new(e.variants.span(), "Expected raw variant annotated with #[raw_variant]"))?
             .ident;
-        let new_variants = e.variants.iter().filter(|v| !v.attrs.iter().any(|a| a.path.to_token_stream().to_string() == "raw_variant")).collect::<Vec<_>>();
-        let attr_names = new_variants.iter().map(|v| v.ident.to_string()).map(|s| quote! { #s }).collect::<Vec<_>>();
+        let mut new_variants = e.variants.iter().filter(|v| !v.attrs.iter().any(|a| a.path.to_token_stream().to_string() == "raw_variant")).collect::<Vec<_>>();
+        let lazy_of = |v: &Variant| v.attrs.iter().find(|a| a.path.to_token_stream().to_string() == "lazy_of").map(|a| parse2::<Group>(a.tokens.clone()).map(|g| g.stream().to_string()));
+        // lazy variants must be matched before the variants they are the counterpart of.
+        new_variants.sort_by_key(|v| lazy_of(v).is_none());
+        let attr_names = new_variants.iter().map(|v| Ok(lazy_of(v).transpose()?.unwrap_or_else(|| v.ident.to_string()))).map(|s| s.map(|s| quote! { #s })).collect::<Result<Vec<_>>>()?;
+        let attr_guards = new_variants.iter().map(|v| if lazy_of(v).is_some() { quote! { if cp.shared_pool().is_some() } } else { quote! {} }).collect::<Vec<_>>();
         let variant_fields_idents = new_variants.iter().map(|v| &v.fields).map(generate_idents_for_fields).collect::<Vec<_>>();
         let variant_match_arms = new_variants.iter().zip(variant_fields_idents.iter()).map(|(v, idents)| {
             let variant_ident = &v.ident;
@@ -91,35 +95,53 @@ pub(crate) fn attr_enum(input: DeriveInput) -> Result<TokenStream2> {
                 let mut __inner_writer = std::io::Cursor::new(&mut vec);
                 let mut inner_writer = &mut __inner_writer;
                 #(#tk)*
-                u32::write_to(&(vec.len() as u32), writer)?;
+                u32::write_to(&crate::rw::checked_len("attribute length", vec.len())?, writer)?;
                 writer.write_all(&vec)?;
             };
             variant_write_bodies.push(write);
         }
 
-        let variant_read_bodies = v_r.iter().zip(variant_fields_idents.iter()).zip(new_variants.iter().map(|v| &v.ident)).map(|((r,i), id)| {
+        let variant_read_bodies = v_r.iter().zip(variant_fields_idents.iter()).zip(new_variants.iter()).zip(variant_constructs).zip(attr_names.iter()).map(|((((r,i), v), construct), name)| {
+            let id = &v.ident;
             quote! {
                 let len = u32::read_from(reader)?;
                 let mut vec = vec![0; len as usize];
                 reader.read_exact(&mut vec)?;
                 let mut __inner_reader: &[u8] = vec.as_ref();
                 let inner_reader = &mut __inner_reader;
-                #(let #i = #r;)*
-                if !inner_reader.is_empty() {
-                    return Err(crate::error::Error::AttributeLength(len, len - (inner_reader.len() as u32), stringify!(#id)))
+                let res = (|| -> crate::Result<Self> {
+                    #(let #i = #r;)*
+                    if !inner_reader.is_empty() {
+                        return Err(crate::error::Error::AttributeLength(len, len - (inner_reader.len() as u32), stringify!(#id)))
+                    }
+                    Ok(#construct)
+                })();
+                // the content starts after the name index and the length.
+                match res.map_err(|e| e.located(Some(crate::error::PathSegment::Attribute(std::borrow::Cow::Borrowed(#name))), 6, 6 + (len - inner_reader.len() as u32) as u64)) {
+                    Ok(v) => Ok(v),
+                    // lenient readers keep the malformed attribute as raw data instead.
+                    Err(e) => {
+                        cp.warn(e)?;
+                        Ok(Self::#raw_variant(crate::prelude::RawAttribute::new(attribute_name, vec)))
+                    }
                 }
             }
         });
         let res = quote! {
+            impl #generics #ident #generics #where_c {
+                /// Whether this attribute is written, which raw attributes that are not kept are not.
+                pub(crate) fn is_written(&self) -> bool {
+                    !matches!(self, Self::#raw_variant(crate::prelude::RawAttribute { keep: false, .. }))
+                }
+            }
             impl #generics crate::ConstantPoolReadWrite for #ident #generics #where_c {
                 fn read_from<C: crate::ConstantPoolReader, R: std::io::Read>(cp: &mut C, reader: &mut R) -> crate::Result<Self> {
                     let idx = u16::read_from(reader)?;
                     let attribute_name = cp.read_utf8(idx).ok_or_else(|| crate::error::Error::Invalid("attribute index", Into::into(idx.to_string())))?;
                     match attribute_name.as_ref() {
                         #(
-                                    #attr_names => {
+                                    #attr_names #attr_guards => {
                                         #variant_read_bodies
-                                        Ok(#variant_constructs)
                                     }
                         )*
                         _ => {
@@ -142,7 +164,7 @@ pub(crate) fn attr_enum(input: DeriveInput) -> Result<TokenStream2> {
                         )*
                         Self::#raw_variant(crate::prelude::RawAttribute { keep: true, ref name, ref inner }) => {
                             u16::write_to(&cp.insert_utf8(name.clone()), writer)?;
-                            crate::write_to!(&(inner.len() as u32), writer)?;
+                            crate::write_to!(&crate::rw::checked_len::<u32>("attribute length", inner.len())?, writer)?;
                             writer.write_all(inner)?;
                             Ok(())
                         }
@@ -343,6 +365,12 @@ pub(crate) fn gen_read_and_write<T: ToTokens>(f: &Field, receiver: &T, mut trait
 
                     let (read_vec_len_fn, write_vec_len_fn) = rw_fncalls(&vec_len_ty, quote! { crate::ReadWrite });
                     let (read, write) = rw(quote!(it), ty)?;
+                    // attributes that are not written are not counted either.
+                    let filter = if f.attrs.iter().any(|a| a.path.to_token_stream().to_string() == "attr_vec") {
+                        quote! { .filter(|it| it.is_written()) }
+                    } else {
+                        quote! {}
+                    };
                     return Ok((quote! {
                         {
                             let len = #read_vec_len_fn(#reader)?;
@@ -353,8 +381,8 @@ pub(crate) fn gen_read_and_write<T: ToTokens>(f: &Field, receiver: &T, mut trait
                             vec
                         }
                     }, quote! {
-                        #write_vec_len_fn(&(#receiver.len() as #vec_len_ty), #writer)?;
-                        for it in #receiver.iter() {
+                        #write_vec_len_fn(&crate::rw::checked_len(concat!(stringify!(#ty), " count"), #receiver.iter()#filter.count())?, #writer)?;
+                        for it in #receiver.iter()#filter {
                             #write
                         }
                     }))
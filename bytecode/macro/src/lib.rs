@@ -92,11 +92,13 @@ fn code_block_inner(tokens: TokenStream2) -> syn::Result<TokenStream2> {
 ///   - `tag`: indicates individual tags for enum variants. When the tag attribute is absent, a discriminant is used. When both the tag attribute and a discriminant is missing, the tag is incremented from the last variant.
 ///   - `attr_enum`: indicates this is an enum that is an attribute. Rather than matching tags, it will match on attribute names. A `raw_variant` must be specified.
 ///   - `raw_variant`: indicates this is the raw variant of the attribute enum. The field list must be exactly `(RawAttribute)`.
+///   - `lazy_of`: indicates this variant is a lazily decoded counterpart of another attribute, such as `#[lazy_of(Code)]`. It is read instead of that attribute when the constant pool reader has a shared pool.
 ///   - `use_normal_rw`: indicates using normal `ReadWrite` trait instead of `ConstantPoolReadWrite`.
 ///   - `str_type`: indicates this field is one of the constant pool types that has a string. One of `Package`, `Module`, `String` and `Class` to be exact. Therefore a type must be specified: `#[str_type(Class)]`
 ///   - `str_optional`: indicates this field is an optional string. `None` represents `0` in byte form. The field must be `Option<Cow<'static, str>>`.
 ///   - `vec_len_type`: indicates the length type of the vec. if this is `#[vec_len_type(u32)]`, then the 32-bit length `n` is written/read first.
-#[proc_macro_derive(ConstantPoolReadWrite, attributes(tag_type, tag, attr_enum, raw_variant, use_normal_rw, str_type, str_optional, vec_len_type))]
+///   - `attr_vec`: indicates this vec holds attributes, of which only those that are written are counted and written.
+#[proc_macro_derive(ConstantPoolReadWrite, attributes(tag_type, tag, attr_enum, raw_variant, lazy_of, use_normal_rw, str_type, str_optional, vec_len_type, attr_vec))]
 pub fn derive_cp_readwrite(item: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(item as DeriveInput);
     let is_enum = input.attrs.iter().any(|a| a.path.to_token_stream().to_string() == "attr_enum");
@@ -6,10 +6,12 @@ use std::collections::HashMap;
 use super::Type;
 use crate::{ConstantPoolReadWrite, ConstantPoolReader, ConstantPoolWriter, Read, Write, ReadWrite, Result, read_from, write_to};
 use crate::error::Error;
+use crate::rw::checked_len;
 use std::str::FromStr;
 use std::convert::TryInto;
 
 #[derive(PartialEq, Debug, Clone, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParameterAnnotations(#[vec_len_type(u16)] Vec<Annotation>);
 
 impl std::ops::Deref for ParameterAnnotations {
@@ -28,6 +30,7 @@ impl std::ops::DerefMut for ParameterAnnotations {
 
 /// This is an exhaustive enum representing values of an annotation.
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AnnotationValue {
     /// A signed byte. This will get sign-extended into an integer in the constant pool.
     Byte(i8),
@@ -163,7 +166,7 @@ impl ConstantPoolReadWrite for AnnotationValue {
             }
             AnnotationValue::Array(v) => {
                 b'['.write_to(writer)?;
-                (v.len() as u16).write_to(writer)?;
+                checked_len::<u16>("annotation array length", v.len())?.write_to(writer)?;
                 for e in v {
                     e.write_to(cp, writer)?;
                 }
@@ -175,6 +178,7 @@ impl ConstantPoolReadWrite for AnnotationValue {
 
 /// An Annotation that has a name and named values.
 #[derive(Clone, PartialEq, Debug, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Annotation {
     /// A field descriptor representing the type of the annotation.
     pub annotation_type: Type,
@@ -195,8 +199,7 @@ impl ConstantPoolReadWrite for HashMap<Cow<'static, str>, AnnotationValue> {
     }
 
     fn write_to<C: ConstantPoolWriter, W: Write>(&self, cp: &mut C, writer: &mut W) -> Result<(), Error> {
-        let size = self.len() as u16;
-        size.write_to(writer)?;
+        checked_len::<u16>("annotation element count", self.len())?.write_to(writer)?;
         for (k, e) in self {
             write_to!(k, cp, writer)?;
             e.write_to(cp, writer)?;
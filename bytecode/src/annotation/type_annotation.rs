@@ -9,6 +9,7 @@ use super::super::Type;
 
 /// Represents where a type annotation is annotated in a class.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, ReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[tag_type(u8)]
 pub enum ClassTypeAnnotationTarget {
     /// The type annotation is before a generic type parameter at an index.
@@ -27,6 +28,7 @@ pub enum ClassTypeAnnotationTarget {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, ReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[tag_type(u8)]
 pub enum MethodTypeAnnotationTarget {
     #[tag(0x1)]
@@ -44,6 +46,7 @@ pub enum MethodTypeAnnotationTarget {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LocalVarTarget {
     pub start: Label,
     pub end: Label,
@@ -71,6 +74,7 @@ impl ConstantPoolReadWrite for LocalVarTarget {
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[tag_type(u8)]
 pub enum CodeTypeAnnotationTarget {
     #[tag(0x40)]
@@ -100,13 +104,39 @@ pub enum CodeTypeAnnotationTarget {
     GenericMethodRef(Label, #[use_normal_rw] u8),
 }
 
+impl CodeTypeAnnotationTarget {
+    /// Calls `f` on every label of this target.
+    pub fn visit_labels_mut<F: FnMut(&mut Label)>(&mut self, mut f: F) {
+        match self {
+            CodeTypeAnnotationTarget::LocalVariable(targets) | CodeTypeAnnotationTarget::ResourceVariable(targets) => {
+                for t in targets {
+                    f(&mut t.start);
+                    f(&mut t.end);
+                }
+            }
+            CodeTypeAnnotationTarget::CatchParameter(c) => c.visit_labels_mut(f),
+            CodeTypeAnnotationTarget::InstanceOf(l)
+            | CodeTypeAnnotationTarget::Constructor(l)
+            | CodeTypeAnnotationTarget::ConstructorRef(l)
+            | CodeTypeAnnotationTarget::MethodRef(l)
+            | CodeTypeAnnotationTarget::Cast(l, _)
+            | CodeTypeAnnotationTarget::GenericConstructor(l, _)
+            | CodeTypeAnnotationTarget::GenericMethod(l, _)
+            | CodeTypeAnnotationTarget::GenericConstructorRef(l, _)
+            | CodeTypeAnnotationTarget::GenericMethodRef(l, _) => f(l)
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, ReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[tag_type(u8)]
 pub enum TypePath {
     Array(u8), Nested(u8), TypeBound(u8), TypeArgument(u8)
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClassTypeAnnotation {
     pub target: ClassTypeAnnotationTarget,
     pub type_path: Vec<(u8, u8)>,
@@ -115,6 +145,7 @@ pub struct ClassTypeAnnotation {
 }
 
 #[derive(Debug, Clone, PartialEq, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodTypeAnnotation {
     #[use_normal_rw]
     pub target: MethodTypeAnnotationTarget,
@@ -126,10 +157,12 @@ pub struct MethodTypeAnnotation {
 }
 
 #[derive(Debug, Clone, PartialEq, ReadWrite, Copy, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[tag_type(u8)]
 pub enum FieldTarget { #[tag(0x13)] Field }
 
 #[derive(Debug, Clone, PartialEq, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FieldTypeAnnotation {
     #[use_normal_rw]
     pub target_type: FieldTarget,
@@ -142,6 +175,7 @@ pub struct FieldTypeAnnotation {
 
 
 #[derive(Debug, Clone, PartialEq, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CodeTypeAnnotation {
     pub target: CodeTypeAnnotationTarget,
     #[vec_len_type(u8)]
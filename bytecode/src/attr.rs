@@ -14,12 +14,14 @@
  *     You should have received a copy of the GNU Lesser General Public License
  *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
  */
+use crate::annotation::Annotation;
 use crate::module::Module;
 use crate::prelude::*;
 use crate::mod_utf8::{modified_utf8_to_string, string_to_modified_utf8};
 
 /// An unrecognized, unknown raw attribute.
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RawAttribute {
     /// Whether to keep this attribute upon writing.
     ///
@@ -76,6 +78,7 @@ impl ConstantPoolReadWrite for Option<(Cow<'static,str>, Type)> {
 /// The [`ReadWrite`] implementation for this just comsumes the whole reader.
 /// This works because the macro generates inner readers for attributes which are safe to consume.
 #[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SourceDebugExtension(pub Cow<'static, str>);
 
 impl ReadWrite for SourceDebugExtension {
@@ -92,6 +95,7 @@ impl ReadWrite for SourceDebugExtension {
 }
 
 #[derive(Eq, PartialEq, Debug, Clone, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InnerClass {
     #[str_type(Class)]
     pub inner_fqname: Cow<'static, str>,
@@ -106,6 +110,7 @@ pub struct InnerClass {
 }
 
 #[derive(PartialEq, Debug, Clone, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[attr_enum]
 pub enum ClassAttribute {
     Signature(ClassSignature),
@@ -121,6 +126,8 @@ pub enum ClassAttribute {
     ModuleMainClass(#[str_type(Class)] Cow<'static, str>),
     NestHost(#[str_type(Class)] Cow<'static, str>),
     NestMembers(#[vec_len_type(u16)] #[str_type(Class)] Vec<Cow<'static, str>>),
+    RuntimeVisibleAnnotations(#[vec_len_type(u16)] Vec<Annotation>),
+    RuntimeInvisibleAnnotations(#[vec_len_type(u16)] Vec<Annotation>),
     #[raw_variant]
     Raw(RawAttribute)
 }
\ No newline at end of file
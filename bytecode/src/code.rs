@@ -22,7 +22,7 @@ use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::hash::Hash;
 use std::io::{Cursor, Read, Write};
-use std::rc::Rc;
+use std::sync::Arc;
 use std::str::FromStr;
 
 use nom::lib::std::borrow::Cow;
@@ -31,9 +31,12 @@ use indexmap::map::IndexMap;
 use crate::{ConstantPoolReader, ConstantPoolReadWrite, ConstantPoolWriter, Error, read_from, ReadWrite, try_cp_read, try_cp_read_idx};
 use crate::annotation::CodeTypeAnnotation;
 use crate::prelude::*;
+use crate::error::PathSegment;
+use crate::rw::{Counted, checked_len};
 
 /// Acts as a unique identifier to the code. Labels should be treated carefully because when labels become invalid (i.e. removed from the code array) it will become an error.
 #[derive(Debug, Eq, PartialOrd, PartialEq, Ord, Hash, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Label(pub u32);
 
 impl ConstantPoolReadWrite for Label {
@@ -46,6 +49,7 @@ impl ConstantPoolReadWrite for Label {
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StackValueType {
     /// Represents A stack value of computational type one. This should not be used when the stack type is a f64 or i64.
     One,
@@ -54,6 +58,7 @@ pub enum StackValueType {
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FloatType {
     Double,
     Float,
@@ -80,12 +85,14 @@ impl From<FloatType> for LocalType {
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NaNBehavior {
     ReturnsOne,
     ReturnsNegativeOne,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum JumpCondition {
     ReferenceEquals,
     ReferenceNotEquals,
@@ -167,24 +174,28 @@ impl std::ops::Neg for &JumpCondition {
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LoadOrStore {
     Load,
     Store,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GetOrPut {
     Get,
     Put,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MemberType {
     Static,
     Virtual,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LocalType {
     Int,
     Long,
@@ -194,6 +205,7 @@ pub enum LocalType {
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ArrayType {
     ByteOrBool,
     Short,
@@ -206,6 +218,7 @@ pub enum ArrayType {
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NumberType {
     Int,
     Long,
@@ -214,6 +227,7 @@ pub enum NumberType {
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BitType {
     Byte,
     Short,
@@ -225,12 +239,14 @@ pub enum BitType {
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IntType {
     Int,
     Long,
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FloatOperation {
     Divide,
     Add,
@@ -241,6 +257,7 @@ pub enum FloatOperation {
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IntOperation {
     Divide,
     Add,
@@ -257,6 +274,7 @@ pub enum IntOperation {
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MonitorOperation {
     Enter,
     Exit,
@@ -266,6 +284,7 @@ pub enum MonitorOperation {
 
 
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ClassType {
     Object(Cow<'static, str>),
     Array(u8, Type),
@@ -291,6 +310,7 @@ impl From<ClassType> for Cow<'static, str> {
 ///
 /// However, StackMap frames will not be a variant because they become quite invalid after modifications made to code, thus, frames should be regenerated every time.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     NoOp,
     /// Push a null object reference.
@@ -347,6 +367,7 @@ pub enum Instruction {
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LocalVariable {
     pub start: Label,
     pub end: Label,
@@ -399,6 +420,7 @@ enum CodeAttr {
 }
 
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CodeAttribute {
     VisibleTypeAnnotations(Vec<CodeTypeAnnotation>),
     InvisibleTypeAnnotations(Vec<CodeTypeAnnotation>),
@@ -407,6 +429,7 @@ pub enum CodeAttribute {
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Catch {
     pub start: Label,
     pub end: Label,
@@ -429,6 +452,7 @@ impl ConstantPoolReadWrite for Catch {
 }
 
 #[derive(Clone, PartialEq, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Code {
     pub max_stack: u16,
     pub max_locals: u16,
@@ -437,6 +461,154 @@ pub struct Code {
     pub attrs: Vec<CodeAttribute>,
 }
 
+impl Instruction {
+    /// Calls `f` on every label that this instruction refers to or marks.
+    pub fn visit_labels_mut<F: FnMut(&mut Label)>(&mut self, mut f: F) {
+        match self {
+            Instruction::Jump(_, l) | Instruction::Jsr(l) | Instruction::Label(l) => f(l),
+            Instruction::TableSwitch { default, offsets, .. } => {
+                f(default);
+                offsets.iter_mut().for_each(f);
+            }
+            Instruction::LookupSwitch { default, table } => {
+                f(default);
+                table.values_mut().for_each(f);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Instruction {
+    /// The number of stack slots that this instruction pops and then pushes, where `long` and `double` values take two.
+    ///
+    /// Returns `None` for field and method instructions whose member is computed dynamically, since the type is unknown.
+    pub fn stack_effect(&self) -> Option<(u16, u16)> {
+        fn size(wide: bool) -> u16 {
+            if wide { 2 } else { 1 }
+        }
+        fn member(m: &OrDynamic<MemberRef>) -> Option<&Type> {
+            match m {
+                OrDynamic::Static(m) => Some(&m.descriptor),
+                OrDynamic::Dynamic(_) => None
+            }
+        }
+        fn invoke(descriptor: &Type, receiver: u16) -> Option<(u16, u16)> {
+            match descriptor {
+                Type::Method { parameters, ret } => Some((
+                    parameters.iter().map(|p| size(p.is_wide())).sum::<u16>() + receiver,
+                    ret.as_ref().map_or(0, |r| size(r.is_wide()))
+                )),
+                _ => None
+            }
+        }
+        let local = |ty: &LocalType| size(matches!(ty, LocalType::Long | LocalType::Double));
+        let number = |ty: &NumberType| size(matches!(ty, NumberType::Long | NumberType::Double));
+        Some(match self {
+            Instruction::NoOp | Instruction::Label(_) | Instruction::LineNumber(_) | Instruction::IntIncrement(..) |
+            Instruction::Ret(_) | Instruction::Jump(JumpCondition::Always, _) => (0, 0),
+            Instruction::PushNull | Instruction::New(_) | Instruction::Jsr(_) => (0, 1),
+            Instruction::Push(OrDynamic::Static(c)) => (0, size(matches!(c, Constant::I64(_) | Constant::F64(_)))),
+            Instruction::Push(OrDynamic::Dynamic(d)) => (0, size(d.descriptor.is_wide())),
+            Instruction::Dup => (1, 2),
+            Instruction::DupX1 => (2, 3),
+            Instruction::DupX2 => (3, 4),
+            Instruction::Dup2 => (2, 4),
+            Instruction::Dup2X1 => (3, 5),
+            Instruction::Dup2X2 => (4, 6),
+            Instruction::Swap => (2, 2),
+            Instruction::Pop1 | Instruction::Throw | Instruction::Monitor(_) | Instruction::TableSwitch { .. } |
+            Instruction::LookupSwitch { .. } => (1, 0),
+            Instruction::Pop2 => (2, 0),
+            Instruction::Jump(JumpCondition::ReferenceEquals, _) | Instruction::Jump(JumpCondition::ReferenceNotEquals, _) |
+            Instruction::Jump(JumpCondition::IntegerEquals, _) | Instruction::Jump(JumpCondition::IntegerNotEquals, _) |
+            Instruction::Jump(JumpCondition::IntegerLessThan, _) | Instruction::Jump(JumpCondition::IntegerGreaterThan, _) |
+            Instruction::Jump(JumpCondition::IntegerLessThanOrEquals, _) | Instruction::Jump(JumpCondition::IntegerGreaterThanOrEquals, _) => (2, 0),
+            Instruction::Jump(..) => (1, 0),
+            Instruction::CompareLongs | Instruction::CompareFloats(FloatType::Double, _) => (4, 1),
+            Instruction::CompareFloats(FloatType::Float, _) => (2, 1),
+            Instruction::LocalVariable(LoadOrStore::Load, ty, _) => (0, local(ty)),
+            Instruction::LocalVariable(LoadOrStore::Store, ty, _) => (local(ty), 0),
+            Instruction::Array(kind, ty) => {
+                let element = size(matches!(ty, ArrayType::Long | ArrayType::Double));
+                match kind {
+                    LoadOrStore::Load => (2, element),
+                    LoadOrStore::Store => (2 + element, 0)
+                }
+            }
+            Instruction::ArrayLength | Instruction::CheckCast(_) | Instruction::InstanceOf(_) => (1, 1),
+            Instruction::IntOperation(IntType::Int, IntOperation::Negate) | Instruction::FloatOperation(FloatType::Float, FloatOperation::Negate) => (1, 1),
+            Instruction::IntOperation(IntType::Int, _) | Instruction::FloatOperation(FloatType::Float, _) => (2, 1),
+            Instruction::IntOperation(IntType::Long, IntOperation::Negate) | Instruction::FloatOperation(FloatType::Double, FloatOperation::Negate) => (2, 2),
+            Instruction::IntOperation(IntType::Long, IntOperation::ShiftLeft) | Instruction::IntOperation(IntType::Long, IntOperation::ShiftRight) |
+            Instruction::IntOperation(IntType::Long, IntOperation::UnsignedShiftRight) => (3, 2),
+            Instruction::IntOperation(IntType::Long, _) | Instruction::FloatOperation(FloatType::Double, _) => (4, 2),
+            Instruction::NewArray(_, dim) => (*dim as u16, 1),
+            Instruction::Conversion(from, to) => (number(from), number(to)),
+            Instruction::ConvertInt(to) => (1, size(matches!(to, BitType::Long | BitType::Double))),
+            Instruction::Return(ty) => (ty.as_ref().map_or(0, local), 0),
+            Instruction::Field(kind, ty, m) => {
+                let value = size(member(m)?.is_wide());
+                let receiver = matches!(ty, MemberType::Virtual) as u16;
+                match kind {
+                    GetOrPut::Get => (receiver, value),
+                    GetOrPut::Put => (receiver + value, 0)
+                }
+            }
+            Instruction::InvokeExact(MemberType::Static, m) => invoke(member(m)?, 0)?,
+            Instruction::InvokeExact(MemberType::Virtual, m) | Instruction::InvokeSpecial(m) | Instruction::InvokeInterface(m, _) => invoke(member(m)?, 1)?,
+            Instruction::InvokeDynamic(d) => invoke(&d.descriptor, 0)?
+        })
+    }
+}
+
+impl Code {
+    /// Calls `f` on every label of this code: in instructions, exception handlers, local variables and type annotations.
+    pub fn visit_labels_mut<F: FnMut(&mut Label)>(&mut self, mut f: F) {
+        for insn in &mut self.code {
+            insn.visit_labels_mut(&mut f);
+        }
+        for c in &mut self.catches {
+            c.visit_labels_mut(&mut f);
+        }
+        for a in &mut self.attrs {
+            match a {
+                CodeAttribute::VisibleTypeAnnotations(an) | CodeAttribute::InvisibleTypeAnnotations(an) => {
+                    for an in an {
+                        an.target.visit_labels_mut(&mut f);
+                    }
+                }
+                CodeAttribute::LocalVariables(vars) => {
+                    for v in vars {
+                        f(&mut v.start);
+                        f(&mut v.end);
+                    }
+                }
+                CodeAttribute::Raw(_) => {}
+            }
+        }
+    }
+
+    /// Removes the labels that nothing refers to, returning how many were removed.
+    pub fn remove_unused_labels(&mut self) -> usize {
+        // placed labels are seen once as a marker, so a label seen once more is referenced.
+        let mut uses = HashMap::new();
+        self.visit_labels_mut(|l| *uses.entry(*l).or_insert(0) += 1);
+        let len = self.code.len();
+        self.code.retain(|i| !matches!(i, Instruction::Label(l) if uses[l] == 1));
+        len - self.code.len()
+    }
+}
+
+impl Catch {
+    /// Calls `f` on the start, end and handler labels of this exception handler.
+    pub fn visit_labels_mut<F: FnMut(&mut Label)>(&mut self, mut f: F) {
+        f(&mut self.start);
+        f(&mut self.end);
+        f(&mut self.handler);
+    }
+}
+
 impl ConstantPoolReadWrite for Code {
     fn read_from<C: ConstantPoolReader, R: Read>(cp: &mut C, reader: &mut R) -> crate::Result<Self, Error> {
         use crate::insn::{Instruction as I, Wide, SwitchEntry, TableSwitch as TblS};
@@ -457,7 +629,7 @@ impl ConstantPoolReadWrite for Code {
                 self.inner.read_raw(idx)
             }
 
-            fn resolve_later(&mut self, bsm_idx: u16, bsm: Rc<LazyBsm>) {
+            fn resolve_later(&mut self, bsm_idx: u16, bsm: Arc<LazyBsm>) {
                 self.inner.resolve_later(bsm_idx, bsm)
             }
 
@@ -478,7 +650,12 @@ impl ConstantPoolReadWrite for Code {
             fn get_catch(&mut self, idx: u16) -> Option<Catch> {
                 self.catches.get(idx as usize).cloned()
             }
+
+            fn warn(&mut self, e: Error) -> Result<()> {
+                self.inner.warn(e)
+            }
         }
+        let reader = &mut Counted::new(reader);
         let max_stack = u16::read_from(reader)?;
         let max_locals = u16::read_from(reader)?;
         let mut code = vec![0; u32::read_from(reader)? as usize];
@@ -491,305 +668,307 @@ impl ConstantPoolReadWrite for Code {
         while (code_reader.position() as usize) < len {
             let curpos = code_reader.position();
             pos2idx.insert(curpos as u32, instructions.len());
-            let opcode = code_reader.get_ref()[curpos as usize];
-            let insn = match opcode {
-                crate::constants::insn::TABLESWITCH | crate::constants::insn::LOOKUPSWITCH => {
-                    // pad 0-3 bytes to align properly
-                    code_reader.seek(SeekFrom::Current((4 - (curpos & 3)) as i64))?;
-                    let op = [opcode];
-                    let mut temp_read = (&op).chain(&mut code_reader);
-                    crate::insn::Instruction::read_from(&mut temp_read)?
-                }
-                _ => {
-                    crate::insn::Instruction::read_from(&mut code_reader)?
-                }
-            };
-            macro_rules! lbl {
-                ($off:expr) => ({labeler.get_label((curpos as i64 + $off as i64) as u32)});
-            }
-            #[inline]
-            fn push<C: Into<OrDynamic<Constant>>>(c: C) -> Instruction { Push(c.into()) }
-            let insn = match insn {
-                I::AThrow => Throw,
-                I::Nop => NoOp,
-                I::AConstNull => PushNull,
-                I::Swap => Swap,
-                I::LCmp => CompareLongs,
-                I::ArrayLength => ArrayLength,
-                I::MonitorEnter => Monitor(MonitorOperation::Enter),
-                I::MonitorExit => Monitor(MonitorOperation::Exit),
-
-                I::IInc(idx, val) => IntIncrement(idx as u16, val as i16),
-                I::Wide(Wide::IInc(idx, val)) => IntIncrement(idx, val),
-
-                I::Pop => Pop1,
-                I::Pop2 => Pop2,
-
-                I::Dup => Dup,
-                I::Dup2 => Dup2,
-                I::Dupx1 => DupX1,
-                I::Dupx2 => DupX2,
-                I::Dup2x1 => Dup2X1,
-                I::Dup2x2 => Dup2X2,
-
-                I::IConstM1 => push(Constant::I32(-1)),
-                I::IConst0 => push(Constant::I32(0)),
-                I::IConst1 => push(Constant::I32(1)),
-                I::IConst2 => push(Constant::I32(2)),
-                I::IConst3 => push(Constant::I32(3)),
-                I::IConst4 => push(Constant::I32(4)),
-                I::IConst5 => push(Constant::I32(5)),
-
-                I::FConst0 => push(Constant::F32(0.0)),
-                I::FConst1 => push(Constant::F32(1.0)),
-                I::FConst2 => push(Constant::F32(2.0)),
-
-                I::DConst0 => push(Constant::F64(0.0)),
-                I::DConst1 => push(Constant::F64(1.0)),
-
-                I::LConst0 => push(Constant::I64(0)),
-                I::LConst1 => push(Constant::I64(1)),
-
-                I::Bipush(b) => push(Constant::I32(b as i32)),
-                I::Sipush(s) => push(Constant::I32(s as i32)),
-
-                I::Ldc(b) => push(try_cp_read_idx!(labeler, b as u16, read_constant)?),
-                I::LdcW(i) | I::Ldc2W(i) => push(try_cp_read_idx!(labeler, i, read_constant)?),
-
-                I::IALoad => Array(Load, ArrayType::Int),
-                I::LALoad => Array(Load, ArrayType::Long),
-                I::FALoad => Array(Load, ArrayType::Float),
-                I::DALoad => Array(Load, ArrayType::Double),
-                I::CALoad => Array(Load, ArrayType::Char),
-                I::SALoad => Array(Load, ArrayType::Short),
-                I::BALoad => Array(Load, ArrayType::ByteOrBool),
-                I::AALoad => Array(Load, ArrayType::Reference),
-
-                I::IAStore => Array(Store, ArrayType::Int),
-                I::LAStore => Array(Store, ArrayType::Long),
-                I::FAStore => Array(Store, ArrayType::Float),
-                I::DAStore => Array(Store, ArrayType::Double),
-                I::CAStore => Array(Store, ArrayType::Char),
-                I::SAStore => Array(Store, ArrayType::Short),
-                I::BAStore => Array(Store, ArrayType::ByteOrBool),
-                I::AAStore => Array(Store, ArrayType::Reference),
-
-                I::ALoad0 => LocalVariable(Load, LocalType::Reference, 0),
-                I::ALoad1 => LocalVariable(Load, LocalType::Reference, 1),
-                I::ALoad2 => LocalVariable(Load, LocalType::Reference, 2),
-                I::ALoad3 => LocalVariable(Load, LocalType::Reference, 3),
-                I::ALoad(i) => LocalVariable(Load, LocalType::Reference, i as u16),
-                I::Wide(Wide::ALoad(i)) => LocalVariable(Load, LocalType::Reference, i),
-
-                I::ILoad0 => LocalVariable(Load, LocalType::Int, 0),
-                I::ILoad1 => LocalVariable(Load, LocalType::Int, 1),
-                I::ILoad2 => LocalVariable(Load, LocalType::Int, 2),
-                I::ILoad3 => LocalVariable(Load, LocalType::Int, 3),
-                I::ILoad(i) => LocalVariable(Load, LocalType::Int, i as u16),
-                I::Wide(Wide::ILoad(i)) => LocalVariable(Load, LocalType::Int, i),
-
-                I::LLoad0 => LocalVariable(Load, LocalType::Long, 0),
-                I::LLoad1 => LocalVariable(Load, LocalType::Long, 1),
-                I::LLoad2 => LocalVariable(Load, LocalType::Long, 2),
-                I::LLoad3 => LocalVariable(Load, LocalType::Long, 3),
-                I::LLoad(i) => LocalVariable(Load, LocalType::Long, i as u16),
-                I::Wide(Wide::LLoad(i)) => LocalVariable(Load, LocalType::Long, i),
-
-                I::FLoad0 => LocalVariable(Load, LocalType::Float, 0),
-                I::FLoad1 => LocalVariable(Load, LocalType::Float, 1),
-                I::FLoad2 => LocalVariable(Load, LocalType::Float, 2),
-                I::FLoad3 => LocalVariable(Load, LocalType::Float, 3),
-                I::FLoad(i) => LocalVariable(Load, LocalType::Float, i as u16),
-                I::Wide(Wide::FLoad(i)) => LocalVariable(Load, LocalType::Float, i),
-
-                I::DLoad0 => LocalVariable(Load, LocalType::Double, 0),
-                I::DLoad1 => LocalVariable(Load, LocalType::Double, 1),
-                I::DLoad2 => LocalVariable(Load, LocalType::Double, 2),
-                I::DLoad3 => LocalVariable(Load, LocalType::Double, 3),
-                I::DLoad(i) => LocalVariable(Load, LocalType::Double, i as u16),
-                I::Wide(Wide::DLoad(i)) => LocalVariable(Load, LocalType::Double, i),
-
-                I::AStore0 => LocalVariable(Store, LocalType::Reference, 0),
-                I::AStore1 => LocalVariable(Store, LocalType::Reference, 1),
-                I::AStore2 => LocalVariable(Store, LocalType::Reference, 2),
-                I::AStore3 => LocalVariable(Store, LocalType::Reference, 3),
-                I::AStore(i) => LocalVariable(Store, LocalType::Reference, i as u16),
-                I::Wide(Wide::AStore(i)) => LocalVariable(Store, LocalType::Reference, i),
-
-                I::IStore0 => LocalVariable(Store, LocalType::Int, 0),
-                I::IStore1 => LocalVariable(Store, LocalType::Int, 1),
-                I::IStore2 => LocalVariable(Store, LocalType::Int, 2),
-                I::IStore3 => LocalVariable(Store, LocalType::Int, 3),
-                I::IStore(i) => LocalVariable(Store, LocalType::Int, i as u16),
-                I::Wide(Wide::IStore(i)) => LocalVariable(Store, LocalType::Int, i),
-
-                I::LStore0 => LocalVariable(Store, LocalType::Long, 0),
-                I::LStore1 => LocalVariable(Store, LocalType::Long, 1),
-                I::LStore2 => LocalVariable(Store, LocalType::Long, 2),
-                I::LStore3 => LocalVariable(Store, LocalType::Long, 3),
-                I::LStore(i) => LocalVariable(Store, LocalType::Long, i as u16),
-                I::Wide(Wide::LStore(i)) => LocalVariable(Store, LocalType::Long, i),
-
-                I::FStore0 => LocalVariable(Store, LocalType::Float, 0),
-                I::FStore1 => LocalVariable(Store, LocalType::Float, 1),
-                I::FStore2 => LocalVariable(Store, LocalType::Float, 2),
-                I::FStore3 => LocalVariable(Store, LocalType::Float, 3),
-                I::FStore(i) => LocalVariable(Store, LocalType::Float, i as u16),
-                I::Wide(Wide::FStore(i)) => LocalVariable(Store, LocalType::Float, i),
-
-                I::DStore0 => LocalVariable(Store, LocalType::Double, 0),
-                I::DStore1 => LocalVariable(Store, LocalType::Double, 1),
-                I::DStore2 => LocalVariable(Store, LocalType::Double, 2),
-                I::DStore3 => LocalVariable(Store, LocalType::Double, 3),
-                I::DStore(i) => LocalVariable(Store, LocalType::Double, i as u16),
-                I::Wide(Wide::DStore(i)) => LocalVariable(Store, LocalType::Double, i),
-
-                I::IAdd => IntOperation(IntType::Int, IOp::Add),
-                I::IAnd => IntOperation(IntType::Int, IOp::And),
-                I::INeg => IntOperation(IntType::Int, IOp::Negate),
-                I::IXor => IntOperation(IntType::Int, IOp::ExclusiveOr),
-                I::IOr => IntOperation(IntType::Int, IOp::Or),
-                I::ISub => IntOperation(IntType::Int, IOp::Subtract),
-                I::IMul => IntOperation(IntType::Int, IOp::Multiply),
-                I::IDiv => IntOperation(IntType::Int, IOp::Divide),
-                I::IShr => IntOperation(IntType::Int, IOp::ShiftRight),
-                I::IShl => IntOperation(IntType::Int, IOp::ShiftLeft),
-                I::IUshr => IntOperation(IntType::Int, IOp::UnsignedShiftRight),
-                I::IRem => IntOperation(IntType::Int, IOp::Remainder),
-
-                I::LAdd => IntOperation(IntType::Long, IOp::Add),
-                I::LAnd => IntOperation(IntType::Long, IOp::And),
-                I::LNeg => IntOperation(IntType::Long, IOp::Negate),
-                I::LXor => IntOperation(IntType::Long, IOp::ExclusiveOr),
-                I::LOr => IntOperation(IntType::Long, IOp::Or),
-                I::LSub => IntOperation(IntType::Long, IOp::Subtract),
-                I::LMul => IntOperation(IntType::Long, IOp::Multiply),
-                I::LDiv => IntOperation(IntType::Long, IOp::Divide),
-                I::LShr => IntOperation(IntType::Long, IOp::ShiftRight),
-                I::LShl => IntOperation(IntType::Long, IOp::ShiftLeft),
-                I::LUshr => IntOperation(IntType::Long, IOp::UnsignedShiftRight),
-                I::LRem => IntOperation(IntType::Long, IOp::Remainder),
-
-                I::FAdd => FloatOperation(FloatType::Float, FOp::Add),
-                I::FNeg => FloatOperation(FloatType::Float, FOp::Negate),
-                I::FSub => FloatOperation(FloatType::Float, FOp::Subtract),
-                I::FMul => FloatOperation(FloatType::Float, FOp::Multiply),
-                I::FDiv => FloatOperation(FloatType::Float, FOp::Divide),
-                I::FRem => FloatOperation(FloatType::Float, FOp::Remainder),
-
-                I::DAdd => FloatOperation(FloatType::Double, FOp::Add),
-                I::DNeg => FloatOperation(FloatType::Double, FOp::Negate),
-                I::DSub => FloatOperation(FloatType::Double, FOp::Subtract),
-                I::DMul => FloatOperation(FloatType::Double, FOp::Multiply),
-                I::DDiv => FloatOperation(FloatType::Double, FOp::Divide),
-                I::DRem => FloatOperation(FloatType::Double, FOp::Remainder),
-
-                I::I2B => ConvertInt(BitType::Byte),
-                I::I2C => ConvertInt(BitType::Char),
-                I::I2S => ConvertInt(BitType::Short),
-                I::I2F => ConvertInt(BitType::Float),
-                I::I2L => ConvertInt(BitType::Long),
-                I::I2D => ConvertInt(BitType::Double),
-
-                I::L2I => Conversion(NumberType::Long, NumberType::Int),
-                I::L2D => Conversion(NumberType::Long, NumberType::Double),
-                I::L2F => Conversion(NumberType::Long, NumberType::Float),
-
-                I::F2I => Conversion(NumberType::Float, NumberType::Int),
-                I::F2D => Conversion(NumberType::Float, NumberType::Double),
-                I::F2L => Conversion(NumberType::Float, NumberType::Long),
-
-                I::D2I => Conversion(NumberType::Double, NumberType::Int),
-                I::D2L => Conversion(NumberType::Double, NumberType::Long),
-                I::D2F => Conversion(NumberType::Double, NumberType::Float),
-
-                I::FCmpG => CompareFloats(FloatType::Float, NaNBehavior::ReturnsOne),
-                I::FCmpL => CompareFloats(FloatType::Float, NaNBehavior::ReturnsNegativeOne),
-                I::DCmpG => CompareFloats(FloatType::Double, NaNBehavior::ReturnsOne),
-                I::DCmpL => CompareFloats(FloatType::Double, NaNBehavior::ReturnsNegativeOne),
-
-                I::Goto(off) => Jump(JumpCondition::Always, lbl!(off)),
-                I::GotoW(off) => Jump(JumpCondition::Always, lbl!(off)),
-                I::IfEq(off) => Jump(JumpCondition::IntegerEqualsZero, lbl!(off)),
-                I::IfNe(off) => Jump(JumpCondition::IntegerNotEqualsZero, lbl!(off)),
-                I::IfGt(off) => Jump(JumpCondition::IntegerGreaterThanZero, lbl!(off)),
-                I::IfGe(off) => Jump(JumpCondition::IntegerGreaterThanOrEqualsZero, lbl!(off)),
-                I::IfLt(off) => Jump(JumpCondition::IntegerLessThanZero, lbl!(off)),
-                I::IfLe(off) => Jump(JumpCondition::IntegerLessThanOrEqualsZero, lbl!(off)),
-                I::IfNull(off) => Jump(JumpCondition::IsNull, lbl!(off)),
-                I::IfNonNull(off) => Jump(JumpCondition::IsNonNull, lbl!(off)),
-                I::IfACmpEq(off) => Jump(JumpCondition::ReferenceEquals, lbl!(off)),
-                I::IfACmpNe(off) => Jump(JumpCondition::ReferenceNotEquals, lbl!(off)),
-                I::IfICmpEq(off) => Jump(JumpCondition::IntegerEquals, lbl!(off)),
-                I::IfICmpNe(off) => Jump(JumpCondition::IntegerNotEquals, lbl!(off)),
-                I::IfICmpGt(off) => Jump(JumpCondition::IntegerGreaterThan, lbl!(off)),
-                I::IfICmpGe(off) => Jump(JumpCondition::IntegerGreaterThanOrEquals, lbl!(off)),
-                I::IfICmpLt(off) => Jump(JumpCondition::IntegerLessThan, lbl!(off)),
-                I::IfICmpLe(off) => Jump(JumpCondition::IntegerLessThanOrEquals, lbl!(off)),
-
-                I::Jsr(off) => Jsr(lbl!(off)),
-                I::JsrW(off) => Jsr(lbl!(off)),
-
-                I::Ret(l) => Ret(l as u16),
-                I::Wide(Wide::Ret(l)) => Ret(l),
-
-                I::AReturn => Return(Some(LocalType::Reference)),
-                I::IReturn => Return(Some(LocalType::Int)),
-                I::LReturn => Return(Some(LocalType::Long)),
-                I::DReturn => Return(Some(LocalType::Double)),
-                I::FReturn => Return(Some(LocalType::Float)),
-                I::Return => Return(None),
-
-                I::TableSwitch(dflt, TblS { low, offsets, .. }) => TableSwitch { default: lbl!(dflt), low, offsets: offsets.into_iter().map(|i| lbl!(i)).collect() },
-                I::LookupSwitch(dflt, switches) => LookupSwitch { default: lbl!(dflt), table: switches.into_iter().map(|SwitchEntry(i, to)| (i, lbl!(to))).collect() },
-
-                I::GetStatic(field) => Field(Get, Static, try_cp_read!(field, labeler.read_or_dynamic(field, ConstantPoolReader::read_member))?),
-                I::PutStatic(field) => Field(Put, Static, try_cp_read!(field, labeler.read_or_dynamic(field, ConstantPoolReader::read_member))?),
-                I::GetField(field) => Field(Get, Virtual, try_cp_read!(field, labeler.read_or_dynamic(field, ConstantPoolReader::read_member))?),
-                I::PutField(field) => Field(Put, Virtual, try_cp_read!(field, labeler.read_or_dynamic(field, ConstantPoolReader::read_member))?),
-
-                I::InvokeStatic(m) => InvokeExact(Static, try_cp_read!(m, labeler.read_or_dynamic(m, ConstantPoolReader::read_member))?),
-                I::InvokeVirtual(m) => InvokeExact(Virtual, try_cp_read!(m, labeler.read_or_dynamic(m, ConstantPoolReader::read_member))?),
-
-                I::InvokeSpecial(m) => InvokeSpecial(try_cp_read!(m, labeler.read_or_dynamic(m, ConstantPoolReader::read_member))?),
-                I::InvokeInterface(m, c, _) => InvokeInterface(try_cp_read!(m, labeler.read_or_dynamic(m, ConstantPoolReader::read_member))?, c),
-                I::InvokeDynamic(d, _) => InvokeDynamic(try_cp_read_idx!(labeler, d, read_invokedynamic)?),
-
-                I::New(n) => New(try_cp_read!(n, labeler.read_or_dynamic(n, ConstantPoolReader::read_class))?),
-
-                I::NewArray(4) => NewArray(OrDynamic::Static(Type::Boolean), 1),
-                I::NewArray(5) => NewArray(OrDynamic::Static(Type::Char), 1),
-                I::NewArray(6) => NewArray(OrDynamic::Static(Type::Float), 1),
-                I::NewArray(7) => NewArray(OrDynamic::Static(Type::Double), 1),
-                I::NewArray(8) => NewArray(OrDynamic::Static(Type::Byte), 1),
-                I::NewArray(9) => NewArray(OrDynamic::Static(Type::Short), 1),
-                I::NewArray(10) => NewArray(OrDynamic::Static(Type::Int), 1),
-                I::NewArray(11) => NewArray(OrDynamic::Static(Type::Long), 1),
-                I::NewArray(n) => return Err(Error::Invalid("NewArray type", n.to_string().into())),
-
-                I::ANewArray(r) => NewArray(try_cp_read!(r, labeler.read_or_dynamic(r, ConstantPoolReader::read_class))?.map_static(|c| c.parse().unwrap_or(Type::Ref(c))), 1),
-                I::MultiANewArray(r, dim) => NewArray(try_cp_read!(r, labeler.read_or_dynamic(r, ConstantPoolReader::read_class))?.map_static(|c| c.parse().unwrap_or(Type::Ref(c))), dim),
-                I::CheckCast(r) => CheckCast(try_cp_read!(r, labeler.read_or_dynamic(r, ConstantPoolReader::read_class)).and_then(|t|
-                    match t {
-                        OrDynamic::Static(c) => Ok(OrDynamic::Static(
-                            if c.starts_with('[') {
-                                if let Type::ArrayRef(dim, ty) = Type::from_str(c.as_ref())? { ClassType::Array(dim, *ty) } else { unsafe { std::hint::unreachable_unchecked() } }
-                            } else { ClassType::Object(c) }
-                        )),
-                        OrDynamic::Dynamic(d) => Ok(OrDynamic::Dynamic(d))
+            let insn = (|| -> crate::Result<Instruction> {
+                let opcode = code_reader.get_ref()[curpos as usize];
+                let insn = match opcode {
+                    crate::constants::insn::TABLESWITCH | crate::constants::insn::LOOKUPSWITCH => {
+                        // pad 0-3 bytes to align properly
+                        code_reader.seek(SeekFrom::Current((4 - (curpos & 3)) as i64))?;
+                        let op = [opcode];
+                        let mut temp_read = (&op).chain(&mut code_reader);
+                        crate::insn::Instruction::read_from(&mut temp_read)?
                     }
-                )?),
-                I::InstanceOf(r) => InstanceOf(try_cp_read!(r, labeler.read_or_dynamic(r, ConstantPoolReader::read_class)).and_then(|t|
-                    match t {
-                        OrDynamic::Static(c) => Ok(OrDynamic::Static(
-                            if c.starts_with('[') {
-                                if let Type::ArrayRef(dim, ty) = Type::from_str(c.as_ref())? { ClassType::Array(dim, *ty) } else { unsafe { std::hint::unreachable_unchecked() } }
-                            } else { ClassType::Object(c) }
-                        )),
-                        OrDynamic::Dynamic(d) => Ok(OrDynamic::Dynamic(d))
+                    _ => {
+                        crate::insn::Instruction::read_from(&mut code_reader)?
                     }
-                )?),
-            };
+                };
+                macro_rules! lbl {
+                    ($off:expr) => ({labeler.get_label((curpos as i64 + $off as i64) as u32)});
+                }
+                #[inline]
+                fn push<C: Into<OrDynamic<Constant>>>(c: C) -> Instruction { Push(c.into()) }
+                Ok(match insn {
+                    I::AThrow => Throw,
+                    I::Nop => NoOp,
+                    I::AConstNull => PushNull,
+                    I::Swap => Swap,
+                    I::LCmp => CompareLongs,
+                    I::ArrayLength => ArrayLength,
+                    I::MonitorEnter => Monitor(MonitorOperation::Enter),
+                    I::MonitorExit => Monitor(MonitorOperation::Exit),
+
+                    I::IInc(idx, val) => IntIncrement(idx as u16, val as i16),
+                    I::Wide(Wide::IInc(idx, val)) => IntIncrement(idx, val),
+
+                    I::Pop => Pop1,
+                    I::Pop2 => Pop2,
+
+                    I::Dup => Dup,
+                    I::Dup2 => Dup2,
+                    I::Dupx1 => DupX1,
+                    I::Dupx2 => DupX2,
+                    I::Dup2x1 => Dup2X1,
+                    I::Dup2x2 => Dup2X2,
+
+                    I::IConstM1 => push(Constant::I32(-1)),
+                    I::IConst0 => push(Constant::I32(0)),
+                    I::IConst1 => push(Constant::I32(1)),
+                    I::IConst2 => push(Constant::I32(2)),
+                    I::IConst3 => push(Constant::I32(3)),
+                    I::IConst4 => push(Constant::I32(4)),
+                    I::IConst5 => push(Constant::I32(5)),
+
+                    I::FConst0 => push(Constant::F32(0.0)),
+                    I::FConst1 => push(Constant::F32(1.0)),
+                    I::FConst2 => push(Constant::F32(2.0)),
+
+                    I::DConst0 => push(Constant::F64(0.0)),
+                    I::DConst1 => push(Constant::F64(1.0)),
+
+                    I::LConst0 => push(Constant::I64(0)),
+                    I::LConst1 => push(Constant::I64(1)),
+
+                    I::Bipush(b) => push(Constant::I32(b as i32)),
+                    I::Sipush(s) => push(Constant::I32(s as i32)),
+
+                    I::Ldc(b) => push(try_cp_read_idx!(labeler, b as u16, read_constant)?),
+                    I::LdcW(i) | I::Ldc2W(i) => push(try_cp_read_idx!(labeler, i, read_constant)?),
+
+                    I::IALoad => Array(Load, ArrayType::Int),
+                    I::LALoad => Array(Load, ArrayType::Long),
+                    I::FALoad => Array(Load, ArrayType::Float),
+                    I::DALoad => Array(Load, ArrayType::Double),
+                    I::CALoad => Array(Load, ArrayType::Char),
+                    I::SALoad => Array(Load, ArrayType::Short),
+                    I::BALoad => Array(Load, ArrayType::ByteOrBool),
+                    I::AALoad => Array(Load, ArrayType::Reference),
+
+                    I::IAStore => Array(Store, ArrayType::Int),
+                    I::LAStore => Array(Store, ArrayType::Long),
+                    I::FAStore => Array(Store, ArrayType::Float),
+                    I::DAStore => Array(Store, ArrayType::Double),
+                    I::CAStore => Array(Store, ArrayType::Char),
+                    I::SAStore => Array(Store, ArrayType::Short),
+                    I::BAStore => Array(Store, ArrayType::ByteOrBool),
+                    I::AAStore => Array(Store, ArrayType::Reference),
+
+                    I::ALoad0 => LocalVariable(Load, LocalType::Reference, 0),
+                    I::ALoad1 => LocalVariable(Load, LocalType::Reference, 1),
+                    I::ALoad2 => LocalVariable(Load, LocalType::Reference, 2),
+                    I::ALoad3 => LocalVariable(Load, LocalType::Reference, 3),
+                    I::ALoad(i) => LocalVariable(Load, LocalType::Reference, i as u16),
+                    I::Wide(Wide::ALoad(i)) => LocalVariable(Load, LocalType::Reference, i),
+
+                    I::ILoad0 => LocalVariable(Load, LocalType::Int, 0),
+                    I::ILoad1 => LocalVariable(Load, LocalType::Int, 1),
+                    I::ILoad2 => LocalVariable(Load, LocalType::Int, 2),
+                    I::ILoad3 => LocalVariable(Load, LocalType::Int, 3),
+                    I::ILoad(i) => LocalVariable(Load, LocalType::Int, i as u16),
+                    I::Wide(Wide::ILoad(i)) => LocalVariable(Load, LocalType::Int, i),
+
+                    I::LLoad0 => LocalVariable(Load, LocalType::Long, 0),
+                    I::LLoad1 => LocalVariable(Load, LocalType::Long, 1),
+                    I::LLoad2 => LocalVariable(Load, LocalType::Long, 2),
+                    I::LLoad3 => LocalVariable(Load, LocalType::Long, 3),
+                    I::LLoad(i) => LocalVariable(Load, LocalType::Long, i as u16),
+                    I::Wide(Wide::LLoad(i)) => LocalVariable(Load, LocalType::Long, i),
+
+                    I::FLoad0 => LocalVariable(Load, LocalType::Float, 0),
+                    I::FLoad1 => LocalVariable(Load, LocalType::Float, 1),
+                    I::FLoad2 => LocalVariable(Load, LocalType::Float, 2),
+                    I::FLoad3 => LocalVariable(Load, LocalType::Float, 3),
+                    I::FLoad(i) => LocalVariable(Load, LocalType::Float, i as u16),
+                    I::Wide(Wide::FLoad(i)) => LocalVariable(Load, LocalType::Float, i),
+
+                    I::DLoad0 => LocalVariable(Load, LocalType::Double, 0),
+                    I::DLoad1 => LocalVariable(Load, LocalType::Double, 1),
+                    I::DLoad2 => LocalVariable(Load, LocalType::Double, 2),
+                    I::DLoad3 => LocalVariable(Load, LocalType::Double, 3),
+                    I::DLoad(i) => LocalVariable(Load, LocalType::Double, i as u16),
+                    I::Wide(Wide::DLoad(i)) => LocalVariable(Load, LocalType::Double, i),
+
+                    I::AStore0 => LocalVariable(Store, LocalType::Reference, 0),
+                    I::AStore1 => LocalVariable(Store, LocalType::Reference, 1),
+                    I::AStore2 => LocalVariable(Store, LocalType::Reference, 2),
+                    I::AStore3 => LocalVariable(Store, LocalType::Reference, 3),
+                    I::AStore(i) => LocalVariable(Store, LocalType::Reference, i as u16),
+                    I::Wide(Wide::AStore(i)) => LocalVariable(Store, LocalType::Reference, i),
+
+                    I::IStore0 => LocalVariable(Store, LocalType::Int, 0),
+                    I::IStore1 => LocalVariable(Store, LocalType::Int, 1),
+                    I::IStore2 => LocalVariable(Store, LocalType::Int, 2),
+                    I::IStore3 => LocalVariable(Store, LocalType::Int, 3),
+                    I::IStore(i) => LocalVariable(Store, LocalType::Int, i as u16),
+                    I::Wide(Wide::IStore(i)) => LocalVariable(Store, LocalType::Int, i),
+
+                    I::LStore0 => LocalVariable(Store, LocalType::Long, 0),
+                    I::LStore1 => LocalVariable(Store, LocalType::Long, 1),
+                    I::LStore2 => LocalVariable(Store, LocalType::Long, 2),
+                    I::LStore3 => LocalVariable(Store, LocalType::Long, 3),
+                    I::LStore(i) => LocalVariable(Store, LocalType::Long, i as u16),
+                    I::Wide(Wide::LStore(i)) => LocalVariable(Store, LocalType::Long, i),
+
+                    I::FStore0 => LocalVariable(Store, LocalType::Float, 0),
+                    I::FStore1 => LocalVariable(Store, LocalType::Float, 1),
+                    I::FStore2 => LocalVariable(Store, LocalType::Float, 2),
+                    I::FStore3 => LocalVariable(Store, LocalType::Float, 3),
+                    I::FStore(i) => LocalVariable(Store, LocalType::Float, i as u16),
+                    I::Wide(Wide::FStore(i)) => LocalVariable(Store, LocalType::Float, i),
+
+                    I::DStore0 => LocalVariable(Store, LocalType::Double, 0),
+                    I::DStore1 => LocalVariable(Store, LocalType::Double, 1),
+                    I::DStore2 => LocalVariable(Store, LocalType::Double, 2),
+                    I::DStore3 => LocalVariable(Store, LocalType::Double, 3),
+                    I::DStore(i) => LocalVariable(Store, LocalType::Double, i as u16),
+                    I::Wide(Wide::DStore(i)) => LocalVariable(Store, LocalType::Double, i),
+
+                    I::IAdd => IntOperation(IntType::Int, IOp::Add),
+                    I::IAnd => IntOperation(IntType::Int, IOp::And),
+                    I::INeg => IntOperation(IntType::Int, IOp::Negate),
+                    I::IXor => IntOperation(IntType::Int, IOp::ExclusiveOr),
+                    I::IOr => IntOperation(IntType::Int, IOp::Or),
+                    I::ISub => IntOperation(IntType::Int, IOp::Subtract),
+                    I::IMul => IntOperation(IntType::Int, IOp::Multiply),
+                    I::IDiv => IntOperation(IntType::Int, IOp::Divide),
+                    I::IShr => IntOperation(IntType::Int, IOp::ShiftRight),
+                    I::IShl => IntOperation(IntType::Int, IOp::ShiftLeft),
+                    I::IUshr => IntOperation(IntType::Int, IOp::UnsignedShiftRight),
+                    I::IRem => IntOperation(IntType::Int, IOp::Remainder),
+
+                    I::LAdd => IntOperation(IntType::Long, IOp::Add),
+                    I::LAnd => IntOperation(IntType::Long, IOp::And),
+                    I::LNeg => IntOperation(IntType::Long, IOp::Negate),
+                    I::LXor => IntOperation(IntType::Long, IOp::ExclusiveOr),
+                    I::LOr => IntOperation(IntType::Long, IOp::Or),
+                    I::LSub => IntOperation(IntType::Long, IOp::Subtract),
+                    I::LMul => IntOperation(IntType::Long, IOp::Multiply),
+                    I::LDiv => IntOperation(IntType::Long, IOp::Divide),
+                    I::LShr => IntOperation(IntType::Long, IOp::ShiftRight),
+                    I::LShl => IntOperation(IntType::Long, IOp::ShiftLeft),
+                    I::LUshr => IntOperation(IntType::Long, IOp::UnsignedShiftRight),
+                    I::LRem => IntOperation(IntType::Long, IOp::Remainder),
+
+                    I::FAdd => FloatOperation(FloatType::Float, FOp::Add),
+                    I::FNeg => FloatOperation(FloatType::Float, FOp::Negate),
+                    I::FSub => FloatOperation(FloatType::Float, FOp::Subtract),
+                    I::FMul => FloatOperation(FloatType::Float, FOp::Multiply),
+                    I::FDiv => FloatOperation(FloatType::Float, FOp::Divide),
+                    I::FRem => FloatOperation(FloatType::Float, FOp::Remainder),
+
+                    I::DAdd => FloatOperation(FloatType::Double, FOp::Add),
+                    I::DNeg => FloatOperation(FloatType::Double, FOp::Negate),
+                    I::DSub => FloatOperation(FloatType::Double, FOp::Subtract),
+                    I::DMul => FloatOperation(FloatType::Double, FOp::Multiply),
+                    I::DDiv => FloatOperation(FloatType::Double, FOp::Divide),
+                    I::DRem => FloatOperation(FloatType::Double, FOp::Remainder),
+
+                    I::I2B => ConvertInt(BitType::Byte),
+                    I::I2C => ConvertInt(BitType::Char),
+                    I::I2S => ConvertInt(BitType::Short),
+                    I::I2F => ConvertInt(BitType::Float),
+                    I::I2L => ConvertInt(BitType::Long),
+                    I::I2D => ConvertInt(BitType::Double),
+
+                    I::L2I => Conversion(NumberType::Long, NumberType::Int),
+                    I::L2D => Conversion(NumberType::Long, NumberType::Double),
+                    I::L2F => Conversion(NumberType::Long, NumberType::Float),
+
+                    I::F2I => Conversion(NumberType::Float, NumberType::Int),
+                    I::F2D => Conversion(NumberType::Float, NumberType::Double),
+                    I::F2L => Conversion(NumberType::Float, NumberType::Long),
+
+                    I::D2I => Conversion(NumberType::Double, NumberType::Int),
+                    I::D2L => Conversion(NumberType::Double, NumberType::Long),
+                    I::D2F => Conversion(NumberType::Double, NumberType::Float),
+
+                    I::FCmpG => CompareFloats(FloatType::Float, NaNBehavior::ReturnsOne),
+                    I::FCmpL => CompareFloats(FloatType::Float, NaNBehavior::ReturnsNegativeOne),
+                    I::DCmpG => CompareFloats(FloatType::Double, NaNBehavior::ReturnsOne),
+                    I::DCmpL => CompareFloats(FloatType::Double, NaNBehavior::ReturnsNegativeOne),
+
+                    I::Goto(off) => Jump(JumpCondition::Always, lbl!(off)),
+                    I::GotoW(off) => Jump(JumpCondition::Always, lbl!(off)),
+                    I::IfEq(off) => Jump(JumpCondition::IntegerEqualsZero, lbl!(off)),
+                    I::IfNe(off) => Jump(JumpCondition::IntegerNotEqualsZero, lbl!(off)),
+                    I::IfGt(off) => Jump(JumpCondition::IntegerGreaterThanZero, lbl!(off)),
+                    I::IfGe(off) => Jump(JumpCondition::IntegerGreaterThanOrEqualsZero, lbl!(off)),
+                    I::IfLt(off) => Jump(JumpCondition::IntegerLessThanZero, lbl!(off)),
+                    I::IfLe(off) => Jump(JumpCondition::IntegerLessThanOrEqualsZero, lbl!(off)),
+                    I::IfNull(off) => Jump(JumpCondition::IsNull, lbl!(off)),
+                    I::IfNonNull(off) => Jump(JumpCondition::IsNonNull, lbl!(off)),
+                    I::IfACmpEq(off) => Jump(JumpCondition::ReferenceEquals, lbl!(off)),
+                    I::IfACmpNe(off) => Jump(JumpCondition::ReferenceNotEquals, lbl!(off)),
+                    I::IfICmpEq(off) => Jump(JumpCondition::IntegerEquals, lbl!(off)),
+                    I::IfICmpNe(off) => Jump(JumpCondition::IntegerNotEquals, lbl!(off)),
+                    I::IfICmpGt(off) => Jump(JumpCondition::IntegerGreaterThan, lbl!(off)),
+                    I::IfICmpGe(off) => Jump(JumpCondition::IntegerGreaterThanOrEquals, lbl!(off)),
+                    I::IfICmpLt(off) => Jump(JumpCondition::IntegerLessThan, lbl!(off)),
+                    I::IfICmpLe(off) => Jump(JumpCondition::IntegerLessThanOrEquals, lbl!(off)),
+
+                    I::Jsr(off) => Jsr(lbl!(off)),
+                    I::JsrW(off) => Jsr(lbl!(off)),
+
+                    I::Ret(l) => Ret(l as u16),
+                    I::Wide(Wide::Ret(l)) => Ret(l),
+
+                    I::AReturn => Return(Some(LocalType::Reference)),
+                    I::IReturn => Return(Some(LocalType::Int)),
+                    I::LReturn => Return(Some(LocalType::Long)),
+                    I::DReturn => Return(Some(LocalType::Double)),
+                    I::FReturn => Return(Some(LocalType::Float)),
+                    I::Return => Return(None),
+
+                    I::TableSwitch(dflt, TblS { low, offsets, .. }) => TableSwitch { default: lbl!(dflt), low, offsets: offsets.into_iter().map(|i| lbl!(i)).collect() },
+                    I::LookupSwitch(dflt, switches) => LookupSwitch { default: lbl!(dflt), table: switches.into_iter().map(|SwitchEntry(i, to)| (i, lbl!(to))).collect() },
+
+                    I::GetStatic(field) => Field(Get, Static, try_cp_read!(field, labeler.read_or_dynamic(field, ConstantPoolReader::read_member))?),
+                    I::PutStatic(field) => Field(Put, Static, try_cp_read!(field, labeler.read_or_dynamic(field, ConstantPoolReader::read_member))?),
+                    I::GetField(field) => Field(Get, Virtual, try_cp_read!(field, labeler.read_or_dynamic(field, ConstantPoolReader::read_member))?),
+                    I::PutField(field) => Field(Put, Virtual, try_cp_read!(field, labeler.read_or_dynamic(field, ConstantPoolReader::read_member))?),
+
+                    I::InvokeStatic(m) => InvokeExact(Static, try_cp_read!(m, labeler.read_or_dynamic(m, ConstantPoolReader::read_member))?),
+                    I::InvokeVirtual(m) => InvokeExact(Virtual, try_cp_read!(m, labeler.read_or_dynamic(m, ConstantPoolReader::read_member))?),
+
+                    I::InvokeSpecial(m) => InvokeSpecial(try_cp_read!(m, labeler.read_or_dynamic(m, ConstantPoolReader::read_member))?),
+                    I::InvokeInterface(m, c, _) => InvokeInterface(try_cp_read!(m, labeler.read_or_dynamic(m, ConstantPoolReader::read_member))?, c),
+                    I::InvokeDynamic(d, _) => InvokeDynamic(try_cp_read_idx!(labeler, d, read_invokedynamic)?),
+
+                    I::New(n) => New(try_cp_read!(n, labeler.read_or_dynamic(n, ConstantPoolReader::read_class))?),
+
+                    I::NewArray(4) => NewArray(OrDynamic::Static(Type::Boolean), 1),
+                    I::NewArray(5) => NewArray(OrDynamic::Static(Type::Char), 1),
+                    I::NewArray(6) => NewArray(OrDynamic::Static(Type::Float), 1),
+                    I::NewArray(7) => NewArray(OrDynamic::Static(Type::Double), 1),
+                    I::NewArray(8) => NewArray(OrDynamic::Static(Type::Byte), 1),
+                    I::NewArray(9) => NewArray(OrDynamic::Static(Type::Short), 1),
+                    I::NewArray(10) => NewArray(OrDynamic::Static(Type::Int), 1),
+                    I::NewArray(11) => NewArray(OrDynamic::Static(Type::Long), 1),
+                    I::NewArray(n) => return Err(Error::Invalid("NewArray type", n.to_string().into())),
+
+                    I::ANewArray(r) => NewArray(try_cp_read!(r, labeler.read_or_dynamic(r, ConstantPoolReader::read_class))?.map_static(|c| c.parse().unwrap_or(Type::Ref(c))), 1),
+                    I::MultiANewArray(r, dim) => NewArray(try_cp_read!(r, labeler.read_or_dynamic(r, ConstantPoolReader::read_class))?.map_static(|c| c.parse().unwrap_or(Type::Ref(c))), dim),
+                    I::CheckCast(r) => CheckCast(try_cp_read!(r, labeler.read_or_dynamic(r, ConstantPoolReader::read_class)).and_then(|t|
+                        match t {
+                            OrDynamic::Static(c) => Ok(OrDynamic::Static(
+                                if c.starts_with('[') {
+                                    if let Type::ArrayRef(dim, ty) = Type::from_str(c.as_ref())? { ClassType::Array(dim, *ty) } else { unsafe { std::hint::unreachable_unchecked() } }
+                                } else { ClassType::Object(c) }
+                            )),
+                            OrDynamic::Dynamic(d) => Ok(OrDynamic::Dynamic(d))
+                        }
+                    )?),
+                    I::InstanceOf(r) => InstanceOf(try_cp_read!(r, labeler.read_or_dynamic(r, ConstantPoolReader::read_class)).and_then(|t|
+                        match t {
+                            OrDynamic::Static(c) => Ok(OrDynamic::Static(
+                                if c.starts_with('[') {
+                                    if let Type::ArrayRef(dim, ty) = Type::from_str(c.as_ref())? { ClassType::Array(dim, *ty) } else { unsafe { std::hint::unreachable_unchecked() } }
+                                } else { ClassType::Object(c) }
+                            )),
+                            OrDynamic::Dynamic(d) => Ok(OrDynamic::Dynamic(d))
+                        }
+                    )?),
+                })
+            })().map_err(|e| e.located(Some(PathSegment::Insn(curpos as u32)), 8 + curpos, 8 + curpos))?;
             instructions.push(insn);
         }
         pos2idx.insert(code_reader.get_ref().len() as u32, instructions.len()); // the last position that is still valid but will not be covered in the loop
@@ -819,17 +998,29 @@ impl ConstantPoolReadWrite for Code {
         let mut local_vars: HashMap<LocalVarKey, LocalVar> = HashMap::new();
         #[derive(Hash, Eq, PartialEq)]
         struct LocalVarKey(Lbl, Lbl, u16, Cow<'static, str>);
+        fn valid_range(pos2idx: &HashMap<u32, usize>, start: u16, len: u16) -> bool {
+            pos2idx.contains_key(&(start as u32)) && pos2idx.contains_key(&(start as u32 + len as u32))
+        }
         for _ in 0..numattrs {
-            match CodeAttr::read_from(&mut labeler, reader)? {
+            let start = reader.pos;
+            match CodeAttr::read_from(&mut labeler, reader).map_err(|e| e.located(None, start, reader.pos))? {
                 CodeAttr::LineNumberTable(ln) => {
                     for self::LineNumber(off, line) in ln {
-                        to_insert.insert(pos2idx[&(off as u32)], vec![LineNumber(line)]);
+                        if let Some(&idx) = pos2idx.get(&(off as u32)) {
+                            to_insert.insert(idx, vec![LineNumber(line)]);
+                        } else {
+                            labeler.warn(Error::Invalid("line number offset", off.to_string().into()))?;
+                        }
                     }
                 }
                 CodeAttr::LocalVariableTable(localvar) => {
                     for l in localvar {
+                        if !valid_range(&pos2idx, l.start, l.len) {
+                            labeler.warn(Error::Invalid("local variable range", l.name.into_owned().into()))?;
+                            continue;
+                        }
                         let start = labeler.get_label(l.start as u32);
-                        let end = labeler.get_label((l.start + l.len) as u32);
+                        let end = labeler.get_label(l.start as u32 + l.len as u32);
 
                         let key = LocalVarKey(start, end, l.index, l.name.clone());
                         if let Some(v) = local_vars.get_mut(&key) {
@@ -848,8 +1039,12 @@ impl ConstantPoolReadWrite for Code {
                 }
                 CodeAttr::LocalVariableTypeTable(vartypes) => {
                     for l in vartypes {
+                        if !valid_range(&pos2idx, l.start, l.len) {
+                            labeler.warn(Error::Invalid("local variable range", l.name.into_owned().into()))?;
+                            continue;
+                        }
                         let start = labeler.get_label(l.start as u32);
-                        let end = labeler.get_label((l.start + l.len) as u32);
+                        let end = labeler.get_label(l.start as u32 + l.len as u32);
                         let key = LocalVarKey(start, end, l.index, l.name.clone());
                         if let Some(v) = local_vars.get_mut(&key) {
                             v.signature = Some(l.signature);
@@ -876,7 +1071,8 @@ impl ConstantPoolReadWrite for Code {
         }
         instructions.reserve(to_insert.len());
         for (k, v) in labeler.labels {
-            to_insert.entry(pos2idx[&k]).or_default().push(Label(v));
+            let idx = *pos2idx.get(&k).ok_or_else(|| Error::Invalid("label offset", k.to_string().into()))?;
+            to_insert.entry(idx).or_default().push(Label(v));
         }
         for (k, v) in to_insert.into_iter().rev() {
             for i in v {
@@ -900,7 +1096,7 @@ impl ConstantPoolReadWrite for Code {
         let mut jumps: Vec<&Instruction> = Vec::new();
         let insns = self.code.iter();
         let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-        let mut line_numbers: HashMap<usize, u16> = HashMap::new();
+        let mut line_numbers: Vec<(usize, usize, u16)> = Vec::new();
         let mut labels: HashMap<Label, (usize, usize)> = HashMap::new();
         macro_rules! get_label {
             ($label: expr) => {
@@ -1066,7 +1262,39 @@ impl ConstantPoolReadWrite for Code {
                     NEW.write_to(&mut cursor)?;
                     cp.insert_ordynamic(ty.clone(), C::insert_class).write_to(&mut cursor)?;
                 }
-                Instruction::NewArray(_, _) => {}
+                Instruction::NewArray(_, 0) => return Err(Error::Invalid("array dimensions", "0".into())),
+                Instruction::NewArray(OrDynamic::Static(t), 1) if !matches!(t, Type::Ref(_) | Type::ArrayRef(..)) => {
+                    NEWARRAY.write_to(&mut cursor)?;
+                    match t {
+                        Type::Boolean => 4u8,
+                        Type::Char => 5,
+                        Type::Float => 6,
+                        Type::Double => 7,
+                        Type::Byte => 8,
+                        Type::Short => 9,
+                        Type::Int => 10,
+                        Type::Long => 11,
+                        t => return Err(Error::Invalid("NewArray type", t.to_string().into()))
+                    }.write_to(&mut cursor)?;
+                }
+                Instruction::NewArray(ty, dim) => {
+                    // anewarray takes the component type, multianewarray takes the type of the whole array.
+                    match (ty, dim) {
+                        (OrDynamic::Static(Type::ArrayRef(255, _)), 1) => return Err(Error::Limit("array dimensions", 256, 255)),
+                        (OrDynamic::Static(t), 2..=255) if !matches!(t, Type::ArrayRef(n, _) if n >= dim) => {
+                            return Err(Error::Invalid("array dimensions", format!("{} for {}", dim, t).into()))
+                        }
+                        _ => {}
+                    }
+                    if *dim == 1 { ANEWARRAY } else { MULTIANEWARRAY }.write_to(&mut cursor)?;
+                    cp.insert_ordynamic(ty.clone().map_static(|t| match t {
+                        Type::Ref(name) => name,
+                        t => t.into()
+                    }), C::insert_class).write_to(&mut cursor)?;
+                    if *dim != 1 {
+                        dim.write_to(&mut cursor)?;
+                    }
+                }
                 Instruction::Monitor(MonitorOperation::Enter) => MONITORENTER.write_to(&mut cursor)?,
                 Instruction::Monitor(MonitorOperation::Exit) => MONITOREXIT.write_to(&mut cursor)?,
                 Instruction::Conversion(NumberType::Long, NumberType::Int) => L2I.write_to(&mut cursor)?,
@@ -1102,7 +1330,7 @@ impl ConstantPoolReadWrite for Code {
                         (GetOrPut::Get, MemberType::Virtual) => GETFIELD,
                         (GetOrPut::Put, MemberType::Virtual) => PUTFIELD,
                         (GetOrPut::Get, MemberType::Static) => GETSTATIC,
-                        (GetOrPut::Put, MemberType::Static) => GETSTATIC,
+                        (GetOrPut::Put, MemberType::Static) => PUTSTATIC,
                     }.write_to(&mut cursor)?;
                     cp.insert_ordynamic(mem.clone(), C::insert_member).write_to(&mut cursor)?;
                 }
@@ -1131,9 +1359,9 @@ impl ConstantPoolReadWrite for Code {
 
                 Instruction::Ret(i) => wide_or_normal!(RET, i => u8),
                 Instruction::Swap => SWAP.write_to(&mut cursor)?,
-                Instruction::IntIncrement(l, inc) => wide_or_normal!(RET, l => u8, inc => i8),
+                Instruction::IntIncrement(l, inc) => wide_or_normal!(IINC, l => u8, inc => i8),
                 Instruction::LineNumber(ln) => {
-                    line_numbers.insert(cursor.position() as usize, *ln);
+                    line_numbers.push((buf.len(), cursor.position() as usize, *ln));
                 }
                 Instruction::LookupSwitch { .. } | Instruction::TableSwitch { .. } | Instruction::Jump(_, _) | Instruction::Jsr(_)  => {
                     buf.push(cursor.into_inner());
@@ -1149,7 +1377,7 @@ impl ConstantPoolReadWrite for Code {
         let mut index_hints = Vec::new();
         let mut last_max_index = 0usize;
         let mut buf_iter = buf.iter();
-        // Get minimum/maximum starting index of the next buffer, that is: index_hints[n] is max of buf[n + 1] resulting index.
+        // Get maximum starting index of the next buffer, that is: index_hints[n] is max of buf[n + 1] resulting index.
         for j in &jumps {
             let this_size_max = 1 + match *j {
                 Instruction::LookupSwitch { default: _, table } => 11 + table.len() * 8,
@@ -1174,33 +1402,28 @@ impl ConstantPoolReadWrite for Code {
                 cont:
                     ops
         */
-        let mut actual_indices = Vec::new();
+        let mut actual_indices: Vec<usize> = Vec::new();
         let mut last_idx = 0;
         buf_iter = buf.iter();
         let mut actual_sizes = Vec::new();
-        for j in &jumps {
+        for (i, j) in jumps.iter().enumerate() {
             last_idx += buf_iter.next().unwrap().len();
+            // whether the offset from this jump to the target fits in an i16. Targets before this jump have their
+            // positions resolved already, targets after it are bounded by their maximum position.
+            let short = |target: &Label| -> Result<bool> {
+                let (buf_idx, buf_off) = get_label!(target);
+                Ok(if buf_idx <= i {
+                    let target_off = if buf_idx != 0 { actual_indices[buf_idx - 1] } else { 0 } + buf_off;
+                    last_idx - target_off <= 32768
+                } else {
+                    index_hints[buf_idx - 1] + buf_off - last_idx <= 32767
+                })
+            };
             let actual_size = 1 + match *j {
-                Instruction::LookupSwitch { default: _, table } => ((4 - ((last_idx+2) & 3)) & 3) + 8 + table.len() * 8,
-                Instruction::TableSwitch { default: _, low: _, offsets } => ((4 - ((last_idx+2) & 3)) & 3) + 12 + offsets.len() * 4,
-                Instruction::Jsr(target) | Instruction::Jump(JumpCondition::Always, target) => {
-                    let (buf_idx, buf_off) = get_label!(target);
-                    let target_off = if buf_idx != 0 { index_hints[buf_idx + 1] } else { 0 } + buf_off;
-                    if target_off <= 65535 {
-                        2
-                    } else {
-                        4
-                    }
-                },
-                Instruction::Jump(_, target) => {
-                    let (buf_idx, buf_off) = get_label!(target);
-                    let target_off = if buf_idx != 0 { index_hints[buf_idx - 1] } else { 0 } + buf_off;
-                    if target_off <= 65535 {
-                        2
-                    } else {
-                        7
-                    }
-                },
+                Instruction::LookupSwitch { default: _, table } => ((4 - ((last_idx + 1) & 3)) & 3) + 8 + table.len() * 8,
+                Instruction::TableSwitch { default: _, low: _, offsets } => ((4 - ((last_idx + 1) & 3)) & 3) + 12 + offsets.len() * 4,
+                Instruction::Jsr(target) | Instruction::Jump(JumpCondition::Always, target) => if short(target)? { 2 } else { 4 },
+                Instruction::Jump(_, target) => if short(target)? { 2 } else { 7 },
                 // SAFETY: other variants are not inserted
                 _ => unsafe { std::hint::unreachable_unchecked() }
             };
@@ -1208,12 +1431,13 @@ impl ConstantPoolReadWrite for Code {
             actual_sizes.push(actual_size);
             actual_indices.push(last_idx);
         }
-        let code_len = (buf_iter.next().unwrap().len() + last_idx) as u32;
-        code_len.write_to(writer)?;
+        // code offsets are u16 in exception tables and attributes, so the code must stay under 64KiB.
+        let code_len = checked_len::<u16>("code length", buf_iter.next().unwrap().len() + last_idx)?;
+        (code_len as u32).write_to(writer)?;
         let mut jumps_iter = jumps.into_iter();
         let mut buf_iter = buf.into_iter();
         writer.write_all(&buf_iter.next().unwrap())?;
-        for ((i, bytes), (idx, act)) in buf_iter.enumerate().zip(actual_indices.iter().zip(actual_sizes.iter())) {
+        for (bytes, (idx, act)) in buf_iter.zip(actual_indices.iter().zip(actual_sizes.iter())) {
             macro_rules! resolve_label {
                 ($label: expr) => ({
                     let (buf_off, inner_off) = get_label!($label);
@@ -1226,21 +1450,24 @@ impl ConstantPoolReadWrite for Code {
                 });
             }
 
+            // the size was decided above, 3 bytes being the non-wide form of every jump.
             macro_rules! wide {
                 ($label: ident, $off: ident => $non_wide: expr, $wide: expr) => ({
                     let $off = resolve_label!($label);
-                    if let Ok($off) = u16::try_from($off) {
+                    if *act == 3 {
+                        let $off = $off as i16;
                         $non_wide
                     } else {
                         $wide
                     }
                 });
             }
+            let opcode_idx = idx - act;
             let jump = jumps_iter.next().unwrap();
             match jump {
                 Instruction::LookupSwitch { default, table } => {
                     LOOKUPSWITCH.write_to(writer)?;
-                    writer.write_all(&vec![0; (4 - (actual_indices[i] & 3)) & 3])?; // proper 4 byte alignment
+                    writer.write_all(&vec![0; (4 - ((opcode_idx + 1) & 3)) & 3])?; // proper 4 byte alignment
                     write_to!(&resolve_label!(default), writer)?;
 
                     (table.len() as u32).write_to(writer)?;
@@ -1253,7 +1480,7 @@ impl ConstantPoolReadWrite for Code {
                 }
                 Instruction::TableSwitch { default, low, offsets } => {
                     TABLESWITCH.write_to(writer)?;
-                    writer.write_all(&vec![0; (4 - (actual_indices[i] & 3)) & 3])?; // proper 4 byte alignment
+                    writer.write_all(&vec![0; (4 - ((opcode_idx + 1) & 3)) & 3])?; // proper 4 byte alignment
                     write_to!(&resolve_label!(default), writer)?;
                     write_to!(low, writer)?;
                     write_to!(&(low + (offsets.len() - 1) as i32), writer)?;
@@ -1284,10 +1511,11 @@ impl ConstantPoolReadWrite for Code {
                         u8::write_to(&(*cond).into(), writer)?;
                         write_to!(&off, writer)?;
                     }, {
+                        // branch over the goto_w when the condition is not met, the goto_w is 3 bytes after the branch.
                         u8::write_to(&(-cond).unwrap_or_else(|| unsafe { std::hint::unreachable_unchecked() }).into(), writer)?;
-                        write_to!(&5i32, writer)?;
+                        write_to!(&8i16, writer)?;
                         GOTO_W.write_to(writer)?;
-                        write_to!(&off, writer)?;
+                        write_to!(&(off - 3), writer)?;
                     })
                 }
                 // SAFETY: other variants are not inserted
@@ -1323,7 +1551,7 @@ impl ConstantPoolReadWrite for Code {
             }
         }
 
-        (self.catches.len() as u16).write_to(writer)?;
+        checked_len::<u16>("exception table length", self.catches.len())?.write_to(writer)?;
         let mut labeler = Labeler(&actual_indices, &labels, cp, &self.catches);
         for Catch { start, end, handler, catch } in &self.catches {
             labeler.label(start).write_to(writer)?;
@@ -1335,9 +1563,15 @@ impl ConstantPoolReadWrite for Code {
                 0
             }.write_to(writer)?;
         }
-        (self.attrs.len() as u16).write_to(writer)?;
+        let mut attrs = Vec::with_capacity(self.attrs.len() + 1);
+        if !line_numbers.is_empty() {
+            attrs.push(CodeAttr::LineNumberTable(line_numbers.into_iter().map(|(buf_off, inner_off, line)| {
+                let off = (if buf_off == 0 { 0 } else { actual_indices[buf_off - 1] as u16 }) + inner_off as u16;
+                LineNumber(off, line)
+            }).collect()));
+        }
         for a in &self.attrs {
-            match a {
+            let attr = match a {
                 CodeAttribute::VisibleTypeAnnotations(a) => CodeAttr::RuntimeVisibleTypeAnnotations(a.clone()),
                 CodeAttribute::InvisibleTypeAnnotations(a) => CodeAttr::RuntimeInvisibleTypeAnnotations(a.clone()),
                 CodeAttribute::LocalVariables(l) => {
@@ -1372,13 +1606,20 @@ impl ConstantPoolReadWrite for Code {
                         (false, true) => CodeAttr::LocalVariableTypeTable(ty),
                         (true, false) => CodeAttr::LocalVariableTable(var),
                         (false, false) => {
-                            CodeAttr::LocalVariableTable(var).write_to(&mut labeler, writer)?;
+                            attrs.push(CodeAttr::LocalVariableTable(var));
                             CodeAttr::LocalVariableTypeTable(ty)
                         }
                     }
                 }
                 CodeAttribute::Raw(r) => CodeAttr::Raw(r.clone())
-            }.write_to(&mut labeler, writer)?;
+            };
+            attrs.push(attr);
+        }
+        // raw attributes that are not kept are not written, so they must not be counted either.
+        attrs.retain(CodeAttr::is_written);
+        checked_len::<u16>("code attribute count", attrs.len())?.write_to(writer)?;
+        for a in attrs {
+            a.write_to(&mut labeler, writer)?;
         }
         Ok(())
     }
@@ -1477,11 +1718,11 @@ impl ConstantPoolReadWrite for RawFrame {
             RawFrame::Full(off, locals, stack) => {
                 255u8.write_to(writer)?;
                 off.write_to(writer)?;
-                (locals.len() as u16).write_to(writer)?;
+                checked_len::<u16>("frame local count", locals.len())?.write_to(writer)?;
                 for local in locals {
                     local.write_to(cp, writer)?;
                 }
-                (stack.len() as u16).write_to(writer)?;
+                checked_len::<u16>("frame stack size", stack.len())?.write_to(writer)?;
                 for s in stack {
                     s.write_to(cp, writer)?;
                 }
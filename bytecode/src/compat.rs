@@ -0,0 +1,291 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Binary compatibility between two versions of a library.
+//!
+//! [`check`] compares the classes of an old and a new version and reports the changes that the Java Language Specification
+//! (chapter 13) describes as breaking compatibility with pre-existing binaries, such as removing a member that clients may link
+//! against or making a method final.
+//!
+//! Only the API of the old version is considered: public classes, and their public and protected members.
+//! A member that is removed from a class but still inherited from one of its supertypes in the new version is not reported.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use crate::prelude::*;
+use crate::Class;
+
+/// The accessibility of a class or member, from the least to the most accessible.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum Visibility {
+    Private,
+    Package,
+    Protected,
+    Public
+}
+
+impl Visibility {
+    fn from_bits(public: bool, protected: bool, private: bool) -> Self {
+        if public {
+            Visibility::Public
+        } else if protected {
+            Visibility::Protected
+        } else if private {
+            Visibility::Private
+        } else {
+            Visibility::Package
+        }
+    }
+}
+
+impl From<FieldFlags> for Visibility {
+    fn from(f: FieldFlags) -> Self {
+        Visibility::from_bits(f.contains(FieldFlags::ACC_PUBLIC), f.contains(FieldFlags::ACC_PROTECTED), f.contains(FieldFlags::ACC_PRIVATE))
+    }
+}
+
+impl From<MethodFlags> for Visibility {
+    fn from(f: MethodFlags) -> Self {
+        Visibility::from_bits(f.contains(MethodFlags::ACC_PUBLIC), f.contains(MethodFlags::ACC_PROTECTED), f.contains(MethodFlags::ACC_PRIVATE))
+    }
+}
+
+impl From<ClassFlags> for Visibility {
+    fn from(f: ClassFlags) -> Self {
+        Visibility::from_bits(f.contains(ClassFlags::ACC_PUBLIC), false, false)
+    }
+}
+
+/// A binary incompatible change.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Incompatibility {
+    /// A public class is missing from the new version.
+    ClassRemoved(Cow<'static, str>),
+    /// A public class is no longer public.
+    ClassNarrowed(Cow<'static, str>),
+    /// A public class was made final, so it can no longer be extended.
+    ClassFinalAdded(Cow<'static, str>),
+    /// A public class was made abstract, so it can no longer be instantiated.
+    ClassAbstractAdded(Cow<'static, str>),
+    /// A class no longer has the second class or interface among its supertypes.
+    SupertypeRemoved(Cow<'static, str>, Cow<'static, str>),
+    /// A member is missing from the new version, and is not inherited either.
+    MemberRemoved(MemberRef),
+    /// A member is missing from the new version, but a member with the same name and another descriptor was added.
+    DescriptorChanged(MemberRef, Type),
+    /// A field, or an instance method of a class that is not final, was made final.
+    FinalAdded(MemberRef),
+    /// A method with a body was made abstract, which breaks the code invoking it and the subclasses not overriding it.
+    AbstractAdded(MemberRef),
+    /// A field or method was made static, or is no longer static if this is `false`.
+    StaticChanged(MemberRef, bool),
+    /// A member is less accessible than it was.
+    VisibilityNarrowed(MemberRef, Visibility, Visibility),
+    /// An abstract method was added to a public interface, which breaks the classes implementing it.
+    AbstractMethodAdded(MemberRef)
+}
+
+/// A field or method, with the properties compatibility depends on.
+struct Member<'a> {
+    name: &'a Cow<'static, str>,
+    descriptor: &'a Type,
+    visibility: Visibility,
+    is_static: bool,
+    is_final: bool,
+    is_abstract: bool,
+    is_synthetic: bool
+}
+
+impl Member<'_> {
+    fn to_ref(&self, owner: &Class) -> MemberRef {
+        MemberRef {
+            owner: owner.name.clone(),
+            name: self.name.clone(),
+            descriptor: self.descriptor.clone(),
+            itfs: owner.access.contains(ClassFlags::ACC_INTERFACE)
+        }
+    }
+}
+
+fn members(class: &Class) -> impl Iterator<Item = Member<'_>> {
+    class.fields.iter().map(|f| Member {
+        name: &f.name,
+        descriptor: &f.descriptor,
+        visibility: f.access.into(),
+        is_static: f.access.contains(FieldFlags::ACC_STATIC),
+        is_final: f.access.contains(FieldFlags::ACC_FINAL),
+        is_abstract: false,
+        is_synthetic: f.access.contains(FieldFlags::ACC_SYNTHETIC)
+    }).chain(class.methods.iter().map(|m| Member {
+        name: &m.name,
+        descriptor: &m.descriptor,
+        visibility: m.access.into(),
+        is_static: m.access.contains(MethodFlags::ACC_STATIC),
+        is_final: m.access.contains(MethodFlags::ACC_FINAL),
+        is_abstract: m.access.contains(MethodFlags::ACC_ABSTRACT),
+        is_synthetic: m.access.contains(MethodFlags::ACC_SYNTHETIC)
+    }))
+}
+
+/// A set of classes indexed by name.
+struct Classes<'a>(HashMap<&'a str, &'a Class>);
+
+impl<'a> Classes<'a> {
+    fn new(classes: &'a [Class]) -> Self {
+        Classes(classes.iter().map(|c| (c.name.as_ref(), c)).collect())
+    }
+
+    /// The direct supertypes of the class.
+    fn direct_supertypes(class: &'a Class) -> impl Iterator<Item = &'a Cow<'static, str>> {
+        class.super_name.iter().chain(class.interfaces.iter())
+    }
+
+    /// All supertypes of the class, as far as they are known.
+    fn supertypes(&self, class: &'a Class) -> Vec<&'a Cow<'static, str>> {
+        let mut seen = HashSet::new();
+        let mut order = vec![];
+        let mut queue = Self::direct_supertypes(class).collect::<Vec<_>>();
+        while let Some(name) = queue.pop() {
+            if seen.insert(name) {
+                order.push(name);
+                if let Some(c) = self.0.get(name.as_ref()) {
+                    queue.extend(Self::direct_supertypes(c));
+                }
+            }
+        }
+        order
+    }
+
+    /// Whether a member with this name and descriptor is accessible through a supertype of the class.
+    fn inherits(&self, class: &'a Class, name: &str, descriptor: &Type) -> bool {
+        self.supertypes(class).into_iter().filter_map(|s| self.0.get(s.as_ref())).any(|s| {
+            members(s).any(|m| m.name == name && m.descriptor == descriptor && m.visibility > Visibility::Private)
+        })
+    }
+}
+
+/// Reports the binary incompatible changes from the `old` to the `new` version of a library.
+///
+/// Incompatibilities are reported in the order of the old classes and their members.
+pub fn check(old: &[Class], new: &[Class]) -> Vec<Incompatibility> {
+    let (old_classes, new_classes) = (Classes::new(old), Classes::new(new));
+    let mut found = vec![];
+    for o in old.iter().filter(|c| c.access.contains(ClassFlags::ACC_PUBLIC)) {
+        let n = match new_classes.0.get(o.name.as_ref()) {
+            Some(n) => *n,
+            None => {
+                found.push(Incompatibility::ClassRemoved(o.name.clone()));
+                continue
+            }
+        };
+        if !n.access.contains(ClassFlags::ACC_PUBLIC) {
+            found.push(Incompatibility::ClassNarrowed(o.name.clone()));
+        }
+        let was_final = o.access.contains(ClassFlags::ACC_FINAL);
+        if !was_final && n.access.contains(ClassFlags::ACC_FINAL) {
+            found.push(Incompatibility::ClassFinalAdded(o.name.clone()));
+        }
+        // interfaces are abstract already.
+        if !o.access.intersects(ClassFlags::ACC_ABSTRACT | ClassFlags::ACC_INTERFACE) && n.access.contains(ClassFlags::ACC_ABSTRACT) {
+            found.push(Incompatibility::ClassAbstractAdded(o.name.clone()));
+        }
+        let new_supertypes = new_classes.supertypes(n);
+        for s in old_classes.supertypes(o) {
+            if !new_supertypes.contains(&s) {
+                found.push(Incompatibility::SupertypeRemoved(o.name.clone(), s.clone()));
+            }
+        }
+        check_members(o, n, &new_classes, &mut found);
+    }
+    found
+}
+
+fn check_members(o: &Class, n: &Class, new_classes: &Classes, found: &mut Vec<Incompatibility>) {
+    // protected members of a final class cannot be reached from outside its package.
+    let class_final = o.access.contains(ClassFlags::ACC_FINAL);
+    let is_api = |m: &Member| !m.is_synthetic && m.name != "<clinit>"
+        && (m.visibility == Visibility::Public || (m.visibility == Visibility::Protected && !class_final));
+    let new_members = members(n).map(|m| ((m.name, m.descriptor), m)).collect::<HashMap<_, _>>();
+    let old_members = members(o).map(|m| (m.name, m.descriptor)).collect::<HashSet<_>>();
+    for m in members(o).filter(is_api) {
+        let r = m.to_ref(o);
+        let nm = match new_members.get(&(m.name, m.descriptor)) {
+            Some(nm) => nm,
+            None => {
+                if m.name == "<init>" || !new_classes.inherits(n, m.name, m.descriptor) {
+                    let replacement = members(n).find(|nm| nm.name == m.name && nm.descriptor.is_method() == m.descriptor.is_method()
+                        && !old_members.contains(&(nm.name, nm.descriptor)) && is_api(nm));
+                    found.push(match replacement {
+                        Some(nm) => Incompatibility::DescriptorChanged(r, nm.descriptor.clone()),
+                        None => Incompatibility::MemberRemoved(r)
+                    });
+                }
+                continue
+            }
+        };
+        if nm.visibility < m.visibility {
+            found.push(Incompatibility::VisibilityNarrowed(r.clone(), m.visibility, nm.visibility));
+        }
+        // static methods and methods of final classes could not be overridden (JLS 13.4.17), but fields made final
+        // break the code assigning them (JLS 13.4.9).
+        let overridable = !m.is_static && !class_final;
+        if !m.is_final && nm.is_final && (overridable || !m.descriptor.is_method()) {
+            found.push(Incompatibility::FinalAdded(r.clone()));
+        }
+        // existing subclasses do not implement the method, and invoking it on them fails (JLS 13.4.16).
+        if !m.is_abstract && nm.is_abstract {
+            found.push(Incompatibility::AbstractAdded(r.clone()));
+        }
+        if m.is_static != nm.is_static {
+            found.push(Incompatibility::StaticChanged(r, nm.is_static));
+        }
+    }
+    if o.access.contains(ClassFlags::ACC_INTERFACE) {
+        for nm in members(n).filter(|m| m.is_abstract && !old_members.contains(&(m.name, m.descriptor))) {
+            found.push(Incompatibility::AbstractMethodAdded(nm.to_ref(n)));
+        }
+    }
+}
+
+/// Formats a member reference as `owner.name descriptor`.
+struct DisplayRef<'a>(&'a MemberRef);
+
+impl Display for DisplayRef<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{} {}", self.0.owner, self.0.name, self.0.descriptor)
+    }
+}
+
+impl Display for Incompatibility {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Incompatibility::ClassRemoved(c) => write!(f, "{}: class removed", c),
+            Incompatibility::ClassNarrowed(c) => write!(f, "{}: class is no longer public", c),
+            Incompatibility::ClassFinalAdded(c) => write!(f, "{}: class made final", c),
+            Incompatibility::ClassAbstractAdded(c) => write!(f, "{}: class made abstract", c),
+            Incompatibility::SupertypeRemoved(c, s) => write!(f, "{}: supertype {} removed", c, s),
+            Incompatibility::MemberRemoved(m) => write!(f, "{}: removed", DisplayRef(m)),
+            Incompatibility::DescriptorChanged(m, t) => write!(f, "{}: descriptor changed to {}", DisplayRef(m), t),
+            Incompatibility::FinalAdded(m) => write!(f, "{}: made final", DisplayRef(m)),
+            Incompatibility::AbstractAdded(m) => write!(f, "{}: made abstract", DisplayRef(m)),
+            Incompatibility::StaticChanged(m, true) => write!(f, "{}: made static", DisplayRef(m)),
+            Incompatibility::StaticChanged(m, false) => write!(f, "{}: no longer static", DisplayRef(m)),
+            Incompatibility::VisibilityNarrowed(m, o, n) => write!(f, "{}: visibility narrowed from {:?} to {:?}", DisplayRef(m), o, n),
+            Incompatibility::AbstractMethodAdded(m) => write!(f, "{}: abstract method added to interface", DisplayRef(m))
+        }
+    }
+}
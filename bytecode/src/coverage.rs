@@ -0,0 +1,269 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Coverage probes in the style of JaCoCo.
+//!
+//! [`instrument_class`] inserts a probe at the start of every basic block of every method body, and on every edge of
+//! conditional jumps and switches. Each probe sets an element of a `boolean[]` held by a static field of the class,
+//! [`FIELD`], which is created by the static initializer before anything else. The returned [`ClassProbes`] map the index
+//! of each probe to its method, line and kind, and display as tab-separated lines to store next to the instrumented jar.
+//!
+//! Branch edges are measured with trampolines: a conditional jump is redirected to a probe followed by a jump to the
+//! original target, and the probe of the other edge is placed right after the jump. Code running before the static
+//! initializer of its class, which only happens when class initialization is recursive, fails with a
+//! `NullPointerException`.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use crate::flow::{is_executed, ControlFlowGraph};
+use crate::prelude::*;
+use crate::Class;
+
+/// The name of the field holding the probes of a class.
+pub const FIELD: &str = "$coffer$coverage";
+
+/// What a probe records.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ProbeKind {
+    /// The basic block starting at the probe was entered.
+    Block,
+    /// A conditional jump was taken, or not.
+    Jump { taken: bool },
+    /// A switch went to the target of these keys, or also of the default case.
+    Switch { keys: Vec<i32>, default: bool }
+}
+
+/// A coverage probe.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Probe {
+    /// The name of the method holding the probe.
+    pub method: Cow<'static, str>,
+    /// The descriptor of the method holding the probe.
+    pub descriptor: Type,
+    /// The line of the first instruction of the block, or of the branching instruction.
+    pub line: Option<u16>,
+    pub kind: ProbeKind
+}
+
+/// The probes of a class, indexed like the elements of the array in [`FIELD`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ClassProbes {
+    pub class: Cow<'static, str>,
+    pub probes: Vec<Probe>
+}
+
+impl Display for ProbeKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeKind::Block => f.write_str("block"),
+            ProbeKind::Jump { taken: true } => f.write_str("taken"),
+            ProbeKind::Jump { taken: false } => f.write_str("not-taken"),
+            ProbeKind::Switch { keys, default } => {
+                f.write_str("case")?;
+                let mut sep = " ";
+                for k in keys {
+                    write!(f, "{}{}", sep, k)?;
+                    sep = ",";
+                }
+                if *default {
+                    write!(f, "{}default", sep)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// One line per probe, with the class, the index of the probe, the method, the line or `-`, and the kind of the probe.
+impl Display for ClassProbes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (id, p) in self.probes.iter().enumerate() {
+            write!(f, "{}\t{}\t{}{}\t", self.class, id, p.method, p.descriptor)?;
+            match p.line {
+                Some(l) => write!(f, "{}", l)?,
+                None => f.write_str("-")?
+            }
+            writeln!(f, "\t{}", p.kind)?;
+        }
+        Ok(())
+    }
+}
+
+/// Inserts coverage probes in every method body of the class, and returns them.
+///
+/// Classes without method bodies are left untouched. This fails if the class was already instrumented, or if a method
+/// body cannot be decoded or refers to a label that is not placed.
+pub fn instrument_class(class: &mut Class) -> Result<ClassProbes> {
+    if class.fields.iter().any(|f| f.name == FIELD) {
+        return Err(Error::Invalid("class", format!("{} already has coverage probes", class.name).into()))
+    }
+    let field = MemberRef { owner: class.name.clone(), name: FIELD.into(), descriptor: Type::array(1, Type::Boolean), itfs: false };
+    let mut probes = vec![];
+    for m in &mut class.methods {
+        let (name, descriptor) = (m.name.clone(), m.descriptor.clone());
+        if let Some(code) = m.code_mut()? {
+            let mut method = MethodProbes { probes: &mut probes, name: &name, descriptor: &descriptor };
+            method.instrument(code, &field)?;
+        }
+    }
+    if probes.is_empty() {
+        return Ok(ClassProbes { class: class.name.clone(), probes })
+    }
+
+    let interface = class.access.contains(ClassFlags::ACC_INTERFACE);
+    class.fields.push(Field {
+        // fields of interfaces must be public.
+        access: FieldFlags::ACC_STATIC | FieldFlags::ACC_FINAL | FieldFlags::ACC_SYNTHETIC |
+            if interface { FieldFlags::ACC_PUBLIC } else { FieldFlags::ACC_PRIVATE | FieldFlags::ACC_TRANSIENT },
+        name: FIELD.into(),
+        descriptor: field.descriptor.clone(),
+        attrs: vec![]
+    });
+    let init = vec![
+        Instruction::Push(OrDynamic::Static(Constant::I32(probes.len() as i32))),
+        Instruction::NewArray(OrDynamic::Static(Type::Boolean), 1),
+        Instruction::Field(GetOrPut::Put, MemberType::Static, OrDynamic::Static(field))
+    ];
+    let clinit = class.methods.iter_mut().find(|m| m.name == "<clinit>");
+    match clinit.map(Method::code_mut).transpose()?.flatten() {
+        Some(code) => {
+            code.code.splice(0..0, init);
+            code.max_stack = code.max_stack.max(1);
+        }
+        None => {
+            let mut code = init;
+            code.push(Instruction::Return(None));
+            class.methods.push(Method {
+                access: MethodFlags::ACC_STATIC | MethodFlags::ACC_SYNTHETIC,
+                name: "<clinit>".into(),
+                descriptor: Type::method([], None),
+                attributes: vec![MethodAttribute::Code(Code { max_stack: 1, max_locals: 0, code, catches: vec![], attrs: vec![] })]
+            });
+        }
+    }
+    Ok(ClassProbes { class: class.name.clone(), probes })
+}
+
+struct MethodProbes<'a> {
+    probes: &'a mut Vec<Probe>,
+    name: &'a Cow<'static, str>,
+    descriptor: &'a Type
+}
+
+impl MethodProbes<'_> {
+    /// Adds a probe and returns the instructions setting it.
+    fn probe(&mut self, field: &MemberRef, line: Option<u16>, kind: ProbeKind) -> [Instruction; 4] {
+        self.probes.push(Probe { method: self.name.clone(), descriptor: self.descriptor.clone(), line, kind });
+        [
+            Instruction::Field(GetOrPut::Get, MemberType::Static, OrDynamic::Static(field.clone())),
+            Instruction::Push(OrDynamic::Static(Constant::I32(self.probes.len() as i32 - 1))),
+            Instruction::Push(OrDynamic::Static(Constant::I32(1))),
+            Instruction::Array(LoadOrStore::Store, ArrayType::ByteOrBool)
+        ]
+    }
+
+    fn instrument(&mut self, code: &mut Code, field: &MemberRef) -> Result<()> {
+        let graph = ControlFlowGraph::new(code)?;
+        let mut next_label = 0;
+        code.visit_labels_mut(|l| next_label = next_label.max(l.0 + 1));
+        let mut new_label = || {
+            next_label += 1;
+            Label(next_label - 1)
+        };
+        let old = std::mem::take(&mut code.code);
+        let mut trampolines: Vec<Trampoline> = vec![];
+        let mut line = None;
+        for block in &graph.blocks {
+            let insns = &old[block.range.clone()];
+            let first = insns.iter().position(is_executed).unwrap_or(insns.len());
+            for insn in &insns[..first] {
+                if let Instruction::LineNumber(l) = insn {
+                    line = Some(*l);
+                }
+                code.code.push(insn.clone());
+            }
+            if first < insns.len() {
+                code.code.extend(self.probe(field, line, ProbeKind::Block));
+            }
+            for insn in &insns[first..] {
+                match insn {
+                    Instruction::LineNumber(l) => {
+                        line = Some(*l);
+                        code.code.push(insn.clone());
+                    }
+                    Instruction::Jump(cond, target) if *cond != JumpCondition::Always => {
+                        let trampoline = new_label();
+                        code.code.push(Instruction::Jump(*cond, trampoline));
+                        trampolines.push((trampoline, *target, line, ProbeKind::Jump { taken: true }));
+                        code.code.extend(self.probe(field, line, ProbeKind::Jump { taken: false }));
+                    }
+                    Instruction::TableSwitch { default, low, offsets } => {
+                        let keys = offsets.iter().enumerate().map(|(i, l)| (low.wrapping_add(i as i32), *l));
+                        let redirect = Self::switch(keys, *default, line, &mut new_label, &mut trampolines);
+                        code.code.push(Instruction::TableSwitch {
+                            default: redirect[default],
+                            low: *low,
+                            offsets: offsets.iter().map(|l| redirect[l]).collect()
+                        });
+                    }
+                    Instruction::LookupSwitch { default, table } => {
+                        let redirect = Self::switch(table.iter().map(|(k, l)| (*k, *l)), *default, line, &mut new_label, &mut trampolines);
+                        code.code.push(Instruction::LookupSwitch {
+                            default: redirect[default],
+                            table: table.iter().map(|(k, l)| (*k, redirect[l])).collect()
+                        });
+                    }
+                    insn => code.code.push(insn.clone())
+                }
+            }
+        }
+        for (trampoline, target, line, kind) in trampolines {
+            code.code.push(Instruction::Label(trampoline));
+            code.code.extend(self.probe(field, line, kind));
+            code.code.push(Instruction::Jump(JumpCondition::Always, target));
+        }
+        code.max_stack += 3;
+        Ok(())
+    }
+
+    /// Creates a trampoline for every distinct target of a switch, and returns the trampoline of each target.
+    fn switch<I, F>(keys: I, default: Label, line: Option<u16>, new_label: &mut F, trampolines: &mut Vec<Trampoline>) -> HashMap<Label, Label>
+        where I: Iterator<Item = (i32, Label)>, F: FnMut() -> Label {
+        let mut cases: Vec<(Label, Vec<i32>, bool)> = vec![];
+        for (key, target) in keys.map(|(k, l)| (Some(k), l)).chain(std::iter::once((None, default))) {
+            let i = match cases.iter().position(|(l, ..)| *l == target) {
+                Some(i) => i,
+                None => {
+                    cases.push((target, vec![], false));
+                    cases.len() - 1
+                }
+            };
+            match key {
+                Some(k) => cases[i].1.push(k),
+                None => cases[i].2 = true
+            }
+        }
+        cases.into_iter().map(|(target, keys, default)| {
+            let trampoline = new_label();
+            trampolines.push((trampoline, target, line, ProbeKind::Switch { keys, default }));
+            (target, trampoline)
+        }).collect()
+    }
+}
+
+/// A label to redirect a branch to, the original target, and the line and kind of the probe on the way.
+type Trampoline = (Label, Label, Option<u16>, ProbeKind);
@@ -21,8 +21,11 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use crate::{ReadWrite, ConstantPoolReader, ConstantPoolWriter, Error};
 use crate::prelude::{Read, Write, Result, BootstrapMethod, LazyBsm};
-use std::rc::Rc;
+use std::sync::Arc;
 use std::collections::hash_map::Entry;
+use crate::lazy::SharedPool;
+use crate::error::PathSegment;
+use crate::rw::checked_len;
 
 /// A raw constant entry that has unresolved indices to other entries.
 #[derive(ReadWrite, Debug, Clone)]
@@ -75,6 +78,29 @@ impl Hash for RawConstantEntry {
     }
 }
 
+/// Floating point entries are compared by their bits, as they are hashed.
+impl PartialEq for RawConstantEntry {
+    fn eq(&self, other: &Self) -> bool {
+        use RawConstantEntry::*;
+        match (self, other) {
+            (UTF8(a), UTF8(b)) => a == b,
+            (Int(a), Int(b)) => a == b,
+            (Float(a), Float(b)) => a.to_bits() == b.to_bits(),
+            (Long(a), Long(b)) => a == b,
+            (Double(a), Double(b)) => a.to_bits() == b.to_bits(),
+            (Class(a), Class(b)) | (String(a), String(b)) | (MethodType(a), MethodType(b)) |
+            (Module(a), Module(b)) | (Package(a), Package(b)) => a == b,
+            (Field(a1, a2), Field(b1, b2)) | (Method(a1, a2), Method(b1, b2)) |
+            (InterfaceMethod(a1, a2), InterfaceMethod(b1, b2)) | (NameAndType(a1, a2), NameAndType(b1, b2)) |
+            (Dynamic(a1, a2), Dynamic(b1, b2)) | (InvokeDynamic(a1, a2), InvokeDynamic(b1, b2)) => a1 == b1 && a2 == b2,
+            (MethodHandle(a1, a2), MethodHandle(b1, b2)) => a1 == b1 && a2 == b2,
+            _ => false
+        }
+    }
+}
+
+impl Eq for RawConstantEntry {}
+
 impl RawConstantEntry {
     /// returns the size that this entry takes.
     #[inline]
@@ -90,6 +116,26 @@ impl RawConstantEntry {
     pub const fn is_wide(&self) -> bool {
         matches!(self, RawConstantEntry::Long(_) | RawConstantEntry::Double(_))
     }
+
+    /// Maps the indices of the entries this entry refers to. Bootstrap method indices are kept.
+    pub(crate) fn map_indices(&self, mut f: impl FnMut(u16) -> u16) -> Self {
+        use RawConstantEntry::*;
+        match *self {
+            Class(u) => Class(f(u)),
+            String(u) => String(f(u)),
+            MethodType(u) => MethodType(f(u)),
+            Module(u) => Module(f(u)),
+            Package(u) => Package(f(u)),
+            Field(a, b) => Field(f(a), f(b)),
+            Method(a, b) => Method(f(a), f(b)),
+            InterfaceMethod(a, b) => InterfaceMethod(f(a), f(b)),
+            NameAndType(a, b) => NameAndType(f(a), f(b)),
+            MethodHandle(kind, u) => MethodHandle(kind, f(u)),
+            Dynamic(bsm, u) => Dynamic(bsm, f(u)),
+            InvokeDynamic(bsm, u) => InvokeDynamic(bsm, f(u)),
+            ref e => e.clone()
+        }
+    }
 }
 
 /// A simple constant pool reader implementation using hashmaps for constant entries and bootstrap method references.
@@ -98,15 +144,22 @@ pub struct MapCp {
     /// The entries of this constant pool, represented as a hashmap
     /// as some entries may be absent when they are preceded by a double/long entry
     pub entries: HashMap<u16, RawConstantEntry>,
-    refs: HashMap<u16, Vec<Rc<LazyBsm>>>,
+    refs: HashMap<u16, Vec<Arc<LazyBsm>>>,
+    shared: Option<Arc<SharedPool>>,
+    warnings: Option<Vec<Error>>
 }
 
 /// A constant pool writer implementation using a vector and a number for tracking entries.
 pub struct VecCp {
     entries: Vec<RawConstantEntry>,
     /// Not actual len. (if e.wide 2 else 1 for e in entries) + 1 in pseudocode
-    len: u16,
-    pub(crate) bsm: Vec<BootstrapMethod>
+    ///
+    /// This is wider than the written count so that overflowing the pool is reported when writing instead of wrapping around.
+    len: u32,
+    pub(crate) bsm: Vec<BootstrapMethod>,
+    source: Option<Arc<SharedPool>>,
+    /// The index of each entry of a seeded pool, so that the entries already present are reused.
+    indices: Option<HashMap<RawConstantEntry, u16>>
 }
 impl VecCp {
     /// Creates an empty constant pool.
@@ -115,9 +168,50 @@ impl VecCp {
         Self {
             entries: vec![],
             len: 1,
-            bsm: vec![]
+            bsm: vec![],
+            source: None,
+            indices: None
         }
     }
+
+    /// Creates a constant pool that starts with all entries and bootstrap methods of a shared pool, at their original indices.
+    ///
+    /// Inserting an entry that is already in the pool returns its index, so writing an unchanged class gives back its pool.
+    pub fn seeded(pool: Arc<SharedPool>) -> Self {
+        let mut cp = Self::new();
+        let mut indices = pool.entries.keys().copied().collect::<Vec<_>>();
+        indices.sort_unstable();
+        let mut canonical = HashMap::new();
+        let mut seen = HashMap::new();
+        for idx in indices {
+            cp.len = idx as u32;
+            canonical_index(&pool.entries, idx, &mut canonical, &mut seen);
+            cp.insert_raw(pool.entries[&idx].clone());
+        }
+        cp.indices = Some(seen);
+        cp.bsm = pool.bootstrap_methods().to_vec();
+        cp.source = Some(pool);
+        cp
+    }
+}
+
+/// The index of the first entry equal to the one at `idx`, once the entries both refer to are replaced by their own first equal entry.
+///
+/// Pools written without deduplication repeat entries, and entries referring to either copy must be found when writing them again.
+fn canonical_index(entries: &HashMap<u16, RawConstantEntry>, idx: u16, canonical: &mut HashMap<u16, u16>, seen: &mut HashMap<RawConstantEntry, u16>) -> u16 {
+    if let Some(&c) = canonical.get(&idx) {
+        return c;
+    }
+    let entry = match entries.get(&idx) {
+        Some(e) => e,
+        None => return idx
+    };
+    // entries of malformed pools may refer to themselves.
+    canonical.insert(idx, idx);
+    let key = entry.map_indices(|i| canonical_index(entries, i, canonical, seen));
+    let c = *seen.entry(key).or_insert(idx);
+    canonical.insert(idx, c);
+    c
 }
 
 impl Default for VecCp {
@@ -132,9 +226,42 @@ impl MapCp {
     pub fn new() -> Self {
         Self {
             entries: HashMap::new(),
-            refs: HashMap::new()
+            refs: HashMap::new(),
+            shared: None,
+            warnings: None
         }
     }
+
+    /// Makes this reader lenient: recoverable problems are recorded as warnings instead of failing.
+    pub fn set_lenient(&mut self) {
+        self.warnings.get_or_insert_with(Vec::new);
+    }
+
+    /// The warnings recorded so far when this reader is lenient.
+    pub fn warnings(&self) -> &[Error] {
+        self.warnings.as_deref().unwrap_or(&[])
+    }
+
+    /// Takes the warnings recorded so far.
+    pub fn take_warnings(&mut self) -> Vec<Error> {
+        self.warnings.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Adds the location of the element that was being read to the warnings recorded since `from`.
+    pub(crate) fn locate_warnings(&mut self, from: usize, segment: Option<PathSegment>, start: u64) {
+        if let Some(w) = &mut self.warnings {
+            let located = w.split_off(from);
+            w.extend(located.into_iter().map(|e| e.located(segment.clone(), start, start)));
+        }
+    }
+
+    /// Shares the entries of this pool so method bodies can be decoded later.
+    ///
+    /// Afterwards, this reader reads `Code` attributes as [`LazyCode`](crate::lazy::LazyCode).
+    pub fn share(&mut self) -> Arc<SharedPool> {
+        let entries = self.entries.clone();
+        self.shared.get_or_insert_with(|| Arc::new(SharedPool::new(entries))).clone()
+    }
 }
 
 impl Default for MapCp {
@@ -169,10 +296,25 @@ impl ConstantPoolReader for MapCp {
         self.entries.get(&idx).cloned()
     }
 
-    fn resolve_later(&mut self, bsm_idx: u16, bsm: Rc<LazyBsm>) {
+    fn resolve_later(&mut self, bsm_idx: u16, bsm: Arc<LazyBsm>) {
         self.refs.entry(bsm_idx).or_default().push(bsm);
     }
 
+    fn warn(&mut self, e: Error) -> Result<()> {
+        match &mut self.warnings {
+            Some(w) => {
+                w.push(e);
+                Ok(())
+            }
+            None => Err(e)
+        }
+    }
+
+    #[inline]
+    fn shared_pool(&self) -> Option<&Arc<SharedPool>> {
+        self.shared.as_ref()
+    }
+
     fn bootstrap_methods(&mut self, bsms: &[BootstrapMethod]) -> Result<()> {
         for (i, b) in bsms.iter().enumerate() {
             if let Entry::Occupied(bsm) = self.refs.entry(i as _) {
@@ -196,7 +338,7 @@ impl ReadWrite for VecCp {
     }
 
     fn write_to<T: Write>(&self, writer: &mut T) -> Result<()> {
-        self.len.write_to(writer)?;
+        checked_len::<u16>("constant pool size", self.len as usize)?.write_to(writer)?;
         for e in &self.entries {
             e.write_to(writer)?;
         }
@@ -206,16 +348,29 @@ impl ReadWrite for VecCp {
 
 impl ConstantPoolWriter for VecCp {
     fn insert_raw(&mut self, value: RawConstantEntry) -> u16 {
+        if let Some(&idx) = self.indices.as_ref().and_then(|i| i.get(&value)) {
+            return idx;
+        }
         let idx = self.len;
-        self.len = idx + value.size();
+        self.len = idx + value.size() as u32;
+        if let Some(indices) = &mut self.indices {
+            indices.insert(value.clone(), idx as u16);
+        }
         self.entries.push(value);
-        idx
+        // indices past the limit are never written, as writing the pool fails.
+        idx as u16
     }
 
     fn insert_bsm(&mut self, bsm: BootstrapMethod) -> u16 {
-        let ret = self.bsm.len() as u16;
+        // indices past the limit are never written, as writing the bootstrap method count fails.
+        let ret = checked_len::<u16>("bootstrap method count", self.bsm.len()).unwrap_or(u16::MAX);
         self.bsm.push(bsm);
         ret
     }
+
+    #[inline]
+    fn source_pool(&self) -> Option<&Arc<SharedPool>> {
+        self.source.as_ref()
+    }
 }
 
@@ -0,0 +1,155 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Elimination of unreachable code.
+//!
+//! Instructions that cannot be reached from the start of a method body, such as those after a `return`, `athrow` or
+//! `goto` that no jump targets, are left in place when a [`Code`] is written. The JVM rejects them when the method has stack
+//! map frames, since they have no frame. [`eliminate`] removes them along with
+//! what refers to them: exception handlers and local variables whose range no longer covers any instruction, labels that
+//! nothing refers to anymore and line numbers that mark no instruction.
+
+use std::collections::HashMap;
+
+use crate::flow::{ControlFlowGraph, is_executed};
+use crate::prelude::*;
+use crate::Class;
+
+/// What was removed from a method body.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct DeadCode {
+    /// The number of unreachable instructions.
+    pub instructions: usize,
+    /// The number of exception handlers whose protected range became empty.
+    pub catches: usize,
+    /// The number of local variables whose scope became empty.
+    pub local_variables: usize,
+    /// The number of labels that nothing refers to.
+    pub labels: usize,
+    /// The number of line numbers that mark no instruction.
+    pub line_numbers: usize
+}
+
+impl DeadCode {
+    /// Whether nothing was removed.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        *self == DeadCode::default()
+    }
+}
+
+impl std::ops::AddAssign for DeadCode {
+    fn add_assign(&mut self, rhs: Self) {
+        self.instructions += rhs.instructions;
+        self.catches += rhs.catches;
+        self.local_variables += rhs.local_variables;
+        self.labels += rhs.labels;
+        self.line_numbers += rhs.line_numbers;
+    }
+}
+
+/// Removes the unreachable code of every method body of the class.
+///
+/// This fails when a lazily decoded method body cannot be decoded, or refers to a label that is not placed.
+pub fn eliminate_class(class: &mut Class) -> Result<DeadCode> {
+    let mut removed = DeadCode::default();
+    for m in &mut class.methods {
+        if let Some(code) = m.code_mut()? {
+            removed += eliminate(code)?;
+        }
+    }
+    Ok(removed)
+}
+
+/// Removes the unreachable code of a method body.
+///
+/// This fails if an instruction or exception handler refers to a label that is not placed in the code.
+pub fn eliminate(code: &mut Code) -> Result<DeadCode> {
+    let mut removed = DeadCode::default();
+    loop {
+        let pass = eliminate_once(code)?;
+        if pass.is_empty() {
+            return Ok(removed)
+        }
+        removed += pass;
+    }
+}
+
+fn eliminate_once(code: &mut Code) -> Result<DeadCode> {
+    let mut removed = DeadCode::default();
+    let graph = ControlFlowGraph::new(code)?;
+    let reachable = graph.reachable();
+    let old = std::mem::take(&mut code.code);
+    for (block, reached) in graph.blocks.iter().zip(reachable) {
+        for insn in &old[block.range.clone()] {
+            match insn {
+                Instruction::Label(_) => {}
+                _ if reached => {}
+                Instruction::LineNumber(_) => {
+                    removed.line_numbers += 1;
+                    continue
+                }
+                _ => {
+                    removed.instructions += 1;
+                    continue
+                }
+            }
+            code.code.push(insn.clone());
+        }
+    }
+
+    let positions = code.code.iter().enumerate().filter_map(|(i, insn)| match insn {
+        Instruction::Label(l) => Some((*l, i)),
+        _ => None
+    }).collect::<HashMap<_, _>>();
+    let instructions = &code.code;
+    // a range is collapsed when both labels are placed and no instruction is executed between them.
+    let collapsed = |start: &Label, end: &Label| match (positions.get(start), positions.get(end)) {
+        (Some(&s), Some(&e)) => s >= e || !instructions[s..e].iter().any(is_executed),
+        _ => false
+    };
+    let catches = code.catches.len();
+    code.catches.retain(|c| !collapsed(&c.start, &c.end));
+    removed.catches = catches - code.catches.len();
+    for a in &mut code.attrs {
+        if let CodeAttribute::LocalVariables(vars) = a {
+            let len = vars.len();
+            vars.retain(|v| !collapsed(&v.start, &v.end));
+            removed.local_variables += len - vars.len();
+        }
+    }
+    code.attrs.retain(|a| !matches!(a, CodeAttribute::LocalVariables(v) if v.is_empty()));
+
+    removed.labels = code.remove_unused_labels();
+
+    // a line number marks the next executed instruction, unless another line number comes first.
+    let mut marked = false;
+    let len = code.code.len();
+    let mut kept = Vec::with_capacity(len);
+    for insn in std::mem::take(&mut code.code).into_iter().rev() {
+        match insn {
+            Instruction::LineNumber(_) if !marked => continue,
+            Instruction::LineNumber(_) => marked = false,
+            Instruction::Label(_) => {}
+            _ => marked = true
+        }
+        kept.push(insn);
+    }
+    kept.reverse();
+    code.code = kept;
+    removed.line_numbers += len - code.code.len();
+    Ok(removed)
+}
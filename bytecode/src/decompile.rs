@@ -0,0 +1,1643 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Decompilation of method bodies into Java-like pseudo-source, to review generated code.
+//!
+//! [`decompile`] rebuilds expressions from the operand stack and structures the control flow graph of a [`Code`] into
+//! `if`, `while`, `do`/`while`, `switch` and `try`/`catch` statements. Blocks keep the order they have in the code: a
+//! construct is recognized where its blocks follow each other and are dominated by its head, which is how compilers lay
+//! out structured code. Conditions of consecutive branches to the same places are joined with `&&` and `||`. Everything
+//! else becomes labels and `goto` statements, so the output always describes the code, even when it is not valid Java.
+//!
+//! Local variables are named after the local variable table when the code has one. Values left on the operand stack
+//! at the end of a block are assigned to `stack0`, `stack1`..., and values that must be computed before a statement
+//! runs are assigned to `tmp0`, `tmp1`... Declarations are not written.
+//!
+//! Call sites and constants computed by bootstrap methods are written `dynamic name(...)`. The decompiler gives up on
+//! subroutines (`jsr` and `ret`), which have no statement to become, and on field and method instructions whose member is
+//! computed dynamically, as nothing tells which value they push.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+
+use crate::flow::{dominators, is_executed, may_throw, shuffle, take, ControlFlowGraph, EdgeKind};
+use crate::instrument::local_type;
+use crate::prelude::*;
+
+/// An expression of the pseudo-source.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Expr {
+    Literal(Constant),
+    Null,
+    Bool(bool),
+    /// A local variable, or a variable standing for a value of the operand stack.
+    Local(String),
+    /// An object created by `new` whose constructor has not been called yet. The number identifies the allocation.
+    Uninitialized(usize, Cow<'static, str>),
+    /// `-` or `!` applied to an operand.
+    Unary(&'static str, Box<Expr>),
+    Binary(Box<Expr>, &'static str, Box<Expr>),
+    /// The comparison of two `long`, `float` or `double` values, written as a call to `compare` of their box class.
+    Compare(LocalType, Box<Expr>, Box<Expr>),
+    Cast(Type, Box<Expr>),
+    InstanceOf(Box<Expr>, Type),
+    Field { target: Target, name: Cow<'static, str>, descriptor: Type },
+    Invoke { target: Target, name: Cow<'static, str>, descriptor: Type, arguments: Vec<Expr> },
+    /// A call site or a constant computed by a bootstrap method.
+    Dynamic { name: Cow<'static, str>, arguments: Vec<Expr> },
+    New { class: Cow<'static, str>, arguments: Vec<Expr> },
+    /// An array of `element` with the given lengths, and `extra` more dimensions that are not allocated.
+    NewArray { element: Type, lengths: Vec<Expr>, extra: u8 },
+    ArrayElement(Box<Expr>, Box<Expr>),
+    ArrayLength(Box<Expr>)
+}
+
+/// What a field or method is accessed on.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Target {
+    /// A static member of a class.
+    Static(Cow<'static, str>),
+    Instance(Box<Expr>),
+    /// The super class of the method being decompiled, for `super(...)` and `super.m()`.
+    Super,
+    /// The class of the method being decompiled, for `this(...)`.
+    This
+}
+
+/// A statement of the pseudo-source.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Stmt {
+    /// The target of `goto` statements, named after the index of the block it starts in the control flow graph.
+    Label(usize),
+    Expression(Expr),
+    Assign(Expr, Expr),
+    /// `iinc` on a local variable.
+    Increment(String, i16),
+    Monitor(MonitorOperation, Expr),
+    Return(Option<Expr>),
+    Throw(Expr),
+    If { condition: Expr, then: Vec<Stmt>, otherwise: Vec<Stmt> },
+    /// A loop, labeled when a nested `break` or `continue` refers to it.
+    While { label: Option<usize>, condition: Expr, body: Vec<Stmt> },
+    DoWhile { label: Option<usize>, body: Vec<Stmt>, condition: Expr },
+    Switch { label: Option<usize>, value: Expr, cases: Vec<Case> },
+    Try { body: Vec<Stmt>, catches: Vec<CatchClause> },
+    /// Leaves the innermost loop or switch, or the labeled one.
+    Break(Option<usize>),
+    Continue(Option<usize>),
+    Goto(usize),
+    Comment(String)
+}
+
+/// Labels of a `switch` and the statements that follow them. The key `None` is the `default` label.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Case {
+    pub keys: Vec<Option<i32>>,
+    pub body: Vec<Stmt>
+}
+
+/// A `catch` clause. The type `None` catches every exception, like the handlers of `finally` blocks.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CatchClause {
+    pub types: Vec<Option<Cow<'static, str>>>,
+    pub name: String,
+    pub body: Vec<Stmt>
+}
+
+/// A decompiled method body, which displays as indented pseudo-source.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Body {
+    pub statements: Vec<Stmt>
+}
+
+impl Expr {
+    fn children(&self) -> Vec<&Expr> {
+        fn target(t: &Target) -> Option<&Expr> {
+            match t {
+                Target::Instance(e) => Some(e),
+                _ => None
+            }
+        }
+        match self {
+            Expr::Unary(_, e) | Expr::Cast(_, e) | Expr::InstanceOf(e, _) | Expr::ArrayLength(e) => vec![e],
+            Expr::Binary(a, _, b) | Expr::Compare(_, a, b) | Expr::ArrayElement(a, b) => vec![a, b],
+            Expr::Field { target: t, .. } => target(t).into_iter().collect(),
+            Expr::Invoke { target: t, arguments, .. } => target(t).into_iter().chain(arguments).collect(),
+            Expr::Dynamic { arguments, .. } | Expr::New { arguments, .. } => arguments.iter().collect(),
+            Expr::NewArray { lengths, .. } => lengths.iter().collect(),
+            Expr::Literal(_) | Expr::Null | Expr::Bool(_) | Expr::Local(_) | Expr::Uninitialized(..) => vec![]
+        }
+    }
+
+    fn any(&self, f: &dyn Fn(&Expr) -> bool) -> bool {
+        f(self) || self.children().into_iter().any(|c| c.any(f))
+    }
+
+    /// Whether evaluating the expression only reads local variables, so that it can be moved across statements that do
+    /// not write them.
+    fn is_pure(&self) -> bool {
+        !self.any(&|e| matches!(e, Expr::Field { .. } | Expr::Invoke { .. } | Expr::Dynamic { .. } | Expr::New { .. } |
+            Expr::NewArray { .. } | Expr::ArrayElement(..) | Expr::ArrayLength(_)))
+    }
+
+    fn reads(&self, local: &str) -> bool {
+        self.any(&|e| matches!(e, Expr::Local(n) if n == local))
+    }
+}
+
+fn inverse(op: &str) -> Option<&'static str> {
+    Some(match op {
+        "==" => "!=",
+        "!=" => "==",
+        "<" => ">=",
+        ">=" => "<",
+        ">" => "<=",
+        "<=" => ">",
+        _ => return None
+    })
+}
+
+/// The negation of a condition.
+fn not(condition: Expr) -> Expr {
+    match condition {
+        Expr::Binary(a, "&&", b) => Expr::Binary(Box::new(not(*a)), "||", Box::new(not(*b))),
+        Expr::Binary(a, "||", b) => Expr::Binary(Box::new(not(*a)), "&&", Box::new(not(*b))),
+        Expr::Binary(a, op, b) => match inverse(op) {
+            Some(op) => Expr::Binary(a, op, b),
+            None => Expr::Unary("!", Box::new(Expr::Binary(a, op, b)))
+        },
+        Expr::Unary("!", e) => *e,
+        Expr::Bool(b) => Expr::Bool(!b),
+        e => Expr::Unary("!", Box::new(e))
+    }
+}
+
+/// Writes `0` and `1` as `false` and `true` where a boolean is expected.
+fn coerce(expr: Expr, ty: &Type) -> Expr {
+    match expr {
+        Expr::Literal(Constant::I32(i @ 0..=1)) if *ty == Type::Boolean => Expr::Bool(i == 1),
+        e => e
+    }
+}
+
+fn java_name(internal: &str) -> String {
+    internal.replace('/', ".")
+}
+
+fn java_type(ty: &Type) -> String {
+    match ty {
+        Type::Byte => "byte".into(),
+        Type::Char => "char".into(),
+        Type::Double => "double".into(),
+        Type::Float => "float".into(),
+        Type::Int => "int".into(),
+        Type::Long => "long".into(),
+        Type::Boolean => "boolean".into(),
+        Type::Short => "short".into(),
+        Type::Ref(name) => java_name(name),
+        Type::ArrayRef(dim, ty) => format!("{}{}", java_type(ty), "[]".repeat(*dim as usize)),
+        Type::Method { .. } => ty.to_string()
+    }
+}
+
+fn class_type(ty: &ClassType) -> Type {
+    match ty {
+        ClassType::Object(name) => Type::Ref(name.clone()),
+        ClassType::Array(dim, ty) => Type::ArrayRef(*dim, Box::new(ty.clone()))
+    }
+}
+
+fn precedence(e: &Expr) -> u8 {
+    match e {
+        Expr::Binary(_, op, _) => match *op {
+            "||" => 3,
+            "&&" => 4,
+            "|" => 5,
+            "^" => 6,
+            "&" => 7,
+            "==" | "!=" => 8,
+            "<" | ">" | "<=" | ">=" => 9,
+            "<<" | ">>" | ">>>" => 10,
+            "+" | "-" => 11,
+            _ => 12
+        },
+        Expr::InstanceOf(..) => 9,
+        Expr::Unary(..) | Expr::Cast(..) | Expr::Dynamic { .. } => 13,
+        Expr::Literal(c) if match c {
+            Constant::I32(i) => *i < 0,
+            Constant::I64(l) => *l < 0,
+            Constant::F32(f) => f.is_sign_negative(),
+            Constant::F64(d) => d.is_sign_negative(),
+            _ => false
+        } => 13,
+        _ => 14
+    }
+}
+
+/// An expression in parentheses if it binds less tightly than the given precedence.
+struct Operand<'a>(&'a Expr, u8);
+
+impl Display for Operand<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if precedence(self.0) < self.1 {
+            write!(f, "({})", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+struct Arguments<'a>(&'a [Expr]);
+
+impl Display for Arguments<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, a) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", a)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_constant(f: &mut Formatter<'_>, c: &Constant) -> fmt::Result {
+    match c {
+        Constant::I32(i) => write!(f, "{}", i),
+        Constant::I64(l) => write!(f, "{}L", l),
+        Constant::F32(x) if x.is_nan() => f.write_str("Float.NaN"),
+        Constant::F32(x) if x.is_infinite() => write!(f, "Float.{}_INFINITY", if *x > 0.0 { "POSITIVE" } else { "NEGATIVE" }),
+        Constant::F32(x) => write!(f, "{:?}F", x),
+        Constant::F64(x) if x.is_nan() => f.write_str("Double.NaN"),
+        Constant::F64(x) if x.is_infinite() => write!(f, "Double.{}_INFINITY", if *x > 0.0 { "POSITIVE" } else { "NEGATIVE" }),
+        Constant::F64(x) => write!(f, "{:?}", x),
+        Constant::String(s) => {
+            f.write_str("\"")?;
+            for c in s.chars() {
+                match c {
+                    '"' => f.write_str("\\\"")?,
+                    '\\' => f.write_str("\\\\")?,
+                    '\n' => f.write_str("\\n")?,
+                    '\r' => f.write_str("\\r")?,
+                    '\t' => f.write_str("\\t")?,
+                    c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                    c => write!(f, "{}", c)?
+                }
+            }
+            f.write_str("\"")
+        }
+        Constant::Class(c) => match c.parse::<Type>() {
+            Ok(ty) if c.starts_with('[') => write!(f, "{}.class", java_type(&ty)),
+            _ => write!(f, "{}.class", java_name(c))
+        },
+        Constant::Member(m) => write!(f, "{}::{}", java_name(&m.owner), m.name),
+        Constant::MethodHandle(h) => write!(f, "{}::{}", java_name(&h.member.owner), h.member.name),
+        Constant::MethodType(ty) => write!(f, "MethodType({})", ty)
+    }
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Static(class) => f.write_str(&java_name(class)),
+            Target::Instance(e) => write!(f, "{}", Operand(e, 14)),
+            Target::Super => f.write_str("super"),
+            Target::This => f.write_str("this")
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal(c) => write_constant(f, c),
+            Expr::Null => f.write_str("null"),
+            Expr::Bool(b) => write!(f, "{}", b),
+            Expr::Local(name) => f.write_str(name),
+            Expr::Uninitialized(_, class) => write!(f, "new {}", java_name(class)),
+            Expr::Unary(op, e) => {
+                // `- -x` must not become `--x`.
+                if *op == "-" && precedence(e) == 13 && !matches!(**e, Expr::Cast(..)) {
+                    write!(f, "-({})", e)
+                } else {
+                    write!(f, "{}{}", op, Operand(e, 13))
+                }
+            }
+            Expr::Binary(a, op, b) => {
+                let p = precedence(self);
+                write!(f, "{} {} {}", Operand(a, p), op, Operand(b, p + 1))
+            }
+            Expr::Compare(ty, a, b) => {
+                let class = match ty {
+                    LocalType::Long => "Long",
+                    LocalType::Float => "Float",
+                    _ => "Double"
+                };
+                write!(f, "{}.compare({}, {})", class, a, b)
+            }
+            Expr::Cast(ty, e) => write!(f, "({}) {}", java_type(ty), Operand(e, 13)),
+            Expr::InstanceOf(e, ty) => write!(f, "{} instanceof {}", Operand(e, 9), java_type(ty)),
+            Expr::Field { target, name, .. } => write!(f, "{}.{}", target, name),
+            Expr::Invoke { target: target @ (Target::Super | Target::This), name, arguments, .. } if name == "<init>" => {
+                write!(f, "{}({})", target, Arguments(arguments))
+            }
+            Expr::Invoke { target, name, arguments, .. } => write!(f, "{}.{}({})", target, name, Arguments(arguments)),
+            Expr::Dynamic { name, arguments } => write!(f, "dynamic {}({})", name, Arguments(arguments)),
+            Expr::New { class, arguments } => write!(f, "new {}({})", java_name(class), Arguments(arguments)),
+            Expr::NewArray { element, lengths, extra } => {
+                write!(f, "new {}", java_type(element))?;
+                for l in lengths {
+                    write!(f, "[{}]", l)?;
+                }
+                f.write_str(&"[]".repeat(*extra as usize))
+            }
+            Expr::ArrayElement(array, index) => write!(f, "{}[{}]", Operand(array, 14), index),
+            Expr::ArrayLength(array) => write!(f, "{}.length", Operand(array, 14))
+        }
+    }
+}
+
+struct Labeled(Option<usize>);
+
+impl Display for Labeled {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(l) => write!(f, "L{}: ", l),
+            None => Ok(())
+        }
+    }
+}
+
+/// The text of a statement that has no nested statements.
+fn simple(s: &Stmt) -> Option<String> {
+    Some(match s {
+        Stmt::Expression(e) => format!("{};", e),
+        Stmt::Assign(to, value) => format!("{} = {};", to, value),
+        Stmt::Increment(name, 1) => format!("{}++;", name),
+        Stmt::Increment(name, -1) => format!("{}--;", name),
+        Stmt::Increment(name, n) if *n < 0 => format!("{} -= {};", name, -(*n as i32)),
+        Stmt::Increment(name, n) => format!("{} += {};", name, n),
+        Stmt::Monitor(MonitorOperation::Enter, e) => format!("monitorenter({});", e),
+        Stmt::Monitor(MonitorOperation::Exit, e) => format!("monitorexit({});", e),
+        Stmt::Return(None) => "return;".into(),
+        Stmt::Return(Some(e)) => format!("return {};", e),
+        Stmt::Throw(e) => format!("throw {};", e),
+        Stmt::Break(None) => "break;".into(),
+        Stmt::Break(Some(l)) => format!("break L{};", l),
+        Stmt::Continue(None) => "continue;".into(),
+        Stmt::Continue(Some(l)) => format!("continue L{};", l),
+        Stmt::Goto(l) => format!("goto L{};", l),
+        Stmt::Comment(c) => format!("// {}", c),
+        _ => return None
+    })
+}
+
+fn write_block(f: &mut Formatter<'_>, statements: &[Stmt], depth: usize) -> fmt::Result {
+    statements.iter().try_for_each(|s| write_statement(f, s, depth))
+}
+
+fn write_if(f: &mut Formatter<'_>, condition: &Expr, then: &[Stmt], otherwise: &[Stmt], depth: usize) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    if let ([s @ (Stmt::Break(_) | Stmt::Continue(_) | Stmt::Goto(_) | Stmt::Return(_) | Stmt::Throw(_))], []) = (then, otherwise) {
+        return writeln!(f, "if ({}) {}", condition, simple(s).unwrap_or_default())
+    }
+    writeln!(f, "if ({}) {{", condition)?;
+    write_block(f, then, depth + 1)?;
+    match otherwise {
+        [] => writeln!(f, "{}}}", indent),
+        [Stmt::If { condition, then, otherwise }] => {
+            write!(f, "{}}} else ", indent)?;
+            write_if(f, condition, then, otherwise, depth)
+        }
+        _ => {
+            writeln!(f, "{}}} else {{", indent)?;
+            write_block(f, otherwise, depth + 1)?;
+            writeln!(f, "{}}}", indent)
+        }
+    }
+}
+
+fn write_statement(f: &mut Formatter<'_>, s: &Stmt, depth: usize) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    match s {
+        Stmt::Label(l) => writeln!(f, "{}L{}:", indent, l),
+        Stmt::If { condition, then, otherwise } => {
+            f.write_str(&indent)?;
+            write_if(f, condition, then, otherwise, depth)
+        }
+        Stmt::While { label, condition, body } => {
+            writeln!(f, "{}{}while ({}) {{", indent, Labeled(*label), condition)?;
+            write_block(f, body, depth + 1)?;
+            writeln!(f, "{}}}", indent)
+        }
+        Stmt::DoWhile { label, body, condition } => {
+            writeln!(f, "{}{}do {{", indent, Labeled(*label))?;
+            write_block(f, body, depth + 1)?;
+            writeln!(f, "{}}} while ({});", indent, condition)
+        }
+        Stmt::Switch { label, value, cases } => {
+            writeln!(f, "{}{}switch ({}) {{", indent, Labeled(*label), value)?;
+            for case in cases {
+                for key in &case.keys {
+                    match key {
+                        Some(k) => writeln!(f, "{}    case {}:", indent, k)?,
+                        None => writeln!(f, "{}    default:", indent)?
+                    }
+                }
+                write_block(f, &case.body, depth + 2)?;
+            }
+            writeln!(f, "{}}}", indent)
+        }
+        Stmt::Try { body, catches } => {
+            writeln!(f, "{}try {{", indent)?;
+            write_block(f, body, depth + 1)?;
+            for c in catches {
+                let types = c.types.iter().map(|t| java_name(t.as_deref().unwrap_or("java/lang/Throwable"))).collect::<Vec<_>>();
+                writeln!(f, "{}}} catch ({} {}) {{", indent, types.join(" | "), c.name)?;
+                write_block(f, &c.body, depth + 1)?;
+            }
+            writeln!(f, "{}}}", indent)
+        }
+        s => writeln!(f, "{}{}", indent, simple(s).unwrap_or_default())
+    }
+}
+
+impl Display for Body {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_block(f, &self.statements, 0)
+    }
+}
+
+/// The names of local variables.
+struct Names {
+    /// The index, the range of instructions, the name and the descriptor of each entry of the local variable table.
+    table: Vec<(u16, Range<usize>, String, Option<Type>)>,
+    this: bool
+}
+
+impl Names {
+    fn new(code: &Code, this: bool) -> Names {
+        let positions = code.code.iter().enumerate().filter_map(|(i, insn)| match insn {
+            Instruction::Label(l) => Some((*l, i)),
+            _ => None
+        }).collect::<HashMap<_, _>>();
+        let table = code.attrs.iter().filter_map(|a| match a {
+            CodeAttribute::LocalVariables(v) => Some(v),
+            _ => None
+        }).flatten().filter_map(|v| {
+            let range = *positions.get(&v.start)?..*positions.get(&v.end)?;
+            Some((v.index, range, v.name.to_string(), v.descriptor.clone()))
+        }).collect();
+        Names { table, this }
+    }
+
+    /// The name of a local variable at an instruction, and whether it holds a boolean. The scope of a variable starts
+    /// after the store that first assigns it.
+    fn get(&self, index: u16, at: usize, store: bool) -> (String, bool) {
+        let find = |p: usize| self.table.iter().find(|(i, range, ..)| *i == index && range.contains(&p));
+        let entry = if store { find(at + 1).or_else(|| find(at)) } else { find(at) };
+        match entry {
+            Some((.., name, descriptor)) => (name.clone(), *descriptor == Some(Type::Boolean)),
+            None if index == 0 && self.this => ("this".into(), false),
+            None => (format!("local{}", index), false)
+        }
+    }
+}
+
+/// A value of the operand stack.
+#[derive(Clone, Debug)]
+struct Entry {
+    expr: Expr,
+    ty: LocalType,
+    boolean: bool
+}
+
+impl Entry {
+    fn new(expr: Expr, ty: LocalType) -> Entry {
+        Entry { expr, ty, boolean: false }
+    }
+
+    fn of_type(expr: Expr, ty: &Type) -> Entry {
+        Entry { expr, ty: local_type(ty), boolean: *ty == Type::Boolean }
+    }
+}
+
+/// How a block ends, with the indices of the blocks it continues to.
+#[derive(Clone, Debug)]
+enum Exit {
+    Goto(usize),
+    /// Goes to the first block if the condition holds, to the second one otherwise.
+    Branch(Expr, usize, usize),
+    Switch(Expr, Vec<(i32, usize)>, usize),
+    /// Returns or throws.
+    End
+}
+
+impl Exit {
+    fn targets(&self) -> Vec<usize> {
+        match self {
+            Exit::Goto(t) => vec![*t],
+            Exit::Branch(_, t, o) => vec![*t, *o],
+            Exit::Switch(_, cases, default) => cases.iter().map(|(_, t)| *t).chain(Some(*default)).collect(),
+            Exit::End => vec![]
+        }
+    }
+
+    fn targets_mut(&mut self) -> Vec<&mut usize> {
+        match self {
+            Exit::Goto(t) => vec![t],
+            Exit::Branch(_, t, o) => vec![t, o],
+            Exit::Switch(_, cases, default) => cases.iter_mut().map(|(_, t)| t).chain(Some(default)).collect(),
+            Exit::End => vec![]
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Translated {
+    statements: Vec<Stmt>,
+    exit: Exit
+}
+
+fn unsupported(insn: &Instruction) -> Error {
+    Error::Invalid("instruction", format!("{:?} is not supported by the decompiler", insn).into())
+}
+
+fn underflow() -> Error {
+    Error::Invalid("stack height", "the operand stack underflows".into())
+}
+
+/// Rebuilds the statements of basic blocks from their instructions.
+struct Translator<'a> {
+    code: &'a Code,
+    cfg: &'a ControlFlowGraph,
+    names: Names,
+    class: Option<&'a str>,
+    returns: Option<Type>,
+    stack: Vec<Entry>,
+    statements: Vec<Stmt>,
+    temporaries: usize,
+    allocations: usize
+}
+
+impl Translator<'_> {
+    fn pop(&mut self) -> Result<Entry> {
+        self.stack.pop().ok_or_else(underflow)
+    }
+
+    fn pop_n(&mut self, n: usize) -> Result<Vec<Entry>> {
+        if n > self.stack.len() {
+            return Err(underflow())
+        }
+        Ok(self.stack.split_off(self.stack.len() - n))
+    }
+
+    fn take(&mut self, size: u16) -> Result<Vec<Entry>> {
+        take(&mut self.stack, size, |e| e.ty)
+    }
+
+    fn push(&mut self, expr: Expr, ty: LocalType) {
+        self.stack.push(Entry::new(expr, ty));
+    }
+
+    fn temporary(&mut self, expr: Expr) -> Expr {
+        let local = Expr::Local(format!("tmp{}", self.temporaries));
+        self.temporaries += 1;
+        self.statements.push(Stmt::Assign(local.clone(), expr));
+        local
+    }
+
+    /// Assigns the values of the stack that a statement may change to temporary variables before it runs: all those
+    /// that are not pure, or those that read the local variable a statement assigns.
+    fn flush(&mut self, assigned: Option<&str>) {
+        for i in 0..self.stack.len() {
+            let expr = &self.stack[i].expr;
+            if !expr.is_pure() || assigned.is_some_and(|l| expr.reads(l)) {
+                let expr = std::mem::replace(&mut self.stack[i].expr, Expr::Null);
+                self.stack[i].expr = self.temporary(expr);
+            }
+        }
+    }
+
+    fn statement(&mut self, s: Stmt) {
+        self.flush(None);
+        self.statements.push(s);
+    }
+
+    /// Assigns what is left on the stack at the end of a block to the variables standing for the stack, except objects
+    /// that are not initialized yet, which are passed as is. `operands` are the values the block ends with.
+    fn leave(&mut self, operands: &mut [&mut Expr]) -> Vec<Entry> {
+        let mut exit = vec![];
+        for (k, entry) in std::mem::take(&mut self.stack).into_iter().enumerate() {
+            let name = format!("stack{}", k);
+            let var = Expr::Local(name.clone());
+            if matches!(entry.expr, Expr::Uninitialized(..)) || entry.expr == var {
+                exit.push(entry);
+                continue
+            }
+            for o in operands.iter_mut() {
+                if o.reads(&name) {
+                    let expr = std::mem::replace(&mut **o, Expr::Null);
+                    **o = self.temporary(expr);
+                }
+            }
+            self.statements.push(Stmt::Assign(var.clone(), entry.expr));
+            exit.push(Entry { expr: var, ..entry });
+        }
+        exit
+    }
+
+    fn target(&self, label: &Label) -> Result<usize> {
+        self.cfg.block_of(*label).ok_or_else(|| Error::Invalid("label", format!("{:?} is not placed in the code", label).into()))
+    }
+
+    fn member<'m>(&self, m: &'m OrDynamic<MemberRef>, insn: &Instruction) -> Result<&'m MemberRef> {
+        match m {
+            OrDynamic::Static(m) => Ok(m),
+            OrDynamic::Dynamic(_) => Err(unsupported(insn))
+        }
+    }
+
+    /// Translates a block entered with the given stack, returning its statements and how it ends, and the stack it
+    /// leaves to its successors. The store that starts a handler is left out: it names the variable of the exception.
+    fn block(&mut self, b: usize, entry: Vec<Entry>, exception: Option<&str>) -> Result<(Translated, Vec<Entry>)> {
+        self.stack = entry;
+        self.statements = vec![];
+        let mut handler = exception.is_some();
+        let mut exit = None;
+        for at in self.cfg.blocks[b].range.clone() {
+            let insn = &self.code.code[at];
+            if !is_executed(insn) || *insn == Instruction::NoOp {
+                continue
+            }
+            if std::mem::take(&mut handler) {
+                if let Instruction::LocalVariable(LoadOrStore::Store, LocalType::Reference, i) = insn {
+                    if Some(self.names.get(*i, at, true).0.as_str()) == exception {
+                        self.pop()?;
+                        continue
+                    }
+                }
+            }
+            match insn {
+                Instruction::PushNull => self.push(Expr::Null, LocalType::Reference),
+                Instruction::Push(OrDynamic::Static(c)) => {
+                    let ty = match c {
+                        Constant::I32(_) => LocalType::Int,
+                        Constant::F32(_) => LocalType::Float,
+                        Constant::I64(_) => LocalType::Long,
+                        Constant::F64(_) => LocalType::Double,
+                        _ => LocalType::Reference
+                    };
+                    self.push(Expr::Literal(c.clone()), ty)
+                }
+                Instruction::Push(OrDynamic::Dynamic(d)) => {
+                    self.stack.push(Entry::of_type(Expr::Dynamic { name: d.name.clone(), arguments: vec![] }, &d.descriptor))
+                }
+                Instruction::LocalVariable(LoadOrStore::Load, ty, i) => {
+                    let (name, boolean) = self.names.get(*i, at, false);
+                    self.stack.push(Entry { expr: Expr::Local(name), ty: *ty, boolean });
+                }
+                Instruction::LocalVariable(LoadOrStore::Store, _, i) => {
+                    let value = self.pop()?;
+                    let (name, boolean) = self.names.get(*i, at, true);
+                    if value.expr != Expr::Local(name.clone()) {
+                        self.flush(Some(&name));
+                        let value = if boolean { coerce(value.expr, &Type::Boolean) } else { value.expr };
+                        self.statements.push(Stmt::Assign(Expr::Local(name), value));
+                    }
+                }
+                Instruction::IntIncrement(i, amount) => {
+                    let name = self.names.get(*i, at, false).0;
+                    self.flush(Some(&name));
+                    self.statements.push(Stmt::Increment(name, *amount));
+                }
+                Instruction::Pop1 | Instruction::Pop2 => {
+                    let popped = self.take(if *insn == Instruction::Pop1 { 1 } else { 2 })?;
+                    for e in popped {
+                        if !e.expr.is_pure() {
+                            self.statement(Stmt::Expression(e.expr));
+                        }
+                    }
+                }
+                Instruction::Dup | Instruction::DupX1 | Instruction::DupX2 | Instruction::Dup2 | Instruction::Dup2X1 |
+                Instruction::Dup2X2 | Instruction::Swap => {
+                    // Copies and reordered values are evaluated where they are used, so the others must be evaluated first.
+                    self.flush(None);
+                    let values = shuffle(insn, |n| self.take(n))?;
+                    self.stack.extend(values);
+                }
+                Instruction::CompareLongs | Instruction::CompareFloats(..) => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(Expr::Compare(a.ty, Box::new(a.expr), Box::new(b.expr)), LocalType::Int);
+                }
+                Instruction::Array(LoadOrStore::Load, ty) => {
+                    let index = self.pop()?;
+                    let array = self.pop()?;
+                    let ty = match ty {
+                        ArrayType::Long => LocalType::Long,
+                        ArrayType::Float => LocalType::Float,
+                        ArrayType::Double => LocalType::Double,
+                        ArrayType::Reference => LocalType::Reference,
+                        _ => LocalType::Int
+                    };
+                    self.push(Expr::ArrayElement(Box::new(array.expr), Box::new(index.expr)), ty);
+                }
+                Instruction::Array(LoadOrStore::Store, _) => {
+                    let value = self.pop()?;
+                    let index = self.pop()?;
+                    let array = self.pop()?;
+                    self.statement(Stmt::Assign(Expr::ArrayElement(Box::new(array.expr), Box::new(index.expr)), value.expr));
+                }
+                Instruction::ArrayLength => {
+                    let array = self.pop()?;
+                    self.push(Expr::ArrayLength(Box::new(array.expr)), LocalType::Int);
+                }
+                Instruction::IntOperation(_, IntOperation::Negate) | Instruction::FloatOperation(_, FloatOperation::Negate) => {
+                    let e = self.pop()?;
+                    self.push(Expr::Unary("-", Box::new(e.expr)), e.ty);
+                }
+                Instruction::IntOperation(_, op) => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    let op = match op {
+                        IntOperation::Divide => "/",
+                        IntOperation::Add => "+",
+                        IntOperation::Subtract => "-",
+                        IntOperation::Multiply => "*",
+                        IntOperation::Remainder => "%",
+                        IntOperation::ExclusiveOr => "^",
+                        IntOperation::Or => "|",
+                        IntOperation::And => "&",
+                        IntOperation::ShiftLeft => "<<",
+                        IntOperation::ShiftRight => ">>",
+                        _ => ">>>"
+                    };
+                    let boolean = a.boolean && b.boolean && matches!(op, "^" | "|" | "&");
+                    self.stack.push(Entry { expr: Expr::Binary(Box::new(a.expr), op, Box::new(b.expr)), ty: a.ty, boolean });
+                }
+                Instruction::FloatOperation(_, op) => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    let op = match op {
+                        FloatOperation::Divide => "/",
+                        FloatOperation::Add => "+",
+                        FloatOperation::Subtract => "-",
+                        FloatOperation::Multiply => "*",
+                        _ => "%"
+                    };
+                    self.push(Expr::Binary(Box::new(a.expr), op, Box::new(b.expr)), a.ty);
+                }
+                Instruction::Conversion(from, to) if from == to => {}
+                Instruction::ConvertInt(BitType::Int) => {}
+                Instruction::Conversion(_, to) => {
+                    let (ty, local) = match to {
+                        NumberType::Int => (Type::Int, LocalType::Int),
+                        NumberType::Long => (Type::Long, LocalType::Long),
+                        NumberType::Float => (Type::Float, LocalType::Float),
+                        NumberType::Double => (Type::Double, LocalType::Double)
+                    };
+                    let e = self.pop()?;
+                    self.push(Expr::Cast(ty, Box::new(e.expr)), local);
+                }
+                Instruction::ConvertInt(to) => {
+                    let ty = match to {
+                        BitType::Byte => Type::Byte,
+                        BitType::Short => Type::Short,
+                        BitType::Char => Type::Char,
+                        BitType::Long => Type::Long,
+                        BitType::Float => Type::Float,
+                        BitType::Double => Type::Double,
+                        BitType::Int => Type::Int
+                    };
+                    let e = self.pop()?;
+                    self.push(Expr::Cast(ty.clone(), Box::new(e.expr)), local_type(&ty));
+                }
+                Instruction::CheckCast(OrDynamic::Static(ty)) => {
+                    let e = self.pop()?;
+                    self.push(Expr::Cast(class_type(ty), Box::new(e.expr)), LocalType::Reference);
+                }
+                Instruction::InstanceOf(OrDynamic::Static(ty)) => {
+                    let e = self.pop()?;
+                    self.stack.push(Entry::of_type(Expr::InstanceOf(Box::new(e.expr), class_type(ty)), &Type::Boolean));
+                }
+                Instruction::NewArray(OrDynamic::Static(ty), dimensions) => {
+                    let lengths = self.pop_n(*dimensions as usize)?.into_iter().map(|e| e.expr).collect();
+                    let (element, depth) = match ty {
+                        Type::ArrayRef(depth, element) => ((**element).clone(), *depth),
+                        ty => (ty.clone(), 0)
+                    };
+                    // `anewarray` names the type of the elements, `multianewarray` the type of the array.
+                    let extra = if *dimensions == 1 { depth } else { depth.saturating_sub(*dimensions) };
+                    self.push(Expr::NewArray { element, lengths, extra }, LocalType::Reference);
+                }
+                Instruction::Monitor(op) => {
+                    let e = self.pop()?;
+                    self.statement(Stmt::Monitor(*op, e.expr));
+                }
+                Instruction::New(OrDynamic::Static(class)) => {
+                    self.push(Expr::Uninitialized(self.allocations, class.clone()), LocalType::Reference);
+                    self.allocations += 1;
+                }
+                Instruction::Field(op, kind, m) => {
+                    let m = self.member(m, insn)?;
+                    let value = if *op == GetOrPut::Put { Some(self.pop()?) } else { None };
+                    let target = match kind {
+                        MemberType::Static => Target::Static(m.owner.clone()),
+                        MemberType::Virtual => Target::Instance(Box::new(self.pop()?.expr))
+                    };
+                    let field = Expr::Field { target, name: m.name.clone(), descriptor: m.descriptor.clone() };
+                    match value {
+                        Some(v) => self.statement(Stmt::Assign(field, coerce(v.expr, &m.descriptor))),
+                        None => self.stack.push(Entry::of_type(field, &m.descriptor))
+                    }
+                }
+                Instruction::InvokeExact(_, m) | Instruction::InvokeSpecial(m) | Instruction::InvokeInterface(m, _) => {
+                    let m = self.member(m, insn)?;
+                    let (parameters, ret) = match &m.descriptor {
+                        Type::Method { parameters, ret } => (parameters, ret.as_deref()),
+                        _ => return Err(Error::Invalid("descriptor", format!("{} is not a method descriptor", m.descriptor).into()))
+                    };
+                    let arguments = self.pop_n(parameters.len())?.into_iter().zip(parameters).map(|(e, t)| coerce(e.expr, t)).collect();
+                    let special = matches!(insn, Instruction::InvokeSpecial(_));
+                    let receiver = match insn {
+                        Instruction::InvokeExact(MemberType::Static, _) => None,
+                        _ => Some(self.pop()?.expr)
+                    };
+                    let on_this = matches!(&receiver, Some(Expr::Local(n)) if n == "this") && self.class.is_some();
+                    let target = match receiver {
+                        None => Target::Static(m.owner.clone()),
+                        Some(_) if special && on_this && m.name == "<init>" && self.class == Some(&*m.owner) => Target::This,
+                        Some(_) if special && on_this && self.class != Some(&*m.owner) => Target::Super,
+                        Some(Expr::Uninitialized(id, class)) if special && m.name == "<init>" => {
+                            // The constructor call completes the object wherever `new` left it on the stack.
+                            let new = Expr::New { class, arguments };
+                            let mut used = false;
+                            for e in &mut self.stack {
+                                if matches!(e.expr, Expr::Uninitialized(i, _) if i == id) {
+                                    e.expr = new.clone();
+                                    used = true;
+                                }
+                            }
+                            if !used {
+                                self.statement(Stmt::Expression(new));
+                            }
+                            continue
+                        }
+                        Some(e) => Target::Instance(Box::new(e))
+                    };
+                    let call = Expr::Invoke { target, name: m.name.clone(), descriptor: m.descriptor.clone(), arguments };
+                    match ret {
+                        Some(ty) => self.stack.push(Entry::of_type(call, ty)),
+                        None => self.statement(Stmt::Expression(call))
+                    }
+                }
+                Instruction::InvokeDynamic(d) => {
+                    let (parameters, ret) = match &d.descriptor {
+                        Type::Method { parameters, ret } => (parameters, ret.as_deref()),
+                        _ => return Err(Error::Invalid("descriptor", format!("{} is not a method descriptor", d.descriptor).into()))
+                    };
+                    let arguments = self.pop_n(parameters.len())?.into_iter().zip(parameters).map(|(e, t)| coerce(e.expr, t)).collect();
+                    let call = Expr::Dynamic { name: d.name.clone(), arguments };
+                    match ret {
+                        Some(ty) => self.stack.push(Entry::of_type(call, ty)),
+                        None => self.statement(Stmt::Expression(call))
+                    }
+                }
+                Instruction::Return(value) => {
+                    let value = match value {
+                        Some(_) => {
+                            let e = self.pop()?.expr;
+                            Some(match &self.returns {
+                                Some(ty) => coerce(e, ty),
+                                None => e
+                            })
+                        }
+                        None => None
+                    };
+                    self.statement(Stmt::Return(value));
+                    exit = Some(Exit::End);
+                }
+                Instruction::Throw => {
+                    let e = self.pop()?;
+                    self.statement(Stmt::Throw(e.expr));
+                    exit = Some(Exit::End);
+                }
+                Instruction::Jump(JumpCondition::Always, l) => exit = Some(Exit::Goto(self.target(l)?)),
+                Instruction::Jump(condition, l) => {
+                    let op = |c: &JumpCondition| match c {
+                        JumpCondition::ReferenceEquals | JumpCondition::IntegerEquals | JumpCondition::IntegerEqualsZero |
+                        JumpCondition::IsNull => "==",
+                        JumpCondition::ReferenceNotEquals | JumpCondition::IntegerNotEquals | JumpCondition::IntegerNotEqualsZero |
+                        JumpCondition::IsNonNull => "!=",
+                        JumpCondition::IntegerLessThan | JumpCondition::IntegerLessThanZero => "<",
+                        JumpCondition::IntegerGreaterThan | JumpCondition::IntegerGreaterThanZero => ">",
+                        JumpCondition::IntegerLessThanOrEquals | JumpCondition::IntegerLessThanOrEqualsZero => "<=",
+                        _ => ">="
+                    };
+                    let a = self.pop()?;
+                    let mut condition = match condition {
+                        JumpCondition::ReferenceEquals | JumpCondition::ReferenceNotEquals | JumpCondition::IntegerEquals |
+                        JumpCondition::IntegerNotEquals | JumpCondition::IntegerLessThan | JumpCondition::IntegerGreaterThan |
+                        JumpCondition::IntegerLessThanOrEquals | JumpCondition::IntegerGreaterThanOrEquals => {
+                            let b = a;
+                            let a = self.pop()?;
+                            Expr::Binary(Box::new(a.expr), op(condition), Box::new(b.expr))
+                        }
+                        JumpCondition::IsNull | JumpCondition::IsNonNull => Expr::Binary(Box::new(a.expr), op(condition), Box::new(Expr::Null)),
+                        _ => match a.expr {
+                            Expr::Compare(_, x, y) => Expr::Binary(x, op(condition), y),
+                            e if a.boolean && *condition == JumpCondition::IntegerEqualsZero => not(e),
+                            e if a.boolean && *condition == JumpCondition::IntegerNotEqualsZero => e,
+                            e => Expr::Binary(Box::new(e), op(condition), Box::new(Expr::Literal(Constant::I32(0))))
+                        }
+                    };
+                    let exit_stack = self.leave(&mut [&mut condition]);
+                    let translated = Translated { statements: std::mem::take(&mut self.statements), exit: Exit::Branch(condition, self.target(l)?, b + 1) };
+                    return Ok((translated, exit_stack))
+                }
+                Instruction::TableSwitch { default, low, offsets } => {
+                    let cases = offsets.iter().enumerate().map(|(i, l)| Ok((low.wrapping_add(i as i32), self.target(l)?)))
+                        .collect::<Result<Vec<_>>>()?;
+                    return self.switch(cases, self.target(default)?)
+                }
+                Instruction::LookupSwitch { default, table } => {
+                    let cases = table.iter().map(|(k, l)| Ok((*k, self.target(l)?))).collect::<Result<Vec<_>>>()?;
+                    return self.switch(cases, self.target(default)?)
+                }
+                _ => return Err(unsupported(insn))
+            }
+        }
+        let exit = exit.unwrap_or(if b + 1 < self.cfg.blocks.len() { Exit::Goto(b + 1) } else { Exit::End });
+        let exit_stack = match exit {
+            Exit::End => vec![],
+            _ => self.leave(&mut [])
+        };
+        Ok((Translated { statements: std::mem::take(&mut self.statements), exit }, exit_stack))
+    }
+
+    fn switch(&mut self, cases: Vec<(i32, usize)>, default: usize) -> Result<(Translated, Vec<Entry>)> {
+        let mut value = self.pop()?.expr;
+        let exit_stack = self.leave(&mut [&mut value]);
+        Ok((Translated { statements: std::mem::take(&mut self.statements), exit: Exit::Switch(value, cases, default) }, exit_stack))
+    }
+}
+
+/// The group of a `try` statement, the position after its body, the positions of the blocks of each handler if they
+/// follow the body, and the position after them.
+type TryLayout = (usize, usize, Vec<Option<Range<usize>>>, usize);
+
+/// Where `break` and `continue` statements can go, and the `try` statements being built.
+enum Scope {
+    Loop { header: usize, follow: Option<usize> },
+    Switch { id: usize, follow: Option<usize> },
+    Try(usize)
+}
+
+/// The exception handlers of a range of blocks, which become one `try` statement.
+struct Group {
+    range: Range<usize>,
+    /// The handler blocks, with the types of exceptions each one catches.
+    handlers: Vec<(usize, Vec<Option<Cow<'static, str>>>)>
+}
+
+/// A loop found at a position of a sequence of blocks.
+struct Loop {
+    header: usize,
+    /// The position after the last block of the loop.
+    end: usize,
+    /// The blocks of the loop, starting with the header.
+    blocks: Vec<usize>
+}
+
+/// Turns the translated blocks into structured statements.
+struct Structurer<'a> {
+    code: &'a [Instruction],
+    cfg: &'a ControlFlowGraph,
+    blocks: Vec<Option<Translated>>,
+    /// The edges between the blocks that are left, including those to exception handlers.
+    successors: Vec<Vec<usize>>,
+    predecessors: Vec<Vec<usize>>,
+    idom: Vec<Option<usize>>,
+    /// The header and the blocks of each natural loop.
+    loops: Vec<(usize, HashSet<usize>)>,
+    groups: Vec<Group>,
+    /// The name of the exception in each handler block.
+    exceptions: HashMap<usize, String>,
+    /// Blocks named in comments, whose labels are kept.
+    mentioned: HashSet<usize>
+}
+
+impl Structurer<'_> {
+    /// Computes the edges, the dominators and the loops of the blocks that are left.
+    fn graph(&mut self) {
+        let n = self.blocks.len();
+        self.successors = (0..n).map(|b| match &self.blocks[b] {
+            Some(t) => t.exit.targets().into_iter().chain(self.handlers(b)).collect(),
+            None => vec![]
+        }).collect();
+        self.predecessors = vec![vec![]; n];
+        for (b, s) in self.successors.iter().enumerate() {
+            for t in s {
+                if !self.predecessors[*t].contains(&b) {
+                    self.predecessors[*t].push(b);
+                }
+            }
+        }
+        self.idom = dominators(&self.successors);
+        self.loops.clear();
+        for h in 0..n {
+            let mut stack = self.predecessors[h].iter().copied().filter(|p| self.dominates(h, *p)).collect::<Vec<_>>();
+            if stack.is_empty() {
+                continue
+            }
+            let mut body = HashSet::from([h]);
+            while let Some(b) = stack.pop() {
+                if body.insert(b) {
+                    stack.extend(self.predecessors[b].iter().copied());
+                }
+            }
+            self.loops.push((h, body));
+        }
+    }
+
+    fn dominates(&self, a: usize, mut b: usize) -> bool {
+        loop {
+            if a == b {
+                return true
+            }
+            match self.idom[b] {
+                Some(d) => b = d,
+                None => return false
+            }
+        }
+    }
+
+    /// Whether every edge to a block comes from another one.
+    fn only_from(&self, b: usize, from: usize) -> bool {
+        self.predecessors[b].iter().all(|p| *p == from)
+    }
+
+    /// The end of the run of blocks from a position that `head` dominates.
+    fn run(&self, seq: &[usize], from: usize, head: usize) -> usize {
+        from + seq[from..].iter().take_while(|b| self.dominates(head, **b)).count()
+    }
+
+    fn handlers(&self, b: usize) -> Vec<usize> {
+        self.cfg.blocks[b].successors.iter().filter(|e| matches!(e.kind, EdgeKind::Exception(_))).map(|e| e.target).collect()
+    }
+
+    /// Blocks where a `try` statement starts or that catch exceptions, which must be kept.
+    fn anchors(&self) -> HashSet<usize> {
+        self.groups.iter().map(|g| g.range.start).chain(self.exceptions.keys().copied()).collect()
+    }
+
+    /// Makes jumps to blocks that only jump elsewhere go there directly, and removes those blocks once nothing jumps to
+    /// them.
+    fn thread_jumps(&mut self) {
+        let anchors = self.anchors();
+        let forward = |blocks: &[Option<Translated>], b: usize| match &blocks[b] {
+            Some(Translated { statements, exit: Exit::Goto(t) }) if statements.is_empty() && !anchors.contains(&b) => Some(*t),
+            _ => None
+        };
+        for b in 0..self.blocks.len() {
+            let mut exit = match &self.blocks[b] {
+                Some(t) => t.exit.clone(),
+                None => continue
+            };
+            for t in exit.targets_mut() {
+                let mut steps = 0;
+                while let Some(u) = forward(&self.blocks, *t) {
+                    if u == *t || steps == self.blocks.len() {
+                        break
+                    }
+                    *t = u;
+                    steps += 1;
+                }
+            }
+            self.blocks[b].as_mut().unwrap().exit = exit;
+        }
+        self.graph();
+        for b in 1..self.blocks.len() {
+            if self.predecessors[b].is_empty() && forward(&self.blocks, b).is_some() {
+                self.blocks[b] = None;
+            }
+        }
+    }
+
+    /// Joins the conditions of a branch and of the block it falls through to when that block only branches, to the
+    /// same place or around it.
+    fn merge_conditions(&mut self) {
+        let anchors = self.anchors();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in 0..self.blocks.len() {
+                let (c1, t1, o1) = match &self.blocks[b] {
+                    Some(Translated { exit: Exit::Branch(c, t, o), .. }) => (c.clone(), *t, *o),
+                    _ => continue
+                };
+                if t1 == o1 || anchors.contains(&o1) || !self.only_from(o1, b) || self.handlers(b) != self.handlers(o1) {
+                    continue
+                }
+                let (c2, t2, o2) = match &self.blocks[o1] {
+                    Some(Translated { statements, exit: Exit::Branch(c, t, o) }) if statements.is_empty() => (c.clone(), *t, *o),
+                    _ => continue
+                };
+                let exit = if t2 == t1 {
+                    Exit::Branch(Expr::Binary(Box::new(c1), "||", Box::new(c2)), t1, o2)
+                } else if o2 == t1 {
+                    Exit::Branch(Expr::Binary(Box::new(not(c1)), "&&", Box::new(c2)), t2, o2)
+                } else {
+                    continue
+                };
+                self.blocks[b].as_mut().unwrap().exit = exit;
+                self.blocks[o1] = None;
+                self.graph();
+                changed = true;
+            }
+        }
+    }
+
+    fn loop_at(&self, seq: &[usize], i: usize, scopes: &[Scope]) -> Option<Loop> {
+        let b = seq[i];
+        let mut best: Option<(usize, usize, usize)> = None;
+        'loops: for (header, body) in &self.loops {
+            if !body.contains(&b) || scopes.iter().any(|s| matches!(s, Scope::Loop { header: h, .. } if h == header)) {
+                continue
+            }
+            let mut last = i;
+            for x in body.iter().filter(|x| self.blocks[**x].is_some()) {
+                match seq[i..].iter().position(|y| y == x) {
+                    Some(p) => last = last.max(i + p),
+                    None => continue 'loops
+                }
+            }
+            // Other blocks in the middle of the loop may only be entered from it.
+            if !seq[i..=last].iter().all(|x| body.contains(x) || self.dominates(*header, *x)) {
+                continue
+            }
+            let at = match seq[i..=last].iter().position(|x| x == header) {
+                Some(p) => i + p,
+                None => continue
+            };
+            if best.is_none_or(|(_, end, _)| last + 1 > end) {
+                best = Some((*header, last + 1, at));
+            }
+        }
+        // Compilers often put the condition of a loop after its body, jumping to it first.
+        best.map(|(header, end, at)| Loop { header, end, blocks: seq[at..end].iter().chain(&seq[i..at]).copied().collect() })
+    }
+
+    /// The first block that runs at a position, where blocks do not necessarily run in order.
+    fn entry_at(&self, seq: &[usize], i: usize, next: Option<usize>, scopes: &[Scope]) -> Option<usize> {
+        if i == seq.len() {
+            next
+        } else {
+            Some(self.loop_at(seq, i, scopes).map_or(seq[i], |l| l.header))
+        }
+    }
+
+    /// The `try` statement that starts at a position, if the blocks it covers follow each other.
+    fn try_at(&self, seq: &[usize], i: usize, scopes: &[Scope]) -> Option<TryLayout> {
+        let (g, group) = self.groups.iter().enumerate()
+            .filter(|(g, group)| group.range.start == seq[i] && !scopes.iter().any(|s| matches!(s, Scope::Try(t) if t == g)))
+            .max_by_key(|(g, group)| (group.range.end, std::cmp::Reverse(*g)))?;
+        let mut body_end = i + seq[i..].iter().take_while(|b| group.range.contains(b)).count();
+        let covered = group.range.clone().filter(|b| self.blocks[*b].is_some()).count();
+        if covered != body_end - i {
+            return None
+        }
+        // The code between the body and the handlers, often the jump over them, belongs to the body when only the body
+        // leads to it and it cannot throw.
+        let anchors = self.anchors();
+        let mut bridge = body_end;
+        while bridge < seq.len() && !anchors.contains(&seq[bridge])
+            && self.predecessors[seq[bridge]].iter().all(|p| group.range.contains(p) || seq[body_end..bridge].contains(p))
+            && !self.cfg.blocks[seq[bridge]].range.clone().any(|at| may_throw(&self.code[at])) {
+            bridge += 1;
+        }
+        if bridge < seq.len() && group.handlers.iter().any(|(h, _)| *h == seq[bridge]) {
+            body_end = bridge;
+        }
+        let mut handlers = group.handlers.iter().enumerate().collect::<Vec<_>>();
+        handlers.sort_by_key(|(_, (h, _))| seq.iter().position(|b| b == h).unwrap_or(usize::MAX));
+        let mut layout = vec![None; handlers.len()];
+        let mut end = body_end;
+        for (k, (h, _)) in handlers {
+            if end == seq.len() || seq[end] != *h {
+                break
+            }
+            let to = self.run(seq, end, *h);
+            layout[k] = Some(end..to);
+            end = to;
+        }
+        Some((g, body_end, layout, end))
+    }
+
+    /// Where a jump goes, if it does not go to the next block.
+    fn jump(&self, target: usize, next: Option<usize>, scopes: &[Scope]) -> Option<Stmt> {
+        if Some(target) == next {
+            return None
+        }
+        let (mut innermost_loop, mut innermost) = (true, true);
+        for s in scopes.iter().rev() {
+            match s {
+                Scope::Loop { header, follow } => {
+                    if *header == target {
+                        return Some(Stmt::Continue(if innermost_loop { None } else { Some(*header) }))
+                    }
+                    if *follow == Some(target) {
+                        return Some(Stmt::Break(if innermost { None } else { Some(*header) }))
+                    }
+                    innermost_loop = false;
+                    innermost = false;
+                }
+                Scope::Switch { id, follow } => {
+                    if *follow == Some(target) {
+                        return Some(Stmt::Break(if innermost { None } else { Some(*id) }))
+                    }
+                    innermost = false;
+                }
+                Scope::Try(_) => {}
+            }
+        }
+        Some(Stmt::Goto(target))
+    }
+
+    /// Structures a sequence of blocks, given the block that runs after it.
+    fn emit(&mut self, seq: &[usize], next: Option<usize>, scopes: &mut Vec<Scope>) -> Vec<Stmt> {
+        let mut out = vec![];
+        let mut i = 0;
+        while i < seq.len() {
+            let found = self.loop_at(seq, i, scopes);
+            let tried = self.try_at(seq, i, scopes);
+            match (found, tried) {
+                (Some(l), t) if t.as_ref().is_none_or(|t| t.3 <= l.end) => {
+                    let follow = self.entry_at(seq, l.end, next, scopes);
+                    scopes.push(Scope::Loop { header: l.header, follow });
+                    let body = self.emit(&l.blocks, Some(l.header), scopes);
+                    scopes.pop();
+                    out.push(Stmt::While { label: Some(l.header), condition: Expr::Bool(true), body });
+                    i = l.end;
+                }
+                (_, Some((g, body_end, layout, end))) => {
+                    let follow = self.entry_at(seq, end, next, scopes);
+                    scopes.push(Scope::Try(g));
+                    let body = self.emit(&seq[i..body_end], follow, scopes);
+                    scopes.pop();
+                    let mut catches = vec![];
+                    for (k, range) in layout.into_iter().enumerate() {
+                        let (h, types) = self.groups[g].handlers[k].clone();
+                        let body = match range {
+                            Some(r) => self.emit(&seq[r], follow, scopes),
+                            None => vec![Stmt::Goto(h)]
+                        };
+                        catches.push(CatchClause { types, name: self.exceptions[&h].clone(), body });
+                    }
+                    out.push(Stmt::Try { body, catches });
+                    i = end;
+                }
+                _ => i = self.emit_block(seq, i, next, scopes, &mut out)
+            }
+        }
+        out
+    }
+
+    /// Emits a block and the arms of the statement it starts, returning the position after them.
+    fn emit_block(&mut self, seq: &[usize], i: usize, next: Option<usize>, scopes: &mut Vec<Scope>, out: &mut Vec<Stmt>) -> usize {
+        let b = seq[i];
+        out.push(Stmt::Label(b));
+        for (g, group) in self.groups.iter().enumerate() {
+            if group.range.start == b && !scopes.iter().any(|s| matches!(s, Scope::Try(t) if *t == g)) {
+                for (h, types) in &group.handlers {
+                    let types = types.iter().map(|t| java_name(t.as_deref().unwrap_or("java/lang/Throwable"))).collect::<Vec<_>>();
+                    let end = match self.cfg.blocks.get(group.range.end) {
+                        Some(_) => format!("L{}", group.range.end),
+                        None => "the end".into()
+                    };
+                    out.push(Stmt::Comment(format!("{} thrown from here to {} is caught at L{}", types.join(" | "), end, h)));
+                    self.mentioned.insert(*h);
+                    self.mentioned.insert(group.range.end);
+                }
+            }
+        }
+        let Translated { statements, exit } = self.blocks[b].take().expect("a block is emitted once");
+        out.extend(statements);
+        let after = i + 1;
+        match exit {
+            Exit::End => after,
+            Exit::Goto(t) => {
+                let n = self.entry_at(seq, after, next, scopes);
+                out.extend(self.jump(t, n, scopes));
+                after
+            }
+            Exit::Branch(condition, t, o) => self.branch(seq, i, (condition, t, o), next, scopes, out),
+            Exit::Switch(value, cases, default) => self.switch(seq, i, (value, cases, default), next, scopes, out)
+        }
+    }
+
+    fn branch(&mut self, seq: &[usize], i: usize, (condition, t, o): (Expr, usize, usize), next: Option<usize>,
+              scopes: &mut Vec<Scope>, out: &mut Vec<Stmt>) -> usize {
+        let b = seq[i];
+        let after = i + 1;
+        if after < seq.len() && seq[after] == o && t != o && self.only_from(o, b) {
+            let p = self.run(seq, after, o);
+            if self.entry_at(seq, p, next, scopes) == Some(t) {
+                if p < seq.len() && self.only_from(t, b) {
+                    let q = self.run(seq, p, t);
+                    let follow = self.entry_at(seq, q, next, scopes);
+                    // Only `b` leads to `t`, so the first branch must jump over the second one to make it an `else`.
+                    if follow.is_some_and(|f| seq[after..p].iter().any(|x| self.successors[*x].contains(&f))) {
+                        let then = self.emit(&seq[after..p], follow, scopes);
+                        let otherwise = self.emit(&seq[p..q], follow, scopes);
+                        out.push(Stmt::If { condition: not(condition), then, otherwise });
+                        return q
+                    }
+                }
+                let then = self.emit(&seq[after..p], Some(t), scopes);
+                out.push(Stmt::If { condition: not(condition), then, otherwise: vec![] });
+                return p
+            }
+        }
+        let n = self.entry_at(seq, after, next, scopes);
+        if t == o {
+            out.push(Stmt::If { condition, then: vec![], otherwise: vec![] });
+            out.extend(self.jump(o, n, scopes));
+        } else if Some(t) == n {
+            out.extend(self.jump(o, n, scopes).map(|j| Stmt::If { condition: not(condition), then: vec![j], otherwise: vec![] }));
+        } else {
+            out.extend(self.jump(t, n, scopes).map(|j| Stmt::If { condition, then: vec![j], otherwise: vec![] }));
+            out.extend(self.jump(o, n, scopes));
+        }
+        after
+    }
+
+    fn switch(&mut self, seq: &[usize], i: usize, (value, cases, default): (Expr, Vec<(i32, usize)>, usize),
+              next: Option<usize>, scopes: &mut Vec<Scope>, out: &mut Vec<Stmt>) -> usize {
+        let b = seq[i];
+        let after = i + 1;
+        let mut end = self.run(seq, after, b);
+        // The statement after the switch is the first block after the last case that earlier cases jump to.
+        let last = cases.iter().map(|(_, t)| *t).chain(Some(default)).filter_map(|t| seq[after..end].iter().position(|b| *b == t))
+            .max().map_or(after, |p| after + p);
+        if let Some(q) = (last + 1..end).find(|q| self.predecessors[seq[*q]].iter().any(|p| seq[after..last].contains(p))) {
+            end = q;
+        }
+        let follow = self.entry_at(seq, end, next, scopes);
+        let targets = cases.iter().map(|(_, t)| *t).chain(Some(default)).filter(|t| Some(*t) != follow).collect::<HashSet<_>>();
+        let mut starts = targets.iter().map(|t| seq[after..end].iter().position(|b| b == t).map(|p| after + p)).collect::<Option<Vec<_>>>();
+        if let Some(s) = &mut starts {
+            s.sort_unstable();
+        }
+        let keys = |target: usize| cases.iter().filter(|(_, t)| *t == target).map(|(k, _)| Some(*k))
+            .chain(if default == target { Some(None) } else { None }).collect::<Vec<_>>();
+        let mut arms = vec![];
+        match starts {
+            Some(starts) if starts.first().map_or(after == end, |s| *s == after) => {
+                scopes.push(Scope::Switch { id: b, follow });
+                for (k, s) in starts.iter().enumerate() {
+                    let e = starts.get(k + 1).copied().unwrap_or(end);
+                    let case_next = if e < end { Some(seq[e]) } else { follow };
+                    let body = self.emit(&seq[*s..e], case_next, scopes);
+                    arms.push(Case { keys: keys(seq[*s]), body });
+                }
+                let rest = cases.iter().filter(|(_, t)| Some(*t) == follow).map(|(k, _)| Some(*k)).collect::<Vec<_>>();
+                if !rest.is_empty() {
+                    arms.push(Case { keys: rest, body: vec![Stmt::Break(None)] });
+                }
+                scopes.pop();
+                out.push(Stmt::Switch { label: Some(b), value, cases: arms });
+                end
+            }
+            _ => {
+                let n = self.entry_at(seq, after, next, scopes);
+                scopes.push(Scope::Switch { id: b, follow: n });
+                let mut targets = cases.iter().map(|(_, t)| *t).chain(Some(default)).collect::<Vec<_>>();
+                targets.sort_unstable();
+                targets.dedup();
+                for t in targets {
+                    arms.push(Case { keys: keys(t), body: self.jump(t, None, scopes).into_iter().collect() });
+                }
+                scopes.pop();
+                out.push(Stmt::Switch { label: Some(b), value, cases: arms });
+                after
+            }
+        }
+    }
+}
+
+fn nested(s: &Stmt) -> Vec<&Vec<Stmt>> {
+    match s {
+        Stmt::If { then, otherwise, .. } => vec![then, otherwise],
+        Stmt::While { body, .. } | Stmt::DoWhile { body, .. } => vec![body],
+        Stmt::Switch { cases, .. } => cases.iter().map(|c| &c.body).collect(),
+        Stmt::Try { body, catches } => Some(body).into_iter().chain(catches.iter().map(|c| &c.body)).collect(),
+        _ => vec![]
+    }
+}
+
+fn nested_mut(s: &mut Stmt) -> Vec<&mut Vec<Stmt>> {
+    match s {
+        Stmt::If { then, otherwise, .. } => vec![then, otherwise],
+        Stmt::While { body, .. } | Stmt::DoWhile { body, .. } => vec![body],
+        Stmt::Switch { cases, .. } => cases.iter_mut().map(|c| &mut c.body).collect(),
+        Stmt::Try { body, catches } => Some(body).into_iter().chain(catches.iter_mut().map(|c| &mut c.body)).collect(),
+        _ => vec![]
+    }
+}
+
+fn visit(statements: &[Stmt], f: &mut dyn FnMut(&Stmt)) {
+    for s in statements {
+        f(s);
+        nested(s).into_iter().for_each(|n| visit(n, f));
+    }
+}
+
+/// Applies a function to every list of statements, the innermost first.
+fn rewrite(statements: &mut Vec<Stmt>, f: &mut dyn FnMut(&mut Vec<Stmt>)) {
+    for s in statements.iter_mut() {
+        nested_mut(s).into_iter().for_each(|n| rewrite(n, f));
+    }
+    f(statements)
+}
+
+/// Whether a `continue` goes to the start of a loop, which is not where it goes in a `do`/`while` loop.
+fn continues(statements: &[Stmt], label: Option<usize>, innermost: bool) -> bool {
+    statements.iter().any(|s| match s {
+        Stmt::Continue(None) => innermost,
+        Stmt::Continue(l) => *l == label,
+        Stmt::While { .. } | Stmt::DoWhile { .. } => nested(s).into_iter().any(|n| continues(n, label, false)),
+        _ => nested(s).into_iter().any(|n| continues(n, label, innermost))
+    })
+}
+
+/// Turns `if` statements with an empty first branch around, `while (true)` loops that start with a conditional `break`
+/// into `while` loops, and those that end with a conditional `continue` into `do`/`while` loops.
+fn simplify(s: &mut Stmt) {
+    if let Stmt::If { condition, then, otherwise } = s {
+        if then.is_empty() && !otherwise.is_empty() {
+            let condition = not(std::mem::replace(condition, Expr::Null));
+            *s = Stmt::If { condition, then: std::mem::take(otherwise), otherwise: vec![] };
+        }
+        return
+    }
+    if let Stmt::While { label, condition: Expr::Bool(true), body } = s {
+        if let Some(Stmt::If { condition, then, otherwise }) = body.first() {
+            if then[..] == [Stmt::Break(None)] && otherwise.is_empty() {
+                let condition = not(condition.clone());
+                body.remove(0);
+                *s = Stmt::While { label: *label, condition, body: std::mem::take(body) };
+                return
+            }
+        }
+        // The loop ends with `if (c) continue; break;` or `if (c) break;`.
+        let (condition, n) = match body.as_slice() {
+            [.., Stmt::If { condition, then, otherwise }, Stmt::Break(None)] if then[..] == [Stmt::Continue(None)] && otherwise.is_empty() => {
+                (condition.clone(), body.len() - 2)
+            }
+            [.., Stmt::If { condition, then, otherwise }] if then[..] == [Stmt::Break(None)] && otherwise.is_empty() => {
+                (not(condition.clone()), body.len() - 1)
+            }
+            _ => return
+        };
+        if !continues(&body[..n], *label, true) {
+            body.truncate(n);
+            *s = Stmt::DoWhile { label: *label, body: std::mem::take(body), condition };
+        }
+    }
+}
+
+/// Removes the labels nothing refers to and simplifies loops.
+fn finish(mut statements: Vec<Stmt>, mentioned: &HashSet<usize>) -> Vec<Stmt> {
+    let mut targets = mentioned.clone();
+    visit(&statements, &mut |s| if let Stmt::Goto(l) = s {
+        targets.insert(*l);
+    });
+    rewrite(&mut statements, &mut |list| {
+        list.retain(|s| !matches!(s, Stmt::Label(l) if !targets.contains(l)));
+        list.iter_mut().for_each(simplify);
+    });
+    let mut labels = HashSet::new();
+    visit(&statements, &mut |s| if let Stmt::Break(Some(l)) | Stmt::Continue(Some(l)) = s {
+        labels.insert(*l);
+    });
+    rewrite(&mut statements, &mut |list| for s in list {
+        if let Stmt::While { label, .. } | Stmt::DoWhile { label, .. } | Stmt::Switch { label, .. } = s {
+            *label = label.filter(|l| labels.contains(l));
+        }
+    });
+    statements
+}
+
+fn run(code: &Code, class: Option<&str>, this: bool, returns: Option<Type>) -> Result<Body> {
+    let cfg = ControlFlowGraph::new(code)?;
+    let n = cfg.blocks.len();
+    let names = Names::new(code, this);
+    let target = |l: &Label| cfg.block_of(*l).ok_or_else(|| Error::Invalid("label", format!("{:?} is not placed in the code", l).into()));
+
+    // Handlers take their name from the variable they store the exception in.
+    let mut exceptions = HashMap::new();
+    for c in &code.catches {
+        let h = target(&c.handler)?;
+        let first = cfg.blocks[h].range.clone().find(|i| is_executed(&code.code[*i]));
+        let name = match first.map(|i| (i, &code.code[i])) {
+            Some((at, Instruction::LocalVariable(LoadOrStore::Store, LocalType::Reference, i))) => names.get(*i, at, true).0,
+            _ => "ex".to_string()
+        };
+        exceptions.insert(h, name);
+    }
+    let mut groups: Vec<Group> = vec![];
+    for c in &code.catches {
+        let (range, h) = (target(&c.start)?..target(&c.end)?, target(&c.handler)?);
+        if range.is_empty() {
+            continue
+        }
+        let group = match groups.iter_mut().find(|g| g.range == range) {
+            Some(g) => g,
+            None => {
+                groups.push(Group { range, handlers: vec![] });
+                groups.last_mut().unwrap()
+            }
+        };
+        match group.handlers.iter_mut().find(|(b, _)| *b == h) {
+            Some((_, types)) => types.push(c.catch.clone()),
+            None => group.handlers.push((h, vec![c.catch.clone()]))
+        }
+    }
+
+    // Blocks are translated in order once the stack they start with is known.
+    let mut translator = Translator { code, cfg: &cfg, names, class, returns, stack: vec![], statements: vec![], temporaries: 0, allocations: 0 };
+    let mut entries: Vec<Option<Vec<Entry>>> = vec![None; n];
+    entries[0] = Some(vec![]);
+    for (h, name) in &exceptions {
+        entries[*h] = Some(vec![Entry::new(Expr::Local(name.clone()), LocalType::Reference)]);
+    }
+    let reachable = cfg.reachable();
+    let mut blocks: Vec<Option<Translated>> = vec![None; n];
+    let mut progress = true;
+    while progress {
+        progress = false;
+        for b in 0..n {
+            if blocks[b].is_some() || !reachable[b] {
+                continue
+            }
+            let entry = match &entries[b] {
+                Some(e) => e.clone(),
+                None => continue
+            };
+            let (translated, exit) = translator.block(b, entry, exceptions.get(&b).map(|s| s.as_str()))?;
+            for s in translated.exit.targets() {
+                if entries[s].is_none() {
+                    entries[s] = Some(exit.clone());
+                }
+            }
+            blocks[b] = Some(translated);
+            progress = true;
+        }
+    }
+
+    let mut structurer = Structurer {
+        code: &code.code, cfg: &cfg, blocks, successors: vec![], predecessors: vec![], idom: vec![], loops: vec![], groups, exceptions, mentioned: HashSet::new()
+    };
+    structurer.thread_jumps();
+    structurer.merge_conditions();
+    let seq = (0..n).filter(|b| structurer.blocks[*b].is_some()).collect::<Vec<_>>();
+    let statements = structurer.emit(&seq, None, &mut vec![]);
+    Ok(Body { statements: finish(statements, &structurer.mentioned) })
+}
+
+/// Decompiles a method body. Local variables missing from the local variable table are called `local0`, `local1`...
+///
+/// This fails if the code uses instructions that are not supported, or if its stack does not match its instructions.
+pub fn decompile(code: &Code) -> Result<Body> {
+    run(code, None, false, None)
+}
+
+/// Decompiles the body of a method of `class`.
+///
+/// Unlike with [`decompile`], the receiver of instance methods is called `this`, constructor calls on it are written
+/// `this(...)` or `super(...)`, calls to methods of the super class `super.m()`, and constants returned by methods
+/// returning a boolean are written `true` and `false`. This fails if the method has no code.
+pub fn decompile_method(class: &str, method: &Method) -> Result<Body> {
+    let code = method.code()?.ok_or(Error::Invalid("method", "it has no code".into()))?;
+    let returns = match &method.descriptor {
+        Type::Method { ret, .. } => ret.as_deref().cloned(),
+        _ => None
+    };
+    run(code, Some(class), !method.access.contains(MethodFlags::ACC_STATIC), returns)
+}
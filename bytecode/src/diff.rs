@@ -0,0 +1,449 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Semantic differences between two versions of a class.
+//!
+//! Members are matched by name and descriptor, so reordering them is not a change. Since the class model does not expose
+//! constant pool indices, the ordering of the pool does not matter either, and bootstrap methods are compared regardless of their order. Labels of method bodies are renumbered in the order
+//! they appear before comparing, and instructions are compared with a shortest edit script.
+//!
+//! The resulting [`ClassDiff`] is a list of structured changes, and displays as a readable summary.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use crate::prelude::*;
+use crate::Class;
+
+/// A change to a class.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Change {
+    /// The class file version changed from the first to the second.
+    Version(JavaVersion, JavaVersion),
+    /// The class was renamed from the first to the second name.
+    Name(Cow<'static, str>, Cow<'static, str>),
+    /// The access flags of the class changed from the first to the second.
+    Access(ClassFlags, ClassFlags),
+    /// The superclass changed from the first to the second.
+    SuperName(Option<Cow<'static, str>>, Option<Cow<'static, str>>),
+    /// The new version implements this interface.
+    InterfaceAdded(Cow<'static, str>),
+    /// The new version no longer implements this interface.
+    InterfaceRemoved(Cow<'static, str>),
+    /// The new version has this attribute.
+    ///
+    /// For `BootstrapMethods`, the attribute only holds the bootstrap methods that were added.
+    AttributeAdded(ClassAttribute),
+    /// The new version no longer has this attribute.
+    ///
+    /// For `BootstrapMethods`, the attribute only holds the bootstrap methods that were removed.
+    AttributeRemoved(ClassAttribute),
+    /// The new version has this field.
+    FieldAdded(Field),
+    /// The new version no longer has this field.
+    FieldRemoved(Field),
+    /// A field that exists in both versions, identified by its name and descriptor, was changed.
+    FieldChanged(Cow<'static, str>, Type, Vec<FieldChange>),
+    /// The new version has this method.
+    MethodAdded(Method),
+    /// The new version no longer has this method.
+    MethodRemoved(Method),
+    /// A method that exists in both versions, identified by its name and descriptor, was changed.
+    MethodChanged(Cow<'static, str>, Type, Vec<MethodChange>)
+}
+
+/// A change to a field.
+#[derive(Clone, PartialEq, Debug)]
+pub enum FieldChange {
+    /// The access flags of the field changed from the first to the second.
+    Access(FieldFlags, FieldFlags),
+    /// The new version of the field has this attribute.
+    AttributeAdded(FieldAttribute),
+    /// The new version of the field no longer has this attribute.
+    AttributeRemoved(FieldAttribute)
+}
+
+/// A change to a method.
+///
+/// Code attributes are not reported as attributes when both versions have one, the changes to the code are reported instead.
+#[derive(Clone, PartialEq, Debug)]
+pub enum MethodChange {
+    /// The access flags of the method changed from the first to the second.
+    Access(MethodFlags, MethodFlags),
+    /// The new version of the method has this attribute.
+    AttributeAdded(MethodAttribute),
+    /// The new version of the method no longer has this attribute.
+    AttributeRemoved(MethodAttribute),
+    /// The maximum stack size of the code changed from the first to the second.
+    MaxStack(u16, u16),
+    /// The number of local variable slots of the code changed from the first to the second.
+    MaxLocals(u16, u16),
+    /// The instructions that were removed from the old code and added to the new code.
+    Instructions(Vec<InsnEdit>),
+    /// The exception handlers, with normalized labels, if they differ.
+    Catches(Vec<Catch>, Vec<Catch>),
+    /// The new version of the code has this attribute.
+    CodeAttributeAdded(CodeAttribute),
+    /// The new version of the code no longer has this attribute.
+    CodeAttributeRemoved(CodeAttribute)
+}
+
+/// An edit of an instruction list, with labels normalized.
+#[derive(Clone, PartialEq, Debug)]
+pub enum InsnEdit {
+    /// The instruction at this index of the old code was removed.
+    Removed(usize, Instruction),
+    /// The instruction at this index of the new code was added.
+    Added(usize, Instruction)
+}
+
+/// The changes from one version of a class to another.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ClassDiff {
+    pub changes: Vec<Change>
+}
+
+impl ClassDiff {
+    /// Returns `true` if the two versions are semantically the same.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Compares classes.
+#[derive(Clone, Debug, Default)]
+pub struct Differ {
+    ignore_line_numbers: bool
+}
+
+/// Compares two versions of a class with the default [`Differ`].
+pub fn diff(old: &Class, new: &Class) -> Result<ClassDiff> {
+    Differ::new().diff(old, new)
+}
+
+impl Differ {
+    /// Creates a differ that compares everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Does not report `LineNumber` instructions, which change whenever the source is edited above a method.
+    pub fn ignore_line_numbers(mut self) -> Self {
+        self.ignore_line_numbers = true;
+        self
+    }
+
+    /// Compares two versions of a class.
+    ///
+    /// This fails if a lazily read method body cannot be decoded.
+    pub fn diff(&self, old: &Class, new: &Class) -> Result<ClassDiff> {
+        let mut changes = vec![];
+        if old.version != new.version {
+            changes.push(Change::Version(old.version, new.version));
+        }
+        if old.name != new.name {
+            changes.push(Change::Name(old.name.clone(), new.name.clone()));
+        }
+        if old.access != new.access {
+            changes.push(Change::Access(old.access, new.access));
+        }
+        if old.super_name != new.super_name {
+            changes.push(Change::SuperName(old.super_name.clone(), new.super_name.clone()));
+        }
+        let (removed, added) = unmatched(&old.interfaces, &new.interfaces);
+        changes.extend(removed.into_iter().cloned().map(Change::InterfaceRemoved));
+        changes.extend(added.into_iter().cloned().map(Change::InterfaceAdded));
+        // the order of bootstrap methods follows the constant pool, so they are compared regardless of it.
+        let is_bsms = |a: &&ClassAttribute| matches!(a, ClassAttribute::BootstrapMethods(_));
+        let old_attrs = old.attributes.iter().filter(|a| !is_bsms(a)).collect::<Vec<_>>();
+        let new_attrs = new.attributes.iter().filter(|a| !is_bsms(a)).collect::<Vec<_>>();
+        let (removed, added) = unmatched(&old_attrs, &new_attrs);
+        changes.extend(removed.into_iter().map(|a| Change::AttributeRemoved((*a).clone())));
+        changes.extend(added.into_iter().map(|a| Change::AttributeAdded((*a).clone())));
+        let (old_bsms, new_bsms) = (bootstrap_methods(old), bootstrap_methods(new));
+        let (removed, added) = unmatched(&old_bsms, &new_bsms);
+        if !removed.is_empty() {
+            changes.push(Change::AttributeRemoved(ClassAttribute::BootstrapMethods(removed.into_iter().map(|b| (*b).clone()).collect())));
+        }
+        if !added.is_empty() {
+            changes.push(Change::AttributeAdded(ClassAttribute::BootstrapMethods(added.into_iter().map(|b| (*b).clone()).collect())));
+        }
+
+        let new_fields = new.fields.iter().map(|f| ((&f.name, &f.descriptor), f)).collect::<HashMap<_, _>>();
+        for f in &old.fields {
+            match new_fields.get(&(&f.name, &f.descriptor)) {
+                None => changes.push(Change::FieldRemoved(f.clone())),
+                Some(n) => {
+                    let mut c = vec![];
+                    if f.access != n.access {
+                        c.push(FieldChange::Access(f.access, n.access));
+                    }
+                    let (removed, added) = unmatched(&f.attrs, &n.attrs);
+                    c.extend(removed.into_iter().cloned().map(FieldChange::AttributeRemoved));
+                    c.extend(added.into_iter().cloned().map(FieldChange::AttributeAdded));
+                    if !c.is_empty() {
+                        changes.push(Change::FieldChanged(f.name.clone(), f.descriptor.clone(), c));
+                    }
+                }
+            }
+        }
+        let old_fields = old.fields.iter().map(|f| (&f.name, &f.descriptor)).collect::<std::collections::HashSet<_>>();
+        changes.extend(new.fields.iter().filter(|f| !old_fields.contains(&(&f.name, &f.descriptor))).cloned().map(Change::FieldAdded));
+
+        let new_methods = new.methods.iter().map(|m| ((&m.name, &m.descriptor), m)).collect::<HashMap<_, _>>();
+        for m in &old.methods {
+            match new_methods.get(&(&m.name, &m.descriptor)) {
+                None => changes.push(Change::MethodRemoved(m.clone())),
+                Some(n) => {
+                    let c = self.diff_method(m, n)?;
+                    if !c.is_empty() {
+                        changes.push(Change::MethodChanged(m.name.clone(), m.descriptor.clone(), c));
+                    }
+                }
+            }
+        }
+        let old_methods = old.methods.iter().map(|m| (&m.name, &m.descriptor)).collect::<std::collections::HashSet<_>>();
+        changes.extend(new.methods.iter().filter(|m| !old_methods.contains(&(&m.name, &m.descriptor))).cloned().map(Change::MethodAdded));
+        Ok(ClassDiff { changes })
+    }
+
+    fn diff_method(&self, old: &Method, new: &Method) -> Result<Vec<MethodChange>> {
+        let mut c = vec![];
+        if old.access != new.access {
+            c.push(MethodChange::Access(old.access, new.access));
+        }
+        let is_code = |a: &&MethodAttribute| matches!(a, MethodAttribute::Code(_) | MethodAttribute::LazyCode(_));
+        let (old_code, new_code) = (old.code()?, new.code()?);
+        let skip_code = old_code.is_some() && new_code.is_some();
+        let old_attrs = old.attributes.iter().filter(|a| !(skip_code && is_code(a))).collect::<Vec<_>>();
+        let new_attrs = new.attributes.iter().filter(|a| !(skip_code && is_code(a))).collect::<Vec<_>>();
+        let (removed, added) = unmatched(&old_attrs, &new_attrs);
+        c.extend(removed.into_iter().map(|a| MethodChange::AttributeRemoved((*a).clone())));
+        c.extend(added.into_iter().map(|a| MethodChange::AttributeAdded((*a).clone())));
+        if let (Some(o), Some(n)) = (old_code, new_code) {
+            self.diff_code(o, n, &mut c);
+        }
+        Ok(c)
+    }
+
+    fn diff_code(&self, old: &Code, new: &Code, c: &mut Vec<MethodChange>) {
+        let (old, new) = (normalize(old), normalize(new));
+        if old.max_stack != new.max_stack {
+            c.push(MethodChange::MaxStack(old.max_stack, new.max_stack));
+        }
+        if old.max_locals != new.max_locals {
+            c.push(MethodChange::MaxLocals(old.max_locals, new.max_locals));
+        }
+        let keep = |i: &&Instruction| !(self.ignore_line_numbers && matches!(i, Instruction::LineNumber(_)));
+        let old_insns = old.code.iter().filter(keep).collect::<Vec<_>>();
+        let new_insns = new.code.iter().filter(keep).collect::<Vec<_>>();
+        let edits = edit_script(&old_insns, &new_insns).into_iter().map(|(added, i)| if added {
+            InsnEdit::Added(i, new_insns[i].clone())
+        } else {
+            InsnEdit::Removed(i, old_insns[i].clone())
+        }).collect::<Vec<_>>();
+        if !edits.is_empty() {
+            c.push(MethodChange::Instructions(edits));
+        }
+        if old.catches != new.catches {
+            c.push(MethodChange::Catches(old.catches.clone(), new.catches.clone()));
+        }
+        let (removed, added) = unmatched(&old.attrs, &new.attrs);
+        c.extend(removed.into_iter().cloned().map(MethodChange::CodeAttributeRemoved));
+        c.extend(added.into_iter().cloned().map(MethodChange::CodeAttributeAdded));
+    }
+}
+
+/// Renumbers the labels of the code in the order they are placed, then in the order they are referred to for those that are not placed.
+fn normalize(code: &Code) -> Code {
+    let mut order: HashMap<Label, Label> = HashMap::new();
+    for insn in &code.code {
+        if let Instruction::Label(l) = insn {
+            let next = Label(order.len() as u32);
+            order.entry(*l).or_insert(next);
+        }
+    }
+    let mut code = code.clone();
+    code.visit_labels_mut(|l| {
+        let next = Label(order.len() as u32);
+        *l = *order.entry(*l).or_insert(next);
+    });
+    code
+}
+
+/// The bootstrap methods of a class, from all of its bootstrap method attributes.
+fn bootstrap_methods(class: &Class) -> Vec<&BootstrapMethod> {
+    class.attributes.iter().flat_map(|a| match a {
+        ClassAttribute::BootstrapMethods(b) => b.as_slice(),
+        _ => &[]
+    }).collect()
+}
+
+/// The elements of `old` that have no equal element in `new`, and the elements of `new` that have no equal element in `old`.
+///
+/// Every element is matched at most once, so duplicates are reported as well.
+fn unmatched<'a, T: PartialEq>(old: &'a [T], new: &'a [T]) -> (Vec<&'a T>, Vec<&'a T>) {
+    let mut matched = vec![false; new.len()];
+    let mut removed = vec![];
+    for o in old {
+        match new.iter().enumerate().position(|(i, n)| !matched[i] && n == o) {
+            Some(i) => matched[i] = true,
+            None => removed.push(o)
+        }
+    }
+    let added = new.iter().zip(matched).filter(|(_, m)| !m).map(|(n, _)| n).collect();
+    (removed, added)
+}
+
+/// The shortest edit script from `a` to `b`, using the linear space variant of Myers' algorithm.
+///
+/// Each edit is `(true, index in b)` for an insertion or `(false, index in a)` for a deletion, in order.
+fn edit_script<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(bool, usize)> {
+    // the furthest reaching paths of both directions, reused by every step of the recursion.
+    let len = a.len() + b.len() + 4;
+    let (mut forward, mut backward) = (vec![0; len], vec![0; len]);
+    let mut edits = vec![];
+    conquer(a, b, 0, 0, &mut forward, &mut backward, &mut edits);
+    // the edits between two unchanged elements are ordered with deletions first, wherever the recursion split them.
+    let (mut x, mut y, mut run) = (0, 0, 0);
+    let mut runs = Vec::with_capacity(edits.len());
+    for &(added, i) in &edits {
+        let unchanged = if added { i - y } else { i - x };
+        if unchanged > 0 {
+            run += 1;
+        }
+        x += unchanged + !added as usize;
+        y += unchanged + added as usize;
+        runs.push(run);
+    }
+    let mut edits = runs.into_iter().zip(edits).collect::<Vec<_>>();
+    edits.sort_by_key(|&(run, (added, _))| (run, added));
+    edits.into_iter().map(|(_, e)| e).collect()
+}
+
+/// Appends the edits from `a` to `b`, which start at `a_off` and `b_off` of the whole sequences.
+fn conquer<T: PartialEq>(mut a: &[T], mut b: &[T], mut a_off: usize, mut b_off: usize, forward: &mut [usize], backward: &mut [usize], edits: &mut Vec<(bool, usize)>) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    a = &a[prefix..];
+    b = &b[prefix..];
+    a_off += prefix;
+    b_off += prefix;
+    let suffix = a.iter().rev().zip(b.iter().rev()).take_while(|(x, y)| x == y).count();
+    a = &a[..a.len() - suffix];
+    b = &b[..b.len() - suffix];
+    if a.is_empty() || b.is_empty() {
+        edits.extend((0..a.len()).map(|i| (false, a_off + i)));
+        edits.extend((0..b.len()).map(|i| (true, b_off + i)));
+        return
+    }
+    let (x, y) = middle_snake(a, b, forward, backward);
+    conquer(&a[..x], &b[..y], a_off, b_off, forward, backward, edits);
+    conquer(&a[x..], &b[y..], a_off + x, b_off + y, forward, backward, edits);
+}
+
+/// The start of the snake in the middle of a shortest edit path from `a` to `b`, which are not empty and differ at both
+/// ends, found by searching from both ends at once.
+fn middle_snake<T: PartialEq>(a: &[T], b: &[T], forward: &mut [usize], backward: &mut [usize]) -> (usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta & 1 == 1;
+    let max = (n + m + 1) / 2 + 1;
+    // diagonal `k` is at `k + max`, the backward search runs on the reversed sequences.
+    let at = |k: isize| (k + max) as usize;
+    forward[at(1)] = 0;
+    backward[at(1)] = 0;
+    for d in 0..max {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = (if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) { forward[at(k + 1)] } else { forward[at(k - 1)] + 1 }) as isize;
+            let start = (x, x - k);
+            while x < n && x - k < m && x - k >= 0 && a[x as usize] == b[(x - k) as usize] {
+                x += 1;
+            }
+            forward[at(k)] = x as usize;
+            if odd && (k - delta).abs() < d && x + backward[at(delta - k)] as isize >= n {
+                return (start.0 as usize, start.1 as usize)
+            }
+        }
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = (if k == -d || (k != d && backward[at(k - 1)] < backward[at(k + 1)]) { backward[at(k + 1)] } else { backward[at(k - 1)] + 1 }) as isize;
+            while x < n && x - k < m && x - k >= 0 && a[(n - x - 1) as usize] == b[(m - x + k - 1) as usize] {
+                x += 1;
+            }
+            backward[at(k)] = x as usize;
+            if !odd && (k - delta).abs() <= d && x + forward[at(delta - k)] as isize >= n {
+                return ((n - x) as usize, (m - x + k) as usize)
+            }
+        }
+    }
+    // a path of at most `n + m` edits always exists, so the searches meet before.
+    unreachable!()
+}
+
+impl Display for ClassDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for c in &self.changes {
+            match c {
+                Change::Version(o, n) => writeln!(f, "version: {} -> {}", o, n)?,
+                Change::Name(o, n) => writeln!(f, "name: {} -> {}", o, n)?,
+                Change::Access(o, n) => writeln!(f, "access: {:?} -> {:?}", o, n)?,
+                Change::SuperName(o, n) => writeln!(f, "super: {} -> {}", o.as_deref().unwrap_or("none"), n.as_deref().unwrap_or("none"))?,
+                Change::InterfaceAdded(i) => writeln!(f, "+ interface {}", i)?,
+                Change::InterfaceRemoved(i) => writeln!(f, "- interface {}", i)?,
+                Change::AttributeAdded(a) => writeln!(f, "+ attribute {:?}", a)?,
+                Change::AttributeRemoved(a) => writeln!(f, "- attribute {:?}", a)?,
+                Change::FieldAdded(m) => writeln!(f, "+ field {} {}", m.name, m.descriptor)?,
+                Change::FieldRemoved(m) => writeln!(f, "- field {} {}", m.name, m.descriptor)?,
+                Change::MethodAdded(m) => writeln!(f, "+ method {}{}", m.name, m.descriptor)?,
+                Change::MethodRemoved(m) => writeln!(f, "- method {}{}", m.name, m.descriptor)?,
+                Change::FieldChanged(name, desc, changes) => {
+                    writeln!(f, "~ field {} {}", name, desc)?;
+                    for c in changes {
+                        match c {
+                            FieldChange::Access(o, n) => writeln!(f, "    access: {:?} -> {:?}", o, n)?,
+                            FieldChange::AttributeAdded(a) => writeln!(f, "    + attribute {:?}", a)?,
+                            FieldChange::AttributeRemoved(a) => writeln!(f, "    - attribute {:?}", a)?
+                        }
+                    }
+                }
+                Change::MethodChanged(name, desc, changes) => {
+                    writeln!(f, "~ method {}{}", name, desc)?;
+                    for c in changes {
+                        match c {
+                            MethodChange::Access(o, n) => writeln!(f, "    access: {:?} -> {:?}", o, n)?,
+                            MethodChange::AttributeAdded(a) => writeln!(f, "    + attribute {:?}", a)?,
+                            MethodChange::AttributeRemoved(a) => writeln!(f, "    - attribute {:?}", a)?,
+                            MethodChange::MaxStack(o, n) => writeln!(f, "    max stack: {} -> {}", o, n)?,
+                            MethodChange::MaxLocals(o, n) => writeln!(f, "    max locals: {} -> {}", o, n)?,
+                            MethodChange::Instructions(edits) => {
+                                for e in edits {
+                                    match e {
+                                        InsnEdit::Removed(i, insn) => writeln!(f, "    - {}: {:?}", i, insn)?,
+                                        InsnEdit::Added(i, insn) => writeln!(f, "    + {}: {:?}", i, insn)?
+                                    }
+                                }
+                            }
+                            MethodChange::Catches(o, n) => writeln!(f, "    catches: {:?} -> {:?}", o, n)?,
+                            MethodChange::CodeAttributeAdded(a) => writeln!(f, "    + code attribute {:?}", a)?,
+                            MethodChange::CodeAttributeRemoved(a) => writeln!(f, "    - code attribute {:?}", a)?
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -15,13 +15,14 @@
  *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::cell::UnsafeCell;
-use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
 use crate::prelude::*;
+use crate::rw::checked_len;
 use std::hash::{Hash, Hasher};
 
 
 #[derive(Debug, Clone, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BootstrapMethod {
     pub handle: MethodHandle,
     pub arguments: Vec<OrDynamic<Constant>>
@@ -43,7 +44,7 @@ impl ConstantPoolReadWrite for BootstrapMethod {
 
     fn write_to<C: ConstantPoolWriter, W: Write>(&self, cp: &mut C, writer: &mut W) -> Result<(), Error> {
         cp.insert_method_handle(self.handle.clone()).write_to(writer)?;
-        (self.arguments.len() as u16).write_to(writer)?;
+        checked_len::<u16>("bootstrap method argument count", self.arguments.len())?.write_to(writer)?;
         for arg in self.arguments.iter().cloned() {
             cp.insert_ordynamic(arg, ConstantPoolWriter::insert_constant).write_to(writer)?;
         }
@@ -56,8 +57,9 @@ impl ConstantPoolReadWrite for BootstrapMethod {
 /// Note: dynamic computed constants are syntactically allowed to refer to themselves via the bootstrap method table but it will fail during resolution.
 /// Rust ownership rules prevent us from doing so.
 #[derive(Debug, Clone, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Dynamic {
-    pub(crate) bsm: Rc<LazyBsm>,
+    pub(crate) bsm: Arc<LazyBsm>,
     /// The name of the bootstrap method that will compute the constant value.
     pub name: Cow<'static, str>,
     /// The descriptor of the dynamically computed value. Must be a field descriptor.
@@ -68,7 +70,7 @@ impl Dynamic {
     /// Creates a new dynamic computed constant.
     pub fn new<N: Into<Cow<'static, str>>, D: Into<Type>>(bsm: BootstrapMethod, name: N, descriptor: D) -> Dynamic {
         Self {
-            bsm: Rc::new(bsm.into()),
+            bsm: Arc::new(bsm.into()),
             name: name.into(),
             descriptor: descriptor.into()
         }
@@ -80,18 +82,152 @@ impl Dynamic {
 
     /// Returns a mutable reference to the bootstrap method of this dynamic computed constant.
     pub fn bsm_mut(&mut self) -> &mut BootstrapMethod {
-        // This is safe because the Rc would only have one owner since the only other owner would be dropped
-        // After reading the entire class file.
-        unsafe { self.bsm.__get_mut() }.expect("Expected bsm to be populated")
+        // The Arc only has one owner after reading the entire class file, unless this dynamic has been cloned.
+        Arc::make_mut(&mut self.bsm).get_mut().expect("Expected bsm to be populated")
     }
 
     pub fn into_inner(self) -> (Option<BootstrapMethod>, Cow<'static, str>, Type) {
-        (Rc::try_unwrap(self.bsm).unwrap().into_inner(), self.name, self.descriptor)
+        (Arc::try_unwrap(self.bsm).unwrap_or_else(|b| (*b).clone()).into_inner(), self.name, self.descriptor)
     }
+
+    /// Creates a `makeConcatWithConstants` call site of [`StringConcatFactory`] that concatenates the parts in order.
+    ///
+    /// Each [`Argument`] becomes a parameter of the call site descriptor, and each [`Constant`] becomes a
+    /// bootstrap method argument. Literals that contain the tag characters `\u{1}` or `\u{2}` are passed
+    /// as constants because they cannot be represented in the recipe.
+    ///
+    /// [`StringConcatFactory`]: STRING_CONCAT_FACTORY
+    /// [`Argument`]: ConcatPart::Argument
+    /// [`Constant`]: ConcatPart::Constant
+    pub fn string_concat<I: IntoIterator<Item = ConcatPart>>(parts: I) -> Dynamic {
+        let mut recipe = String::new();
+        let mut parameters = vec![];
+        let mut arguments = vec![];
+        for part in parts {
+            match part {
+                ConcatPart::Literal(s) if s.contains(&[CONCAT_ARGUMENT_TAG, CONCAT_CONSTANT_TAG][..]) => {
+                    recipe.push(CONCAT_CONSTANT_TAG);
+                    arguments.push(OrDynamic::Static(Constant::String(s)));
+                }
+                ConcatPart::Literal(s) => recipe.push_str(&s),
+                ConcatPart::Argument(t) => {
+                    recipe.push(CONCAT_ARGUMENT_TAG);
+                    parameters.push(t);
+                }
+                ConcatPart::Constant(c) => {
+                    recipe.push(CONCAT_CONSTANT_TAG);
+                    arguments.push(c);
+                }
+            }
+        }
+        arguments.insert(0, OrDynamic::Static(Constant::String(recipe.into())));
+        let handle = MethodHandle {
+            kind: MethodHandleKind::InvokeStatic,
+            member: MemberRef {
+                owner: STRING_CONCAT_FACTORY.into(),
+                name: "makeConcatWithConstants".into(),
+                descriptor: Type::method([
+                    Type::reference("java/lang/invoke/MethodHandles$Lookup"),
+                    Type::reference("java/lang/String"),
+                    Type::reference("java/lang/invoke/MethodType"),
+                    Type::reference("java/lang/String"),
+                    Type::array(1, Type::reference("java/lang/Object"))
+                ], Some(Type::reference("java/lang/invoke/CallSite"))),
+                itfs: false
+            }
+        };
+        Dynamic::new(BootstrapMethod { handle, arguments }, "makeConcatWithConstants", Type::method(parameters, Some(Type::reference("java/lang/String"))))
+    }
+
+    /// Returns `true` if the bootstrap method of this dynamic is a method of [`StringConcatFactory`].
+    ///
+    /// [`StringConcatFactory`]: STRING_CONCAT_FACTORY
+    pub fn is_string_concat(&self) -> bool {
+        matches!(self.bsm.get(), Some(b) if b.handle.member.owner == STRING_CONCAT_FACTORY)
+    }
+
+    /// Parses a [`StringConcatFactory`] call site into the sequence of parts that will be concatenated.
+    ///
+    /// Both `makeConcatWithConstants` and `makeConcat` are supported, the latter concatenates every argument.
+    /// Consecutive literal characters of the recipe are merged into a single [`Literal`].
+    ///
+    /// [`StringConcatFactory`]: STRING_CONCAT_FACTORY
+    /// [`Literal`]: ConcatPart::Literal
+    pub fn string_concat_parts(&self) -> Result<Vec<ConcatPart>> {
+        let bsm = self.bsm.get().ok_or_else(|| Error::Invalid("string concat", "bootstrap method is not resolved".into()))?;
+        if bsm.handle.member.owner != STRING_CONCAT_FACTORY {
+            return Err(Error::Invalid("string concat bootstrap method owner", bsm.handle.member.owner.clone()));
+        }
+        let mut parameters = match &self.descriptor {
+            Type::Method { parameters, .. } => parameters.iter().cloned(),
+            t => return Err(Error::Invalid("string concat descriptor", t.to_string().into()))
+        };
+        match bsm.handle.member.name.as_ref() {
+            "makeConcat" => Ok(parameters.map(ConcatPart::Argument).collect()),
+            "makeConcatWithConstants" => {
+                let mut arguments = bsm.arguments.iter().cloned();
+                let recipe = match arguments.next() {
+                    Some(OrDynamic::Static(Constant::String(s))) => s,
+                    _ => return Err(Error::Invalid("string concat recipe", "first bootstrap argument must be a string".into()))
+                };
+                let mut parts = vec![];
+                let mut literal = String::new();
+                for c in recipe.chars() {
+                    let part = match c {
+                        CONCAT_ARGUMENT_TAG => ConcatPart::Argument(parameters.next()
+                            .ok_or_else(|| Error::Invalid("string concat recipe", "more argument tags than parameters".into()))?),
+                        CONCAT_CONSTANT_TAG => ConcatPart::Constant(arguments.next()
+                            .ok_or_else(|| Error::Invalid("string concat recipe", "more constant tags than bootstrap arguments".into()))?),
+                        c => {
+                            literal.push(c);
+                            continue
+                        }
+                    };
+                    if !literal.is_empty() {
+                        parts.push(ConcatPart::Literal(std::mem::take(&mut literal).into()));
+                    }
+                    parts.push(part);
+                }
+                if !literal.is_empty() {
+                    parts.push(ConcatPart::Literal(literal.into()));
+                }
+                if parameters.next().is_some() {
+                    Err(Error::Invalid("string concat recipe", "fewer argument tags than parameters".into()))
+                } else if arguments.next().is_some() {
+                    Err(Error::Invalid("string concat recipe", "fewer constant tags than bootstrap arguments".into()))
+                } else {
+                    Ok(parts)
+                }
+            }
+            n => Err(Error::Invalid("string concat bootstrap method", n.to_owned().into()))
+        }
+    }
+}
+
+/// The internal name of `java.lang.invoke.StringConcatFactory`, the bootstrap class for string concatenation since Java 9.
+pub const STRING_CONCAT_FACTORY: &str = "java/lang/invoke/StringConcatFactory";
+
+/// The tag in a concatenation recipe that is replaced by the next argument of the call site.
+pub const CONCAT_ARGUMENT_TAG: char = '\u{1}';
+
+/// The tag in a concatenation recipe that is replaced by the next constant of the bootstrap method.
+pub const CONCAT_CONSTANT_TAG: char = '\u{2}';
+
+/// A part of a string concatenation, see [`Dynamic::string_concat_parts`].
+#[derive(Debug, Clone, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConcatPart {
+    /// A literal string that is copied into the result as-is.
+    Literal(Cow<'static, str>),
+    /// An argument that is passed to the call site, the type is the parameter type in the descriptor.
+    Argument(Type),
+    /// A constant that is passed as a bootstrap method argument.
+    Constant(OrDynamic<Constant>),
 }
 
 
 #[derive(Debug, Clone, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OrDynamic<T> {
     Dynamic(Dynamic),
     Static(T),
@@ -128,36 +264,28 @@ impl From<MemberRef> for OrDynamic<MemberRef> {
 }
 
 /// A lazily populated bootstrap method.
-#[derive(Debug)]
+///
+/// It is filled at most once, when the bootstrap methods of the class have been read.
+#[derive(Debug, Clone, Default)]
 pub struct LazyBsm {
-    inner: UnsafeCell<Option<BootstrapMethod>>
+    inner: OnceLock<BootstrapMethod>
 }
 
 impl LazyBsm {
     pub const fn new() -> LazyBsm {
-        Self { inner: UnsafeCell::new(None) }
+        Self { inner: OnceLock::new() }
     }
     pub fn get(&self) -> Option<&BootstrapMethod> {
-        unsafe { &*self.inner.get() }.as_ref()
+        self.inner.get()
     }
     pub fn get_mut(&mut self) -> Option<&mut BootstrapMethod> {
-        unsafe { &mut *self.inner.get() }.as_mut()
-    }
-    unsafe fn __get_mut(&self) -> Option<&mut BootstrapMethod> {
-        (&mut *self.inner.get()).as_mut()
+        self.inner.get_mut()
     }
     pub fn into_inner(self) -> Option<BootstrapMethod> {
         self.inner.into_inner()
     }
     pub(crate) fn fill(&self, value: BootstrapMethod) -> Result<(), BootstrapMethod> {
-        let slot = unsafe { &*self.inner.get() };
-        if slot.is_some() {
-            return Err(value);
-        }
-        let slot = unsafe { &mut *self.inner.get() };
-        *slot = Some(value);
-
-        Ok(())
+        self.inner.set(value)
     }
 }
 
@@ -173,10 +301,26 @@ impl PartialEq for LazyBsm {
     }
 }
 
+/// Serialized as the bootstrap method, which must have been filled. The sharing between [`Dynamic`]s is not preserved:
+/// each of them gets its own copy when deserialized.
+#[cfg(feature = "serde")]
+impl serde::Serialize for LazyBsm {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.get().ok_or_else(|| serde::ser::Error::custom("bootstrap method was not read"))?.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for LazyBsm {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        BootstrapMethod::deserialize(deserializer).map(LazyBsm::from)
+    }
+}
+
 impl From<BootstrapMethod> for LazyBsm {
     fn from(b: BootstrapMethod) -> Self {
         Self {
-            inner: UnsafeCell::new(Some(b))
+            inner: OnceLock::from(b)
         }
     }
 }
@@ -18,6 +18,7 @@
 
 use thiserror::Error;
 use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
 
 /// The base error type.
 #[derive(Debug, Error)]
@@ -43,9 +44,109 @@ pub enum ErrorBase {
     #[error("Attribute length mismatch: actual length ({0}) is greater than length consumed ({1}) for variant ${2}")]
     AttributeLength(u32, u32, &'static str),
 
+    /// Error when a structure exceeds a limit of the class file format while writing, such as the size of the constant pool.
+    ///
+    /// The fields are what exceeded the limit, its actual value and the maximum allowed value.
+    #[error("{0} of {1} exceeds the maximum of {2}")]
+    Limit(&'static str, u64, u64),
+
     /// A custom error type.
     #[error(transparent)]
-    Custom(#[from] Box<dyn std::error::Error>)
+    Custom(#[from] Box<dyn std::error::Error + Send + Sync>),
+
+    /// An error along with where it was encountered.
+    #[error("{1} at {0}")]
+    Context(ErrorContext, #[source] Box<ErrorBase>)
+}
+
+impl ErrorBase {
+    /// Returns the context of this error, if it is known where it was encountered.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            ErrorBase::Context(c, _) => Some(c),
+            _ => None
+        }
+    }
+
+    /// Returns the underlying error, without its context.
+    pub fn root(&self) -> &ErrorBase {
+        match self {
+            ErrorBase::Context(_, e) => e.root(),
+            e => e
+        }
+    }
+
+    /// Adds the location of the element that was being read to this error.
+    ///
+    /// `start` is the offset of the element in its parent, and `failed_at` is the offset in the parent at which reading failed.
+    /// When this error already has a context, its offset was relative to the element, and `start` is added to it.
+    pub(crate) fn located(self, segment: Option<PathSegment>, start: u64, failed_at: u64) -> Self {
+        match self {
+            ErrorBase::Context(mut c, e) => {
+                c.offset += start;
+                if let Some(s) = segment {
+                    c.path.insert(0, s);
+                }
+                ErrorBase::Context(c, e)
+            }
+            e => ErrorBase::Context(ErrorContext {
+                offset: failed_at,
+                path: segment.into_iter().collect()
+            }, Box::new(e))
+        }
+    }
+}
+
+/// An element of the path to where an error was encountered.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum PathSegment {
+    /// A class by its name.
+    Class(Cow<'static, str>),
+    /// A field or a method by its name and descriptor.
+    Member(Cow<'static, str>, Cow<'static, str>),
+    /// An attribute by its name.
+    Attribute(Cow<'static, str>),
+    /// An instruction by its offset in the code array.
+    Insn(u32)
+}
+
+/// Where an error was encountered.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct ErrorContext {
+    /// The offset in bytes from the start of the outermost element that was being read, usually the class file.
+    pub offset: u64,
+    /// The path to the element, from the outermost to the innermost.
+    pub path: Vec<PathSegment>
+}
+
+impl Display for ErrorContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "offset 0x{:X}", self.offset)?;
+        if !self.path.is_empty() {
+            write!(f, " in {}", DisplayPath(&self.path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays a path like `Foo.run()V/Code/insn 1`.
+pub(crate) struct DisplayPath<'a>(pub &'a [PathSegment]);
+
+impl Display for DisplayPath<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, s) in self.0.iter().enumerate() {
+            match s {
+                PathSegment::Class(c) => f.write_str(c)?,
+                PathSegment::Member(n, d) if i != 0 => write!(f, ".{}{}", n, d)?,
+                PathSegment::Member(n, d) => write!(f, "{}{}", n, d)?,
+                PathSegment::Attribute(a) if i != 0 => write!(f, "/{}", a)?,
+                PathSegment::Attribute(a) => f.write_str(a)?,
+                PathSegment::Insn(pc) if i != 0 => write!(f, "/insn {}", pc)?,
+                PathSegment::Insn(pc) => write!(f, "insn {}", pc)?
+            }
+        }
+        Ok(())
+    }
 }
 
 /// The backtrace module containing an error type that holds a backtrace.
@@ -75,6 +176,27 @@ pub mod backtrace {
         trace: Backtrace
     }
 
+    impl ErrorTrace {
+        /// Returns the context of this error, if it is known where it was encountered.
+        #[inline]
+        pub fn context(&self) -> Option<&super::ErrorContext> {
+            self.inner.context()
+        }
+
+        /// Returns the underlying error, without its context.
+        #[inline]
+        pub fn root(&self) -> &ErrorBase {
+            self.inner.root()
+        }
+
+        pub(crate) fn located(self, segment: Option<super::PathSegment>, start: u64, failed_at: u64) -> Self {
+            Self {
+                inner: self.inner.located(segment, start, failed_at),
+                trace: self.trace
+            }
+        }
+    }
+
     impl std::fmt::Display for ErrorTrace {
         fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
             write!(f, "{}\nBacktrace:\n{}", self.inner, self.trace)
@@ -138,11 +260,25 @@ pub mod backtrace {
             AttributeLength(act: u32, exp: u32, var: &'static str),
             /// Creates a new instance of [`ErrorTrace`].
             ///
+            /// This is intentionally named the same as the [ErrorBase enum variant] so one can use `Error::Limit` in any context.
+            ///
+            /// [`ErrorTrace`]: ErrorTrace
+            /// [ErrorBase enum variant]: ErrorBase::Limit
+            Limit(what: &'static str, actual: u64, max: u64),
+            /// Creates a new instance of [`ErrorTrace`].
+            ///
             /// This is intentionally named the same as the [ErrorBase enum variant] so one can use `Error::Custom` in any context.
             ///
             /// [`ErrorTrace`]: ErrorTrace
             /// [ErrorBase enum variant]: ErrorBase::Custom
-            Custom(b: Box<dyn std::error::Error>)
+            Custom(b: Box<dyn std::error::Error + Send + Sync>),
+            /// Creates a new instance of [`ErrorTrace`].
+            ///
+            /// This is intentionally named the same as the [ErrorBase enum variant] so one can use `Error::Context` in any context.
+            ///
+            /// [`ErrorTrace`]: ErrorTrace
+            /// [ErrorBase enum variant]: ErrorBase::Context
+            Context(c: super::ErrorContext, e: Box<ErrorBase>)
         );
     }
 }
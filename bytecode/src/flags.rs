@@ -22,6 +22,7 @@ use std::io::{Read, Write};
 
 bitflags! {
     /// Access flags for classes.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
     pub struct ClassFlags: u16 {
         /// This class may be accessed from outside its package.
         const ACC_PUBLIC       = 0b0000_0000_0000_0001;
@@ -48,6 +49,7 @@ bitflags! {
 
 bitflags! {
     /// Access flags for fields.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
     pub struct FieldFlags: u16 {
         /// This field may be accessed from outside its package.
         const ACC_PUBLIC       = 0b0000_0000_0000_0001;
@@ -72,6 +74,7 @@ bitflags! {
 
 bitflags! {
     /// Access flags for methods.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
     pub struct MethodFlags: u16 {
         /// This method may be accessed from outside its package.
         const ACC_PUBLIC       = 0b0000_0000_0000_0001;
@@ -102,6 +105,7 @@ bitflags! {
 
 bitflags! {
     /// Access flags for inner classes.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
     pub struct InnerClassFlags: u16 {
         /// This class may be accessed from outside its package.
         const ACC_PUBLIC       = 0b0000_0000_0000_0001;
@@ -128,6 +132,7 @@ bitflags! {
 
 bitflags! {
     /// Access flags for method parameters.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
     pub struct MethodParameterFlags: u16 {
         /// This method parameter was declared `final`.
         const ACC_FINAL        = 0b0000_0000_0001_0000;
@@ -140,6 +145,7 @@ bitflags! {
 
 bitflags! {
     /// Flags for modules.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
     pub struct ModuleFlags: u16 {
         /// This module is open.
         const ACC_OPEN         = 0b0000_0000_0010_0000;
@@ -152,6 +158,7 @@ bitflags! {
 
 bitflags! {
     /// Flags for module requires.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
     pub struct RequireFlags: u16 {
         /// Any module which depends on the current module, implicitly declares a dependence on the module indicated by this entry.
         const ACC_TRANSITIVE   = 0b0000_0000_0010_0000;
@@ -166,6 +173,7 @@ bitflags! {
 
 bitflags! {
     /// Flags for module exports and opens.
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
     pub struct ExOpFlags: u16 {
         /// This export/opening was not explicitly or implicitly declared.
         const ACC_SYNTHETIC    = 0b0001_0000_0000_0000;
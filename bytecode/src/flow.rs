@@ -0,0 +1,433 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Control flow graphs of method bodies.
+//!
+//! A [`ControlFlowGraph`] splits the instructions of a [`Code`] into basic blocks, in the order they appear. A block starts
+//! at the first instruction, at a run of labels, and after every instruction that branches; it ends before the next one.
+//! Every label of a run belongs to the block it starts, so the ranges of exception handlers always cover whole blocks.
+//! The graph also gives the [dominators] and [dominance frontiers](dominance_frontiers) of its blocks.
+//!
+//! Subroutines are approximated: `jsr` flows both to its target and to the next instruction, as if the subroutine
+//! returned, and `ret` has no successor.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::prelude::*;
+
+/// How control is transferred along an edge.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum EdgeKind {
+    /// Execution continues with the next block.
+    FallThrough,
+    /// A jump, switch or `jsr` instruction branches to the block.
+    Jump,
+    /// An instruction of the block throws, and the handler at this index of [`Code::catches`] catches the exception.
+    Exception(usize)
+}
+
+/// An edge to a successor block.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct Edge {
+    /// The index of the successor block.
+    pub target: usize,
+    pub kind: EdgeKind
+}
+
+/// A basic block.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Block {
+    /// The indices of the instructions of this block in [`Code::code`].
+    pub range: Range<usize>,
+    pub successors: Vec<Edge>,
+    /// The indices of the blocks that have an edge to this one, in order and without duplicates.
+    pub predecessors: Vec<usize>
+}
+
+/// The basic blocks of a method body and the edges between them.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<Block>,
+    labels: HashMap<Label, usize>
+}
+
+/// Whether an instruction is executed, as opposed to labels and line numbers that only mark a position.
+#[inline]
+pub(crate) fn is_executed(insn: &Instruction) -> bool {
+    !matches!(insn, Instruction::Label(_) | Instruction::LineNumber(_))
+}
+
+/// Whether the next instruction cannot be reached from this one without a jump.
+#[inline]
+pub(crate) fn ends_flow(insn: &Instruction) -> bool {
+    matches!(insn, Instruction::Jump(JumpCondition::Always, _) | Instruction::Return(_) | Instruction::Throw |
+        Instruction::Ret(_) | Instruction::TableSwitch { .. } | Instruction::LookupSwitch { .. })
+}
+
+fn branches(insn: &Instruction) -> bool {
+    ends_flow(insn) || matches!(insn, Instruction::Jump(..) | Instruction::Jsr(_))
+}
+
+/// Whether an instruction may throw an exception, other than the virtual machine errors any instruction can throw.
+pub(crate) fn may_throw(insn: &Instruction) -> bool {
+    match insn {
+        Instruction::Push(OrDynamic::Static(c)) => !matches!(c, Constant::I32(_) | Constant::F32(_) | Constant::I64(_) |
+            Constant::F64(_) | Constant::String(_)),
+        Instruction::IntOperation(_, op) => matches!(op, IntOperation::Divide | IntOperation::Remainder),
+        Instruction::Push(OrDynamic::Dynamic(_)) | Instruction::Array(..) | Instruction::ArrayLength | Instruction::Throw |
+        Instruction::CheckCast(_) | Instruction::NewArray(..) | Instruction::Monitor(_) | Instruction::New(_) |
+        Instruction::Field(..) | Instruction::InvokeExact(..) | Instruction::InvokeSpecial(_) | Instruction::InvokeDynamic(_) |
+        Instruction::InvokeInterface(..) | Instruction::InstanceOf(OrDynamic::Dynamic(_)) => true,
+        _ => false
+    }
+}
+
+/// Permutes the operand stack for a dup or swap instruction, with `take` popping the values covering a number of slots
+/// in the order they were pushed. Returns the values to push back, bottom first.
+pub(crate) fn shuffle<T: Clone>(insn: &Instruction, mut take: impl FnMut(u16) -> Result<Vec<T>>) -> Result<Vec<T>> {
+    let (top, below) = match insn {
+        Instruction::Dup => (1, 0),
+        Instruction::DupX1 | Instruction::Swap => (1, 1),
+        Instruction::DupX2 => (1, 2),
+        Instruction::Dup2 => (2, 0),
+        Instruction::Dup2X1 => (2, 1),
+        Instruction::Dup2X2 => (2, 2),
+        _ => return Err(Error::Invalid("instruction", format!("{:?} does not permute the stack", insn).into()))
+    };
+    let top = take(top)?;
+    let below = take(below)?;
+    Ok(if *insn == Instruction::Swap {
+        top.into_iter().chain(below).collect()
+    } else {
+        top.clone().into_iter().chain(below).chain(top).collect()
+    })
+}
+
+/// The number of stack slots or local variables a value of a computational type takes.
+#[inline]
+pub(crate) fn slots(ty: LocalType) -> u16 {
+    if matches!(ty, LocalType::Long | LocalType::Double) { 2 } else { 1 }
+}
+
+/// Pops values covering `size` slots off an operand stack, returning them in the order they were pushed.
+pub(crate) fn take<T>(stack: &mut Vec<T>, size: u16, ty: impl Fn(&T) -> LocalType) -> Result<Vec<T>> {
+    let mut taken = vec![];
+    let mut covered = 0;
+    while covered < size {
+        let value = stack.pop().ok_or(Error::Invalid("stack height", "the operand stack underflows".into()))?;
+        covered += slots(ty(&value));
+        taken.push(value);
+    }
+    if covered != size {
+        return Err(Error::Invalid("stack", "an instruction splits a value of two slots".into()))
+    }
+    taken.reverse();
+    Ok(taken)
+}
+
+impl ControlFlowGraph {
+    /// Builds the graph of a method body.
+    ///
+    /// This fails if an instruction or exception handler refers to a label that is not placed in the code.
+    #[inline]
+    pub fn new(code: &Code) -> Result<Self> {
+        Self::build(code, false)
+    }
+
+    /// Builds the graph of a method body where, in addition, every instruction that may throw within the range of an
+    /// exception handler starts a block.
+    ///
+    /// The local variables a handler is entered with are then those at the start of one of its predecessors, which is
+    /// what analyses that track local variables across exception edges need.
+    #[inline]
+    pub fn precise(code: &Code) -> Result<Self> {
+        Self::build(code, true)
+    }
+
+    fn build(code: &Code, precise: bool) -> Result<Self> {
+        let mut protected = vec![false; code.code.len()];
+        if precise {
+            let positions = code.code.iter().enumerate().filter_map(|(i, insn)| match insn {
+                Instruction::Label(l) => Some((*l, i)),
+                _ => None
+            }).collect::<HashMap<_, _>>();
+            let position = |l: &Label| positions.get(l).copied().ok_or_else(|| Error::Invalid("label", format!("{:?} is not placed in the code", l).into()));
+            for c in &code.catches {
+                let (start, end) = (position(&c.start)?, position(&c.end)?);
+                protected.iter_mut().take(end).skip(start).for_each(|p| *p = true);
+            }
+        }
+        let mut blocks = vec![];
+        let mut labels = HashMap::new();
+        let mut start = 0;
+        for (i, insn) in code.code.iter().enumerate() {
+            let leader = match insn {
+                Instruction::Label(_) => !matches!(code.code.get(i.wrapping_sub(1)), Some(Instruction::Label(_))),
+                _ => i > 0 && (branches(&code.code[i - 1]) || protected[i] && may_throw(insn) &&
+                    code.code[start..i].iter().any(is_executed))
+            };
+            if leader && i > start {
+                blocks.push(Block { range: start..i, successors: vec![], predecessors: vec![] });
+                start = i;
+            }
+            if let Instruction::Label(l) = insn {
+                labels.insert(*l, blocks.len());
+            }
+        }
+        if start < code.code.len() || blocks.is_empty() {
+            blocks.push(Block { range: start..code.code.len(), successors: vec![], predecessors: vec![] });
+        }
+        let mut graph = ControlFlowGraph { blocks, labels };
+        for b in 0..graph.blocks.len() {
+            let mut successors = vec![];
+            let last = code.code[graph.blocks[b].range.clone()].iter().rev().find(|i| is_executed(i));
+            let mut jump = |l: &Label| -> Result<()> {
+                successors.push(Edge { target: graph.target(*l)?, kind: EdgeKind::Jump });
+                Ok(())
+            };
+            match last {
+                Some(Instruction::Jump(_, l)) | Some(Instruction::Jsr(l)) => jump(l)?,
+                Some(Instruction::TableSwitch { default, offsets, .. }) => {
+                    jump(default)?;
+                    offsets.iter().try_for_each(&mut jump)?;
+                }
+                Some(Instruction::LookupSwitch { default, table }) => {
+                    jump(default)?;
+                    table.values().try_for_each(&mut jump)?;
+                }
+                _ => {}
+            }
+            if !matches!(last, Some(i) if ends_flow(i)) && b + 1 < graph.blocks.len() {
+                successors.push(Edge { target: b + 1, kind: EdgeKind::FallThrough });
+            }
+            if last.is_some() {
+                for (i, c) in code.catches.iter().enumerate() {
+                    if (graph.target(c.start)?..graph.target(c.end)?).contains(&b) {
+                        successors.push(Edge { target: graph.target(c.handler)?, kind: EdgeKind::Exception(i) });
+                    }
+                }
+            }
+            let mut seen = std::collections::HashSet::new();
+            successors.retain(|e| seen.insert(*e));
+            graph.blocks[b].successors = successors;
+        }
+        for b in 0..graph.blocks.len() {
+            for e in graph.blocks[b].successors.clone() {
+                let preds = &mut graph.blocks[e.target].predecessors;
+                if preds.last() != Some(&b) {
+                    preds.push(b);
+                }
+            }
+        }
+        Ok(graph)
+    }
+
+    fn target(&self, label: Label) -> Result<usize> {
+        self.block_of(label).ok_or_else(|| Error::Invalid("label", format!("{:?} is not placed in the code", label).into()))
+    }
+
+    /// The index of the block that starts with this label.
+    #[inline]
+    pub fn block_of(&self, label: Label) -> Option<usize> {
+        self.labels.get(&label).copied()
+    }
+
+    /// Whether each block can be reached from the first one, following all kinds of edges.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reached = vec![false; self.blocks.len()];
+        let mut stack = vec![0];
+        while let Some(b) = stack.pop() {
+            if reached[b] {
+                continue
+            }
+            reached[b] = true;
+            stack.extend(self.blocks[b].successors.iter().map(|e| e.target).filter(|t| !reached[*t]));
+        }
+        reached
+    }
+
+    /// The immediate dominator of each block, following all kinds of edges. See [`dominators`].
+    pub fn dominators(&self) -> Vec<Option<usize>> {
+        dominators(&self.successors())
+    }
+
+    /// The dominance frontier of each block, following all kinds of edges. See [`dominance_frontiers`].
+    pub fn dominance_frontiers(&self) -> Vec<Vec<usize>> {
+        let successors = self.successors();
+        dominance_frontiers(&successors, &dominators(&successors))
+    }
+
+    fn successors(&self) -> Vec<Vec<usize>> {
+        self.blocks.iter().map(|b| b.successors.iter().map(|e| e.target).collect()).collect()
+    }
+}
+
+/// The order in which a depth-first search from the first node finishes the nodes it reaches, reversed.
+fn reverse_postorder(successors: &[Vec<usize>]) -> Vec<usize> {
+    let mut order = vec![];
+    let mut visited = vec![false; successors.len()];
+    let mut stack = vec![(0, 0)];
+    visited[0] = true;
+    while let Some((node, next)) = stack.last_mut() {
+        match successors[*node].get(*next) {
+            Some(&s) => {
+                *next += 1;
+                if !visited[s] {
+                    visited[s] = true;
+                    stack.push((s, 0));
+                }
+            }
+            None => {
+                order.push(*node);
+                stack.pop();
+            }
+        }
+    }
+    order.reverse();
+    order
+}
+
+/// The immediate dominator of each node of a graph given by the successors of its nodes, where the first node is the
+/// entry.
+///
+/// The entry and the nodes it does not reach have no immediate dominator.
+pub fn dominators(successors: &[Vec<usize>]) -> Vec<Option<usize>> {
+    let mut idom: Vec<Option<usize>> = vec![None; successors.len()];
+    if successors.is_empty() {
+        return idom
+    }
+    let order = reverse_postorder(successors);
+    let mut rank = vec![usize::MAX; successors.len()];
+    for (i, n) in order.iter().enumerate() {
+        rank[*n] = i;
+    }
+    let mut predecessors = vec![vec![]; successors.len()];
+    for (n, s) in successors.iter().enumerate() {
+        for t in s {
+            predecessors[*t].push(n);
+        }
+    }
+    idom[0] = Some(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &n in order.iter().skip(1) {
+            let mut new: Option<usize> = None;
+            for &p in predecessors[n].iter().filter(|p| idom[**p].is_some()) {
+                new = Some(match new {
+                    None => p,
+                    Some(mut a) => {
+                        let mut b = p;
+                        while a != b {
+                            while rank[a] > rank[b] {
+                                a = idom[a].unwrap();
+                            }
+                            while rank[b] > rank[a] {
+                                b = idom[b].unwrap();
+                            }
+                        }
+                        a
+                    }
+                });
+            }
+            if new != idom[n] {
+                idom[n] = new;
+                changed = true;
+            }
+        }
+    }
+    idom[0] = None;
+    idom
+}
+
+/// The dominance frontier of each node of a graph given by the successors of its nodes and the immediate dominators
+/// returned by [`dominators`]: the nodes that have a predecessor it dominates, but that it does not strictly dominate.
+pub fn dominance_frontiers(successors: &[Vec<usize>], idom: &[Option<usize>]) -> Vec<Vec<usize>> {
+    let mut frontiers = vec![vec![]; successors.len()];
+    let reached = |n: usize| n == 0 || idom[n].is_some();
+    for (p, s) in successors.iter().enumerate().filter(|(p, _)| reached(*p)) {
+        for &n in s {
+            let mut runner = Some(p);
+            while let Some(r) = runner {
+                if Some(r) == idom[n] {
+                    break
+                }
+                if !frontiers[r].contains(&n) {
+                    frontiers[r].push(n);
+                }
+                runner = idom[r];
+            }
+        }
+    }
+    frontiers
+}
+
+/// The height of the operand stack before each instruction, in slots, or `None` for instructions that are not reached.
+///
+/// Exception handlers are assumed to be reached with the exception on the stack. This fails if the code refers to a label
+/// that is not placed, if the stack effect of an instruction is unknown, if the stack underflows, or if an instruction
+/// is reached with two different heights.
+pub fn stack_heights(code: &Code) -> Result<Vec<Option<u16>>> {
+    let positions = code.code.iter().enumerate().filter_map(|(i, insn)| match insn {
+        Instruction::Label(l) => Some((*l, i)),
+        _ => None
+    }).collect::<HashMap<_, _>>();
+    let position = |l: &Label| positions.get(l).copied().ok_or_else(|| Error::Invalid("label", format!("{:?} is not placed in the code", l).into()));
+    let mut heights = vec![None; code.code.len()];
+    let mut work = vec![(0, 0u16)];
+    for c in &code.catches {
+        work.push((position(&c.handler)?, 1));
+    }
+    while let Some((i, height)) = work.pop() {
+        let insn = match code.code.get(i) {
+            Some(insn) => insn,
+            None => continue
+        };
+        match heights[i] {
+            Some(h) if h == height => continue,
+            Some(h) => return Err(Error::Invalid("stack height", format!("instruction {} is reached with {} and {} slots", i, h, height).into())),
+            None => heights[i] = Some(height)
+        }
+        let (pop, push) = insn.stack_effect().ok_or_else(|| Error::Invalid("instruction", format!("stack effect of {:?} is unknown", insn).into()))?;
+        let next = height.checked_sub(pop).ok_or_else(|| Error::Invalid("stack height", format!("instruction {} underflows the stack", i).into()))? + push;
+        match insn {
+            Instruction::Jump(cond, l) => {
+                work.push((position(l)?, next));
+                if *cond != JumpCondition::Always {
+                    work.push((i + 1, next));
+                }
+            }
+            Instruction::Jsr(l) => {
+                work.push((position(l)?, next));
+                work.push((i + 1, height));
+            }
+            Instruction::TableSwitch { default, offsets, .. } => {
+                for l in offsets.iter().chain(std::iter::once(default)) {
+                    work.push((position(l)?, next));
+                }
+            }
+            Instruction::LookupSwitch { default, table } => {
+                for l in table.values().chain(std::iter::once(default)) {
+                    work.push((position(l)?, next));
+                }
+            }
+            Instruction::Return(_) | Instruction::Throw | Instruction::Ret(_) => {}
+            _ => work.push((i + 1, next))
+        }
+    }
+    Ok(heights)
+}
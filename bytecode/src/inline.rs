@@ -0,0 +1,449 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Inlining of calls to small static and private methods.
+//!
+//! [`inline_call`] replaces a call instruction by a copy of the body of the method it calls. The arguments are stored in
+//! local variables above those of the caller, returns become jumps to the end of the copy, and the exception handlers and
+//! local variables of the callee are merged into the caller. When the callee is `synchronized`, the copy holds the monitor
+//! of the receiver or of the class as the call would have, and releases it on every exit. The line numbers of the callee
+//! refer to another source file, so they are dropped and the copy belongs to the line of the call.
+//!
+//! A call to a static method initializes its class, which the copy does not. The [`Inliner`] only inlines the static
+//! methods of the class of the caller, unless calls to [other classes](Inliner::other_classes) are enabled.
+//!
+//! The [`Inliner`] applies it to every call to the methods of the classes added to it. Inlined bodies must only refer to
+//! classes and members that are accessible from the caller, which is always the case within a class, and since Java 11
+//! within a nest. Elsewhere the inliner checks the access of every class and member the body refers to, and skips the
+//! call when one of them is not known to be accessible.
+
+use std::collections::HashMap;
+
+use crate::flow::{is_executed, stack_heights};
+use crate::instrument::local_type;
+use crate::prelude::*;
+use crate::rw::checked_len;
+use crate::Class;
+
+/// The default maximum number of instructions of inlined methods.
+pub const DEFAULT_MAX_SIZE: usize = 32;
+
+/// Inlines calls to the static and private methods of a set of classes.
+#[derive(Clone, Debug)]
+pub struct Inliner {
+    methods: HashMap<(Cow<'static, str>, Cow<'static, str>, Type), Method>,
+    classes: HashMap<Cow<'static, str>, Host>,
+    members: HashMap<(Cow<'static, str>, Cow<'static, str>, Type), Visibility>,
+    max_size: usize,
+    other_classes: bool
+}
+
+/// From where a class or member can be accessed. Protected members are treated as package private.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Visibility {
+    Public,
+    Package,
+    Private
+}
+
+impl Visibility {
+    fn of(public: bool, private: bool) -> Self {
+        if public {
+            Visibility::Public
+        } else if private {
+            Visibility::Private
+        } else {
+            Visibility::Package
+        }
+    }
+}
+
+/// The access of a class, and the nest it belongs to.
+#[derive(Clone, Debug)]
+struct Host {
+    visibility: Visibility,
+    nest: Cow<'static, str>,
+    /// Whether the class version has nest-based access, which came with Java 11.
+    nestmates: bool
+}
+
+impl Host {
+    fn of(class: &Class) -> Self {
+        Host {
+            visibility: Visibility::of(class.access.contains(ClassFlags::ACC_PUBLIC), false),
+            nest: class.attributes.iter().find_map(|a| match a {
+                ClassAttribute::NestHost(host) => Some(host.clone()),
+                _ => None
+            }).unwrap_or_else(|| class.name.clone()),
+            nestmates: class.version.major as u16 >= MajorVersion::J11 as u16
+        }
+    }
+}
+
+fn package(class: &str) -> &str {
+    class.rfind('/').map_or("", |i| &class[..i])
+}
+
+impl Default for Inliner {
+    fn default() -> Self {
+        Inliner::new()
+    }
+}
+
+impl Inliner {
+    /// Creates a new inliner for methods of at most [`DEFAULT_MAX_SIZE`] instructions.
+    pub fn new() -> Self {
+        Inliner { methods: HashMap::new(), classes: HashMap::new(), members: HashMap::new(), max_size: DEFAULT_MAX_SIZE, other_classes: false }
+    }
+
+    /// Sets the maximum number of instructions of inlined methods, not counting labels and line numbers.
+    ///
+    /// This only applies to the classes added afterwards.
+    pub fn max_size(mut self, size: usize) -> Self {
+        self.max_size = size;
+        self
+    }
+
+    /// Whether calls to the static methods of other classes than the caller are inlined, which is disabled by default.
+    ///
+    /// Such a call initializes the class of the callee if it was not yet, and the inlined body does not. Only enable this
+    /// when the static initializers of these classes and their superclasses do nothing the caller depends on.
+    pub fn other_classes(mut self, enabled: bool) -> Self {
+        self.other_classes = enabled;
+        self
+    }
+
+    /// Makes the static and private methods of the class that are small enough candidates for inlining.
+    ///
+    /// This also records the access of the class and its members, see [`add_library`](Self::add_library). This fails
+    /// when a lazily decoded method body cannot be decoded.
+    pub fn add_class(&mut self, class: &Class) -> Result<()> {
+        self.add_library(class);
+        for m in &class.methods {
+            if m.name.starts_with('<') || !m.access.intersects(MethodFlags::ACC_STATIC | MethodFlags::ACC_PRIVATE) {
+                continue
+            }
+            let code = match m.code()? {
+                Some(c) => c,
+                None => continue
+            };
+            if code.code.iter().filter(|i| is_executed(i)).count() <= self.max_size {
+                let mut m = m.clone();
+                m.attributes.retain(|a| matches!(a, MethodAttribute::Code(_) | MethodAttribute::LazyCode(_)));
+                self.methods.insert((class.name.clone(), m.name.clone(), m.descriptor.clone()), m);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the access of the class and its members, without making its methods candidates.
+    ///
+    /// Inlined bodies may only refer to classes and members outside of the caller when their access is known, so the
+    /// classes they use, such as those of the libraries, must be added.
+    pub fn add_library(&mut self, class: &Class) {
+        self.classes.insert(class.name.clone(), Host::of(class));
+        for f in &class.fields {
+            let visibility = Visibility::of(f.access.contains(FieldFlags::ACC_PUBLIC), f.access.contains(FieldFlags::ACC_PRIVATE));
+            self.members.insert((class.name.clone(), f.name.clone(), f.descriptor.clone()), visibility);
+        }
+        for m in &class.methods {
+            let visibility = Visibility::of(m.access.contains(MethodFlags::ACC_PUBLIC), m.access.contains(MethodFlags::ACC_PRIVATE));
+            self.members.insert((class.name.clone(), m.name.clone(), m.descriptor.clone()), visibility);
+        }
+    }
+
+    /// Inlines every call of the method body of the caller class to a candidate, and returns the number of inlined calls.
+    ///
+    /// Calls in the inlined bodies are not inlined in turn. Calls are skipped when the body of the callee refers to classes
+    /// or members that are not known to be accessible from the caller, and when the callee has exception handlers and the
+    /// caller has values on the stack below the arguments, since entering a handler would discard them. This fails if a
+    /// callee cannot be inlined otherwise, see [`inline_call`].
+    ///
+    /// The caller is only known to be in the nest of the callee when its class was added.
+    pub fn inline(&self, caller: &str, code: &mut Code) -> Result<usize> {
+        self.inline_from(caller, self.classes.get(caller), code)
+    }
+
+    fn inline_from(&self, caller: &str, host: Option<&Host>, code: &mut Code) -> Result<usize> {
+        let heights = stack_heights(code)?;
+        let mut sites = vec![];
+        for (i, insn) in code.code.iter().enumerate() {
+            let (owner, callee) = match insn {
+                Instruction::InvokeExact(MemberType::Static, OrDynamic::Static(m)) if m.owner != caller && !self.other_classes => continue,
+                Instruction::InvokeExact(MemberType::Static, OrDynamic::Static(m)) | Instruction::InvokeSpecial(OrDynamic::Static(m)) => {
+                    match self.methods.get(&(m.owner.clone(), m.name.clone(), m.descriptor.clone())) {
+                        Some(callee) => (&m.owner, callee),
+                        None => continue
+                    }
+                }
+                _ => continue
+            };
+            if let Some(body) = callee.code()? {
+                if !clears_stack(heights[i], callee, body) && self.accessible(caller, host, owner, body) {
+                    sites.push((i, callee));
+                }
+            }
+        }
+        // inlining from the end keeps the indices of the other calls valid.
+        for (i, callee) in sites.iter().rev() {
+            inline_call(code, *i, callee)?;
+        }
+        Ok(sites.len())
+    }
+
+    /// Inlines calls in every method body of the class, and returns the number of inlined calls.
+    pub fn inline_class(&self, class: &mut Class) -> Result<usize> {
+        let host = Host::of(class);
+        let mut count = 0;
+        for m in &mut class.methods {
+            if let Some(code) = m.code_mut()? {
+                count += self.inline_from(&class.name, Some(&host), code)?;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Returns `true` if every class and member the body refers to is accessible from the caller.
+    ///
+    /// Bootstrap methods and their arguments are not checked, so dynamic constants and call sites are never accessible.
+    fn accessible(&self, caller: &str, host: Option<&Host>, owner: &str, body: &Code) -> bool {
+        if caller == owner {
+            return true
+        }
+        if let (Some(a), Some(b)) = (host, self.classes.get(owner)) {
+            if a.nestmates && b.nestmates && a.nest == b.nest {
+                return true
+            }
+        }
+        let class = |name: &str| {
+            // array classes are accessible when their element class is.
+            let name = if name.starts_with('[') {
+                match name.trim_start_matches('[').strip_prefix('L').and_then(|n| n.strip_suffix(';')) {
+                    Some(element) => element,
+                    None => return true
+                }
+            } else {
+                name
+            };
+            name == caller || self.classes.get(name).is_some_and(|h| h.visibility == Visibility::Public || package(name) == package(caller))
+        };
+        let ty = |t: &Type| match t {
+            Type::Ref(name) => class(name),
+            Type::ArrayRef(_, t) => match &**t {
+                Type::Ref(name) => class(name),
+                _ => true
+            },
+            _ => true
+        };
+        let member = |m: &MemberRef| class(&m.owner) && (m.owner == caller || {
+            match self.members.get(&(m.owner.clone(), m.name.clone(), m.descriptor.clone())) {
+                Some(Visibility::Public) => true,
+                Some(Visibility::Package) => package(&m.owner) == package(caller),
+                _ => false
+            }
+        });
+        body.catches.iter().all(|c| c.catch.as_deref().is_none_or(class)) && body.code.iter().all(|insn| match insn {
+            Instruction::Push(OrDynamic::Static(c)) => match c {
+                Constant::Class(name) => class(name),
+                Constant::Member(m) => member(m),
+                Constant::MethodHandle(h) => member(&h.member),
+                _ => true
+            },
+            Instruction::CheckCast(OrDynamic::Static(t)) | Instruction::InstanceOf(OrDynamic::Static(t)) => match t {
+                ClassType::Object(name) => class(name),
+                ClassType::Array(_, t) => ty(t)
+            },
+            Instruction::NewArray(OrDynamic::Static(t), _) => ty(t),
+            Instruction::New(OrDynamic::Static(name)) => class(name),
+            Instruction::Field(_, _, OrDynamic::Static(m)) | Instruction::InvokeExact(_, OrDynamic::Static(m))
+            | Instruction::InvokeSpecial(OrDynamic::Static(m)) | Instruction::InvokeInterface(OrDynamic::Static(m), _) => member(m),
+            Instruction::Push(OrDynamic::Dynamic(_)) | Instruction::CheckCast(OrDynamic::Dynamic(_)) | Instruction::InstanceOf(OrDynamic::Dynamic(_))
+            | Instruction::NewArray(OrDynamic::Dynamic(_), _) | Instruction::New(OrDynamic::Dynamic(_)) | Instruction::Field(_, _, OrDynamic::Dynamic(_))
+            | Instruction::InvokeExact(_, OrDynamic::Dynamic(_)) | Instruction::InvokeSpecial(OrDynamic::Dynamic(_))
+            | Instruction::InvokeInterface(OrDynamic::Dynamic(_), _) | Instruction::InvokeDynamic(_) => false,
+            _ => true
+        })
+    }
+}
+
+/// Returns `true` if the body has exception handlers and the caller has more values on the stack than the arguments.
+fn clears_stack(height: Option<u16>, callee: &Method, body: &Code) -> bool {
+    let arguments = match &callee.descriptor {
+        Type::Method { parameters, .. } => parameters.iter().map(|p| if p.is_wide() { 2 } else { 1 }).sum::<u16>(),
+        _ => 0
+    } + !callee.access.contains(MethodFlags::ACC_STATIC) as u16;
+    !body.catches.is_empty() && matches!(height, Some(h) if h > arguments)
+}
+
+fn invalid<T, S: Into<Cow<'static, str>>>(message: S) -> Result<T> {
+    Err(Error::Invalid("inlined call", message.into()))
+}
+
+/// Replaces the call at this index of the caller by the body of the callee.
+///
+/// The instruction must be an `invokestatic` of a static method, or an `invokespecial` of an instance method other than a
+/// constructor, whose descriptor is that of the callee. This fails if the callee has no code, or if it returns with other
+/// values than the returned one on the stack, since they would be left on the stack of the caller. It also fails if the
+/// callee has exception handlers and the caller has other values on the stack than the arguments, since a handler starts
+/// with an empty stack, or if the caller would need more than 65535 local variables or stack slots.
+///
+/// Inlining a static method of another class than the caller leaves its class uninitialized where the call would have
+/// initialized it, see [`Inliner::other_classes`].
+pub fn inline_call(caller: &mut Code, index: usize, callee: &Method) -> Result<()> {
+    let (member, receiver) = match caller.code.get(index) {
+        Some(Instruction::InvokeExact(MemberType::Static, OrDynamic::Static(m))) => (m.clone(), false),
+        Some(Instruction::InvokeSpecial(OrDynamic::Static(m))) if m.name != "<init>" => (m.clone(), true),
+        insn => return invalid(format!("{:?} is not a call that can be inlined", insn))
+    };
+    if member.descriptor != callee.descriptor || receiver == callee.access.contains(MethodFlags::ACC_STATIC) {
+        return invalid(format!("{}.{}{} is not the called method", member.owner, callee.name, callee.descriptor))
+    }
+    let parameters = match &callee.descriptor {
+        Type::Method { parameters, .. } => parameters,
+        _ => return invalid("the callee has no method descriptor")
+    };
+    let body = match callee.code()? {
+        Some(c) => c,
+        None => return invalid(format!("{}{} has no code", callee.name, callee.descriptor))
+    };
+    if clears_stack(stack_heights(caller)?[index], callee, body) {
+        return invalid(format!("{}{} has exception handlers and the stack of the caller is not empty", callee.name, callee.descriptor))
+    }
+    let heights = stack_heights(body)?;
+    for (insn, height) in body.code.iter().zip(&heights) {
+        if let (Instruction::Return(ty), Some(height)) = (insn, height) {
+            if *height != ty.map_or(0, |t| if let LocalType::Long | LocalType::Double = t { 2 } else { 1 }) {
+                return invalid(format!("{}{} returns with values left on the stack", callee.name, callee.descriptor))
+            }
+        }
+    }
+
+    let base = caller.max_locals;
+    let arguments = receiver as u16 + parameters.iter().map(|p| if p.is_wide() { 2 } else { 1 }).sum::<u16>();
+    let locals = body.max_locals.max(arguments);
+    let synchronized = callee.access.contains(MethodFlags::ACC_SYNCHRONIZED);
+    let max_locals = checked_len::<u16>("local variables", base as usize + locals as usize + synchronized as usize)?;
+    let max_stack = checked_len::<u16>("stack size", caller.max_stack as usize + body.max_stack.max(if synchronized { 2 } else { 0 }) as usize)?;
+    let mut next_label = 0;
+    caller.visit_labels_mut(|l| next_label = next_label.max(l.0 + 1));
+    let mut new_label = || {
+        next_label += 1;
+        Label(next_label - 1)
+    };
+    let (end, start, range_end, handler) = (new_label(), new_label(), new_label(), new_label());
+    let mut labels = HashMap::new();
+    let mut map = |l: &mut Label| *l = *labels.entry(*l).or_insert_with(&mut new_label);
+
+    let mut out = vec![];
+    let mut slot = base + arguments;
+    for p in parameters.iter().rev() {
+        slot -= if p.is_wide() { 2 } else { 1 };
+        out.push(Instruction::LocalVariable(LoadOrStore::Store, local_type(p), slot));
+    }
+    if receiver {
+        out.push(Instruction::LocalVariable(LoadOrStore::Store, LocalType::Reference, base));
+        // the call would have thrown a `NullPointerException` on a null receiver.
+        out.push(Instruction::LocalVariable(LoadOrStore::Load, LocalType::Reference, base));
+        out.push(Instruction::InvokeExact(MemberType::Virtual, OrDynamic::Static(MemberRef {
+            owner: "java/lang/Object".into(),
+            name: "getClass".into(),
+            descriptor: Type::method([], Some(Type::reference("java/lang/Class"))),
+            itfs: false
+        })));
+        out.push(Instruction::Pop1);
+    }
+    let lock = if synchronized {
+        out.push(if receiver {
+            Instruction::LocalVariable(LoadOrStore::Load, LocalType::Reference, base)
+        } else {
+            Instruction::Push(OrDynamic::Static(Constant::Class(member.owner.clone())))
+        });
+        out.push(Instruction::Dup);
+        out.push(Instruction::LocalVariable(LoadOrStore::Store, LocalType::Reference, base + locals));
+        out.push(Instruction::Monitor(MonitorOperation::Enter));
+        out.push(Instruction::Label(start));
+        Some(base + locals)
+    } else {
+        None
+    };
+
+    let last = body.code.iter().rposition(is_executed);
+    for (i, insn) in body.code.iter().enumerate() {
+        let mut insn = insn.clone();
+        insn.visit_labels_mut(&mut map);
+        match &mut insn {
+            Instruction::LocalVariable(_, _, idx) | Instruction::IntIncrement(idx, _) | Instruction::Ret(idx) => {
+                *idx = checked_len("local variables", *idx as usize + base as usize)?
+            }
+            Instruction::LineNumber(_) => continue,
+            Instruction::Return(_) => {
+                if let Some(local) = lock {
+                    out.push(Instruction::LocalVariable(LoadOrStore::Load, LocalType::Reference, local));
+                    out.push(Instruction::Monitor(MonitorOperation::Exit));
+                }
+                // the last return falls through to the end, unless the handler releasing the monitor is in between.
+                if Some(i) != last || lock.is_some() {
+                    out.push(Instruction::Jump(JumpCondition::Always, end));
+                }
+                continue
+            }
+            _ => {}
+        }
+        out.push(insn);
+    }
+
+    let mut catches = body.catches.iter().cloned().map(|mut c| {
+        c.visit_labels_mut(&mut map);
+        c
+    }).collect::<Vec<_>>();
+    let mut variables = vec![];
+    for a in &body.attrs {
+        if let CodeAttribute::LocalVariables(vars) = a {
+            for v in vars {
+                let mut v = v.clone();
+                map(&mut v.start);
+                map(&mut v.end);
+                v.index = checked_len("local variables", v.index as usize + base as usize)?;
+                variables.push(v);
+            }
+        }
+    }
+    if let Some(local) = lock {
+        out.push(Instruction::Label(range_end));
+        out.push(Instruction::Label(handler));
+        out.push(Instruction::LocalVariable(LoadOrStore::Load, LocalType::Reference, local));
+        out.push(Instruction::Monitor(MonitorOperation::Exit));
+        out.push(Instruction::Throw);
+        catches.push(Catch { start, end: range_end, handler, catch: None });
+    }
+    out.push(Instruction::Label(end));
+
+    caller.code.splice(index..=index, out);
+    // the handlers of the callee come first, since they are nested in those of the caller.
+    caller.catches.splice(0..0, catches);
+    if !variables.is_empty() {
+        match caller.attrs.iter_mut().find_map(|a| match a {
+            CodeAttribute::LocalVariables(v) => Some(v),
+            _ => None
+        }) {
+            Some(vars) => vars.extend(variables),
+            None => caller.attrs.push(CodeAttribute::LocalVariables(variables))
+        }
+    }
+    caller.max_locals = max_locals;
+    caller.max_stack = max_stack;
+    Ok(())
+}
@@ -0,0 +1,299 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Injection of code at the entry and exits of methods, as profilers and tracers do.
+//!
+//! [`instrument`] calls a closure with a [`Context`] describing the method, which builds the [`Probes`] to inject:
+//!
+//! - the entry probe runs before the original code, or right after the `super` or `this` constructor call in constructors,
+//!   since the object cannot be used before;
+//! - the exit probe runs before every return instruction, with the returned value on top of the stack;
+//! - the exceptional exit probe runs in a synthetic handler catching every exception thrown after the entry probe and not
+//!   caught by the method, including those of `athrow` instructions, with the exception on top of the stack. It is thrown
+//!   again afterwards. The copies of the exit probe and the returns after them are not covered, so an exception thrown by
+//!   the exit probe does not run the exceptional exit probe as well.
+//!
+//! Probes must leave the operand stack as they found it. The maximum stack size and number of local variables of the
+//! method are updated for them, assuming that probes do not branch backwards.
+
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+/// The code to inject in a method.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Probes {
+    /// Injected at the entry of the method.
+    pub entry: Vec<Instruction>,
+    /// Injected before every return instruction.
+    pub exit: Vec<Instruction>,
+    /// Run when an exception leaves the method. No handler is added when it is empty.
+    pub exceptional_exit: Vec<Instruction>
+}
+
+/// The method being instrumented, for building probes.
+#[derive(Clone, Debug)]
+pub struct Context {
+    parameters: Vec<Type>,
+    ret: Option<Type>,
+    is_static: bool,
+    is_constructor: bool,
+    next_local: u16,
+    next_label: u32
+}
+
+pub(crate) fn local_type(ty: &Type) -> LocalType {
+    match ty {
+        Type::Long => LocalType::Long,
+        Type::Float => LocalType::Float,
+        Type::Double => LocalType::Double,
+        Type::Ref(_) | Type::ArrayRef(..) | Type::Method { .. } => LocalType::Reference,
+        _ => LocalType::Int
+    }
+}
+
+#[inline]
+fn slots(ty: &Type) -> u16 {
+    if ty.is_wide() { 2 } else { 1 }
+}
+
+impl Context {
+    /// The types of the parameters of the method, without the receiver.
+    #[inline]
+    pub fn parameters(&self) -> &[Type] {
+        &self.parameters
+    }
+
+    /// The return type of the method, or `None` if it returns `void`.
+    #[inline]
+    pub fn return_type(&self) -> Option<&Type> {
+        self.ret.as_ref()
+    }
+
+    #[inline]
+    pub fn is_static(&self) -> bool {
+        self.is_static
+    }
+
+    /// Whether the method is an instance initializer, in which case the entry probe runs after the `super` call.
+    #[inline]
+    pub fn is_constructor(&self) -> bool {
+        self.is_constructor
+    }
+
+    /// The local variable holding the parameter at this index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the method has no such parameter.
+    pub fn parameter_slot(&self, index: usize) -> u16 {
+        let receiver = !self.is_static as u16;
+        receiver + self.parameters[..index].iter().map(slots).sum::<u16>()
+    }
+
+    /// Loads the parameter at this index.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the method has no such parameter.
+    pub fn load_parameter(&self, index: usize) -> Instruction {
+        Instruction::LocalVariable(LoadOrStore::Load, local_type(&self.parameters[index]), self.parameter_slot(index))
+    }
+
+    /// Loads `this`, unless the method is static.
+    pub fn load_this(&self) -> Option<Instruction> {
+        if self.is_static {
+            None
+        } else {
+            Some(Instruction::LocalVariable(LoadOrStore::Load, LocalType::Reference, 0))
+        }
+    }
+
+    /// Loads every parameter in order.
+    pub fn load_parameters(&self) -> Vec<Instruction> {
+        (0..self.parameters.len()).map(|i| self.load_parameter(i)).collect()
+    }
+
+    /// Pushes an `Object[]` holding every parameter, with primitives boxed by `valueOf`.
+    pub fn box_parameters(&self) -> Vec<Instruction> {
+        let mut code = vec![
+            Instruction::Push(OrDynamic::Static(Constant::I32(self.parameters.len() as i32))),
+            Instruction::NewArray(OrDynamic::Static(Type::reference("java/lang/Object")), 1)
+        ];
+        for (i, p) in self.parameters.iter().enumerate() {
+            code.push(Instruction::Dup);
+            code.push(Instruction::Push(OrDynamic::Static(Constant::I32(i as i32))));
+            code.push(self.load_parameter(i));
+            code.extend(box_value(p));
+            code.push(Instruction::Array(LoadOrStore::Store, ArrayType::Reference));
+        }
+        code
+    }
+
+    /// Reserves a new local variable for a value of this type, for instance to pass a timestamp from the entry probe to
+    /// the exit probes.
+    pub fn new_local(&mut self, ty: &Type) -> u16 {
+        let index = self.next_local;
+        self.next_local += slots(ty);
+        index
+    }
+
+    /// Creates a label that is not used by the method yet.
+    pub fn new_label(&mut self) -> Label {
+        self.next_label += 1;
+        Label(self.next_label - 1)
+    }
+}
+
+/// Converts the primitive value on top of the stack to its wrapper class, and does nothing for references.
+pub fn box_value(ty: &Type) -> Option<Instruction> {
+    let wrapper = match ty {
+        Type::Boolean => "java/lang/Boolean",
+        Type::Byte => "java/lang/Byte",
+        Type::Char => "java/lang/Character",
+        Type::Short => "java/lang/Short",
+        Type::Int => "java/lang/Integer",
+        Type::Long => "java/lang/Long",
+        Type::Float => "java/lang/Float",
+        Type::Double => "java/lang/Double",
+        _ => return None
+    };
+    Some(Instruction::InvokeExact(MemberType::Static, OrDynamic::Static(MemberRef {
+        owner: wrapper.into(),
+        name: "valueOf".into(),
+        descriptor: Type::method([ty.clone()], Some(Type::reference(wrapper))),
+        itfs: false
+    })))
+}
+
+/// Injects probes in a method.
+///
+/// This fails if the method has no code, if its code cannot be decoded, if a constructor never calls another constructor
+/// of the object, or if the stack effect of a probe cannot be computed.
+pub fn instrument<F: FnOnce(&mut Context) -> Probes>(method: &mut Method, probes: F) -> Result<()> {
+    let (parameters, ret) = match &method.descriptor {
+        Type::Method { parameters, ret } => (parameters.clone(), ret.as_deref().cloned()),
+        _ => return Err(Error::Invalid("method descriptor", method.descriptor.to_string().into()))
+    };
+    let is_static = method.access.contains(MethodFlags::ACC_STATIC);
+    let is_constructor = method.name == "<init>";
+    let name = format!("{}{}", method.name, method.descriptor);
+    let code = method.code_mut()?.ok_or_else(|| Error::Invalid("method", format!("{} has no code", name).into()))?;
+
+    let mut next_label = 0;
+    code.visit_labels_mut(|l| next_label = next_label.max(l.0 + 1));
+    let arguments = !is_static as u16 + parameters.iter().map(slots).sum::<u16>();
+    let mut cx = Context { parameters, ret, is_static, is_constructor, next_local: code.max_locals.max(arguments), next_label };
+    let Probes { entry, exit, exceptional_exit } = probes(&mut cx);
+
+    let at = if is_constructor { after_constructor_call(&code.code)? } else { 0 };
+    let mut stack = code.max_stack + peak(&entry)?.max(peak(&exit)?);
+    let mut locals = cx.next_local;
+    for probe in [&entry, &exit, &exceptional_exit] {
+        locals = locals.max(max_local(probe));
+    }
+
+    let old = std::mem::take(&mut code.code);
+    code.code.reserve(old.len() + entry.len());
+    code.code.extend_from_slice(&old[..at]);
+    code.code.extend(entry);
+    // the synthetic handler covers the original code in segments, leaving out the exit probes and their returns.
+    let guarded = !exceptional_exit.is_empty();
+    let mut ranges = vec![];
+    let mut start = cx.new_label();
+    let mut covered = false;
+    code.code.push(Instruction::Label(start));
+    for insn in old.into_iter().skip(at) {
+        if let Instruction::Return(_) = insn {
+            if guarded && covered {
+                let end = cx.new_label();
+                code.code.push(Instruction::Label(end));
+                ranges.push((start, end));
+            }
+            // every copy of the exit probe needs its own labels.
+            let mut copy = exit.clone();
+            let mut labels = HashMap::new();
+            for i in &mut copy {
+                i.visit_labels_mut(|l| *l = *labels.entry(*l).or_insert_with(|| cx.new_label()));
+            }
+            code.code.extend(copy);
+            code.code.push(insn);
+            if guarded {
+                start = cx.new_label();
+                covered = false;
+                code.code.push(Instruction::Label(start));
+            }
+            continue
+        }
+        covered |= !matches!(insn, Instruction::Label(_) | Instruction::LineNumber(_));
+        code.code.push(insn);
+    }
+    if guarded {
+        stack = stack.max(1 + peak(&exceptional_exit)?);
+        let (end, handler) = (cx.new_label(), cx.new_label());
+        code.code.push(Instruction::Label(end));
+        if covered {
+            ranges.push((start, end));
+        }
+        code.code.push(Instruction::Label(handler));
+        code.code.extend(exceptional_exit);
+        code.code.push(Instruction::Throw);
+        code.catches.extend(ranges.into_iter().map(|(start, end)| Catch { start, end, handler, catch: None }));
+    }
+    code.max_stack = stack;
+    code.max_locals = locals;
+    Ok(())
+}
+
+/// The index of the instruction following the call to the `super` or `this` constructor.
+fn after_constructor_call(code: &[Instruction]) -> Result<usize> {
+    // objects created by `new` are initialized by the other constructor calls.
+    let mut created = 0usize;
+    for (i, insn) in code.iter().enumerate() {
+        match insn {
+            Instruction::New(_) => created += 1,
+            Instruction::InvokeSpecial(OrDynamic::Static(m)) if m.name == "<init>" => {
+                if created == 0 {
+                    return Ok(i + 1)
+                }
+                created -= 1;
+            }
+            _ => {}
+        }
+    }
+    Err(Error::Invalid("constructor", "no call to a super or this constructor".into()))
+}
+
+/// The highest number of stack slots a probe uses above the height it starts at.
+fn peak(probe: &[Instruction]) -> Result<u16> {
+    let mut height = 0i32;
+    let mut peak = 0;
+    for insn in probe {
+        let (pop, push) = insn.stack_effect().ok_or_else(|| Error::Invalid("probe instruction", format!("{:?}", insn).into()))?;
+        height += push as i32 - pop as i32;
+        peak = peak.max(height);
+    }
+    Ok(peak as u16)
+}
+
+/// The number of local variables a probe needs.
+fn max_local(probe: &[Instruction]) -> u16 {
+    probe.iter().map(|i| match i {
+        Instruction::LocalVariable(_, ty, idx) => idx + if let LocalType::Long | LocalType::Double = ty { 2 } else { 1 },
+        Instruction::IntIncrement(idx, _) | Instruction::Ret(idx) => idx + 1,
+        _ => 0
+    }).max().unwrap_or(0)
+}
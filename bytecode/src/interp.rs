@@ -0,0 +1,997 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! A small interpreter for static methods over primitives, arrays and strings.
+//!
+//! The [`Interpreter`] evaluates decoded [`Code`] without a JVM, for instance to run the string decryption routine of an
+//! obfuscated class or to fold the constants computed by a `<clinit>` initializer. Values are limited to primitives, `null`,
+//! strings and arrays. Calls to static methods of the classes added to the interpreter are interpreted as well, and every
+//! other call leaves the sandbox through a [`Hook`], which decides what it does.
+//!
+//! Execution is bounded by a fuel limit on the number of instructions, and by a limit on the number of array elements
+//! it allocates. Exceptions thrown by the code, whether by `athrow` or by the JVM itself such as a division by zero, enter
+//! the first exception handler of the code that catches them, in the calling methods as well. Those that are not caught
+//! end the execution and are reported as a [`Completion`].
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Write as _;
+use std::rc::Rc;
+
+use crate::prelude::*;
+use crate::Class;
+
+/// The maximum depth of interpreted calls.
+const MAX_DEPTH: u64 = 256;
+
+/// The default maximum number of array elements allocated by interpreted code.
+const MAX_ELEMENTS: u64 = 1 << 24;
+
+/// A value of the interpreter.
+#[derive(Clone, Debug)]
+pub enum Value {
+    /// An `int`, or a `boolean`, `byte`, `char` or `short` widened to an `int`.
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Null,
+    String(Rc<str>),
+    Array(Rc<RefCell<Array>>),
+    /// An object created by `new` whose constructor was not called yet, identified by a number unique to the interpreter.
+    Uninitialized(u32, Cow<'static, str>),
+    /// An exception thrown by the JVM, such as an `ArithmeticException` for a division by zero, identified by its class.
+    ///
+    /// This is the value that an exception handler receives for the exceptional [`Completion`]s other than [`Threw`].
+    ///
+    /// [`Threw`]: Completion::Threw
+    Exception(Cow<'static, str>)
+}
+
+/// An array of the interpreter.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Array {
+    /// The type of the elements.
+    pub component: Type,
+    pub elements: Vec<Value>
+}
+
+impl Value {
+    /// Creates a new array from its elements.
+    pub fn array(component: Type, elements: Vec<Value>) -> Self {
+        Value::Array(Rc::new(RefCell::new(Array { component, elements })))
+    }
+
+    /// The default value of a field or array element of this type.
+    pub fn default_of(ty: &Type) -> Self {
+        match ty {
+            Type::Long => Value::Long(0),
+            Type::Float => Value::Float(0.0),
+            Type::Double => Value::Double(0.0),
+            Type::Ref(_) | Type::ArrayRef(..) | Type::Method { .. } => Value::Null,
+            _ => Value::Int(0)
+        }
+    }
+
+    #[inline]
+    fn is_wide(&self) -> bool {
+        matches!(self, Value::Long(_) | Value::Double(_))
+    }
+
+    fn is_reference(&self) -> bool {
+        matches!(self, Value::Null | Value::String(_) | Value::Array(_) | Value::Uninitialized(..) | Value::Exception(_))
+    }
+}
+
+/// Strings are compared by content, since all strings of the interpreter would be interned constants in the JVM,
+/// and arrays by identity.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Long(a), Value::Long(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            (Value::Double(a), Value::Double(b)) => a.to_bits() == b.to_bits(),
+            (Value::Null, Value::Null) => true,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b),
+            (Value::Uninitialized(a, _), Value::Uninitialized(b, _)) => a == b,
+            (Value::Exception(a), Value::Exception(b)) => a == b,
+            _ => false
+        }
+    }
+}
+
+impl From<i32> for Value {
+    fn from(i: i32) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(l: i64) -> Self {
+        Value::Long(l)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.into())
+    }
+}
+
+/// How the execution of a method ended.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Completion {
+    /// The method returned, with a value unless it is `void`.
+    Returned(Option<Value>),
+    /// The code threw this value with `athrow`.
+    Threw(Value),
+    /// An integer division or remainder by zero threw an `ArithmeticException`.
+    DivisionByZero,
+    /// A `NullPointerException` was thrown.
+    NullPointer,
+    /// An `ArrayIndexOutOfBoundsException` was thrown for this index.
+    IndexOutOfBounds(i32),
+    /// A `NegativeArraySizeException` was thrown for this size.
+    NegativeArraySize(i32),
+    /// A `ClassCastException` was thrown for a cast to this class.
+    ClassCast(Cow<'static, str>)
+}
+
+/// Performs the operations that leave the sandbox.
+pub trait Hook {
+    /// Calls a method that is not interpreted.
+    ///
+    /// The receiver of instance methods and constructors is the first argument. A constructor returns the object
+    /// it initialized, which replaces the uninitialized object created by `new`.
+    fn invoke(&mut self, method: &MemberRef, args: &[Value]) -> Result<Option<Value>>;
+
+    /// Reads a static field that was not written by the interpreted code nor initialized by a `ConstantValue` attribute.
+    fn get_static(&mut self, field: &MemberRef) -> Result<Value> {
+        Err(Error::Invalid("static field", format!("{}.{} is not available in the sandbox", field.owner, field.name).into()))
+    }
+
+    /// Returns `true` if a value thrown with `athrow` is an instance of this exception class, so that a handler for
+    /// the class catches it.
+    ///
+    /// Handlers for any exception or for `java/lang/Throwable` catch every value without asking. By default, other
+    /// handlers do not catch thrown values.
+    fn is_instance(&mut self, _value: &Value, _class: &str) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+impl<F: FnMut(&MemberRef, &[Value]) -> Result<Option<Value>>> Hook for F {
+    fn invoke(&mut self, method: &MemberRef, args: &[Value]) -> Result<Option<Value>> {
+        self(method, args)
+    }
+}
+
+/// A hook that refuses every call.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Sandboxed;
+
+impl Hook for Sandboxed {
+    fn invoke(&mut self, method: &MemberRef, _: &[Value]) -> Result<Option<Value>> {
+        Err(Error::Invalid("call", format!("{}.{}{} is not available in the sandbox", method.owner, method.name, method.descriptor).into()))
+    }
+}
+
+/// Implements the most common methods of `java.lang.String`, for use in a [`Hook`].
+///
+/// Returns `None` if the method is not one of them.
+pub fn string_methods(method: &MemberRef, args: &[Value]) -> Option<Result<Option<Value>>> {
+    if method.owner != "java/lang/String" {
+        return None
+    }
+    let desc = method.descriptor.to_string();
+    let chars = |s: &str| s.encode_utf16().collect::<Vec<_>>();
+    Some(Ok(match (method.name.as_ref(), desc.as_str(), args) {
+        ("length", "()I", [Value::String(s)]) => Some(Value::Int(chars(s).len() as i32)),
+        ("charAt", "(I)C", [Value::String(s), Value::Int(i)]) => match chars(s).get(*i as usize) {
+            Some(c) if *i >= 0 => Some(Value::Int(*c as i32)),
+            _ => return Some(Err(Error::Invalid("String.charAt index", i.to_string().into())))
+        },
+        ("toCharArray", "()[C", [Value::String(s)]) => Some(Value::array(Type::Char, chars(s).into_iter().map(|c| Value::Int(c as i32)).collect())),
+        ("intern", "()Ljava/lang/String;", [s @ Value::String(_)]) | ("toString", "()Ljava/lang/String;", [s @ Value::String(_)]) => Some(s.clone()),
+        ("<init>", "([C)V", [_, Value::Array(a)]) | ("valueOf", "([C)Ljava/lang/String;", [Value::Array(a)]) => {
+            let units = a.borrow().elements.iter().map(|c| match c {
+                Value::Int(c) => *c as u16,
+                _ => 0
+            }).collect::<Vec<_>>();
+            Some(Value::String(String::from_utf16_lossy(&units).into()))
+        }
+        ("hashCode", "()I", [Value::String(s)]) => Some(Value::Int(chars(s).into_iter().fold(0i32, |h, c| h.wrapping_mul(31).wrapping_add(c as i32)))),
+        _ => return None
+    }))
+}
+
+/// The key of a field or method.
+type Key = (Cow<'static, str>, Cow<'static, str>);
+
+/// Evaluates the code of static methods.
+pub struct Interpreter<'a, H> {
+    hook: H,
+    fuel: u64,
+    steps: u64,
+    max_elements: u64,
+    elements: u64,
+    methods: HashMap<(Cow<'static, str>, Cow<'static, str>, Type), &'a Code>,
+    statics: HashMap<Key, Value>,
+    next_object: u32,
+    depth: u64
+}
+
+impl<'a, H: Hook> Interpreter<'a, H> {
+    /// Creates an interpreter that runs at most `fuel` instructions in total.
+    pub fn new(hook: H, fuel: u64) -> Self {
+        Interpreter {
+            hook,
+            fuel,
+            steps: 0,
+            max_elements: MAX_ELEMENTS,
+            elements: 0,
+            methods: HashMap::new(),
+            statics: HashMap::new(),
+            next_object: 0,
+            depth: 0
+        }
+    }
+
+    /// Sets the maximum number of array elements that interpreted code allocates in total, 2^24 by default.
+    pub fn max_elements(mut self, max: u64) -> Self {
+        self.max_elements = max;
+        self
+    }
+
+    /// Makes the static methods of the class callable by interpreted code, and sets its static fields that have
+    /// a constant value.
+    ///
+    /// This fails if a lazily read method body cannot be decoded.
+    pub fn add_class(&mut self, class: &'a Class) -> Result<()> {
+        for m in class.methods.iter().filter(|m| m.access.contains(MethodFlags::ACC_STATIC)) {
+            if let Some(code) = m.code()? {
+                self.methods.insert((class.name.clone(), m.name.clone(), m.descriptor.clone()), code);
+            }
+        }
+        for f in class.fields.iter().filter(|f| f.access.contains(FieldFlags::ACC_STATIC)) {
+            for a in &f.attrs {
+                if let FieldAttribute::ConstantValue(c) = a {
+                    if let Ok(v) = constant(c) {
+                        self.statics.insert((class.name.clone(), f.name.clone()), v);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The fuel that is left.
+    #[inline]
+    pub fn fuel(&self) -> u64 {
+        self.fuel - self.steps
+    }
+
+    /// The static fields written by the interpreted code, or initialized by a `ConstantValue` attribute.
+    pub fn statics(&self) -> &HashMap<Key, Value> {
+        &self.statics
+    }
+
+    /// Sets the value of a static field.
+    pub fn set_static<O: Into<Cow<'static, str>>, N: Into<Cow<'static, str>>>(&mut self, owner: O, name: N, value: Value) {
+        self.statics.insert((owner.into(), name.into()), value);
+    }
+
+    /// Calls a static method of a class added to the interpreter.
+    pub fn invoke(&mut self, owner: &str, name: &str, descriptor: &Type, args: &[Value]) -> Result<Completion> {
+        let key = (Cow::Owned(owner.to_owned()), Cow::Owned(name.to_owned()), descriptor.clone());
+        match self.methods.get(&key) {
+            Some(code) => self.run(code, args),
+            None => Err(Error::Invalid("method", format!("{}.{}{} was not added to the interpreter", owner, name, descriptor).into()))
+        }
+    }
+
+    /// Runs the code of a static method with these arguments.
+    pub fn run(&mut self, code: &Code, args: &[Value]) -> Result<Completion> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::Limit("call depth", self.depth + 1, MAX_DEPTH))
+        }
+        self.depth += 1;
+        let res = Frame::new(code, args).run(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn call(&mut self, method: &MemberRef, args: &[Value]) -> Result<Option<Value>> {
+        self.hook.invoke(method, args)
+    }
+
+    fn allocate(&mut self, elements: u64) -> Result<()> {
+        let total = self.elements.saturating_add(elements);
+        if total > self.max_elements {
+            return Err(Error::Limit("array elements", total, self.max_elements))
+        }
+        self.elements = total;
+        Ok(())
+    }
+}
+
+fn constant(c: &Constant) -> Result<Value> {
+    Ok(match c {
+        Constant::I32(i) => Value::Int(*i),
+        Constant::I64(l) => Value::Long(*l),
+        Constant::F32(f) => Value::Float(*f),
+        Constant::F64(d) => Value::Double(*d),
+        Constant::String(s) => Value::String(s.as_ref().into()),
+        c => return Err(Error::Invalid("constant", format!("{:?} is not supported", c).into()))
+    })
+}
+
+/// The superclasses of the exceptions thrown by the JVM, up to `java/lang/Exception`.
+fn exception_classes(class: &str) -> &'static [&'static str] {
+    match class {
+        "java/lang/ArrayIndexOutOfBoundsException" => &["java/lang/ArrayIndexOutOfBoundsException", "java/lang/IndexOutOfBoundsException",
+            "java/lang/RuntimeException", "java/lang/Exception"],
+        "java/lang/ArithmeticException" => &["java/lang/ArithmeticException", "java/lang/RuntimeException", "java/lang/Exception"],
+        "java/lang/NullPointerException" => &["java/lang/NullPointerException", "java/lang/RuntimeException", "java/lang/Exception"],
+        "java/lang/NegativeArraySizeException" => &["java/lang/NegativeArraySizeException", "java/lang/RuntimeException", "java/lang/Exception"],
+        "java/lang/ClassCastException" => &["java/lang/ClassCastException", "java/lang/RuntimeException", "java/lang/Exception"],
+        _ => &[]
+    }
+}
+
+fn invalid<T>(what: &'static str) -> Result<T> {
+    Err(Error::Invalid("interpreted code", what.into()))
+}
+
+/// The result of an instruction that does not fall through to the next one.
+enum Flow {
+    Next,
+    Jump(Label),
+    End(Completion)
+}
+
+struct Frame<'c> {
+    code: &'c Code,
+    labels: HashMap<Label, usize>,
+    locals: Vec<Option<Value>>,
+    stack: Vec<Value>
+}
+
+macro_rules! pop {
+    ($frame: expr, $variant: ident) => {
+        match $frame.stack.pop() {
+            Some(Value::$variant(v)) => v,
+            Some(_) => return invalid(concat!("expected ", stringify!($variant), " on the stack")),
+            None => return invalid("stack underflow")
+        }
+    };
+}
+
+impl<'c> Frame<'c> {
+    fn new(code: &'c Code, args: &[Value]) -> Self {
+        let labels = code.code.iter().enumerate().filter_map(|(i, insn)| match insn {
+            Instruction::Label(l) => Some((*l, i)),
+            _ => None
+        }).collect();
+        let mut locals = Vec::with_capacity(code.max_locals as usize);
+        for a in args {
+            locals.push(Some(a.clone()));
+            if a.is_wide() {
+                locals.push(None);
+            }
+        }
+        if locals.len() < code.max_locals as usize {
+            locals.resize(code.max_locals as usize, None);
+        }
+        Frame { code, labels, locals, stack: vec![] }
+    }
+
+    fn pop(&mut self) -> Result<Value> {
+        self.stack.pop().map_or_else(|| invalid("stack underflow"), Ok)
+    }
+
+    fn pop_reference(&mut self) -> Result<Value> {
+        match self.pop()? {
+            v if v.is_reference() => Ok(v),
+            _ => invalid("expected a reference on the stack")
+        }
+    }
+
+    /// Pops values taking up `slots` slots, in stack order.
+    fn pop_slots(&mut self, slots: usize) -> Result<Vec<Value>> {
+        let mut taken = 0;
+        let mut values = vec![];
+        while taken < slots {
+            let v = self.pop()?;
+            taken += if v.is_wide() { 2 } else { 1 };
+            values.push(v);
+        }
+        if taken != slots {
+            return invalid("stack operation splits a long or double")
+        }
+        values.reverse();
+        Ok(values)
+    }
+
+    /// Duplicates the values in the top `take` slots below the `skip` slots under them.
+    fn dup(&mut self, take: usize, skip: usize) -> Result<()> {
+        let top = self.pop_slots(take)?;
+        let below = self.pop_slots(skip)?;
+        self.stack.extend(top.iter().cloned());
+        self.stack.extend(below);
+        self.stack.extend(top);
+        Ok(())
+    }
+
+    fn load(&mut self, ty: LocalType, idx: u16) -> Result<()> {
+        let v = match self.locals.get(idx as usize) {
+            Some(Some(v)) => v.clone(),
+            _ => return invalid("load of an unset local variable")
+        };
+        let ok = match ty {
+            LocalType::Int => matches!(v, Value::Int(_)),
+            LocalType::Long => matches!(v, Value::Long(_)),
+            LocalType::Float => matches!(v, Value::Float(_)),
+            LocalType::Double => matches!(v, Value::Double(_)),
+            LocalType::Reference => v.is_reference()
+        };
+        if !ok {
+            return invalid("local variable has another type")
+        }
+        self.stack.push(v);
+        Ok(())
+    }
+
+    fn store(&mut self, idx: u16, v: Value) {
+        let idx = idx as usize;
+        let end = idx + if v.is_wide() { 2 } else { 1 };
+        if self.locals.len() < end {
+            self.locals.resize(end, None);
+        }
+        // a wide value also takes the next slot, and storing over half of one invalidates it.
+        if idx > 0 && matches!(self.locals[idx - 1], Some(Value::Long(_)) | Some(Value::Double(_))) {
+            self.locals[idx - 1] = None;
+        }
+        if v.is_wide() {
+            self.locals[idx + 1] = None;
+        }
+        self.locals[idx] = Some(v);
+    }
+
+    fn run<H: Hook>(&mut self, interp: &mut Interpreter<'_, H>) -> Result<Completion> {
+        let mut pc = 0;
+        let code = self.code;
+        while let Some(insn) = code.code.get(pc) {
+            pc += 1;
+            if matches!(insn, Instruction::Label(_) | Instruction::LineNumber(_)) {
+                continue
+            }
+            if interp.steps == interp.fuel {
+                return Err(Error::Limit("interpreted instructions", interp.steps + 1, interp.fuel))
+            }
+            interp.steps += 1;
+            match self.step(insn, interp)? {
+                Flow::Next => {}
+                Flow::Jump(l) => match self.labels.get(&l) {
+                    Some(&target) => pc = target,
+                    None => return invalid("jump to a label that is not placed")
+                },
+                Flow::End(c) => match self.catch(pc - 1, &c, interp)? {
+                    Some(handler) => pc = handler,
+                    None => return Ok(c)
+                }
+            }
+        }
+        invalid("execution fell off the end of the code")
+    }
+
+    /// Enters the first exception handler that catches the exceptional completion of the instruction at `pc`, and
+    /// returns where it starts.
+    fn catch<H: Hook>(&mut self, pc: usize, c: &Completion, interp: &mut Interpreter<'_, H>) -> Result<Option<usize>> {
+        let exception = match c {
+            Completion::Returned(_) => return Ok(None),
+            Completion::Threw(v) => v.clone(),
+            Completion::DivisionByZero => Value::Exception("java/lang/ArithmeticException".into()),
+            Completion::NullPointer => Value::Exception("java/lang/NullPointerException".into()),
+            Completion::IndexOutOfBounds(_) => Value::Exception("java/lang/ArrayIndexOutOfBoundsException".into()),
+            Completion::NegativeArraySize(_) => Value::Exception("java/lang/NegativeArraySizeException".into()),
+            Completion::ClassCast(_) => Value::Exception("java/lang/ClassCastException".into())
+        };
+        for catch in &self.code.catches {
+            let label = |l: &Label| self.labels.get(l).copied().map_or_else(|| invalid("exception handler with a label that is not placed"), Ok);
+            if pc < label(&catch.start)? || pc >= label(&catch.end)? {
+                continue
+            }
+            let caught = match (&catch.catch, &exception) {
+                (None, _) => true,
+                (Some(class), _) if class == "java/lang/Throwable" => true,
+                (Some(class), Value::Exception(e)) => exception_classes(e).contains(&class.as_ref()),
+                (Some(class), v) => interp.hook.is_instance(v, class)?
+            };
+            if caught {
+                let handler = label(&catch.handler)?;
+                self.stack.clear();
+                self.stack.push(exception);
+                return Ok(Some(handler))
+            }
+        }
+        Ok(None)
+    }
+
+    fn step<H: Hook>(&mut self, insn: &Instruction, interp: &mut Interpreter<'_, H>) -> Result<Flow> {
+        match insn {
+            Instruction::NoOp | Instruction::Label(_) | Instruction::LineNumber(_) => {}
+            Instruction::PushNull => self.stack.push(Value::Null),
+            Instruction::Push(OrDynamic::Static(c)) => self.stack.push(constant(c)?),
+            Instruction::Dup => self.dup(1, 0)?,
+            Instruction::DupX1 => self.dup(1, 1)?,
+            Instruction::DupX2 => self.dup(1, 2)?,
+            Instruction::Dup2 => self.dup(2, 0)?,
+            Instruction::Dup2X1 => self.dup(2, 1)?,
+            Instruction::Dup2X2 => self.dup(2, 2)?,
+            Instruction::Pop1 => {
+                self.pop_slots(1)?;
+            }
+            Instruction::Pop2 => {
+                self.pop_slots(2)?;
+            }
+            Instruction::Swap => {
+                let mut v = self.pop_slots(2)?;
+                if v.len() != 2 {
+                    return invalid("swap of a long or double")
+                }
+                v.swap(0, 1);
+                self.stack.extend(v);
+            }
+            Instruction::Jump(cond, label) => {
+                let taken = match cond {
+                    JumpCondition::Always => true,
+                    JumpCondition::ReferenceEquals | JumpCondition::ReferenceNotEquals => {
+                        let (b, a) = (self.pop_reference()?, self.pop_reference()?);
+                        (a == b) == (*cond == JumpCondition::ReferenceEquals)
+                    }
+                    JumpCondition::IsNull | JumpCondition::IsNonNull => {
+                        let v = self.pop_reference()?;
+                        (v == Value::Null) == (*cond == JumpCondition::IsNull)
+                    }
+                    JumpCondition::IntegerEquals | JumpCondition::IntegerNotEquals | JumpCondition::IntegerLessThan |
+                    JumpCondition::IntegerGreaterThan | JumpCondition::IntegerLessThanOrEquals | JumpCondition::IntegerGreaterThanOrEquals => {
+                        let b = pop!(self, Int);
+                        let a = pop!(self, Int);
+                        compare(*cond, a, b)
+                    }
+                    _ => {
+                        let a = pop!(self, Int);
+                        compare(*cond, a, 0)
+                    }
+                };
+                if taken {
+                    return Ok(Flow::Jump(*label))
+                }
+            }
+            Instruction::CompareLongs => {
+                let b = pop!(self, Long);
+                let a = pop!(self, Long);
+                self.stack.push(Value::Int(a.cmp(&b) as i32));
+            }
+            Instruction::CompareFloats(ty, nan) => {
+                let (a, b) = match ty {
+                    FloatType::Float => {
+                        let b = pop!(self, Float);
+                        (pop!(self, Float) as f64, b as f64)
+                    }
+                    FloatType::Double => {
+                        let b = pop!(self, Double);
+                        (pop!(self, Double), b)
+                    }
+                };
+                let r = match a.partial_cmp(&b) {
+                    Some(o) => o as i32,
+                    None if *nan == NaNBehavior::ReturnsOne => 1,
+                    None => -1
+                };
+                self.stack.push(Value::Int(r));
+            }
+            Instruction::LocalVariable(LoadOrStore::Load, ty, idx) => self.load(*ty, *idx)?,
+            Instruction::LocalVariable(LoadOrStore::Store, _, idx) => {
+                let v = self.pop()?;
+                self.store(*idx, v);
+            }
+            Instruction::IntIncrement(idx, inc) => match self.locals.get_mut(*idx as usize) {
+                Some(Some(Value::Int(i))) => *i = i.wrapping_add(*inc as i32),
+                _ => return invalid("increment of a local variable that is not an int")
+            },
+            Instruction::Array(LoadOrStore::Load, _) => {
+                let idx = pop!(self, Int);
+                let array = match self.pop_reference()? {
+                    Value::Array(a) => a,
+                    Value::Null => return Ok(Flow::End(Completion::NullPointer)),
+                    _ => return invalid("array load from a value that is not an array")
+                };
+                let v = match array.borrow().elements.get(idx as usize) {
+                    Some(v) if idx >= 0 => v.clone(),
+                    _ => return Ok(Flow::End(Completion::IndexOutOfBounds(idx)))
+                };
+                self.stack.push(v);
+            }
+            Instruction::Array(LoadOrStore::Store, _) => {
+                let v = self.pop()?;
+                let idx = pop!(self, Int);
+                let array = match self.pop_reference()? {
+                    Value::Array(a) => a,
+                    Value::Null => return Ok(Flow::End(Completion::NullPointer)),
+                    _ => return invalid("array store to a value that is not an array")
+                };
+                let mut array = array.borrow_mut();
+                // narrow values are truncated as they are stored.
+                let v = match (&array.component, v) {
+                    (Type::Boolean, Value::Int(i)) => Value::Int(i & 1),
+                    (Type::Byte, Value::Int(i)) => Value::Int(i as i8 as i32),
+                    (Type::Char, Value::Int(i)) => Value::Int(i as u16 as i32),
+                    (Type::Short, Value::Int(i)) => Value::Int(i as i16 as i32),
+                    (_, v) => v
+                };
+                match array.elements.get_mut(idx as usize) {
+                    Some(e) if idx >= 0 => *e = v,
+                    _ => return Ok(Flow::End(Completion::IndexOutOfBounds(idx)))
+                }
+            }
+            Instruction::ArrayLength => match self.pop_reference()? {
+                Value::Array(a) => {
+                    let len = a.borrow().elements.len() as i32;
+                    self.stack.push(Value::Int(len));
+                }
+                Value::Null => return Ok(Flow::End(Completion::NullPointer)),
+                _ => return invalid("length of a value that is not an array")
+            },
+            Instruction::IntOperation(IntType::Int, op) => {
+                let b = pop!(self, Int);
+                let r = if let IntOperation::Negate = op {
+                    Some(b.wrapping_neg())
+                } else {
+                    let a = pop!(self, Int);
+                    int_op(*op, a, b, b as u32)
+                };
+                match r {
+                    Some(r) => self.stack.push(Value::Int(r)),
+                    None => return Ok(Flow::End(Completion::DivisionByZero))
+                }
+            }
+            Instruction::IntOperation(IntType::Long, op) => {
+                let r = match op {
+                    IntOperation::Negate => Some(pop!(self, Long).wrapping_neg()),
+                    IntOperation::ShiftLeft | IntOperation::ShiftRight | IntOperation::UnsignedShiftRight => {
+                        let b = pop!(self, Int);
+                        let a = pop!(self, Long);
+                        int_op(*op, a, b as i64, b as u32)
+                    }
+                    _ => {
+                        let b = pop!(self, Long);
+                        let a = pop!(self, Long);
+                        int_op(*op, a, b, b as u32)
+                    }
+                };
+                match r {
+                    Some(r) => self.stack.push(Value::Long(r)),
+                    None => return Ok(Flow::End(Completion::DivisionByZero))
+                }
+            }
+            Instruction::FloatOperation(FloatType::Float, op) => {
+                let b = pop!(self, Float);
+                let r = if let FloatOperation::Negate = op { -b } else { float_op(*op, pop!(self, Float), b) };
+                self.stack.push(Value::Float(r));
+            }
+            Instruction::FloatOperation(FloatType::Double, op) => {
+                let b = pop!(self, Double);
+                let r = if let FloatOperation::Negate = op { -b } else { float_op(*op, pop!(self, Double), b) };
+                self.stack.push(Value::Double(r));
+            }
+            Instruction::ConvertInt(to) => {
+                let i = pop!(self, Int);
+                self.stack.push(match to {
+                    BitType::Byte => Value::Int(i as i8 as i32),
+                    BitType::Short => Value::Int(i as i16 as i32),
+                    BitType::Char => Value::Int(i as u16 as i32),
+                    BitType::Int => Value::Int(i),
+                    BitType::Long => Value::Long(i as i64),
+                    BitType::Float => Value::Float(i as f32),
+                    BitType::Double => Value::Double(i as f64)
+                });
+            }
+            Instruction::Conversion(from, to) => {
+                // `as` saturates and maps NaN to zero when converting to integers, as the JVM does.
+                let v = match (from, self.pop()?) {
+                    (NumberType::Int, Value::Int(i)) => Value::Long(i as i64),
+                    (NumberType::Long, Value::Long(l)) => Value::Long(l),
+                    (NumberType::Float, Value::Float(f)) => Value::Double(f as f64),
+                    (NumberType::Double, Value::Double(d)) => Value::Double(d),
+                    _ => return invalid("conversion of a value of another type")
+                };
+                self.stack.push(match (v, to) {
+                    (Value::Long(l), NumberType::Int) => Value::Int(l as i32),
+                    (Value::Long(l), NumberType::Long) => Value::Long(l),
+                    (Value::Long(l), NumberType::Float) => Value::Float(l as f32),
+                    (Value::Long(l), NumberType::Double) => Value::Double(l as f64),
+                    (Value::Double(d), NumberType::Int) => Value::Int(d as i32),
+                    (Value::Double(d), NumberType::Long) => Value::Long(d as i64),
+                    (Value::Double(d), NumberType::Float) => Value::Float(d as f32),
+                    (Value::Double(d), NumberType::Double) => Value::Double(d),
+                    _ => unreachable!("values are widened above")
+                });
+            }
+            Instruction::Throw => return Ok(Flow::End(match self.pop_reference()? {
+                Value::Null => Completion::NullPointer,
+                v => Completion::Threw(v)
+            })),
+            Instruction::CheckCast(OrDynamic::Static(ty)) => {
+                let v = self.pop_reference()?;
+                if v != Value::Null && !instance_of(&v, ty) {
+                    return Ok(Flow::End(Completion::ClassCast(ty.clone().into())))
+                }
+                self.stack.push(v);
+            }
+            Instruction::InstanceOf(OrDynamic::Static(ty)) => {
+                let v = self.pop_reference()?;
+                self.stack.push(Value::Int((v != Value::Null && instance_of(&v, ty)) as i32));
+            }
+            Instruction::NewArray(OrDynamic::Static(ty), dim) => {
+                let mut counts = self.pop_slots(*dim as usize)?.into_iter().map(|c| match c {
+                    Value::Int(c) => Ok(c),
+                    _ => invalid("array size is not an int")
+                }).collect::<Result<Vec<_>>>()?;
+                if let Some(&c) = counts.iter().find(|c| **c < 0) {
+                    return Ok(Flow::End(Completion::NegativeArraySize(c)))
+                }
+                // every dimension holds the product of the counts up to it, the innermost one their full product.
+                let mut level = 1u64;
+                interp.allocate(counts.iter().fold(0u64, |total, &c| {
+                    level = level.saturating_mul(c as u64);
+                    total.saturating_add(level)
+                }))?;
+                let component = if *dim == 1 {
+                    ty.clone()
+                } else {
+                    match ty {
+                        Type::ArrayRef(n, elem) if *n >= *dim => Type::array(*n - 1, (**elem).clone()),
+                        _ => return invalid("multianewarray type has less dimensions than created")
+                    }
+                };
+                counts.reverse();
+                self.stack.push(new_array(component, &mut counts));
+            }
+            Instruction::Monitor(_) => {
+                if self.pop_reference()? == Value::Null {
+                    return Ok(Flow::End(Completion::NullPointer))
+                }
+            }
+            Instruction::New(OrDynamic::Static(class)) => {
+                interp.next_object += 1;
+                self.stack.push(Value::Uninitialized(interp.next_object, class.clone()));
+            }
+            Instruction::Return(ty) => return Ok(Flow::End(Completion::Returned(match ty {
+                Some(_) => Some(self.pop()?),
+                None => None
+            }))),
+            Instruction::Field(GetOrPut::Get, MemberType::Static, OrDynamic::Static(f)) => {
+                let key = (f.owner.clone(), f.name.clone());
+                let v = match interp.statics.get(&key) {
+                    Some(v) => v.clone(),
+                    None => interp.hook.get_static(f)?
+                };
+                self.stack.push(v);
+            }
+            Instruction::Field(GetOrPut::Put, MemberType::Static, OrDynamic::Static(f)) => {
+                let v = self.pop()?;
+                interp.statics.insert((f.owner.clone(), f.name.clone()), v);
+            }
+            Instruction::InvokeExact(MemberType::Static, OrDynamic::Static(m)) => {
+                let args = self.pop_args(m, false)?;
+                let key = (m.owner.clone(), m.name.clone(), m.descriptor.clone());
+                let ret = match interp.methods.get(&key) {
+                    Some(&code) => match interp.run(code, &args)? {
+                        Completion::Returned(v) => v,
+                        c => return Ok(Flow::End(c))
+                    },
+                    None => interp.call(m, &args)?
+                };
+                self.push_return(m, ret)?;
+            }
+            Instruction::InvokeExact(MemberType::Virtual, OrDynamic::Static(m)) | Instruction::InvokeSpecial(OrDynamic::Static(m)) |
+            Instruction::InvokeInterface(OrDynamic::Static(m), _) => {
+                let args = self.pop_args(m, true)?;
+                if args[0] == Value::Null {
+                    return Ok(Flow::End(Completion::NullPointer))
+                }
+                let ret = interp.call(m, &args)?;
+                if let Value::Uninitialized(id, _) = args[0] {
+                    if m.name != "<init>" {
+                        return invalid("call on an uninitialized object")
+                    }
+                    let object = match ret {
+                        Some(v) => v,
+                        None => return invalid("constructor hook did not return the initialized object")
+                    };
+                    let replace = |v: &mut Value| if matches!(v, Value::Uninitialized(i, _) if *i == id) {
+                        *v = object.clone();
+                    };
+                    self.stack.iter_mut().for_each(replace);
+                    self.locals.iter_mut().flatten().for_each(replace);
+                } else {
+                    self.push_return(m, ret)?;
+                }
+            }
+            Instruction::InvokeDynamic(d) if d.is_string_concat() => {
+                let parts = d.string_concat_parts()?;
+                let count = parts.iter().filter(|p| matches!(p, ConcatPart::Argument(_))).count();
+                let mut args = self.pop_values(count)?.into_iter();
+                let mut s = String::new();
+                for p in parts {
+                    match p {
+                        ConcatPart::Literal(l) => s.push_str(&l),
+                        ConcatPart::Argument(ty) => append(&mut s, &ty, &args.next().unwrap_or(Value::Null))?,
+                        ConcatPart::Constant(OrDynamic::Static(c)) => {
+                            let v = constant(&c)?;
+                            append(&mut s, &Type::reference("java/lang/Object"), &v)?
+                        }
+                        ConcatPart::Constant(OrDynamic::Dynamic(_)) => return invalid("dynamic constant in string concatenation")
+                    }
+                }
+                self.stack.push(Value::String(s.into()));
+            }
+            Instruction::TableSwitch { default, low, offsets } => {
+                let i = pop!(self, Int);
+                let target = usize::try_from(i as i64 - *low as i64).ok().and_then(|i| offsets.get(i)).unwrap_or(default);
+                return Ok(Flow::Jump(*target))
+            }
+            Instruction::LookupSwitch { default, table } => {
+                let i = pop!(self, Int);
+                return Ok(Flow::Jump(*table.get(&i).unwrap_or(default)))
+            }
+            insn => return Err(Error::Invalid("instruction", format!("{:?} is not supported by the interpreter", insn).into()))
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Pops `count` values, in the order they were pushed.
+    fn pop_values(&mut self, count: usize) -> Result<Vec<Value>> {
+        if self.stack.len() < count {
+            return invalid("stack underflow")
+        }
+        Ok(self.stack.split_off(self.stack.len() - count))
+    }
+
+    fn pop_args(&mut self, m: &MemberRef, receiver: bool) -> Result<Vec<Value>> {
+        match &m.descriptor {
+            Type::Method { parameters, .. } => self.pop_values(parameters.len() + receiver as usize),
+            _ => invalid("call to a member that is not a method")
+        }
+    }
+
+    fn push_return(&mut self, m: &MemberRef, ret: Option<Value>) -> Result<()> {
+        match (&m.descriptor, ret) {
+            (Type::Method { ret: None, .. }, None) => Ok(()),
+            (Type::Method { ret: Some(_), .. }, Some(v)) => {
+                self.stack.push(v);
+                Ok(())
+            }
+            _ => invalid("call returned a value that does not match its descriptor")
+        }
+    }
+}
+
+fn compare(cond: JumpCondition, a: i32, b: i32) -> bool {
+    match cond {
+        JumpCondition::IntegerEquals | JumpCondition::IntegerEqualsZero => a == b,
+        JumpCondition::IntegerNotEquals | JumpCondition::IntegerNotEqualsZero => a != b,
+        JumpCondition::IntegerLessThan | JumpCondition::IntegerLessThanZero => a < b,
+        JumpCondition::IntegerGreaterThan | JumpCondition::IntegerGreaterThanZero => a > b,
+        JumpCondition::IntegerLessThanOrEquals | JumpCondition::IntegerLessThanOrEqualsZero => a <= b,
+        JumpCondition::IntegerGreaterThanOrEquals | JumpCondition::IntegerGreaterThanOrEqualsZero => a >= b,
+        _ => unreachable!("reference conditions are handled by the caller")
+    }
+}
+
+/// Integers of the JVM, with wrapping arithmetic.
+trait JvmInt: Copy {
+    fn wrapping(self, op: IntOperation, other: Self, shift: u32) -> Option<Self>;
+}
+
+macro_rules! jvm_int {
+    ($($ty: ty => $unsigned: ty),*) => {$(
+        impl JvmInt for $ty {
+            fn wrapping(self, op: IntOperation, other: Self, shift: u32) -> Option<Self> {
+                let shift = shift & (<$ty>::BITS - 1);
+                Some(match op {
+                    IntOperation::Add => self.wrapping_add(other),
+                    IntOperation::Subtract => self.wrapping_sub(other),
+                    IntOperation::Multiply => self.wrapping_mul(other),
+                    IntOperation::Divide => self.checked_div(other).or_else(|| if other == 0 { None } else { Some(self) })?,
+                    IntOperation::Remainder => self.checked_rem(other).or_else(|| if other == 0 { None } else { Some(0) })?,
+                    IntOperation::Negate => self.wrapping_neg(),
+                    IntOperation::ExclusiveOr => self ^ other,
+                    IntOperation::Or => self | other,
+                    IntOperation::And => self & other,
+                    IntOperation::ShiftLeft => self.wrapping_shl(shift),
+                    IntOperation::ShiftRight => self.wrapping_shr(shift),
+                    IntOperation::UnsignedShiftRight => ((self as $unsigned) >> shift) as $ty
+                })
+            }
+        }
+    )*};
+}
+
+jvm_int!(i32 => u32, i64 => u64);
+
+/// Applies an integer operation, returning `None` on division by zero.
+fn int_op<T: JvmInt>(op: IntOperation, a: T, b: T, shift: u32) -> Option<T> {
+    a.wrapping(op, b, shift)
+}
+
+fn float_op<T: std::ops::Add<Output = T> + std::ops::Sub<Output = T> + std::ops::Mul<Output = T> + std::ops::Div<Output = T> + std::ops::Rem<Output = T>>(op: FloatOperation, a: T, b: T) -> T {
+    match op {
+        FloatOperation::Add => a + b,
+        FloatOperation::Subtract => a - b,
+        FloatOperation::Multiply => a * b,
+        FloatOperation::Divide => a / b,
+        FloatOperation::Remainder => a % b,
+        FloatOperation::Negate => unreachable!("negation has a single operand")
+    }
+}
+
+fn instance_of(v: &Value, ty: &ClassType) -> bool {
+    const ANY: [&str; 3] = ["java/lang/Object", "java/io/Serializable", "java/lang/Cloneable"];
+    match (v, ty) {
+        (Value::String(_), ClassType::Object(c)) => c == "java/lang/String" || c == "java/lang/CharSequence" || c == "java/lang/Comparable" || ANY[..2].contains(&c.as_ref()),
+        (Value::Array(_), ClassType::Object(c)) => ANY.contains(&c.as_ref()),
+        (Value::Exception(e), ClassType::Object(c)) => exception_classes(e).contains(&c.as_ref()) || c == "java/lang/Throwable" || ANY[..2].contains(&c.as_ref()),
+        (Value::Array(a), ClassType::Array(dim, elem)) => a.borrow().component == if *dim == 1 { elem.clone() } else { Type::array(*dim - 1, elem.clone()) },
+        _ => false
+    }
+}
+
+/// Creates an array of `counts` dimensions, the outermost dimension being the last.
+fn new_array(component: Type, counts: &mut Vec<i32>) -> Value {
+    let count = counts.pop().unwrap_or(0);
+    let elements = if counts.is_empty() {
+        vec![Value::default_of(&component); count as usize]
+    } else {
+        let inner = match &component {
+            Type::ArrayRef(1, elem) => (**elem).clone(),
+            Type::ArrayRef(n, elem) => Type::array(n - 1, (**elem).clone()),
+            ty => ty.clone()
+        };
+        (0..count).map(|_| new_array(inner.clone(), &mut counts.clone())).collect()
+    };
+    Value::array(component, elements)
+}
+
+/// Appends a value as `String.valueOf` would.
+fn append(s: &mut String, ty: &Type, v: &Value) -> Result<()> {
+    match (ty, v) {
+        (Type::Boolean, Value::Int(i)) => s.push_str(if *i != 0 { "true" } else { "false" }),
+        (Type::Char, Value::Int(c)) => s.push_str(&String::from_utf16_lossy(&[*c as u16])),
+        (_, Value::Int(i)) => write!(s, "{}", i).unwrap(),
+        (_, Value::Long(l)) => write!(s, "{}", l).unwrap(),
+        (_, Value::String(v)) => s.push_str(v),
+        (_, Value::Null) => s.push_str("null"),
+        // floating point numbers and arrays are formatted differently by Java.
+        _ => return invalid("string concatenation of a value that cannot be formatted")
+    }
+    Ok(())
+}
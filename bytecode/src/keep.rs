@@ -0,0 +1,206 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Rules naming the classes and members that are used by reflection or from outside the classes being processed, which
+//! the [`Shrinker`](crate::shrink::Shrinker) keeps and the [`Obfuscator`](crate::obfuscate::Obfuscator) does not rename.
+
+use std::str::FromStr;
+
+use crate::annotation::Annotation;
+use crate::pattern::glob;
+use crate::prelude::*;
+use crate::Class;
+
+/// A rule keeping classes, or members of classes, that are not reached from other roots.
+///
+/// A rule matching only classes keeps them, without their members other than the static initializer. Once a member
+/// name or member flags are given, it keeps the matching fields and methods instead, and their classes. An annotation
+/// applies to the members in the latter case, and to the classes otherwise.
+///
+/// Rules can be parsed from `CLASS [MEMBER] [@ANNOTATION] [FLAG ...]`, where flags are `public`, `protected`, `private`,
+/// `static`, `final`, `abstract`, `synthetic`, `interface`, `enum` or `annotation`. Flags apply to the members if a
+/// member name is given, and to the class otherwise. Class names are globs as in ProGuard, where `*` stands for any
+/// text without `/` and `**` for any text, and member names are globs as in [`glob`](crate::pattern::glob):
+///
+/// ```text
+/// com/example/api/* public
+/// com/example/** @com/example/Keep
+/// com/example/** * @com/example/Keep
+/// ```
+///
+/// The first rule keeps the public classes of the package `com/example/api`, but not of its subpackages. The others keep
+/// the classes of `com/example` and its subpackages that are annotated with `com/example/Keep`, and the annotated
+/// members of these classes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Keep {
+    class: String,
+    member: Option<String>,
+    annotation: Option<String>,
+    class_flags: u16,
+    member_flags: u16
+}
+
+impl Keep {
+    /// Creates a rule keeping the classes whose internal names match a glob.
+    pub fn new<S: Into<String>>(class: S) -> Self {
+        Keep { class: class.into(), member: None, annotation: None, class_flags: 0, member_flags: 0 }
+    }
+
+    /// Keeps the fields and methods whose names match a glob, instead of the classes alone.
+    pub fn members<S: Into<String>>(mut self, name: S) -> Self {
+        self.member = Some(name.into());
+        self
+    }
+
+    /// Only matches the classes, or the fields and methods if a member name or member flags are given, with an annotation
+    /// given by the internal name of its type.
+    pub fn annotated<S: Into<String>>(mut self, annotation: S) -> Self {
+        self.annotation = Some(annotation.into().replace('.', "/"));
+        self
+    }
+
+    /// Only matches classes with all of these access flags.
+    pub fn class_flags(mut self, flags: ClassFlags) -> Self {
+        self.class_flags = flags.bits();
+        self
+    }
+
+    /// Keeps the fields and methods with all of these access flags, as written in the class file.
+    pub fn member_flags(mut self, flags: u16) -> Self {
+        self.member_flags = flags;
+        self
+    }
+
+    pub(crate) fn keeps_members(&self) -> bool {
+        self.member.is_some() || self.member_flags != 0
+    }
+
+    pub(crate) fn matches_class(&self, class: &Class) -> bool {
+        class_glob(&self.class, &class.name) && class.access.bits() & self.class_flags == self.class_flags
+            && (self.keeps_members() || self.annotated_with(&class_annotations(class)))
+    }
+
+    pub(crate) fn matches_field(&self, field: &Field) -> bool {
+        self.matches_member(&field.name, field.access.bits(), &field_annotations(field))
+    }
+
+    pub(crate) fn matches_method(&self, method: &Method) -> bool {
+        self.matches_member(&method.name, method.access.bits(), &method_annotations(method))
+    }
+
+    fn matches_member(&self, name: &str, access: u16, annotations: &[&[Annotation]]) -> bool {
+        self.member.as_deref().is_none_or(|m| glob(m, name)) && access & self.member_flags == self.member_flags && self.annotated_with(annotations)
+    }
+
+    fn annotated_with(&self, annotations: &[&[Annotation]]) -> bool {
+        self.annotation.as_deref().is_none_or(|a| {
+            annotations.iter().flat_map(|a| a.iter()).any(|x| matches!(&x.annotation_type, Type::Ref(n) if n == a))
+        })
+    }
+}
+
+impl FromStr for Keep {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut words = s.split_whitespace();
+        let mut keep = Keep::new(words.next().ok_or_else(|| invalid(format!("empty rule `{}`", s)))?);
+        let mut flags = 0;
+        for word in words {
+            let flag = match word {
+                "public" => 0x0001,
+                "private" => 0x0002,
+                "protected" => 0x0004,
+                "static" => 0x0008,
+                "final" => 0x0010,
+                "interface" => 0x0200,
+                "abstract" => 0x0400,
+                "synthetic" => 0x1000,
+                "annotation" => 0x2000,
+                "enum" => 0x4000,
+                _ => 0
+            };
+            if flag != 0 {
+                flags |= flag;
+            } else if let Some(annotation) = word.strip_prefix('@') {
+                keep = keep.annotated(annotation);
+            } else if keep.member.is_none() {
+                keep.member = Some(word.to_owned());
+            } else {
+                return Err(invalid(format!("unexpected `{}` in rule `{}`", word, s)))
+            }
+        }
+        if keep.member.is_some() {
+            keep.member_flags = flags;
+        } else {
+            keep.class_flags = flags;
+        }
+        Ok(keep)
+    }
+}
+
+/// Whether a glob matches the whole internal name of a class, where `*` stands for any text without `/`, so that it stays
+/// in one package, and `**` for any text.
+fn class_glob(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    // whether the pattern read so far matches the name up to each length.
+    let mut matched = vec![false; name.len() + 1];
+    matched[0] = true;
+    let mut i = 0;
+    while i < pattern.len() {
+        if pattern[i] == b'*' {
+            let any = pattern.get(i + 1) == Some(&b'*');
+            for j in 0..name.len() {
+                if matched[j] && (any || name[j] != b'/') {
+                    matched[j + 1] = true;
+                }
+            }
+            i += if any { 2 } else { 1 };
+        } else {
+            for j in (0..name.len()).rev() {
+                matched[j + 1] = matched[j] && name[j] == pattern[i];
+            }
+            matched[0] = false;
+            i += 1;
+        }
+    }
+    matched[name.len()]
+}
+
+fn invalid(message: String) -> Error {
+    Error::Invalid("keep rule", message.into())
+}
+
+fn class_annotations(class: &Class) -> Vec<&[Annotation]> {
+    class.attributes.iter().filter_map(|a| match a {
+        ClassAttribute::RuntimeVisibleAnnotations(a) | ClassAttribute::RuntimeInvisibleAnnotations(a) => Some(a.as_slice()),
+        _ => None
+    }).collect()
+}
+
+fn method_annotations(method: &Method) -> Vec<&[Annotation]> {
+    method.attributes.iter().filter_map(|a| match a {
+        MethodAttribute::RuntimeVisibleAnnotations(a) | MethodAttribute::RuntimeInvisibleAnnotations(a) => Some(a.as_slice()),
+        _ => None
+    }).collect()
+}
+
+fn field_annotations(field: &Field) -> Vec<&[Annotation]> {
+    field.attrs.iter().filter_map(|a| match a {
+        FieldAttribute::RuntimeVisibleAnnotations(a) | FieldAttribute::RuntimeInvisibleAnnotations(a) => Some(a.as_slice()),
+        _ => None
+    }).collect()
+}
@@ -0,0 +1,226 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Lazy decoding of method bodies.
+//!
+//! Decoding a [`Code`] attribute resolves every instruction, label and local variable, which is wasted work when only member signatures are needed.
+//! [`Class::read_lazy`] reads method bodies as [`LazyCode`] instead, which keeps the raw bytes of the attribute along with the [`SharedPool`] of the class,
+//! and only decodes them on first access.
+//!
+//! Writing a class back reuses the raw bytes of every body that has not been mutably accessed, since the written constant pool is seeded with the original one.
+//!
+//! [`Class::read_lazy`]: crate::Class::read_lazy
+
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, OnceLock};
+
+/// The constant pool and bootstrap methods of a class, shared by all of its lazily decoded method bodies.
+pub struct SharedPool {
+    pub(crate) entries: HashMap<u16, RawConstantEntry>,
+    bsms: OnceLock<Vec<BootstrapMethod>>
+}
+
+impl SharedPool {
+    pub(crate) fn new(entries: HashMap<u16, RawConstantEntry>) -> Self {
+        SharedPool { entries, bsms: OnceLock::new() }
+    }
+
+    /// The bootstrap methods of the class.
+    pub fn bootstrap_methods(&self) -> &[BootstrapMethod] {
+        self.bsms.get().map_or(&[], Vec::as_slice)
+    }
+
+    pub(crate) fn set_bootstrap_methods(&self, bsms: &[BootstrapMethod]) {
+        // the class only has one BootstrapMethods attribute that matters, the first one.
+        let _ = self.bsms.set(bsms.to_vec());
+    }
+}
+
+impl Debug for SharedPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedPool")
+            .field("entries", &self.entries.len())
+            .field("bsms", &self.bootstrap_methods().len())
+            .finish()
+    }
+}
+
+/// Reads from a shared pool, resolving bootstrap methods immediately since they are already known.
+struct SharedReader<'a> {
+    pool: &'a SharedPool,
+    missing: Vec<u16>
+}
+
+impl ConstantPoolReader for SharedReader<'_> {
+    fn read_raw(&mut self, idx: u16) -> Option<RawConstantEntry> {
+        self.pool.entries.get(&idx).cloned()
+    }
+
+    fn resolve_later(&mut self, bsm_idx: u16, bsm: Arc<LazyBsm>) {
+        match self.pool.bootstrap_methods().get(bsm_idx as usize) {
+            Some(b) => bsm.fill(b.clone()).unwrap(),
+            None => self.missing.push(bsm_idx)
+        }
+    }
+
+    fn bootstrap_methods(&mut self, _bsms: &[BootstrapMethod]) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn decode(pool: &SharedPool, mut raw: &[u8]) -> Result<Code> {
+    let mut reader = SharedReader { pool, missing: vec![] };
+    let code = Code::read_from(&mut reader, &mut raw)?;
+    if reader.missing.is_empty() {
+        Ok(code)
+    } else {
+        Err(Error::Invalid("reference(s) to bootstrap method", format!("{:?}", reader.missing).into()))
+    }
+}
+
+/// A method body that is decoded on first access.
+#[derive(Clone)]
+pub struct LazyCode {
+    raw: Cow<'static, [u8]>,
+    pool: Arc<SharedPool>,
+    code: OnceLock<Code>,
+    modified: bool
+}
+
+impl LazyCode {
+    /// The raw content of the `Code` attribute.
+    #[inline]
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The pool this body refers to.
+    #[inline]
+    pub fn pool(&self) -> &Arc<SharedPool> {
+        &self.pool
+    }
+
+    /// Returns `true` if this body has been decoded.
+    #[inline]
+    pub fn is_decoded(&self) -> bool {
+        self.code.get().is_some()
+    }
+
+    /// Returns `true` if this body has been mutably accessed, in which case it is encoded again upon writing.
+    #[inline]
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    /// Decodes this body if it has not been decoded yet, and returns it.
+    pub fn get(&self) -> Result<&Code> {
+        if let Some(code) = self.code.get() {
+            return Ok(code)
+        }
+        let code = decode(&self.pool, &self.raw)?;
+        Ok(self.code.get_or_init(|| code))
+    }
+
+    /// Decodes this body if it has not been decoded yet, and returns it mutably.
+    ///
+    /// The body will be encoded again upon writing.
+    pub fn get_mut(&mut self) -> Result<&mut Code> {
+        if self.code.get().is_none() {
+            let _ = self.code.set(decode(&self.pool, &self.raw)?);
+        }
+        self.modified = true;
+        Ok(self.code.get_mut().unwrap())
+    }
+
+    /// Decodes this body and returns it.
+    pub fn into_code(self) -> Result<Code> {
+        match self.code.into_inner() {
+            Some(code) => Ok(code),
+            None => decode(&self.pool, &self.raw)
+        }
+    }
+}
+
+impl From<Code> for LazyCode {
+    /// Wraps an already decoded body, which is always encoded upon writing.
+    fn from(code: Code) -> Self {
+        LazyCode {
+            raw: Cow::Borrowed(&[]),
+            pool: Arc::new(SharedPool::new(HashMap::new())),
+            code: OnceLock::from(code),
+            modified: true
+        }
+    }
+}
+
+/// Serialized as the decoded body, since the raw bytes are meaningless without the constant pool.
+#[cfg(feature = "serde")]
+impl serde::Serialize for LazyCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.get().map_err(serde::ser::Error::custom)?.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for LazyCode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Code::deserialize(deserializer).map(LazyCode::from)
+    }
+}
+
+impl Debug for LazyCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LazyCode")
+            .field("raw", &self.raw.len())
+            .field("code", &self.code.get())
+            .field("modified", &self.modified)
+            .finish()
+    }
+}
+
+impl PartialEq for LazyCode {
+    fn eq(&self, other: &Self) -> bool {
+        if !self.modified && !other.modified && Arc::ptr_eq(&self.pool, &other.pool) {
+            self.raw == other.raw
+        } else {
+            match (self.get(), other.get()) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => false
+            }
+        }
+    }
+}
+
+impl ConstantPoolReadWrite for LazyCode {
+    fn read_from<C: ConstantPoolReader, R: Read>(cp: &mut C, reader: &mut R) -> Result<Self> {
+        let pool = cp.shared_pool().cloned().ok_or(Error::Invalid("lazy code", Cow::Borrowed("the constant pool is not shared")))?;
+        let mut raw = vec![];
+        reader.read_to_end(&mut raw)?;
+        Ok(LazyCode { raw: raw.into(), pool, code: OnceLock::new(), modified: false })
+    }
+
+    fn write_to<C: ConstantPoolWriter, W: Write>(&self, cp: &mut C, writer: &mut W) -> Result<()> {
+        match cp.source_pool() {
+            Some(p) if !self.modified && Arc::ptr_eq(p, &self.pool) => {
+                writer.write_all(&self.raw)?;
+                Ok(())
+            }
+            _ => self.get()?.write_to(cp, writer)
+        }
+    }
+}
@@ -23,6 +23,10 @@
 //!
 //! Many implementors of [`ReadWrite`] and [`ConstantPoolReadWrite`] uses a derive macro internally to avoid repeating implementation for structures that just calls the trait functions of its fields.
 //!
+//! With the `serde` feature, the class model implements `Serialize` and `Deserialize`. Flags are serialized as their bits,
+//! lazily read method bodies as their decoded code, and bootstrap methods shared between dynamic constants are serialized with each of them.
+//! That sharing is not restored by deserializing: each dynamic constant gets its own copy of its bootstrap method.
+//!
 //! [`ReadWrite`]: crate::ReadWrite
 //! [`ConstantPoolReadWrite`]: crate::ConstantPoolReadWrite
 //! [`ConstantPoolWriter`]: crate::ConstantPoolWriter
@@ -42,6 +46,8 @@ use std::io::{Read, Write};
 
 use prelude::*;
 pub use rw::*;
+use crate::error::PathSegment;
+use crate::rw::{Counted, checked_len};
 
 pub use crate::error::Error;
 pub use crate::error::Result;
@@ -49,21 +55,42 @@ pub use crate::error::Result;
 pub mod annotation;
 pub mod attr;
 pub mod code;
+pub mod compat;
 pub mod constants;
+pub mod coverage;
 pub mod cp;
+pub mod dce;
+pub mod decompile;
+pub mod diff;
 pub mod dynamic;
 pub mod error;
 pub mod flags;
+pub mod flow;
+pub mod inline;
+pub mod instrument;
+pub mod interp;
+pub mod keep;
+pub mod lazy;
 
 pub mod mod_utf8;
 pub mod module;
+pub mod obfuscate;
 pub mod member;
+pub mod pattern;
+pub mod peephole;
 pub mod prelude;
+pub mod remap;
 pub mod ty;
 pub mod signature;
+pub mod shrink;
+pub mod ssa;
+pub mod strip;
+pub mod validate;
 pub mod loadable;
 
 pub mod version;
+pub mod view;
+pub mod xref;
 pub mod rw;
 
 
@@ -72,8 +99,10 @@ pub mod rw;
 #[cfg(test)]
 mod tests;
 pub(crate) mod insn;
+pub(crate) mod visit;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Class {
     pub version: JavaVersion,
     pub access: ClassFlags,
@@ -92,99 +121,209 @@ pub struct Class {
 
 
 
-#[derive(ConstantPoolReadWrite)]
-struct ClassWrapper {
-    #[use_normal_rw]
-    pub access: ClassFlags,
-    #[str_type(Class)]
-    pub name: Cow<'static, str>,
-    #[str_optional]
-    #[str_type(Class)]
-    pub super_name: Option<Cow<'static, str>>,
-    #[vec_len_type(u16)]
-    #[str_type(Class)]
-    pub interfaces: Vec<Cow<'static, str>>,
-    #[vec_len_type(u16)]
-    pub fields: Vec<Field>,
-    #[vec_len_type(u16)]
-    pub methods: Vec<Method>,
-    #[vec_len_type(u16)]
-    pub attributes: Vec<ClassAttribute>
-}
+impl Class {
+    /// Reads a class, leaving method bodies undecoded until they are accessed.
+    ///
+    /// Method bodies are read as [`MethodAttribute::LazyCode`] rather than [`MethodAttribute::Code`].
+    /// Writing the class back copies the bodies that were not mutably accessed as is.
+    pub fn read_lazy<T: Read>(reader: &mut T) -> Result<Self> {
+        Self::read_inner(reader, true, false).map(|(class, _)| class)
+    }
 
-impl ReadWrite for Class {
-    fn read_from<T: Read>(reader: &mut T) -> Result<Self> {
-        match u32::read_from(reader)? {
+    /// Reads a class, recovering from problems that do not prevent reading the rest of the file.
+    ///
+    /// Known attributes that fail to parse are kept as raw attributes, which are written back as they were read, local
+    /// variable and line number entries pointing outside of the code are dropped, unreadable class attributes and
+    /// trailing data are skipped.
+    /// Each recovered problem is returned as a located warning; anything else still fails.
+    pub fn read_lenient<T: Read>(reader: &mut T) -> Result<(Self, Vec<Error>)> {
+        Self::read_inner(reader, false, true)
+    }
+
+    /// Reads many classes in parallel, returning the results in the same order as the buffers.
+    ///
+    /// The buffers are split evenly between scoped threads, one for each available core.
+    pub fn read_all<B: AsRef<[u8]> + Sync>(buffers: &[B]) -> Vec<Result<Self>> {
+        if buffers.is_empty() {
+            return vec![]
+        }
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(buffers.len());
+        let chunk_size = buffers.len().div_ceil(threads);
+        std::thread::scope(|s| {
+            let handles = buffers.chunks(chunk_size).map(|chunk| s.spawn(move || {
+                chunk.iter().map(|b| Class::read_from(&mut b.as_ref())).collect::<Vec<_>>()
+            })).collect::<Vec<_>>();
+            handles.into_iter().flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e))).collect()
+        })
+    }
+
+    fn read_inner<T: Read>(reader: &mut T, lazy: bool, lenient: bool) -> Result<(Self, Vec<Error>)> {
+        let mut rd = Counted::new(reader);
+        let mut name = None;
+        Self::read_counted(&mut rd, lazy, lenient, &mut name).map_err(|e| e.located(name.map(PathSegment::Class), 0, rd.pos))
+    }
+
+    fn read_counted<T: Read>(rd: &mut Counted<T>, lazy: bool, lenient: bool, class_name: &mut Option<Cow<'static, str>>) -> Result<(Self, Vec<Error>)> {
+        match u32::read_from(rd)? {
             0xCAFEBABE => {
-                let version = JavaVersion::read_from(reader)?;
-                let mut cp = MapCp::read_from(reader)?;
-                let c = ClassWrapper::read_from(&mut cp, reader)?;
-                for attr in &c.attributes {
-                    if let ClassAttribute::BootstrapMethods(b) = attr {
-                        cp.bootstrap_methods(b)?;
-                        break
+                let version = JavaVersion::read_from(rd)?;
+                let mut cp = MapCp::read_from(rd)?;
+                if lenient {
+                    cp.set_lenient();
+                }
+                let shared = if lazy { Some(cp.share()) } else { None };
+                let access = ClassFlags::read_from(rd)?;
+                let name: Cow<'static, str> = try_cp_read!(cp, rd, read_class)?;
+                *class_name = Some(name.clone());
+                let super_name = match u16::read_from(rd)? {
+                    0 => None,
+                    idx => Some(try_cp_read_idx!(cp, idx, read_class)?)
+                };
+                let count = u16::read_from(rd)?;
+                let mut interfaces = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    interfaces.push(try_cp_read!(cp, rd, read_class)?);
+                }
+                let count = u16::read_from(rd)?;
+                let mut fields = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let (access, name, descriptor, attrs) = read_member(&mut cp, rd)?;
+                    fields.push(Field { access, name, descriptor, attrs });
+                }
+                let count = u16::read_from(rd)?;
+                let mut methods = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let (access, name, descriptor, attributes) = read_member(&mut cp, rd)?;
+                    methods.push(Method { access, name, descriptor, attributes });
+                }
+                let count = u16::read_from(rd)?;
+                let mut attributes = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let start = rd.pos;
+                    let from = cp.warnings().len();
+                    match ClassAttribute::read_from(&mut cp, rd) {
+                        Ok(attr) => attributes.push(attr),
+                        // the length of a broken attribute is unknown, the remaining ones cannot be found.
+                        Err(e) => {
+                            cp.warn(e.located(None, start, rd.pos))?;
+                            break
+                        }
                     }
+                    cp.locate_warnings(from, None, start);
+                }
+                let bsms = attributes.iter().find_map(|a| if let ClassAttribute::BootstrapMethods(b) = a { Some(b) } else { None });
+                // dynamic constants are unusable without their bootstrap method, which fails even when reading leniently
+                // if the attribute is missing or could not be parsed.
+                cp.bootstrap_methods(bsms.map_or(&[][..], Vec::as_slice))?;
+                if let (Some(b), Some(shared)) = (bsms, &shared) {
+                    shared.set_bootstrap_methods(b);
                 }
-                Ok(Class {
+                if lenient {
+                    let start = rd.pos;
+                    let trailing = std::io::copy(rd, &mut std::io::sink())?;
+                    if trailing != 0 {
+                        cp.warn(Error::Invalid("trailing data", format!("{} bytes", trailing).into()).located(None, start, start))?;
+                    }
+                }
+                cp.locate_warnings(0, Some(PathSegment::Class(name.clone())), 0);
+                Ok((Class {
                     version,
-                    access: c.access,
-                    name: c.name,
-                    super_name: c.super_name,
-                    interfaces: c.interfaces,
-                    fields: c.fields,
-                    methods: c.methods,
-                    attributes: c.attributes
-                })
+                    access,
+                    name,
+                    super_name,
+                    interfaces,
+                    fields,
+                    methods,
+                    attributes
+                }, cp.take_warnings()))
             }
             n => Err(Error::Invalid("class header", n.to_string().into()))
         }
     }
+}
+
+/// Reads a field or a method, locating errors in its attributes.
+fn read_member<R: Read, F: ReadWrite, A: ConstantPoolReadWrite>(cp: &mut MapCp, rd: &mut Counted<R>) -> Result<(F, Cow<'static, str>, Type, Vec<A>)> {
+    let access = F::read_from(rd)?;
+    let name: Cow<'static, str> = read_from!(cp, rd)?;
+    let descriptor: Type = read_from!(cp, rd)?;
+    let count = u16::read_from(rd)?;
+    let mut attrs = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let start = rd.pos;
+        let from = cp.warnings().len();
+        let segment = || Some(PathSegment::Member(name.clone(), descriptor.to_string().into()));
+        attrs.push(A::read_from(cp, rd).map_err(|e| e.located(segment(), start, rd.pos))?);
+        cp.locate_warnings(from, segment(), start);
+    }
+    Ok((access, name, descriptor, attrs))
+}
+
+impl ReadWrite for Class {
+    fn read_from<T: Read>(reader: &mut T) -> Result<Self> {
+        Self::read_inner(reader, false, false).map(|(class, _)| class)
+    }
 
     fn write_to<T: Write>(&self, writer: &mut T) -> Result<()> {
         0xCAFEBABEu32.write_to(writer)?;
         self.version.write_to(writer)?;
-        let mut cp = VecCp::new();
+        // lazily read bodies that are left untouched refer to the original pool, so keep its indices.
+        let source = self.methods.iter().flat_map(|m| &m.attributes).find_map(|a| match a {
+            MethodAttribute::LazyCode(c) if !c.is_modified() => Some(c.pool().clone()),
+            _ => None
+        });
+        let mut cp = source.map_or_else(VecCp::new, VecCp::seeded);
         let mut buf = vec![];
         self.access.write_to(&mut buf)?;
         cp.insert_class(self.name.clone()).write_to(&mut buf)?;
         self.super_name.as_ref().map_or(0, |n| cp.insert_class(n.clone())).write_to(&mut buf)?;
-        (self.interfaces.len() as u16).write_to(&mut buf)?;
+        checked_len::<u16>("interface count", self.interfaces.len())?.write_to(&mut buf)?;
         for i in &self.interfaces {
             cp.insert_class(i.clone()).write_to(&mut buf)?;
         }
-        (self.fields.len() as u16).write_to(&mut buf)?;
+        checked_len::<u16>("field count", self.fields.len())?.write_to(&mut buf)?;
         for f in &self.fields {
             f.write_to(&mut cp, &mut buf)?;
         }
-        (self.methods.len() as u16).write_to(&mut buf)?;
+        checked_len::<u16>("method count", self.methods.len())?.write_to(&mut buf)?;
         for m in &self.methods {
+            if let Type::Method { parameters, .. } = &m.descriptor {
+                let this = !m.access.contains(MethodFlags::ACC_STATIC) as usize;
+                let slots = parameters.iter().map(|p| if p.is_wide() { 2 } else { 1 }).sum::<usize>() + this;
+                if slots > 255 {
+                    return Err(Error::Limit("parameter slots", slots as u64, 255))
+                }
+            }
             m.write_to(&mut cp, &mut buf)?;
         }
-        (self.attributes.len() as u16).write_to(&mut buf)?;
-        let mut bsm = vec![];
+        // the bootstrap methods written are those of the dynamic constants, collected by the constant pool.
+        let mut attributes = vec![];
+        let mut count = 0;
         for a in &self.attributes {
-            if let ClassAttribute::BootstrapMethods(b) = a {
-                bsm.extend_from_slice(b);
-            } else {
-                a.write_to(&mut cp, &mut buf)?;
+            if a.is_written() && !matches!(a, ClassAttribute::BootstrapMethods(_)) {
+                a.write_to(&mut cp, &mut attributes)?;
+                count += 1;
             }
         }
-        if !bsm.is_empty() {
-            let mut i: u16 = 0;
+        if !cp.bsm.is_empty() {
+            let mut i = 0;
             let mut buf2 = vec![];
             while !cp.bsm.is_empty() {
                 let v = cp.bsm;
                 cp.bsm = vec![];
-                i += v.len() as u16;
+                i += v.len();
                 for bsm in v {
                     bsm.write_to(&mut cp, &mut buf2)?;
                 }
             }
-            write_to!(&Cow::Borrowed("BootstrapMethods"), &mut cp, &mut buf)?;
-            (buf2.len() as u32).write_to(&mut buf)?;
-            i.write_to(&mut buf)?;
-            buf.write_all(&buf2)?;
+            write_to!(&Cow::Borrowed("BootstrapMethods"), &mut cp, &mut attributes)?;
+            checked_len::<u32>("attribute length", buf2.len() + 2)?.write_to(&mut attributes)?; // the length includes the count
+            checked_len::<u16>("bootstrap method count", i)?.write_to(&mut attributes)?;
+            attributes.write_all(&buf2)?;
+            count += 1;
         }
+        checked_len::<u16>("class attribute count", count)?.write_to(&mut buf)?;
+        buf.write_all(&attributes)?;
         cp.write_to(writer)?;
         writer.write_all(&buf)?;
         Ok(())
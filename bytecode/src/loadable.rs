@@ -26,6 +26,7 @@ use std::convert::TryFrom;
 
 /// The kind of a method handle. It generally represents an instruction related to a member with one exception.
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash, ReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[tag_type(u8)]
 pub enum MethodHandleKind {
     /// A method handle reading from a virtual field.
@@ -65,6 +66,7 @@ impl TryFrom<u8> for MethodHandleKind {
 ///
 /// There are some restrictions of its kind and its member, one can use [`check`](MethodHandle::check) to check the validity.
 #[derive(Clone, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodHandle {
     /// The kind of the handle.
     pub kind: MethodHandleKind,
@@ -140,7 +142,9 @@ impl ConstantPoolReadWrite for MethodHandle {
         let res = Self {
             kind, member
         };
-        res.check()?;
+        if let Err(e) = res.check() {
+            cp.warn(e)?;
+        }
         Ok(res)
     }
 
@@ -153,6 +157,7 @@ impl ConstantPoolReadWrite for MethodHandle {
 
 /// A constant value that is located in the constant pool which can be loaded onto the stack.
 #[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Constant {
     /// A 32 bit integer.
     I32(i32),
@@ -23,6 +23,7 @@ use crate::prelude::*;
 
 /// A reference to a member.
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MemberRef {
     /// The class holding this member.
     pub owner: Cow<'static, str>,
@@ -52,6 +53,7 @@ impl ConstantPoolReadWrite for MemberRef {
 
 /// Completed
 #[derive(PartialEq, Debug, Clone, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[attr_enum]
 pub enum FieldAttribute {
     Deprecated,
@@ -67,16 +69,19 @@ pub enum FieldAttribute {
 }
 
 #[derive(PartialEq, Debug, Clone, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Field {
     #[use_normal_rw]
     pub access: FieldFlags,
     pub name: Cow<'static, str>,
     pub descriptor: Type,
     #[vec_len_type(u16)]
+    #[attr_vec]
     pub attrs: Vec<FieldAttribute>
 }
 
 #[derive(PartialEq, Debug, Clone, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodParameter {
     #[str_optional]
     name: Option<Cow<'static, str>>,
@@ -85,9 +90,13 @@ pub struct MethodParameter {
 }
 
 #[derive(PartialEq, Debug, Clone, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[attr_enum]
 pub enum MethodAttribute {
     Code(Code),
+    /// A method body that is decoded on first access, read by [`Class::read_lazy`](crate::Class::read_lazy).
+    #[lazy_of(Code)]
+    LazyCode(LazyCode),
     Deprecated,
     Synthetic,
     Signature(MethodSignature),
@@ -105,11 +114,43 @@ pub enum MethodAttribute {
 }
 
 #[derive(PartialEq, Debug, Clone, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Method {
     #[use_normal_rw]
     pub access: MethodFlags,
     pub name: Cow<'static, str>,
     pub descriptor: Type,
     #[vec_len_type(u16)]
+    #[attr_vec]
     pub attributes: Vec<MethodAttribute>
 }
+
+impl Method {
+    /// Returns the body of this method, decoding it if it was read lazily, or `None` if the method has no code.
+    ///
+    /// This fails when a lazily read body cannot be decoded.
+    pub fn code(&self) -> Result<Option<&Code>> {
+        for a in &self.attributes {
+            match a {
+                MethodAttribute::Code(c) => return Ok(Some(c)),
+                MethodAttribute::LazyCode(c) => return c.get().map(Some),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the body of this method mutably, see [`Method::code`].
+    ///
+    /// A lazily read body will be encoded again upon writing.
+    pub fn code_mut(&mut self) -> Result<Option<&mut Code>> {
+        for a in &mut self.attributes {
+            match a {
+                MethodAttribute::Code(c) => return Ok(Some(c)),
+                MethodAttribute::LazyCode(c) => return c.get_mut().map(Some),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+}
@@ -24,6 +24,7 @@
 //!
 //! Refer to the [JVM Spec](https://docs.oracle.com/javase/specs/jvms/se16/html/jvms-4.html#jvms-4.4.7) for more info.
 
+use std::borrow::Cow;
 use std::convert::TryFrom;
 use thiserror::Error;
 
@@ -127,6 +128,17 @@ pub fn modified_utf8_to_string(buf: &[u8]) -> Result<String, MUTFError> {
     Ok(str)
 }
 
+/// Converts a modified utf-8 sequence to a rust string, borrowing from the buffer when possible.
+///
+/// Plain ASCII is encoded the same way in both encodings, so it is borrowed as is; anything else is decoded with [`modified_utf8_to_string`].
+pub fn modified_utf8_to_cow(buf: &[u8]) -> Result<Cow<'_, str>, MUTFError> {
+    if buf.is_ascii() {
+        Ok(Cow::Borrowed(std::str::from_utf8(buf)?))
+    } else {
+        modified_utf8_to_string(buf).map(Cow::Owned)
+    }
+}
+
 /// Converts a string to modified UTF-8.
 ///
 /// This will never error because `&str` is guarenteed to be in UTF-8,
@@ -28,6 +28,7 @@ use crate::prelude::*;
 
 
 #[derive(Clone, Eq, PartialEq, Debug, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Require {
     #[str_type(Module)]
     pub module: Cow<'static, str>,
@@ -38,6 +39,7 @@ pub struct Require {
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Provide {
     #[str_type(Class)]
     pub class: Cow<'static, str>,
@@ -47,6 +49,7 @@ pub struct Provide {
 }
 
 #[derive(Eq, PartialEq, Debug, Clone, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Module {
     #[str_type(Module)]
     pub name: Cow<'static, str>,
@@ -69,6 +72,7 @@ pub struct Module {
 
 
 #[derive(Clone, Eq, PartialEq, Debug, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Export {
     #[str_type(Package)]
     pub package: Cow<'static, str>,
@@ -93,6 +97,7 @@ impl Export {
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, ConstantPoolReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Open {
     #[str_type(Package)]
     pub package: Cow<'static, str>,
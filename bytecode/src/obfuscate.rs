@@ -0,0 +1,373 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Renaming of the classes and members that are not visible outside their package to short names, as done by ProGuard
+//! to make a library smaller and harder to read.
+//!
+//! Classes, fields and methods that are private or package-private are renamed to `a`, `b`, ..., `aa` and so on, and
+//! keep their package, so that package access still holds. Nested classes are renamed inside their outer class, and an
+//! outer class keeps its name when one of its nested classes does, as it is part of their names. A
+//! package-private method and the methods overriding it get the same name, and no method is renamed if one method of
+//! its family is public or protected. Fields related by inheritance get distinct names, and methods only share a name
+//! when their parameters differ, so that a reference through a subclass still resolves to the same member.
+//!
+//! The renaming itself is done by a [`Remapper`], which updates every reference, including the `InnerClasses`,
+//! `EnclosingMethod` and `NestMembers` attributes, signatures and annotations. The [`Mapping`] it returns is written in
+//! the format of ProGuard, which tools such as `retrace` read.
+//!
+//! Members used by serialization, like `serialVersionUID` and `readObject`, are never renamed. Neither are native methods
+//! and the classes declaring them, since JNI links them by the symbol `Java_<class>_<method>`. Other classes and members
+//! found by reflection need [`Keep`] rules.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use crate::keep::Keep;
+use crate::prelude::*;
+use crate::remap::Remapper;
+use crate::Class;
+
+/// The names serialization looks up by reflection.
+const SERIALIZATION: [&str; 7] = ["serialVersionUID", "serialPersistentFields", "writeObject", "readObject", "readObjectNoData", "writeReplace", "readResolve"];
+
+/// A renamed field or method.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemberMapping {
+    /// The descriptor of the member, with the old names of classes.
+    pub descriptor: Type,
+    /// The old name.
+    pub from: Cow<'static, str>,
+    /// The new name.
+    pub to: Cow<'static, str>
+}
+
+/// A class that is renamed or has renamed members.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClassMapping {
+    /// The old internal name.
+    pub from: Cow<'static, str>,
+    /// The new internal name, which is the old one if only members are renamed.
+    pub to: Cow<'static, str>,
+    /// The renamed fields.
+    pub fields: Vec<MemberMapping>,
+    /// The renamed methods.
+    pub methods: Vec<MemberMapping>
+}
+
+/// What an [`Obfuscator`] renamed. It is displayed in the format of ProGuard mapping files:
+///
+/// ```text
+/// com.example.Main -> com.example.Main:
+///     int count -> a
+///     void log(java.lang.String) -> a
+/// com.example.Helper -> com.example.a:
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Mapping {
+    /// The classes that are renamed or have renamed members, in the order they were given.
+    pub classes: Vec<ClassMapping>
+}
+
+impl Display for Mapping {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for c in &self.classes {
+            writeln!(f, "{} -> {}:", c.from.replace('/', "."), c.to.replace('/', "."))?;
+            for m in &c.fields {
+                writeln!(f, "    {} {} -> {}", java_type(&m.descriptor), m.from, m.to)?;
+            }
+            for m in &c.methods {
+                if let Type::Method { parameters, ret } = &m.descriptor {
+                    let ret = ret.as_deref().map_or_else(|| "void".to_owned(), java_type);
+                    let parameters = parameters.iter().map(java_type).collect::<Vec<_>>().join(",");
+                    writeln!(f, "    {} {}({}) -> {}", ret, m.from, parameters, m.to)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The name of a type in Java source, such as `int[]` or `java.lang.String`.
+fn java_type(ty: &Type) -> String {
+    match ty {
+        Type::Byte => "byte".to_owned(),
+        Type::Char => "char".to_owned(),
+        Type::Double => "double".to_owned(),
+        Type::Float => "float".to_owned(),
+        Type::Int => "int".to_owned(),
+        Type::Long => "long".to_owned(),
+        Type::Boolean => "boolean".to_owned(),
+        Type::Short => "short".to_owned(),
+        Type::Ref(name) => name.replace('/', "."),
+        Type::ArrayRef(dim, inner) => format!("{}{}", java_type(inner), "[]".repeat(*dim as usize)),
+        Type::Method { .. } => ty.to_string()
+    }
+}
+
+/// The `n`th short name: `a` to `z`, then `aa`, `ab` and so on.
+fn short_name(mut n: usize) -> String {
+    let mut name = vec![];
+    loop {
+        name.push(b'a' + (n % 26) as u8);
+        if n < 26 {
+            break
+        }
+        n = n / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap_or_default()
+}
+
+/// The first short name that `free` accepts.
+fn first_free<F: FnMut(&str) -> bool>(mut free: F) -> String {
+    (0..).map(short_name).find(|n| free(n)).unwrap_or_default()
+}
+
+/// The parameter types of a method descriptor.
+fn parameters(descriptor: &Type) -> &[Type] {
+    match descriptor {
+        Type::Method { parameters, .. } => parameters,
+        _ => &[]
+    }
+}
+
+/// Renames private and package-private classes and members.
+#[derive(Clone, Debug, Default)]
+pub struct Obfuscator {
+    rules: Vec<Keep>
+}
+
+impl Obfuscator {
+    /// Creates an obfuscator that renames everything it can.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the names of what a rule matches: the classes, or the fields and methods and their classes.
+    pub fn keep(mut self, rule: Keep) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    fn keeps_member<F: Fn(&Keep) -> bool>(&self, class: &Class, matches: F) -> bool {
+        self.rules.iter().any(|r| r.keeps_members() && r.matches_class(class) && matches(r))
+    }
+
+    /// Renames a set of classes and the references between them, returning the mapping from the old names.
+    ///
+    /// Classes outside the set are not known, so classes of the set are expected not to be referred to by their
+    /// private or package-private names from elsewhere. This fails if a lazily read method body cannot be decoded.
+    pub fn obfuscate(&self, classes: &mut [Class]) -> Result<Mapping> {
+        let plan = Plan::new(self, classes);
+        let mut remapper = Remapper::new();
+        for (from, to) in &plan.classes {
+            remapper.map_class(from.clone(), to.clone());
+        }
+        // members that keep their name are mapped to it, so that they are not renamed like a member of a supertype.
+        for (i, class) in classes.iter().enumerate() {
+            for (j, f) in class.fields.iter().enumerate() {
+                let to = plan.fields.get(&(i, j)).cloned().unwrap_or_else(|| f.name.clone());
+                remapper.map_field(class.name.clone(), f.name.clone(), to);
+            }
+            for (j, m) in class.methods.iter().enumerate().filter(|(_, m)| !m.name.starts_with('<')) {
+                let to = plan.methods.get(&(i, j)).cloned().unwrap_or_else(|| m.name.clone());
+                remapper.map_method(class.name.clone(), m.name.clone(), m.descriptor.clone(), to);
+            }
+        }
+        let mut mapping = Mapping::default();
+        for (i, class) in classes.iter().enumerate() {
+            let to = remapper.class_name(&class.name).unwrap_or_else(|| class.name.clone());
+            let renamed = |members: &HashMap<(usize, usize), Cow<'static, str>>, j: usize, name: &Cow<'static, str>, descriptor: &Type| {
+                members.get(&(i, j)).map(|to| MemberMapping { descriptor: descriptor.clone(), from: name.clone(), to: to.clone() })
+            };
+            let fields = class.fields.iter().enumerate().filter_map(|(j, f)| renamed(&plan.fields, j, &f.name, &f.descriptor)).collect::<Vec<_>>();
+            let methods = class.methods.iter().enumerate().filter_map(|(j, m)| renamed(&plan.methods, j, &m.name, &m.descriptor)).collect::<Vec<_>>();
+            if to != class.name || !fields.is_empty() || !methods.is_empty() {
+                mapping.classes.push(ClassMapping { from: class.name.clone(), to, fields, methods });
+            }
+        }
+        remapper.remap_all(classes)?;
+        Ok(mapping)
+    }
+}
+
+/// The new names, by class and member index.
+struct Plan {
+    classes: Vec<(Cow<'static, str>, Cow<'static, str>)>,
+    fields: HashMap<(usize, usize), Cow<'static, str>>,
+    methods: HashMap<(usize, usize), Cow<'static, str>>
+}
+
+/// Sets that are merged, by index.
+struct Sets(Vec<usize>);
+
+impl Sets {
+    fn new(len: usize) -> Self {
+        Sets((0..len).collect())
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.0[i] != i {
+            self.0[i] = self.0[self.0[i]];
+            i = self.0[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.0[a.max(b)] = a.min(b);
+    }
+}
+
+impl Plan {
+    fn new(obfuscator: &Obfuscator, classes: &[Class]) -> Self {
+        let by_name = classes.iter().enumerate().map(|(i, c)| (c.name.as_ref(), i)).collect::<HashMap<_, _>>();
+        let supertypes = |c: &Class| c.super_name.iter().chain(c.interfaces.iter()).filter_map(|s| by_name.get(s.as_ref()).copied()).collect::<Vec<_>>();
+        // classes related by inheritance, where a member may be referred to through another class.
+        let mut components = Sets::new(classes.len());
+        for (i, c) in classes.iter().enumerate() {
+            for s in supertypes(c) {
+                components.union(i, s);
+            }
+        }
+        Plan {
+            classes: Self::classes(classes, |c| obfuscator.rules.iter().any(|r| r.matches_class(c))),
+            fields: Self::fields(classes, &mut components, |c, f| obfuscator.keeps_member(c, |r| r.matches_field(f))),
+            methods: Self::methods(classes, &mut components, &supertypes, |c, m| obfuscator.keeps_member(c, |r| r.matches_method(m)))
+        }
+    }
+
+    fn classes<K: Fn(&Class) -> bool>(classes: &[Class], kept: K) -> Vec<(Cow<'static, str>, Cow<'static, str>)> {
+        let nested = |c: &Class| c.attributes.iter().any(|a| matches!(a, ClassAttribute::InnerClasses(i) if i.iter().any(|i| i.inner_fqname == c.name)));
+        // the namespace of a nested class is its outer class, and the package for others.
+        let namespace = |c: &Class| -> (String, String) {
+            let split = if nested(c) { c.name.rsplit_once('$').map(|(o, s)| (o, '$', s)) } else { None };
+            let (outer, separator, simple) = split.or_else(|| c.name.rsplit_once('/').map(|(p, s)| (p, '/', s))).unwrap_or(("", '/', &c.name));
+            (if outer.is_empty() { String::new() } else { format!("{}{}", outer, separator) }, simple.to_owned())
+        };
+        let renamed = |c: &Class| {
+            let visible = c.access.contains(ClassFlags::ACC_PUBLIC) || c.attributes.iter().any(|a| matches!(a, ClassAttribute::InnerClasses(i)
+                if i.iter().any(|i| i.inner_fqname == c.name && i.inner_access.intersects(InnerClassFlags::ACC_PUBLIC | InnerClassFlags::ACC_PROTECTED))));
+            let special = c.access.contains(ClassFlags::ACC_MODULE) || c.name.ends_with("package-info") || c.name.ends_with("module-info");
+            let native = c.methods.iter().any(|m| m.access.contains(MethodFlags::ACC_NATIVE));
+            !visible && !special && !native && !kept(c)
+        };
+        // a nested class is named after its outer classes, which keep their names when it does.
+        let outers = classes.iter().filter(|c| nested(c) && !renamed(c))
+            .flat_map(|c| c.name.match_indices('$').map(move |(i, _)| c.name[..i].to_owned()))
+            .collect::<HashSet<_>>();
+        let renamed = |c: &Class| renamed(c) && !outers.contains(c.name.as_ref());
+        let mut used = HashSet::new();
+        for c in classes.iter().filter(|c| !renamed(c)) {
+            used.insert(namespace(c));
+        }
+        // outer classes are renamed first, since nested classes are named after them.
+        let mut order = classes.iter().filter(|c| renamed(c)).collect::<Vec<_>>();
+        order.sort_by_key(|c| (c.name.matches('$').count(), c.name.clone()));
+        let mut new_names = HashMap::<String, String>::new();
+        let mut renames = vec![];
+        for c in order {
+            let (prefix, _) = namespace(c);
+            let name = first_free(|n| !used.contains(&(prefix.clone(), n.to_owned())));
+            used.insert((prefix.clone(), name.clone()));
+            let outer = prefix.strip_suffix('$').and_then(|o| new_names.get(o)).map_or(prefix.clone(), |o| format!("{}$", o));
+            let to = format!("{}{}", outer, name);
+            new_names.insert(c.name.to_string(), to.clone());
+            renames.push((c.name.clone(), Cow::Owned(to)));
+        }
+        renames
+    }
+
+    fn fields<K: Fn(&Class, &Field) -> bool>(classes: &[Class], components: &mut Sets, kept: K) -> HashMap<(usize, usize), Cow<'static, str>> {
+        let renamed = |c: &Class, f: &Field| {
+            let ambiguous = c.fields.iter().filter(|g| g.name == f.name).count() > 1;
+            !f.access.intersects(FieldFlags::ACC_PUBLIC | FieldFlags::ACC_PROTECTED) && !SERIALIZATION.contains(&f.name.as_ref()) && !ambiguous && !kept(c, f)
+        };
+        let mut used = HashSet::new();
+        for (i, c) in classes.iter().enumerate() {
+            for f in c.fields.iter().filter(|f| !renamed(c, f)) {
+                used.insert((components.find(i), f.name.to_string()));
+            }
+        }
+        let mut names = HashMap::new();
+        for (i, c) in classes.iter().enumerate() {
+            let component = components.find(i);
+            for (j, _) in c.fields.iter().enumerate().filter(|(_, f)| renamed(c, f)) {
+                let name = first_free(|n| !used.contains(&(component, n.to_owned())));
+                used.insert((component, name.clone()));
+                names.insert((i, j), Cow::Owned(name));
+            }
+        }
+        names
+    }
+
+    fn methods<S, K>(classes: &[Class], components: &mut Sets, supertypes: &S, kept: K) -> HashMap<(usize, usize), Cow<'static, str>>
+        where S: Fn(&Class) -> Vec<usize>, K: Fn(&Class, &Method) -> bool {
+        let ids = classes.iter().enumerate().flat_map(|(i, c)| (0..c.methods.len()).map(move |j| (i, j))).collect::<Vec<_>>();
+        let index = ids.iter().enumerate().map(|(k, &id)| (id, k)).collect::<HashMap<_, _>>();
+        let overridable = |m: &Method| !m.name.starts_with('<') && !m.access.intersects(MethodFlags::ACC_STATIC | MethodFlags::ACC_PRIVATE);
+        // a method and the methods it overrides form a family, which is renamed as a whole.
+        let mut families = Sets::new(ids.len());
+        for (i, c) in classes.iter().enumerate() {
+            let mut seen = HashSet::new();
+            let mut queue = supertypes(c);
+            while let Some(s) = queue.pop() {
+                if !seen.insert(s) {
+                    continue
+                }
+                queue.extend(supertypes(&classes[s]));
+                for (j, m) in c.methods.iter().enumerate().filter(|(_, m)| overridable(m)) {
+                    if let Some(k) = classes[s].methods.iter().position(|n| overridable(n) && n.name == m.name && n.descriptor == m.descriptor) {
+                        families.union(index[&(i, j)], index[&(s, k)]);
+                    }
+                }
+            }
+        }
+        let mut fixed = HashSet::new();
+        for (k, &(i, j)) in ids.iter().enumerate() {
+            let (c, m) = (&classes[i], &classes[i].methods[j]);
+            if m.name.starts_with('<') || m.access.intersects(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_PROTECTED | MethodFlags::ACC_NATIVE)
+                || SERIALIZATION.contains(&m.name.as_ref()) || kept(c, m) {
+                fixed.insert(families.find(k));
+            }
+        }
+        let mut used = HashSet::new();
+        for (k, &(i, j)) in ids.iter().enumerate() {
+            if fixed.contains(&families.find(k)) {
+                let m = &classes[i].methods[j];
+                used.insert((components.find(i), m.name.to_string(), parameters(&m.descriptor)));
+            }
+        }
+        let mut family_names = HashMap::new();
+        let mut names = HashMap::new();
+        for (k, &(i, j)) in ids.iter().enumerate() {
+            let family = families.find(k);
+            if fixed.contains(&family) {
+                continue
+            }
+            let m = &classes[i].methods[j];
+            let component = components.find(i);
+            let name = family_names.entry(family).or_insert_with(|| {
+                let name = first_free(|n| !used.contains(&(component, n.to_owned(), parameters(&m.descriptor))));
+                used.insert((component, name.clone(), parameters(&m.descriptor)));
+                name
+            });
+            names.insert((i, j), Cow::Owned(name.clone()));
+        }
+        names
+    }
+}
@@ -0,0 +1,646 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Searching method bodies for sequences of instructions.
+//!
+//! A [`Pattern`] is a sequence of steps: a [`Matcher`] matches one instruction, and a gap skips instructions. Labels
+//! and line numbers are not instructions and are skipped. A pattern still does not go past a label that a jump, a
+//! switch or an exception handler targets, since other paths join the code there, except through the gaps made to
+//! cross them with [`Pattern::gap_across_labels`]. Names, owners, descriptors and strings are matched with globs, where
+//! `*` stands for any text, and the member, constant, type or local variable of a matched instruction can be captured
+//! under a name.
+//!
+//! Patterns are built with the methods of [`Pattern`], or parsed from steps separated by `;`:
+//!
+//! ```text
+//! push string @name; ..3; invoke static java/lang/Class.forName @call
+//! ```
+//!
+//! | Step | Matches |
+//! |------|---------|
+//! | `_` | any instruction |
+//! | `..`, `..N` | any number of instructions, or at most `N` |
+//! | `...` | any number of instructions, across jump targets |
+//! | `invoke [static\|virtual\|special\|interface] OWNER.NAME [DESCRIPTOR]` | a method call |
+//! | `invoke dynamic NAME [DESCRIPTOR]` | an `invokedynamic` instruction |
+//! | `get [static] OWNER.NAME [DESCRIPTOR]`, `put ...` | a field access |
+//! | `push [INTEGER\|"STRING"\|KIND]` | a constant, where `KIND` is `int`, `long`, `float`, `double`, `string`, `class`, `handle`, `type` or `dynamic` |
+//! | `push null` | `aconst_null` |
+//! | `new TYPE`, `checkcast TYPE`, `instanceof TYPE` | an instruction on a class or array type |
+//! | `load [N]`, `store [N]`, `iinc [N]` | an access to a local variable |
+//! | `jump`, `switch`, `return`, `throw`, `monitorenter`, `monitorexit` | those instructions |
+//!
+//! A step ends with `@NAME` to capture what the instruction refers to.
+
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::prelude::*;
+use crate::Class;
+
+/// Whether a glob, where `*` stands for any text, matches the whole text.
+pub fn glob(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false
+    };
+    let parts = parts.collect::<Vec<_>>();
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(at) => rest = &rest[at + part.len()..],
+                    None => return false
+                }
+            }
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
+
+/// A kind of method call.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum InvokeKind {
+    Static,
+    Virtual,
+    Special,
+    Interface
+}
+
+/// Globs matching the owner, the name and the descriptor of a member.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct MemberPattern {
+    pub owner: Cow<'static, str>,
+    pub name: Cow<'static, str>,
+    pub descriptor: Cow<'static, str>
+}
+
+impl MemberPattern {
+    /// Matches the members with the given owner and name, and any descriptor.
+    pub fn new<O: Into<Cow<'static, str>>, N: Into<Cow<'static, str>>>(owner: O, name: N) -> Self {
+        MemberPattern { owner: owner.into(), name: name.into(), descriptor: "*".into() }
+    }
+
+    /// Only matches the members with a descriptor matching the glob.
+    pub fn descriptor<D: Into<Cow<'static, str>>>(mut self, descriptor: D) -> Self {
+        self.descriptor = descriptor.into();
+        self
+    }
+
+    pub fn matches(&self, member: &MemberRef) -> bool {
+        glob(&self.owner, &member.owner) && glob(&self.name, &member.name) && glob(&self.descriptor, &member.descriptor.to_string())
+    }
+}
+
+/// Matches the constants pushed by `ldc` and the instructions pushing numbers.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ConstantPattern {
+    Any,
+    /// An `int` or a `long` with this value.
+    Integer(i64),
+    /// A string matching a glob.
+    String(Cow<'static, str>),
+    /// A class, or an array type, matching a glob.
+    Class(Cow<'static, str>),
+    Int,
+    Long,
+    Float,
+    Double,
+    MethodHandle,
+    MethodType,
+    /// A constant computed by a bootstrap method.
+    Dynamic
+}
+
+impl ConstantPattern {
+    pub fn matches(&self, constant: &OrDynamic<Constant>) -> bool {
+        match (self, constant) {
+            (ConstantPattern::Any, _) | (ConstantPattern::Dynamic, OrDynamic::Dynamic(_)) => true,
+            (_, OrDynamic::Dynamic(_)) | (ConstantPattern::Dynamic, _) => false,
+            (_, OrDynamic::Static(c)) => match (self, c) {
+                (ConstantPattern::Integer(i), Constant::I32(c)) => *i == *c as i64,
+                (ConstantPattern::Integer(i), Constant::I64(c)) => i == c,
+                (ConstantPattern::String(g), Constant::String(s)) | (ConstantPattern::Class(g), Constant::Class(s)) => glob(g, s),
+                (ConstantPattern::Int, Constant::I32(_)) | (ConstantPattern::Long, Constant::I64(_)) |
+                (ConstantPattern::Float, Constant::F32(_)) | (ConstantPattern::Double, Constant::F64(_)) |
+                (ConstantPattern::MethodHandle, Constant::MethodHandle(_)) | (ConstantPattern::MethodType, Constant::MethodType(_)) => true,
+                _ => false
+            }
+        }
+    }
+}
+
+/// Matches one instruction.
+#[derive(Clone, Debug)]
+pub enum Matcher {
+    Any,
+    /// A method call of any kind if `None`, except `invokedynamic`.
+    Invoke(Option<InvokeKind>, MemberPattern),
+    /// An `invokedynamic` instruction with a name and a descriptor matching the globs.
+    InvokeDynamic { name: Cow<'static, str>, descriptor: Cow<'static, str> },
+    /// A field access, reading or writing if `None`, static or not if `None`.
+    Field(Option<GetOrPut>, Option<MemberType>, MemberPattern),
+    Push(ConstantPattern),
+    PushNull,
+    /// `new` with a class matching a glob.
+    New(Cow<'static, str>),
+    /// `checkcast` with a class or an array type matching a glob.
+    CheckCast(Cow<'static, str>),
+    InstanceOf(Cow<'static, str>),
+    /// A load of a local variable, of any one if `None`.
+    Load(Option<u16>),
+    Store(Option<u16>),
+    Increment(Option<u16>),
+    /// A jump, conditional or not.
+    Jump,
+    Switch,
+    Return,
+    Throw,
+    Monitor(MonitorOperation),
+    /// The instructions a function accepts.
+    Custom(fn(&Instruction) -> bool)
+}
+
+fn class_name(ty: &OrDynamic<ClassType>) -> Option<Cow<'static, str>> {
+    match ty {
+        OrDynamic::Static(ty) => Some(ty.clone().into()),
+        OrDynamic::Dynamic(_) => None
+    }
+}
+
+impl Matcher {
+    pub fn matches(&self, insn: &Instruction) -> bool {
+        match (self, insn) {
+            (Matcher::Any, _) | (Matcher::PushNull, Instruction::PushNull) | (Matcher::Jump, Instruction::Jump(..)) |
+            (Matcher::Switch, Instruction::TableSwitch { .. } | Instruction::LookupSwitch { .. }) |
+            (Matcher::Return, Instruction::Return(_)) | (Matcher::Throw, Instruction::Throw) => true,
+            (Matcher::Invoke(kind, member), insn) => {
+                let (k, m) = match insn {
+                    Instruction::InvokeExact(MemberType::Static, OrDynamic::Static(m)) => (InvokeKind::Static, m),
+                    Instruction::InvokeExact(MemberType::Virtual, OrDynamic::Static(m)) => (InvokeKind::Virtual, m),
+                    Instruction::InvokeSpecial(OrDynamic::Static(m)) => (InvokeKind::Special, m),
+                    Instruction::InvokeInterface(OrDynamic::Static(m), _) => (InvokeKind::Interface, m),
+                    _ => return false
+                };
+                kind.is_none_or(|kind| kind == k) && member.matches(m)
+            }
+            (Matcher::InvokeDynamic { name, descriptor }, Instruction::InvokeDynamic(d)) => {
+                glob(name, &d.name) && glob(descriptor, &d.descriptor.to_string())
+            }
+            (Matcher::Field(op, kind, member), Instruction::Field(o, k, OrDynamic::Static(m))) => {
+                op.is_none_or(|op| op == *o) && kind.is_none_or(|kind| kind == *k) && member.matches(m)
+            }
+            (Matcher::Push(c), Instruction::Push(constant)) => c.matches(constant),
+            (Matcher::New(g), Instruction::New(OrDynamic::Static(name))) => glob(g, name),
+            (Matcher::CheckCast(g), Instruction::CheckCast(ty)) | (Matcher::InstanceOf(g), Instruction::InstanceOf(ty)) => {
+                class_name(ty).is_some_and(|name| glob(g, &name))
+            }
+            (Matcher::Load(index), Instruction::LocalVariable(LoadOrStore::Load, _, i)) |
+            (Matcher::Store(index), Instruction::LocalVariable(LoadOrStore::Store, _, i)) |
+            (Matcher::Increment(index), Instruction::IntIncrement(i, _)) => index.is_none_or(|index| index == *i),
+            (Matcher::Monitor(op), Instruction::Monitor(o)) => op == o,
+            (Matcher::Custom(f), insn) => f(insn),
+            _ => false
+        }
+    }
+}
+
+/// What a captured instruction refers to.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Capture {
+    /// The method or field of a call or field access.
+    Member(MemberRef),
+    /// The call site of an `invokedynamic` instruction, or a constant computed by a bootstrap method.
+    Dynamic(Dynamic),
+    Constant(Constant),
+    /// The type of a `new`, `checkcast`, `instanceof` or `anewarray` instruction.
+    Type(ClassType),
+    /// The index of a local variable.
+    Local(u16),
+    /// Any other instruction.
+    Instruction(Instruction)
+}
+
+impl Capture {
+    fn of(insn: &Instruction) -> Capture {
+        match insn {
+            Instruction::Field(_, _, OrDynamic::Static(m)) | Instruction::InvokeExact(_, OrDynamic::Static(m)) |
+            Instruction::InvokeSpecial(OrDynamic::Static(m)) | Instruction::InvokeInterface(OrDynamic::Static(m), _) => Capture::Member(m.clone()),
+            Instruction::InvokeDynamic(d) | Instruction::Push(OrDynamic::Dynamic(d)) => Capture::Dynamic(d.clone()),
+            Instruction::Push(OrDynamic::Static(c)) => Capture::Constant(c.clone()),
+            Instruction::New(OrDynamic::Static(name)) => Capture::Type(ClassType::Object(name.clone())),
+            Instruction::CheckCast(OrDynamic::Static(ty)) | Instruction::InstanceOf(OrDynamic::Static(ty)) => Capture::Type(ty.clone()),
+            Instruction::LocalVariable(_, _, i) | Instruction::IntIncrement(i, _) => Capture::Local(*i),
+            insn => Capture::Instruction(insn.clone())
+        }
+    }
+}
+
+impl Display for Capture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Capture::Member(m) => write!(f, "{}.{}:{}", m.owner, m.name, m.descriptor),
+            Capture::Dynamic(d) => write!(f, "{}:{}", d.name, d.descriptor),
+            Capture::Constant(Constant::I32(i)) => write!(f, "{}", i),
+            Capture::Constant(Constant::I64(l)) => write!(f, "{}L", l),
+            Capture::Constant(Constant::F32(x)) => write!(f, "{}F", x),
+            Capture::Constant(Constant::F64(x)) => write!(f, "{}D", x),
+            Capture::Constant(Constant::String(s)) => write!(f, "{:?}", s),
+            Capture::Constant(Constant::Class(c)) => f.write_str(c),
+            Capture::Constant(Constant::Member(m)) => write!(f, "{}.{}:{}", m.owner, m.name, m.descriptor),
+            Capture::Constant(Constant::MethodHandle(h)) => write!(f, "{:?} {}.{}:{}", h.kind, h.member.owner, h.member.name, h.member.descriptor),
+            Capture::Constant(Constant::MethodType(ty)) => write!(f, "{}", ty),
+            Capture::Type(ty) => f.write_str(&Cow::from(ty.clone())),
+            Capture::Local(i) => write!(f, "local {}", i),
+            Capture::Instruction(insn) => write!(f, "{:?}", insn)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    Insn(Matcher, Option<String>),
+    Gap { max: Option<usize>, across: bool }
+}
+
+/// A sequence of instructions to search for.
+#[derive(Clone, Debug, Default)]
+pub struct Pattern {
+    steps: Vec<Step>
+}
+
+/// Where a pattern matches in a method body.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Occurrence {
+    /// The indices in [`Code::code`] of the first matched instruction and of the instruction after the last one.
+    pub range: Range<usize>,
+    /// The line of the first matched instruction, if the code has line numbers.
+    pub line: Option<u16>,
+    /// The captured steps, in the order of the pattern.
+    pub captures: Vec<(String, Capture)>
+}
+
+impl Occurrence {
+    pub fn capture(&self, name: &str) -> Option<&Capture> {
+        self.captures.iter().find(|(n, _)| n == name).map(|(_, c)| c)
+    }
+}
+
+/// Where a pattern matches in a class.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Match {
+    pub class: Cow<'static, str>,
+    pub method: Cow<'static, str>,
+    pub descriptor: Type,
+    pub occurrence: Occurrence
+}
+
+impl Display for Match {
+    /// Displays the match on one line, with the captures separated by tabs.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Occurrence { range, line, captures } = &self.occurrence;
+        write!(f, "{}.{}{} [{}..{}]", self.class, self.method, self.descriptor, range.start, range.end)?;
+        if let Some(line) = line {
+            write!(f, " line {}", line)?;
+        }
+        for (name, capture) in captures {
+            write!(f, "\t{}={}", name, capture)?;
+        }
+        Ok(())
+    }
+}
+
+/// The instructions of a method body, without labels and line numbers.
+struct Subject<'a> {
+    code: &'a [Instruction],
+    /// The index of each instruction in the code.
+    indices: Vec<usize>,
+    /// Whether code joins right before each instruction.
+    joins: Vec<bool>
+}
+
+impl Subject<'_> {
+    fn new(code: &Code) -> Subject<'_> {
+        let mut targets = HashSet::new();
+        for insn in &code.code {
+            match insn {
+                Instruction::Jump(_, l) | Instruction::Jsr(l) => {
+                    targets.insert(*l);
+                }
+                Instruction::TableSwitch { default, offsets, .. } => targets.extend(offsets.iter().chain(Some(default)).copied()),
+                Instruction::LookupSwitch { default, table } => targets.extend(table.values().chain(Some(default)).copied()),
+                _ => {}
+            }
+        }
+        targets.extend(code.catches.iter().map(|c| c.handler));
+        let (mut indices, mut joins) = (vec![], vec![]);
+        let mut join = false;
+        for (i, insn) in code.code.iter().enumerate() {
+            match insn {
+                Instruction::Label(l) => join |= targets.contains(l),
+                Instruction::LineNumber(_) => {}
+                _ => {
+                    indices.push(i);
+                    joins.push(std::mem::take(&mut join));
+                }
+            }
+        }
+        Subject { code: &code.code, indices, joins }
+    }
+}
+
+impl Pattern {
+    pub fn new() -> Self {
+        Pattern::default()
+    }
+
+    /// Matches an instruction.
+    pub fn insn(mut self, matcher: Matcher) -> Self {
+        self.steps.push(Step::Insn(matcher, None));
+        self
+    }
+
+    /// Matches an instruction and captures what it refers to under a name.
+    pub fn capture<S: Into<String>>(mut self, name: S, matcher: Matcher) -> Self {
+        self.steps.push(Step::Insn(matcher, Some(name.into())));
+        self
+    }
+
+    /// Skips any number of instructions, or at most `max`. The instructions after the gap are the nearest ones that
+    /// match the rest of the pattern.
+    pub fn gap(mut self, max: Option<usize>) -> Self {
+        self.steps.push(Step::Gap { max, across: false });
+        self
+    }
+
+    /// Skips any number of instructions, going past the labels other code jumps to.
+    pub fn gap_across_labels(mut self) -> Self {
+        self.steps.push(Step::Gap { max: None, across: true });
+        self
+    }
+
+    /// Matches the steps from `step` at the instruction `at`, returning the position after the last matched
+    /// instruction. `free` is whether the instruction at `at` may follow a label that other code jumps to.
+    ///
+    /// Whether the steps match does not depend on the captures so far, so `failed` remembers the `(step, at, free)`
+    /// that did not match, which keeps patterns with several gaps from backtracking exponentially.
+    fn matches_at(&self, subject: &Subject, step: usize, at: usize, free: bool, failed: &mut HashSet<(usize, usize, bool)>, captures: &mut Vec<(String, Capture)>) -> Option<usize> {
+        let n = subject.indices.len();
+        let reachable = |k: usize, free: bool| k < n && (free || !subject.joins[k]);
+        if failed.contains(&(step, at, free)) {
+            return None
+        }
+        let end = match self.steps.get(step) {
+            None => Some(at),
+            Some(Step::Insn(matcher, name)) => {
+                if !reachable(at, free) {
+                    return None
+                }
+                let insn = &subject.code[subject.indices[at]];
+                if !matcher.matches(insn) {
+                    return None
+                }
+                if let Some(name) = name {
+                    captures.push((name.clone(), Capture::of(insn)));
+                }
+                let end = self.matches_at(subject, step + 1, at + 1, false, failed, captures);
+                if end.is_none() && name.is_some() {
+                    captures.pop();
+                }
+                end
+            }
+            Some(Step::Gap { max, across }) => {
+                let mut k = at;
+                loop {
+                    if let Some(end) = self.matches_at(subject, step + 1, k, (free && k == at) || *across, failed, captures) {
+                        break Some(end)
+                    }
+                    if max.is_some_and(|max| k - at == max) || !reachable(k, (free && k == at) || *across) {
+                        break None
+                    }
+                    k += 1;
+                }
+            }
+        };
+        if end.is_none() {
+            failed.insert((step, at, free));
+        }
+        end
+    }
+
+    /// Finds where the pattern matches in a method body, without overlaps.
+    pub fn find(&self, code: &Code) -> Vec<Occurrence> {
+        let mut found = vec![];
+        if !self.steps.iter().any(|s| matches!(s, Step::Insn(..))) {
+            return found
+        }
+        let subject = Subject::new(code);
+        let mut failed = HashSet::new();
+        let mut at = 0;
+        while at < subject.indices.len() {
+            let mut captures = vec![];
+            match self.matches_at(&subject, 0, at, true, &mut failed, &mut captures) {
+                Some(end) if end > at => {
+                    let range = subject.indices[at]..subject.indices[end - 1] + 1;
+                    let line = code.code[..range.start].iter().rev().find_map(|i| match i {
+                        Instruction::LineNumber(l) => Some(*l),
+                        _ => None
+                    });
+                    found.push(Occurrence { range, line, captures });
+                    at = end;
+                }
+                _ => at += 1
+            }
+        }
+        found
+    }
+
+    /// Finds where the pattern matches in the methods of a class.
+    ///
+    /// This fails when a lazily decoded method body cannot be decoded.
+    pub fn search_class(&self, class: &Class) -> Result<Vec<Match>> {
+        let mut found = vec![];
+        for m in &class.methods {
+            if let Some(code) = m.code()? {
+                found.extend(self.find(code).into_iter().map(|occurrence| Match {
+                    class: class.name.clone(),
+                    method: m.name.clone(),
+                    descriptor: m.descriptor.clone(),
+                    occurrence
+                }));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Finds where the pattern matches in the methods of many classes.
+    pub fn search<'a, I: IntoIterator<Item = &'a Class>>(&self, classes: I) -> Result<Vec<Match>> {
+        let mut found = vec![];
+        for class in classes {
+            found.extend(self.search_class(class)?);
+        }
+        Ok(found)
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::Invalid("pattern", message.into())
+}
+
+/// Splits text at a separator, or at whitespace if `None`, outside of quoted strings.
+fn split(text: &str, separator: Option<char>) -> Vec<&str> {
+    let mut parts = vec![];
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in text.char_indices() {
+        if quoted {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            quoted = true;
+        } else if separator.map_or(c.is_whitespace(), |s| c == s) {
+            parts.push(&text[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    parts.push(&text[start..]);
+    parts.into_iter().filter(|p| separator.is_some() || !p.is_empty()).collect()
+}
+
+fn unquote(token: &str) -> Option<String> {
+    let inner = token.strip_prefix('"')?.strip_suffix('"')?;
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        text.push(if c == '\\' { chars.next()? } else { c });
+    }
+    Some(text)
+}
+
+fn member(tokens: &[&str]) -> Result<MemberPattern> {
+    let (spec, descriptor) = match tokens {
+        [spec] => (*spec, None),
+        [spec, descriptor] => (*spec, Some(*descriptor)),
+        _ => return Err(invalid(format!("expected OWNER.NAME [DESCRIPTOR], found `{}`", tokens.join(" "))))
+    };
+    let (owner, name) = spec.rsplit_once('.').ok_or_else(|| invalid(format!("expected OWNER.NAME, found `{}`", spec)))?;
+    let pattern = MemberPattern::new(owner.to_string(), name.to_string());
+    Ok(match descriptor {
+        Some(d) => pattern.descriptor(d.to_string()),
+        None => pattern
+    })
+}
+
+fn index(tokens: &[&str]) -> Result<Option<u16>> {
+    match tokens {
+        [] => Ok(None),
+        [i] => i.parse().map(Some).map_err(|_| invalid(format!("expected a local variable index, found `{}`", i))),
+        _ => Err(invalid(format!("unexpected `{}`", tokens[1..].join(" "))))
+    }
+}
+
+fn matcher(tokens: &[&str]) -> Result<Matcher> {
+    let single = |name: &str| match tokens {
+        [_, name] => Ok(name.to_string()),
+        _ => Err(invalid(format!("`{}` takes one glob", name)))
+    };
+    Ok(match tokens {
+        ["_"] => Matcher::Any,
+        ["invoke", "dynamic", name] => Matcher::InvokeDynamic { name: name.to_string().into(), descriptor: "*".into() },
+        ["invoke", "dynamic", name, descriptor] => Matcher::InvokeDynamic { name: name.to_string().into(), descriptor: descriptor.to_string().into() },
+        ["invoke", kind @ ("static" | "virtual" | "special" | "interface"), rest @ ..] => {
+            let kind = match *kind {
+                "static" => InvokeKind::Static,
+                "virtual" => InvokeKind::Virtual,
+                "special" => InvokeKind::Special,
+                _ => InvokeKind::Interface
+            };
+            Matcher::Invoke(Some(kind), member(rest)?)
+        }
+        ["invoke", rest @ ..] => Matcher::Invoke(None, member(rest)?),
+        [op @ ("get" | "put"), rest @ ..] => {
+            let op = if *op == "get" { GetOrPut::Get } else { GetOrPut::Put };
+            match rest {
+                ["static", rest @ ..] => Matcher::Field(Some(op), Some(MemberType::Static), member(rest)?),
+                rest => Matcher::Field(Some(op), None, member(rest)?)
+            }
+        }
+        ["push"] => Matcher::Push(ConstantPattern::Any),
+        ["push", "null"] => Matcher::PushNull,
+        ["push", value] => Matcher::Push(match *value {
+            "int" => ConstantPattern::Int,
+            "long" => ConstantPattern::Long,
+            "float" => ConstantPattern::Float,
+            "double" => ConstantPattern::Double,
+            "string" => ConstantPattern::String("*".into()),
+            "class" => ConstantPattern::Class("*".into()),
+            "handle" => ConstantPattern::MethodHandle,
+            "type" => ConstantPattern::MethodType,
+            "dynamic" => ConstantPattern::Dynamic,
+            v if v.starts_with('"') => ConstantPattern::String(unquote(v).ok_or_else(|| invalid(format!("unterminated string {}", v)))?.into()),
+            v => ConstantPattern::Integer(v.parse().map_err(|_| invalid(format!("expected a constant, found `{}`", v)))?)
+        }),
+        ["new", ..] => Matcher::New(single("new")?.into()),
+        ["checkcast", ..] => Matcher::CheckCast(single("checkcast")?.into()),
+        ["instanceof", ..] => Matcher::InstanceOf(single("instanceof")?.into()),
+        ["load", rest @ ..] => Matcher::Load(index(rest)?),
+        ["store", rest @ ..] => Matcher::Store(index(rest)?),
+        ["iinc", rest @ ..] => Matcher::Increment(index(rest)?),
+        ["jump"] => Matcher::Jump,
+        ["switch"] => Matcher::Switch,
+        ["return"] => Matcher::Return,
+        ["throw"] => Matcher::Throw,
+        ["monitorenter"] => Matcher::Monitor(MonitorOperation::Enter),
+        ["monitorexit"] => Matcher::Monitor(MonitorOperation::Exit),
+        _ => return Err(invalid(format!("unknown step `{}`", tokens.join(" "))))
+    })
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    /// Parses steps separated by `;`, see the [module documentation](self).
+    fn from_str(s: &str) -> Result<Self> {
+        let mut pattern = Pattern::new();
+        for step in split(s, Some(';')) {
+            let mut tokens = split(step, None);
+            let name = match tokens.last() {
+                Some(t) if t.starts_with('@') && t.len() > 1 => tokens.pop().map(|t| t[1..].to_string()),
+                _ => None
+            };
+            pattern = match (tokens.as_slice(), name) {
+                ([gap], None) if gap.starts_with("..") => match &gap[2..] {
+                    "" => pattern.gap(None),
+                    "." => pattern.gap_across_labels(),
+                    max => pattern.gap(Some(max.parse().map_err(|_| invalid(format!("expected a gap, found `{}`", gap)))?))
+                },
+                ([gap], Some(_)) if gap.starts_with("..") => return Err(invalid("gaps cannot be captured".into())),
+                ([], _) => return Err(invalid("empty step".into())),
+                (tokens, None) => pattern.insn(matcher(tokens)?),
+                (tokens, Some(name)) => pattern.capture(name, matcher(tokens)?)
+            };
+        }
+        Ok(pattern)
+    }
+}
@@ -0,0 +1,229 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! A peephole optimizer rewriting short instruction sequences of method bodies into shorter equivalent ones.
+//!
+//! The rewrites are grouped in [`PeepholeRules`], and the [`Peephole`] pass applies the selected ones until none applies
+//! anymore. A rewrite never spans a [`Label`] nor a [`LineNumber`](Instruction::LineNumber), so that jump targets, exception
+//! handler ranges, local variable scopes and line numbers keep covering the same instructions. When every instruction of
+//! a range protected by an exception handler is removed, a `nop` is kept so the range is not empty.
+
+use std::collections::HashMap;
+
+use crate::prelude::*;
+use crate::Class;
+
+bitflags! {
+    /// Groups of rewrites applied by a [`Peephole`] pass.
+    pub struct PeepholeRules: u16 {
+        /// Removes `nop` instructions.
+        const NO_OPS        = 0b0000_0000_0000_0001;
+        /// Removes constants and local variables that are pushed and popped right away.
+        const PUSH_POP      = 0b0000_0000_0000_0010;
+        /// Removes `dup` and `dup2` followed by a pop of the same size.
+        const DUP_POP       = 0b0000_0000_0000_0100;
+        /// Removes jumps to the instruction right after them. Conditional jumps are replaced by pops of their operands.
+        const JUMP_TO_NEXT  = 0b0000_0000_0000_1000;
+        /// Removes conversions to the same type, conversions that are reverted by the next one, and repeated narrowings.
+        const CONVERSIONS   = 0b0000_0000_0001_0000;
+        /// Removes loads that are stored back to the same local variable, and increments by zero.
+        const LOAD_STORE    = 0b0000_0000_0010_0000;
+        /// Removes labels that nothing refers to, which lets the other rewrites apply across them.
+        const UNUSED_LABELS = 0b0000_0000_0100_0000;
+    }
+}
+
+/// Applies semantics-preserving rewrites to method bodies.
+#[derive(Copy, Clone, Debug)]
+pub struct Peephole {
+    rules: PeepholeRules
+}
+
+impl Default for Peephole {
+    fn default() -> Self {
+        Peephole::new(PeepholeRules::all())
+    }
+}
+
+impl Peephole {
+    /// Creates a new pass applying the given rewrites.
+    pub fn new(rules: PeepholeRules) -> Self {
+        Peephole { rules }
+    }
+
+    /// Optimizes every method body of the class and returns the number of rewrites applied.
+    ///
+    /// This fails when a lazily decoded method body cannot be decoded.
+    pub fn optimize_class(&self, class: &mut Class) -> Result<usize> {
+        let mut count = 0;
+        for m in &mut class.methods {
+            if let Some(code) = m.code_mut()? {
+                count += self.optimize(code);
+            }
+        }
+        Ok(count)
+    }
+
+    /// Optimizes the method body and returns the number of rewrites applied.
+    pub fn optimize(&self, code: &mut Code) -> usize {
+        let mut count = 0;
+        loop {
+            let n = self.pass(code);
+            if n == 0 {
+                return count
+            }
+            count += n;
+        }
+    }
+
+    fn pass(&self, code: &mut Code) -> usize {
+        let mut count = 0;
+        if self.rules.contains(PeepholeRules::UNUSED_LABELS) {
+            count += code.remove_unused_labels();
+        }
+        let positions = code.code.iter().enumerate().filter_map(|(i, insn)| match insn {
+            Instruction::Label(l) => Some((*l, i)),
+            _ => None
+        }).collect::<HashMap<_, _>>();
+        let protected = code.catches.iter().filter_map(|c| Some((*positions.get(&c.start)?, *positions.get(&c.end)?))).collect::<Vec<_>>();
+
+        let old = std::mem::take(&mut code.code);
+        let is_label = |i: &Instruction| matches!(i, Instruction::Label(_));
+        let mut start = 0;
+        loop {
+            let end = old[start..].iter().position(is_label).map_or(old.len(), |p| start + p);
+            let labels = old[end..].iter().take_while(|i| is_label(i)).count();
+            // the labels reached by falling through the end of the segment.
+            let next = old[end..].iter().take_while(|i| matches!(i, Instruction::Label(_) | Instruction::LineNumber(_))).filter_map(|i| match i {
+                Instruction::Label(l) => Some(*l),
+                _ => None
+            }).collect::<Vec<_>>();
+            let mut segment = old[start..end].to_vec();
+            let keep_one = protected.iter().any(|(s, e)| *s < start && start < *e);
+            count += self.rewrite(&mut segment, &next, keep_one);
+            code.code.extend(segment);
+            code.code.extend_from_slice(&old[end..end + labels]);
+            if end == old.len() {
+                break
+            }
+            start = end + labels;
+        }
+        count
+    }
+
+    /// Rewrites a sequence of instructions without labels.
+    fn rewrite(&self, segment: &mut Vec<Instruction>, next: &[Label], keep_one: bool) -> usize {
+        let real = |s: &[Instruction]| s.iter().filter(|i| !matches!(i, Instruction::LineNumber(_))).count();
+        let was_empty = real(segment) == 0;
+        let mut count = 0;
+        let mut i = 0;
+        while i < segment.len() {
+            let pair = segment.get(i + 1).map(|b| (&segment[i], b));
+            if let Some((n, replacement)) = pair.and_then(|(a, b)| self.pair(a, b)).map(|r| (2, r)).or_else(|| {
+                self.single(&segment[i], &segment[i + 1..], next).map(|r| (1, r))
+            }) {
+                // a protected range keeps its last instruction.
+                if keep_one && n == 1 && replacement.is_empty() && real(segment) == 1 {
+                    i += 1;
+                    continue
+                }
+                segment.splice(i..i + n, replacement);
+                count += 1;
+                i = i.saturating_sub(1);
+            } else {
+                i += 1;
+            }
+        }
+        if keep_one && !was_empty && real(segment) == 0 {
+            segment.insert(0, Instruction::NoOp);
+        }
+        count
+    }
+
+    fn single(&self, insn: &Instruction, rest: &[Instruction], next: &[Label]) -> Option<Vec<Instruction>> {
+        let rules = self.rules;
+        match insn {
+            Instruction::NoOp if rules.contains(PeepholeRules::NO_OPS) => Some(vec![]),
+            Instruction::IntIncrement(_, 0) if rules.contains(PeepholeRules::LOAD_STORE) => Some(vec![]),
+            Instruction::Conversion(a, b) if a == b && rules.contains(PeepholeRules::CONVERSIONS) => Some(vec![]),
+            Instruction::ConvertInt(BitType::Int) if rules.contains(PeepholeRules::CONVERSIONS) => Some(vec![]),
+            Instruction::Jump(cond, target) if rules.contains(PeepholeRules::JUMP_TO_NEXT) && next.contains(target) &&
+                rest.iter().all(|i| matches!(i, Instruction::LineNumber(_))) => Some(match cond {
+                JumpCondition::Always => vec![],
+                JumpCondition::ReferenceEquals | JumpCondition::ReferenceNotEquals | JumpCondition::IntegerEquals |
+                JumpCondition::IntegerNotEquals | JumpCondition::IntegerLessThan | JumpCondition::IntegerGreaterThan |
+                JumpCondition::IntegerLessThanOrEquals | JumpCondition::IntegerGreaterThanOrEquals => vec![Instruction::Pop2],
+                _ => vec![Instruction::Pop1]
+            }),
+            _ => None
+        }
+    }
+
+    fn pair(&self, a: &Instruction, b: &Instruction) -> Option<Vec<Instruction>> {
+        let rules = self.rules;
+        match (a, b) {
+            (Instruction::Dup, Instruction::Pop1) | (Instruction::Dup2, Instruction::Pop2) if rules.contains(PeepholeRules::DUP_POP) => Some(vec![]),
+            (a, Instruction::Pop1) if rules.contains(PeepholeRules::PUSH_POP) && pushed_slots(a) == Some(1) => Some(vec![]),
+            (a, Instruction::Pop2) if rules.contains(PeepholeRules::PUSH_POP) && pushed_slots(a) == Some(2) => Some(vec![]),
+            (Instruction::LocalVariable(LoadOrStore::Load, t, n), Instruction::LocalVariable(LoadOrStore::Store, u, m))
+                if t == u && n == m && rules.contains(PeepholeRules::LOAD_STORE) => Some(vec![]),
+            (a, b) if rules.contains(PeepholeRules::CONVERSIONS) => conversions(a, b),
+            _ => None
+        }
+    }
+}
+
+/// The size of the value pushed by an instruction without side effects, or `None` for other instructions.
+fn pushed_slots(insn: &Instruction) -> Option<u8> {
+    match insn {
+        Instruction::PushNull => Some(1),
+        Instruction::LocalVariable(LoadOrStore::Load, LocalType::Long, _) | Instruction::LocalVariable(LoadOrStore::Load, LocalType::Double, _) => Some(2),
+        Instruction::LocalVariable(LoadOrStore::Load, _, _) => Some(1),
+        // class and method handle constants are resolved, which can fail, and dynamic ones call their bootstrap method.
+        Instruction::Push(OrDynamic::Static(c)) => match c {
+            Constant::I32(_) | Constant::F32(_) | Constant::String(_) => Some(1),
+            Constant::I64(_) | Constant::F64(_) => Some(2),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+fn conversions(a: &Instruction, b: &Instruction) -> Option<Vec<Instruction>> {
+    fn narrowing(b: BitType) -> bool {
+        matches!(b, BitType::Byte | BitType::Short | BitType::Char)
+    }
+    fn width(b: BitType) -> u8 {
+        if let BitType::Byte = b { 8 } else { 16 }
+    }
+    match (a, b) {
+        // int to long or double and back is exact, as is float to double and back.
+        (Instruction::ConvertInt(BitType::Long), Instruction::Conversion(NumberType::Long, NumberType::Int)) |
+        (Instruction::ConvertInt(BitType::Double), Instruction::Conversion(NumberType::Double, NumberType::Int)) |
+        (Instruction::Conversion(NumberType::Float, NumberType::Double), Instruction::Conversion(NumberType::Double, NumberType::Float)) => Some(vec![]),
+        (Instruction::ConvertInt(x), Instruction::ConvertInt(y)) if narrowing(*x) && narrowing(*y) => {
+            // truncating to the narrower type last gives the same result as truncating to it only.
+            if width(*y) <= width(*x) {
+                Some(vec![Instruction::ConvertInt(*y)])
+            } else if let (BitType::Byte, BitType::Short) = (x, y) {
+                Some(vec![Instruction::ConvertInt(*x)])
+            } else {
+                None
+            }
+        }
+        _ => None
+    }
+}
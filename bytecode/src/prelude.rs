@@ -31,5 +31,6 @@ pub use crate::rw::*;
 pub use crate::cp::*;
 pub use crate::dynamic::*;
 pub use crate::code::*;
+pub use crate::lazy::*;
 
 pub(crate) use coffer_macros::*;
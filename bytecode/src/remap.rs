@@ -0,0 +1,319 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Renaming of classes and members, as done when applying obfuscation mappings or deobfuscating a library.
+//!
+//! A [`Remapper`] holds the new names, keyed by the old names. Every reference to a renamed class is updated: descriptors,
+//! generic signatures, instructions, constants, bootstrap methods, annotations and the attributes of the class. References
+//! to renamed members are updated as well, resolving them through the supertypes of their owner when the classes are remapped
+//! together with [`Remapper::remap_all`].
+//!
+//! Nested classes follow their outer class: when `a/Outer` is renamed to `b/Renamed`, `a/Outer$Inner` becomes
+//! `b/Renamed$Inner` unless it has a name of its own.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+
+use crate::annotation::AnnotationValue;
+use crate::prelude::*;
+use crate::signature::ClassTypeSignature;
+use crate::visit::{signature_name, MemberUse, VisitMut};
+use crate::Class;
+
+/// New names of classes and members.
+#[derive(Clone, Debug, Default)]
+pub struct Remapper {
+    classes: HashMap<Cow<'static, str>, Cow<'static, str>>,
+    fields: HashMap<(Cow<'static, str>, Cow<'static, str>), Cow<'static, str>>,
+    methods: HashMap<(Cow<'static, str>, Cow<'static, str>, Type), Cow<'static, str>>
+}
+
+impl Remapper {
+    /// Creates a remapper that renames nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses mappings in the compact SRG format, with one mapping per line:
+    ///
+    /// ```text
+    /// # comment
+    /// old/Class new/Class
+    /// old/Class oldField newField
+    /// old/Class oldMethod (Lold/Class;)V newMethod
+    /// ```
+    ///
+    /// Owners and descriptors refer to the old names.
+    pub fn from_csrg(mappings: &str) -> Result<Self> {
+        let mut remapper = Self::new();
+        for (i, line) in mappings.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue
+            }
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [from, to] => remapper.map_class(from.to_owned(), to.to_owned()),
+                [owner, from, to] => remapper.map_field(owner.to_owned(), from.to_owned(), to.to_owned()),
+                [owner, from, desc, to] => {
+                    let descriptor = Type::from_str(desc).map_err(|e| Error::Invalid("mapping", format!("line {}: {}", i + 1, e).into()))?;
+                    remapper.map_method(owner.to_owned(), from.to_owned(), descriptor, to.to_owned())
+                }
+                _ => return Err(Error::Invalid("mapping", format!("line {}: expected 2 to 4 names", i + 1).into()))
+            };
+        }
+        Ok(remapper)
+    }
+
+    /// Renames a class, given its internal name such as `java/lang/Object`.
+    pub fn map_class<F: Into<Cow<'static, str>>, T: Into<Cow<'static, str>>>(&mut self, from: F, to: T) -> &mut Self {
+        self.classes.insert(from.into(), to.into());
+        self
+    }
+
+    /// Renames a field of a class, given the old name of the class.
+    pub fn map_field<O, F, T>(&mut self, owner: O, from: F, to: T) -> &mut Self
+        where O: Into<Cow<'static, str>>, F: Into<Cow<'static, str>>, T: Into<Cow<'static, str>> {
+        self.fields.insert((owner.into(), from.into()), to.into());
+        self
+    }
+
+    /// Renames a method of a class, given the old name of the class and the old descriptor of the method.
+    pub fn map_method<O, F, T>(&mut self, owner: O, from: F, descriptor: Type, to: T) -> &mut Self
+        where O: Into<Cow<'static, str>>, F: Into<Cow<'static, str>>, T: Into<Cow<'static, str>> {
+        self.methods.insert((owner.into(), from.into(), descriptor), to.into());
+        self
+    }
+
+    /// The new name of a class. Array descriptors such as `[Lfoo/Bar;` are accepted as well.
+    pub fn class_name(&self, name: &str) -> Option<Cow<'static, str>> {
+        if name.starts_with('[') {
+            let mut ty = Type::from_str(name).ok()?;
+            return if remap_type(self, &mut ty) { Some(ty.to_string().into()) } else { None }
+        }
+        if let Some(n) = self.classes.get(name) {
+            return Some(n.clone())
+        }
+        let (outer, inner) = name.rsplit_once('$')?;
+        self.class_name(outer).map(|o| format!("{}${}", o, inner).into())
+    }
+
+    /// Renames a class and the references it holds, without knowledge of its supertypes.
+    ///
+    /// A method that overrides a renamed method of another class is only renamed if its own name is mapped.
+    /// Use [`remap_all`](Self::remap_all) to remap the classes of a library consistently.
+    ///
+    /// This fails if a lazily read method body cannot be decoded.
+    pub fn remap(&self, class: &mut Class) -> Result<()> {
+        Context { remapper: self, supertypes: HashMap::new() }.class(class)
+    }
+
+    /// Renames a set of classes and the references they hold.
+    ///
+    /// References to members are resolved through the supertypes that are part of the set, so that a renamed method
+    /// is renamed in overriding classes as well, and calls through a subclass follow the rename.
+    pub fn remap_all(&self, classes: &mut [Class]) -> Result<()> {
+        let supertypes = classes.iter().map(|c| {
+            (c.name.to_string(), c.super_name.iter().chain(c.interfaces.iter()).map(|s| s.to_string()).collect())
+        }).collect();
+        let mut cx = Context { remapper: self, supertypes };
+        for class in classes {
+            cx.class(class)?;
+        }
+        Ok(())
+    }
+}
+
+/// Renames the classes of a type, returning whether anything changed.
+fn remap_type(remapper: &Remapper, ty: &mut Type) -> bool {
+    match ty {
+        Type::Ref(name) => match remapper.class_name(name) {
+            Some(n) => {
+                *name = n;
+                true
+            }
+            None => false
+        },
+        Type::ArrayRef(_, inner) => remap_type(remapper, inner),
+        Type::Method { parameters, ret } => {
+            let mut changed = false;
+            for p in parameters.iter_mut().chain(ret.iter_mut().map(|r| &mut **r)) {
+                changed |= remap_type(remapper, p);
+            }
+            changed
+        }
+        _ => false
+    }
+}
+
+/// The package of a class, empty for the unnamed package.
+fn package(class: &str) -> &str {
+    class.rsplit_once('/').map_or("", |(p, _)| p)
+}
+
+struct Context<'a> {
+    remapper: &'a Remapper,
+    /// The old names of the direct supertypes of the classes being remapped.
+    supertypes: HashMap<String, Vec<String>>
+}
+
+impl Context<'_> {
+    /// Looks up a member mapping in the owner, then in its supertypes.
+    fn lookup<T, F: Fn(&str) -> Option<T>>(&self, owner: &str, find: F) -> Option<T> {
+        let mut seen = HashSet::new();
+        let mut queue = vec![owner];
+        while let Some(o) = queue.pop() {
+            if !seen.insert(o) {
+                continue
+            }
+            if let Some(t) = find(o) {
+                return Some(t)
+            }
+            if let Some(s) = self.supertypes.get(o) {
+                queue.extend(s.iter().rev().map(String::as_str));
+            }
+        }
+        None
+    }
+
+    fn field_name(&self, owner: &str, name: &str) -> Option<Cow<'static, str>> {
+        self.lookup(owner, |o| self.remapper.fields.get(&(Cow::Owned(o.to_owned()), Cow::Owned(name.to_owned()))).cloned())
+    }
+
+    fn method_name(&self, owner: &str, name: &str, descriptor: &Type) -> Option<Cow<'static, str>> {
+        if name.starts_with('<') {
+            return None
+        }
+        self.lookup(owner, |o| self.remapper.methods.get(&(Cow::Owned(o.to_owned()), Cow::Owned(name.to_owned()), descriptor.clone())).cloned())
+    }
+
+    fn class(&mut self, class: &mut Class) -> Result<()> {
+        self.visit_class(class)?;
+        self.name(&mut class.name);
+        Ok(())
+    }
+}
+
+impl VisitMut for Context<'_> {
+    fn name(&mut self, name: &mut Cow<'static, str>) {
+        if let Some(n) = self.remapper.class_name(name) {
+            *name = n;
+        }
+    }
+
+    fn class_type_signature(&mut self, sig: &mut ClassTypeSignature) {
+        if let Some(n) = self.remapper.class_name(&signature_name(sig)) {
+            // nested classes are split on `$` again, which keeps their outer classes as long as they were renamed together.
+            let mut parts = n.split('$');
+            let outer = parts.next().unwrap_or_default();
+            let suffix = parts.collect::<Vec<_>>();
+            if suffix.len() == sig.suffix.len() {
+                let mut path = outer.split('/').map(|p| Cow::Owned(p.to_owned())).collect::<Vec<_>>();
+                sig.name.name = path.pop().unwrap_or_default();
+                sig.package = path;
+                for (s, n) in sig.suffix.iter_mut().zip(suffix) {
+                    s.name = Cow::Owned(n.to_owned());
+                }
+            }
+        }
+    }
+
+    fn member(&mut self, member: &mut MemberRef, _usage: MemberUse) {
+        let renamed = if member.descriptor.is_method() {
+            self.method_name(&member.owner, &member.name, &member.descriptor)
+        } else {
+            self.field_name(&member.owner, &member.name)
+        };
+        if let Some(n) = renamed {
+            member.name = n;
+        }
+    }
+
+    fn dynamic(&mut self, dynamic: &mut Dynamic) {
+        // a lambda call site is named after the method it implements, which belongs to the interface it returns.
+        let bsm = dynamic.bsm();
+        if bsm.handle.member.owner == "java/lang/invoke/LambdaMetafactory" && (bsm.handle.member.name == "metafactory" || bsm.handle.member.name == "altMetafactory") {
+            if let (Type::Method { ret: Some(itf), .. }, Some(OrDynamic::Static(Constant::MethodType(sam)))) = (&dynamic.descriptor, bsm.arguments.first()) {
+                if let Type::Ref(itf) = &**itf {
+                    if let Some(n) = self.method_name(itf, &dynamic.name, sam) {
+                        dynamic.name = n;
+                    }
+                }
+            }
+        }
+    }
+
+    fn annotation(&mut self, ty: &Type, values: &mut HashMap<Cow<'static, str>, AnnotationValue>) {
+        // the elements are named after the methods of the annotation type, which take no parameters.
+        if let Type::Ref(owner) = ty {
+            let element = |name: &str| self.remapper.methods.iter()
+                .find(|((o, n, d), _)| o == owner && n == name && matches!(d, Type::Method { parameters, .. } if parameters.is_empty()))
+                .map(|(_, new)| new.clone());
+            *values = values.drain().map(|(k, v)| (element(&k).unwrap_or(k), v)).collect();
+        }
+    }
+
+    fn enum_constant(&mut self, ty: &Type, name: &mut Cow<'static, str>) {
+        if let Type::Ref(owner) = ty {
+            if let Some(n) = self.field_name(owner, name) {
+                *name = n;
+            }
+        }
+    }
+
+    fn field(&mut self, owner: &Cow<'static, str>, name: &mut Cow<'static, str>, _descriptor: &Type) {
+        if let Some(n) = self.field_name(owner, name) {
+            *name = n;
+        }
+    }
+
+    fn method(&mut self, owner: &Cow<'static, str>, name: &mut Cow<'static, str>, descriptor: &Type) {
+        if let Some(n) = self.method_name(owner, name, descriptor) {
+            *name = n;
+        }
+    }
+
+    fn enclosing_method(&mut self, class: &str, name: &mut Cow<'static, str>, descriptor: &Type) {
+        if let Some(n) = self.method_name(class, name, descriptor) {
+            *name = n;
+        }
+    }
+
+    fn inner_class(&mut self, class: &mut InnerClass) {
+        let old = class.inner_fqname.clone();
+        self.name(&mut class.inner_fqname);
+        if let Some(o) = &mut class.outer_fqname {
+            self.name(o);
+        }
+        // the simple name of a member class is what follows its outer class in its name.
+        if let (Some(name), Some(outer)) = (&mut class.inner_name, &class.outer_fqname) {
+            if old != class.inner_fqname {
+                if let Some(simple) = class.inner_fqname.strip_prefix(outer.as_ref()).and_then(|s| s.strip_prefix('$')) {
+                    *name = Cow::Owned(simple.to_owned());
+                }
+            }
+        }
+    }
+
+    fn module_packages(&mut self, packages: &mut Vec<Cow<'static, str>>) {
+        // the packages that classes of the module are moved to become part of it, the old ones may still be used.
+        let added = self.remapper.classes.iter()
+            .filter(|(from, _)| packages.iter().any(|p| p == package(from)))
+            .map(|(_, to)| package(to))
+            .filter(|to| !packages.iter().any(|p| p == to))
+            .collect::<BTreeSet<_>>();
+        packages.extend(added.into_iter().map(|p| Cow::Owned(p.to_owned())));
+    }
+}
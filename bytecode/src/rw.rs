@@ -16,7 +16,7 @@
  */
 
 use std::convert::TryFrom;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::prelude::*;
 
@@ -29,6 +29,34 @@ pub trait ReadWrite where Self: Sized {
     fn write_to<T: Write>(&self, writer: &mut T) -> Result<()>;
 }
 
+/// Converts a length to the integer type it is written as, failing if it does not fit.
+///
+/// Class files store most counts as `u16`, casting instead would wrap around and write a corrupt class.
+pub(crate) fn checked_len<T: TryFrom<usize>>(what: &'static str, len: usize) -> Result<T> {
+    T::try_from(len).map_err(|_| Error::Limit(what, len as u64, (1u64 << (std::mem::size_of::<T>() * 8)) - 1))
+}
+
+/// A reader that counts the bytes read so far, for locating errors.
+pub(crate) struct Counted<'a, R: Read> {
+    inner: &'a mut R,
+    pub(crate) pos: u64
+}
+
+impl<'a, R: Read> Counted<'a, R> {
+    #[inline]
+    pub(crate) fn new(inner: &'a mut R) -> Self {
+        Counted { inner, pos: 0 }
+    }
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
 /// A trait for writing constant pool entries.
 pub trait ConstantPoolWriter {
     /// Inserts a raw constant pool entry to the constant pool.
@@ -88,7 +116,9 @@ pub trait ConstantPoolWriter {
     ///
     /// Returns an index that points to the inserted entry.
     fn insert_dynamic(&mut self, d: Dynamic) -> u16 {
-        let bsm = self.insert_bsm(Rc::try_unwrap(d.bsm).unwrap().into_inner().unwrap());
+        // the bootstrap method is still shared when the dynamic was cloned, such as when writing an instruction.
+        let bsm = Arc::try_unwrap(d.bsm).map_or_else(|rc| rc.get().cloned(), LazyBsm::into_inner).expect("Expected bsm to be populated");
+        let bsm = self.insert_bsm(bsm);
         let e = if d.descriptor.is_method() {
             RawConstantEntry::InvokeDynamic
         } else {
@@ -155,6 +185,16 @@ pub trait ConstantPoolWriter {
         self.insert_raw(RawConstantEntry::MethodHandle(handle.kind as u8, mem))
     }
 
+    /// The shared pool this writer has been seeded with, if any.
+    ///
+    /// Entries of a seeded writer keep their original indices, so undecoded [`LazyCode`] from the same pool can be copied as is.
+    ///
+    /// [`LazyCode`]: crate::lazy::LazyCode
+    #[inline]
+    fn source_pool(&self) -> Option<&Arc<SharedPool>> {
+        None
+    }
+
     /// Map a label to the actual offset in the code array.
     ///
     /// This does not need to be implemented because it is used internally,
@@ -271,7 +311,7 @@ pub trait ConstantPoolReader {
     fn read_invokedynamic(&mut self, idx: u16) -> Option<Dynamic> {
         match self.read_raw(idx) {
             Some(RawConstantEntry::InvokeDynamic(s, a)) =>  {
-                let cell = Rc::new(LazyBsm::new());
+                let cell = Arc::new(LazyBsm::new());
                 let (name, descriptor) = self.read_nameandtype(a)?;
                 self.resolve_later(s, cell.clone());
                 Some(Dynamic {
@@ -285,7 +325,7 @@ pub trait ConstantPoolReader {
     fn read_dynamic(&mut self, idx: u16) -> Option<Dynamic> {
         match self.read_raw(idx) {
             Some(RawConstantEntry::Dynamic(s, a)) =>  {
-                let cell = Rc::new(LazyBsm::new());
+                let cell = Arc::new(LazyBsm::new());
                 let (name, descriptor) = self.read_nameandtype(a)?;
                 self.resolve_later(s, cell.clone());
                 Some(Dynamic {
@@ -330,10 +370,29 @@ pub trait ConstantPoolReader {
     }
 
     /// Registers a bootstrap method to be resolved.
-    fn resolve_later(&mut self, bsm_idx: u16, bsm: Rc<LazyBsm>);
+    fn resolve_later(&mut self, bsm_idx: u16, bsm: Arc<LazyBsm>);
 
     /// Attempts to complete resolution of bootstrap methods by providing a list of bootstrap methods.
     fn bootstrap_methods(&mut self, bsms: &[BootstrapMethod]) -> Result<()>;
+
+    /// Reports a problem that reading can recover from, such as a malformed attribute or broken debugging information.
+    ///
+    /// Strict readers return the error, which aborts reading. Lenient readers record it as a warning and return `Ok(())`,
+    /// in which case the caller recovers, usually by dropping the offending data.
+    #[inline]
+    fn warn(&mut self, e: Error) -> Result<()> {
+        Err(e)
+    }
+
+    /// The shared pool this reader reads from, if method bodies should be decoded lazily.
+    ///
+    /// When this returns `Some`, `Code` attributes are read as [`LazyCode`] instead.
+    ///
+    /// [`LazyCode`]: crate::lazy::LazyCode
+    #[inline]
+    fn shared_pool(&self) -> Option<&Arc<SharedPool>> {
+        None
+    }
     // Implementations from Code
 
     /// get a uniquely identified label from an actual offset of the code array.
@@ -449,7 +508,7 @@ impl ReadWrite for String {
 
     fn write_to<T: Write>(&self, writer: &mut T) -> Result<()> {
         let string = crate::mod_utf8::string_to_modified_utf8(self);
-        (string.len() as u16).write_to(writer)?;
+        checked_len::<u16>("UTF8 length", string.len())?.write_to(writer)?;
         writer.write_all(&string)?;
         Ok(())
     }
@@ -0,0 +1,369 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Removal of the classes, methods and fields an application does not use, similar to the shrinking step of ProGuard.
+//!
+//! A [`Shrinker`] starts from roots: the `main` methods of main classes, the classes and members matched by [`Keep`]
+//! rules, classes loaded by reflection, and service providers. It follows calls through an [`Index`](crate::xref::Index),
+//! including virtual calls to the overriding methods of subtypes and the targets of `invokedynamic` instructions, and
+//! keeps what it reaches:
+//!
+//! - the methods that can run, along with the static initializers of the classes that are kept,
+//! - the fields these methods access,
+//! - the classes declaring them, the classes they refer to, and the supertypes, nest hosts and outer classes of those.
+//!
+//! Some methods are only called from outside the classes being shrunk. The methods of an instantiated class that may
+//! override a method of a library supertype, `values` and `valueOf` of enums, the elements of annotation interfaces,
+//! and the providers named in the `Module` attribute are kept as well. Other methods called by reflection or by the
+//! virtual machine, such as those of serialization, need keep rules.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::keep::Keep;
+use crate::prelude::*;
+use crate::xref::{Index, MemberId};
+use crate::Class;
+
+/// The methods of `java/lang/Object` that library code calls on any object.
+const OBJECT_METHODS: [(&str, &str); 5] = [
+    ("equals", "(Ljava/lang/Object;)Z"),
+    ("hashCode", "()I"),
+    ("toString", "()Ljava/lang/String;"),
+    ("clone", "()Ljava/lang/Object;"),
+    ("finalize", "()V")
+];
+
+/// What a [`Shrinker`] removed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ShrinkReport {
+    /// The names of the classes removed.
+    pub classes: Vec<Cow<'static, str>>,
+    /// The methods removed from the classes that are kept.
+    pub methods: Vec<MemberId>,
+    /// The fields removed from the classes that are kept.
+    pub fields: Vec<MemberId>
+}
+
+/// Removes the classes and members that cannot be reached from the roots.
+#[derive(Clone, Debug)]
+pub struct Shrinker {
+    main_classes: Vec<String>,
+    rules: Vec<Keep>,
+    reflected: Vec<String>,
+    providers: Vec<String>,
+    string_hints: bool
+}
+
+impl Default for Shrinker {
+    fn default() -> Self {
+        Shrinker::new()
+    }
+}
+
+impl Shrinker {
+    /// Creates a shrinker without roots, which takes string constants naming classes as reflection hints.
+    pub fn new() -> Self {
+        Shrinker { main_classes: vec![], rules: vec![], reflected: vec![], providers: vec![], string_hints: true }
+    }
+
+    /// Keeps the `main` method of a class, given by its internal or binary name.
+    pub fn main_class<S: AsRef<str>>(mut self, class: S) -> Self {
+        self.main_classes.push(class.as_ref().replace('.', "/"));
+        self
+    }
+
+    /// Keeps what a rule matches.
+    pub fn keep(mut self, rule: Keep) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Keeps a class that is loaded by name and instantiated through reflection, with its constructors.
+    pub fn reflected<S: AsRef<str>>(mut self, class: S) -> Self {
+        self.reflected.push(class.as_ref().replace('.', "/"));
+        self
+    }
+
+    /// Keeps a service provider found by `ServiceLoader`, as listed in `META-INF/services`, with its public
+    /// constructor without parameters and its `provider` method.
+    ///
+    /// The providers of the `Module` attribute of a `module-info` class are kept without being listed here.
+    pub fn provider<S: AsRef<str>>(mut self, class: S) -> Self {
+        self.providers.push(class.as_ref().replace('.', "/"));
+        self
+    }
+
+    /// Whether string constants naming a class, such as the argument of `Class.forName`, are taken as reflection
+    /// hints for it when a kept method loads them.
+    pub fn string_hints(mut self, enabled: bool) -> Self {
+        self.string_hints = enabled;
+        self
+    }
+
+    /// Removes from a set of classes what cannot be reached from the roots.
+    ///
+    /// This fails if a lazily read method body cannot be decoded.
+    pub fn shrink(&self, classes: &mut Vec<Class>) -> Result<ShrinkReport> {
+        let index = Index::build(classes.iter())?;
+        let kept = Reach::new(&index, classes).run(self)?;
+        let mut report = ShrinkReport::default();
+        classes.retain(|c| {
+            let keep = kept.classes.contains(c.name.as_ref());
+            if !keep {
+                report.classes.push(c.name.clone());
+            }
+            keep
+        });
+        let in_index = |name: &str| index.contains(name);
+        for class in classes.iter_mut() {
+            let owner = class.name.clone();
+            class.methods.retain(|m| {
+                let id = MemberId::new(owner.clone(), m.name.clone(), m.descriptor.clone());
+                let keep = kept.methods.contains(&id);
+                if !keep {
+                    report.methods.push(id);
+                }
+                keep
+            });
+            class.fields.retain(|f| {
+                let id = MemberId::new(owner.clone(), f.name.clone(), f.descriptor.clone());
+                let keep = kept.fields.contains(&id);
+                if !keep {
+                    report.fields.push(id);
+                }
+                keep
+            });
+            for attr in &mut class.attributes {
+                match attr {
+                    ClassAttribute::InnerClasses(inner) => {
+                        inner.retain(|i| !in_index(&i.inner_fqname) || kept.classes.contains(i.inner_fqname.as_ref()));
+                    }
+                    ClassAttribute::NestMembers(members) => members.retain(|m| !in_index(m) || kept.classes.contains(m.as_ref())),
+                    ClassAttribute::EnclosingMethod(c, method)
+                        if method.as_ref().is_some_and(|(name, ty)| in_index(c) && !kept.methods.contains(&MemberId::new(c.clone(), name.clone(), ty.clone()))) => {
+                        *method = None;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// What is kept.
+struct Kept {
+    classes: HashSet<String>,
+    methods: HashSet<MemberId>,
+    fields: HashSet<MemberId>
+}
+
+/// The search for what is reached from the roots.
+struct Reach<'a> {
+    index: &'a Index,
+    classes: HashMap<&'a str, &'a Class>,
+    /// The classes referred to by each field and method.
+    references: HashMap<&'a MemberId, Vec<&'a str>>
+}
+
+impl<'a> Reach<'a> {
+    fn new(index: &'a Index, classes: &'a [Class]) -> Self {
+        let mut references = HashMap::<_, Vec<_>>::new();
+        for name in index.referenced_classes() {
+            for site in index.references(name) {
+                if let Some(m) = &site.member {
+                    references.entry(m).or_default().push(name);
+                }
+            }
+        }
+        Reach { index, classes: classes.iter().map(|c| (c.name.as_ref(), c)).collect(), references }
+    }
+
+    fn methods(&self, class: &str) -> impl Iterator<Item = (MemberId, &'a Method)> {
+        self.classes.get(class).copied().into_iter().flat_map(|c| c.methods.iter().map(move |m| (MemberId::new(c.name.clone(), m.name.clone(), m.descriptor.clone()), m)))
+    }
+
+    fn run(&self, shrinker: &Shrinker) -> Result<Kept> {
+        let mut roots = HashSet::new();
+        let mut classes = HashSet::new();
+        let mut fields = HashSet::new();
+        let string_array = Type::ArrayRef(1, Box::new(Type::reference("java/lang/String")));
+        let mut main_classes = shrinker.main_classes.clone();
+        let mut providers = shrinker.providers.clone();
+        for class in self.classes.values() {
+            for attr in &class.attributes {
+                match attr {
+                    ClassAttribute::Module(module) => {
+                        classes.insert(class.name.to_string());
+                        classes.extend(module.uses.iter().map(|u| u.to_string()));
+                        for p in &module.provides {
+                            classes.insert(p.class.to_string());
+                            providers.extend(p.with.iter().map(|w| w.to_string()));
+                        }
+                    }
+                    ClassAttribute::ModuleMainClass(c) => main_classes.push(c.to_string()),
+                    _ => {}
+                }
+            }
+        }
+        for class in main_classes {
+            roots.insert(MemberId::new(class, "main", Type::method([string_array.clone()], None)));
+        }
+        for class in &shrinker.reflected {
+            roots.extend(self.constructors(class));
+            classes.insert(class.clone());
+        }
+        for class in providers {
+            roots.extend(self.methods(&class).map(|(id, _)| id).filter(|m| {
+                m.name == "<init>" && m.descriptor == Type::method([], None) || m.name == "provider" && matches!(&m.descriptor, Type::Method { parameters, .. } if parameters.is_empty())
+            }));
+            classes.insert(class);
+        }
+        for rule in &shrinker.rules {
+            for class in self.classes.values().filter(|c| rule.matches_class(c)) {
+                if !rule.keeps_members() {
+                    classes.insert(class.name.to_string());
+                    continue
+                }
+                for m in &class.methods {
+                    if rule.matches_method(m) {
+                        roots.insert(MemberId::new(class.name.clone(), m.name.clone(), m.descriptor.clone()));
+                    }
+                }
+                for f in &class.fields {
+                    if rule.matches_field(f) {
+                        fields.insert(MemberId::new(class.name.clone(), f.name.clone(), f.descriptor.clone()));
+                    }
+                }
+            }
+        }
+        // keeping classes keeps methods, which may refer to more classes, until nothing new is found.
+        loop {
+            let methods = self.index.reachable(roots.iter().cloned());
+            let mut more = vec![];
+            if shrinker.string_hints {
+                for string in self.index.strings() {
+                    let class = string.replace('.', "/");
+                    let used = self.index.string_uses(string).iter().any(|s| s.member.as_ref().is_some_and(|m| methods.contains(m)));
+                    if used && self.index.contains(&class) && classes.insert(class.clone()) {
+                        more.extend(self.constructors(&class));
+                    }
+                }
+            }
+            let mut kept_fields = fields.clone();
+            for m in &methods {
+                kept_fields.extend(self.index.accessed(m).filter_map(|a| self.index.resolve(&a.field)));
+            }
+            let kept_classes = self.classes(&classes, methods.iter().chain(kept_fields.iter()));
+            for class in &kept_classes {
+                let c = match self.classes.get(class.as_str()) {
+                    Some(c) => c,
+                    None => continue
+                };
+                for (id, m) in self.methods(class) {
+                    let element = c.access.contains(ClassFlags::ACC_ANNOTATION) && !m.access.contains(MethodFlags::ACC_STATIC);
+                    let enum_method = c.access.contains(ClassFlags::ACC_ENUM) && m.access.contains(MethodFlags::ACC_STATIC) && (m.name == "values" || m.name == "valueOf");
+                    if m.name == "<clinit>" || element || enum_method {
+                        more.push(id);
+                    }
+                }
+                if methods.iter().any(|m| m.owner == class.as_str() && m.name == "<init>") {
+                    more.extend(self.library_overrides(class));
+                }
+            }
+            more.retain(|m| !roots.contains(m) && !methods.contains(m));
+            if more.is_empty() {
+                return Ok(Kept { classes: kept_classes, methods, fields: kept_fields })
+            }
+            roots.extend(more);
+        }
+    }
+
+    fn constructors(&self, class: &str) -> impl Iterator<Item = MemberId> + 'a {
+        self.methods(class).map(|(id, _)| id).filter(|m| m.name == "<init>")
+    }
+
+    /// The classes declaring or referred to by kept members, and the classes they need to be loaded.
+    fn classes<'b, I: Iterator<Item = &'b MemberId>>(&self, roots: &HashSet<String>, members: I) -> HashSet<String> {
+        let mut queue = roots.iter().cloned().collect::<Vec<_>>();
+        for m in members {
+            queue.push(m.owner.to_string());
+            queue.extend(self.references.get(m).into_iter().flatten().map(|c| c.to_string()));
+        }
+        let mut kept = HashSet::new();
+        while let Some(name) = queue.pop() {
+            if !self.index.contains(&name) || !kept.insert(name.clone()) {
+                continue
+            }
+            let class = self.classes[name.as_str()];
+            queue.extend(class.super_name.iter().chain(class.interfaces.iter()).map(|s| s.to_string()));
+            for attr in &class.attributes {
+                match attr {
+                    ClassAttribute::NestHost(host) => queue.push(host.to_string()),
+                    ClassAttribute::EnclosingMethod(outer, _) => queue.push(outer.to_string()),
+                    ClassAttribute::InnerClasses(inner) => {
+                        let outer = inner.iter().find(|i| i.inner_fqname == class.name).and_then(|i| i.outer_fqname.as_ref());
+                        queue.extend(outer.map(|o| o.to_string()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        kept
+    }
+
+    /// The methods of a class that may override a method of one of its supertypes outside the index, and so be called
+    /// by library code.
+    fn library_overrides(&self, class: &str) -> Vec<MemberId> {
+        let mut library = HashSet::new();
+        let mut chain = vec![];
+        let mut queue = vec![class];
+        let mut seen = HashSet::new();
+        while let Some(c) = queue.pop() {
+            if !seen.insert(c) {
+                continue
+            }
+            match self.classes.get(c) {
+                Some(c) => {
+                    chain.push(c.name.as_ref());
+                    queue.extend(c.super_name.iter().chain(c.interfaces.iter()).map(|s| s.as_ref()));
+                }
+                None => {
+                    library.insert(c);
+                }
+            }
+        }
+        let only_object = library.iter().all(|&c| c == "java/lang/Object");
+        let mut found = vec![];
+        for c in chain {
+            for (id, m) in self.methods(c) {
+                if m.name.starts_with('<') || m.access.intersects(MethodFlags::ACC_STATIC | MethodFlags::ACC_PRIVATE) {
+                    continue
+                }
+                if only_object && !OBJECT_METHODS.iter().any(|(n, d)| m.name == *n && m.descriptor.to_string() == *d) {
+                    continue
+                }
+                // the method that runs is the one the class inherits.
+                if let Some(resolved) = self.index.resolve(&MemberId::new(class.to_owned(), id.name, id.descriptor)) {
+                    if !found.contains(&resolved) {
+                        found.push(resolved);
+                    }
+                }
+            }
+        }
+        found
+    }
+}
@@ -15,6 +15,7 @@ use crate::Result;
 
 /// A type signature represents either a reference type or a primitive type.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TypeSignature {
     /// A type signature representing the primitive `byte`. In Java, `byte`s are signed 8-bit integers.
     Byte,
@@ -39,12 +40,14 @@ pub enum TypeSignature {
 /// Signature for a field. It must be a reference type as primitive types do not have type parameters.
 #[repr(transparent)]
 #[derive(Debug, Clone, Eq, PartialEq)]
-pub struct FieldSignature(RefTypeSignature);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FieldSignature(pub(crate) RefTypeSignature);
 
 /// Signature for classes.
 ///
 /// It contains information about its type parameters (bounds), super class type parameters (bounds), interface type parameters (bounds).
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClassSignature {
     /// The type parameters for this class.
     pub type_parameters: Vec<TypeParameter>,
@@ -58,6 +61,7 @@ pub struct ClassSignature {
 ///
 /// It contains information about its type parameters, arguments, return type, and exceptions.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MethodSignature {
     /// Signature for type parameters of this method.
     pub type_parameters: Vec<TypeParameter>,
@@ -73,6 +77,7 @@ pub struct MethodSignature {
 ///
 /// A type parameter may have a class bound and some interface bounds.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TypeParameter {
     /// The name of the type parameter. Most of the time it is `T`.
     pub name: Cow<'static, str>,
@@ -86,6 +91,7 @@ pub struct TypeParameter {
 
 /// Signature for exception that a method can throw.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Throws {
     /// Throws a type parameter.
     TypeParameter(Cow<'static, str>),
@@ -95,6 +101,7 @@ pub enum Throws {
 
 /// A type argument.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TypeArgument {
     /// Specifies that the type argument is some type extending this type.
     Extends(RefTypeSignature),
@@ -108,6 +115,7 @@ pub enum TypeArgument {
 
 /// A simple class type signature. Has the simple name and type arguments.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SimpleClassTypeSignature {
     /// The simple name of this class.
     pub name: Cow<'static, str>,
@@ -117,6 +125,7 @@ pub struct SimpleClassTypeSignature {
 
 /// A class type signature, contains package directives, a simple type signature, and suffixes (inner classes).
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClassTypeSignature {
     /// The package directives for this class.
     pub package: Vec<Cow<'static, str>>,
@@ -128,6 +137,7 @@ pub struct ClassTypeSignature {
 
 /// A reference type.
 #[derive(Debug, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RefTypeSignature {
     /// References a type variable with name.
     TypeVariable(Cow<'static, str>),
@@ -341,7 +351,7 @@ macro_rules! convert_result {
     ($s: expr, $i:expr) => {
         match complete!($s, $i) {
             Ok((i, r)) => { if i.is_empty() { Ok(r) } else { unexpected_end() } }
-            Err(e) => { Err(Box::<dyn std::error::Error>::from(e.to_string()).into()) }
+            Err(e) => { Err(Box::<dyn std::error::Error + Send + Sync>::from(e.to_string()).into()) }
         }
     };
 }
@@ -0,0 +1,929 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Static single assignment form of method bodies.
+//!
+//! [`Function::lift`] turns the stack-based instructions of a [`Code`] into a graph of blocks where every value is
+//! computed once by a [`Statement`] and has a [computational type](LocalType). Loads, stores and stack manipulations
+//! disappear: statements refer to the values they consume directly, and [`Phi`] nodes merge the values of a local
+//! variable or stack slot where control flow joins. Exceptions are explicit edges from a block to its [`Handler`]s, and
+//! a handler block defines the exception it catches.
+//!
+//! The lifter builds on the [precise](ControlFlowGraph::precise) control flow graph of the code, so that a handler is
+//! always entered with the values its predecessors start with, and places phi nodes on the iterated dominance frontiers
+//! of the definitions of each variable. [`Function::lower`] goes back to instructions, allocating the values to local
+//! variables of the JVM with a coloring of their interference graph.
+//!
+//! Subroutines (`jsr` and `ret`) and instructions with dynamically computed member references are not supported.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use indexmap::map::IndexMap;
+
+use crate::flow::{dominators, dominance_frontiers, is_executed, shuffle, slots, stack_heights, take, ControlFlowGraph, EdgeKind};
+use crate::instrument::local_type;
+use crate::prelude::*;
+
+/// A value computed once in a [`Function`], identified by its index in [`Function::types`].
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct Value(pub u32);
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+/// An instruction applied to values.
+///
+/// The instruction is never a load, store, stack manipulation, label or control transfer: its operands are the values
+/// it pops in the order they are pushed, and `value` is what it pushes, if anything. Line numbers are kept as statements
+/// without operands.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Statement {
+    pub value: Option<Value>,
+    pub instruction: Instruction,
+    pub operands: Vec<Value>
+}
+
+/// A value that is one of `incoming`, depending on the predecessor block control comes from.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Phi {
+    pub value: Value,
+    /// The index of a predecessor block and the value it provides.
+    pub incoming: Vec<(usize, Value)>
+}
+
+/// An edge to the block that handles the exceptions thrown by a block.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Handler {
+    /// The class of the exceptions handled, or `None` for all of them.
+    pub catch: Option<Cow<'static, str>>,
+    pub target: usize
+}
+
+/// How a block ends.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Terminator {
+    /// Continues with a block.
+    Jump(usize),
+    /// Continues with `taken` if the condition holds for the operands, and with `otherwise` if not.
+    Branch { condition: JumpCondition, operands: Vec<Value>, taken: usize, otherwise: usize },
+    /// Continues with the block of the case equal to the value, or with `default`.
+    Switch { value: Value, default: usize, cases: IndexMap<i32, usize> },
+    Return(Option<Value>),
+    Throw(Value)
+}
+
+impl Terminator {
+    /// The blocks control continues with, without exception edges.
+    pub fn successors(&self) -> Vec<usize> {
+        match self {
+            Terminator::Jump(b) => vec![*b],
+            Terminator::Branch { taken, otherwise, .. } => vec![*taken, *otherwise],
+            Terminator::Switch { default, cases, .. } => std::iter::once(*default).chain(cases.values().copied()).collect(),
+            Terminator::Return(_) | Terminator::Throw(_) => vec![]
+        }
+    }
+
+    /// The values this terminator uses.
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Terminator::Branch { operands, .. } => operands.clone(),
+            Terminator::Switch { value, .. } | Terminator::Throw(value) | Terminator::Return(Some(value)) => vec![*value],
+            Terminator::Jump(_) | Terminator::Return(None) => vec![]
+        }
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Terminator::Branch { operands, .. } => operands.iter_mut().collect(),
+            Terminator::Switch { value, .. } | Terminator::Throw(value) | Terminator::Return(Some(value)) => vec![value],
+            Terminator::Jump(_) | Terminator::Return(None) => vec![]
+        }
+    }
+}
+
+/// A basic block: phi nodes, then statements, then a terminator.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Block {
+    pub phis: Vec<Phi>,
+    /// The exception caught, if this block is an exception handler.
+    pub exception: Option<Value>,
+    pub statements: Vec<Statement>,
+    pub terminator: Terminator,
+    /// The handlers of the exceptions thrown by the statements or the terminator of this block, in order of priority.
+    pub handlers: Vec<Handler>
+}
+
+/// A method body in static single assignment form.
+///
+/// The first block is the entry, where the parameters are defined. It has no predecessors.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Function {
+    pub blocks: Vec<Block>,
+    /// The values of the parameters, including the receiver of instance methods.
+    pub parameters: Vec<Value>,
+    /// The type of each value.
+    pub types: Vec<LocalType>
+}
+
+fn unsupported(insn: &Instruction) -> Error {
+    Error::Invalid("instruction", format!("{:?} is not supported in static single assignment form", insn).into())
+}
+
+/// The type of the value an instruction that is not a stack manipulation pushes.
+fn result_type(insn: &Instruction) -> Option<LocalType> {
+    fn number(ty: &NumberType) -> LocalType {
+        match ty {
+            NumberType::Int => LocalType::Int,
+            NumberType::Long => LocalType::Long,
+            NumberType::Float => LocalType::Float,
+            NumberType::Double => LocalType::Double
+        }
+    }
+    fn ret(descriptor: &Type) -> Option<LocalType> {
+        match descriptor {
+            Type::Method { ret, .. } => ret.as_deref().map(local_type),
+            ty => Some(local_type(ty))
+        }
+    }
+    Some(match insn {
+        Instruction::Push(OrDynamic::Static(c)) => match c {
+            Constant::I32(_) => LocalType::Int,
+            Constant::F32(_) => LocalType::Float,
+            Constant::I64(_) => LocalType::Long,
+            Constant::F64(_) => LocalType::Double,
+            _ => LocalType::Reference
+        },
+        Instruction::Push(OrDynamic::Dynamic(d)) => local_type(&d.descriptor),
+        Instruction::CompareLongs | Instruction::CompareFloats(..) | Instruction::ArrayLength | Instruction::InstanceOf(_) => LocalType::Int,
+        Instruction::Array(LoadOrStore::Load, ty) => match ty {
+            ArrayType::Long => LocalType::Long,
+            ArrayType::Float => LocalType::Float,
+            ArrayType::Double => LocalType::Double,
+            ArrayType::Reference => LocalType::Reference,
+            _ => LocalType::Int
+        },
+        Instruction::IntOperation(IntType::Int, _) => LocalType::Int,
+        Instruction::IntOperation(IntType::Long, _) => LocalType::Long,
+        Instruction::FloatOperation(FloatType::Float, _) => LocalType::Float,
+        Instruction::FloatOperation(FloatType::Double, _) => LocalType::Double,
+        Instruction::Conversion(_, to) => number(to),
+        Instruction::ConvertInt(BitType::Long) => LocalType::Long,
+        Instruction::ConvertInt(BitType::Float) => LocalType::Float,
+        Instruction::ConvertInt(BitType::Double) => LocalType::Double,
+        Instruction::ConvertInt(_) => LocalType::Int,
+        Instruction::Field(GetOrPut::Get, _, OrDynamic::Static(m)) => local_type(&m.descriptor),
+        Instruction::InvokeExact(_, OrDynamic::Static(m)) | Instruction::InvokeSpecial(OrDynamic::Static(m)) |
+        Instruction::InvokeInterface(OrDynamic::Static(m), _) => return ret(&m.descriptor),
+        Instruction::InvokeDynamic(d) => return ret(&d.descriptor),
+        Instruction::PushNull | Instruction::New(_) | Instruction::NewArray(..) | Instruction::CheckCast(_) => LocalType::Reference,
+        _ => return None
+    })
+}
+
+/// The local variables and operand stack at a point of a method body, with `T` standing for a value or its type.
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct Frame<T> {
+    locals: Vec<Option<T>>,
+    stack: Vec<T>
+}
+
+impl<T: Clone> Frame<T> {
+    fn take(&mut self, size: u16, ty: &dyn Fn(&T) -> LocalType) -> Result<Vec<T>> {
+        take(&mut self.stack, size, ty)
+    }
+
+    fn store(&mut self, index: u16, value: T, wide: bool, ty: &dyn Fn(&T) -> LocalType) -> Result<()> {
+        let index = index as usize;
+        if index + wide as usize >= self.locals.len() {
+            return Err(Error::Invalid("local variable", format!("{} is out of the bounds of the method", index).into()))
+        }
+        if index > 0 && matches!(self.locals[index - 1].as_ref().map(ty), Some(LocalType::Long) | Some(LocalType::Double)) {
+            self.locals[index - 1] = None;
+        }
+        self.locals[index] = Some(value);
+        if wide {
+            self.locals[index + 1] = None;
+        }
+        Ok(())
+    }
+
+    /// Applies an instruction that does not transfer control. `compute` creates the values of the other instructions.
+    fn step(&mut self, insn: &Instruction, ty: &dyn Fn(&T) -> LocalType,
+            compute: &mut dyn FnMut(&Instruction, Vec<T>) -> Option<T>) -> Result<()> {
+        match insn {
+            Instruction::NoOp | Instruction::Label(_) => {}
+            Instruction::LocalVariable(LoadOrStore::Load, lt, i) => {
+                let value = self.locals.get(*i as usize).cloned().flatten().filter(|v| ty(v) == *lt)
+                    .ok_or_else(|| Error::Invalid("local variable", format!("{} does not hold a value of type {:?}", i, lt).into()))?;
+                self.stack.push(value);
+            }
+            Instruction::LocalVariable(LoadOrStore::Store, lt, i) => {
+                let value = self.take(slots(*lt), ty)?.remove(0);
+                self.store(*i, value, slots(*lt) == 2, ty)?;
+            }
+            Instruction::IntIncrement(i, amount) => {
+                let value = self.locals.get(*i as usize).cloned().flatten().filter(|v| ty(v) == LocalType::Int)
+                    .ok_or_else(|| Error::Invalid("local variable", format!("{} does not hold an int", i).into()))?;
+                let amount = compute(&Instruction::Push(OrDynamic::Static(Constant::I32(*amount as i32))), vec![]).unwrap();
+                let sum = compute(&Instruction::IntOperation(IntType::Int, IntOperation::Add), vec![value, amount]).unwrap();
+                self.store(*i, sum, false, ty)?;
+            }
+            Instruction::Pop1 => { self.take(1, ty)?; }
+            Instruction::Pop2 => { self.take(2, ty)?; }
+            Instruction::Dup | Instruction::DupX1 | Instruction::DupX2 | Instruction::Dup2 | Instruction::Dup2X1 |
+            Instruction::Dup2X2 | Instruction::Swap => {
+                let values = shuffle(insn, |n| self.take(n, ty))?;
+                self.stack.extend(values);
+            }
+            Instruction::Jsr(_) | Instruction::Ret(_) => return Err(unsupported(insn)),
+            _ => {
+                let (pop, push) = insn.stack_effect().ok_or_else(|| unsupported(insn))?;
+                let operands = self.take(pop, ty)?;
+                let value = compute(insn, operands);
+                if push > 0 {
+                    self.stack.push(value.ok_or_else(|| unsupported(insn))?);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merges the types of the local variables and operand stack of a frame into another, returning whether it changed.
+fn merge(into: &mut Frame<LocalType>, frame: &Frame<LocalType>) -> Result<bool> {
+    if into.stack != frame.stack {
+        return Err(Error::Invalid("stack", format!("a block is reached with stacks {:?} and {:?}", into.stack, frame.stack).into()))
+    }
+    let mut changed = false;
+    for (a, b) in into.locals.iter_mut().zip(&frame.locals) {
+        if a.is_some() && a != b {
+            *a = None;
+            changed = true;
+        }
+    }
+    Ok(changed)
+}
+
+impl Function {
+    /// Lifts the body of a method.
+    ///
+    /// This fails if the method has no code, or for the reasons [`Function::lift`] does.
+    pub fn from_method(method: &Method) -> Result<Function> {
+        let code = method.code()?.ok_or(Error::Invalid("method", "it has no code".into()))?;
+        let mut parameters = vec![];
+        if !method.access.contains(MethodFlags::ACC_STATIC) {
+            parameters.push(LocalType::Reference);
+        }
+        if let Type::Method { parameters: p, .. } = &method.descriptor {
+            parameters.extend(p.iter().map(local_type));
+        }
+        Function::lift(code, &parameters)
+    }
+
+    /// Lifts a method body that receives parameters of these types, including the receiver of instance methods.
+    ///
+    /// Blocks that cannot be reached are dropped, as are line numbers and local variable tables, but line numbers are
+    /// kept as statements. This fails if the code uses subroutines or members that are computed dynamically, if it loads
+    /// a local variable that does not hold a value of the right type, if the stack does not have the same types of
+    /// values wherever a block is reached from, or if an exception handler is also reached without an exception.
+    pub fn lift(code: &Code, parameters: &[LocalType]) -> Result<Function> {
+        let graph = ControlFlowGraph::precise(code)?;
+        let max_locals = (code.max_locals as usize).max(parameters.iter().map(|p| slots(*p) as usize).sum());
+        let mut entry = Frame { locals: vec![None; max_locals], stack: vec![] };
+        let mut index = 0;
+        for p in parameters {
+            entry.store(index, *p, slots(*p) == 2, &|t| *t)?;
+            index += slots(*p);
+        }
+        let handler = |b: usize| graph.blocks.iter().any(|p| p.successors.iter().any(|e| e.target == b && matches!(e.kind, EdgeKind::Exception(_))));
+        let identity = |t: &LocalType| *t;
+
+        // The types of the local variables and stack at the start of every block, and the variables each one stores to.
+        let mut frames: Vec<Option<Frame<LocalType>>> = vec![None; graph.blocks.len()];
+        let mut stores = vec![HashSet::new(); graph.blocks.len()];
+        if graph.blocks.is_empty() {
+            return Err(Error::Invalid("code", "it is empty".into()))
+        }
+        frames[0] = Some(entry.clone());
+        let mut work = vec![0];
+        while let Some(b) = work.pop() {
+            let start = frames[b].clone().unwrap();
+            let mut frame = start.clone();
+            let mut low = if handler(b) { 0 } else { frame.stack.len() };
+            for insn in &code.code[graph.blocks[b].range.clone()] {
+                if matches!(insn, Instruction::Jump(..) | Instruction::TableSwitch { .. } | Instruction::LookupSwitch { .. } |
+                    Instruction::Return(_) | Instruction::Throw) {
+                    let (pop, _) = insn.stack_effect().unwrap();
+                    frame.take(pop, &identity)?;
+                } else {
+                    let mut popped = 0;
+                    let mut size = 0;
+                    let pop = match insn {
+                        Instruction::LocalVariable(..) | Instruction::IntIncrement(..) => 0,
+                        insn => insn.stack_effect().map_or(0, |(pop, _)| pop)
+                    };
+                    while size < pop && popped < frame.stack.len() {
+                        size += slots(frame.stack[frame.stack.len() - 1 - popped]);
+                        popped += 1;
+                    }
+                    low = low.min(frame.stack.len() - popped);
+                    frame.step(insn, &identity, &mut |insn, _| result_type(insn))?;
+                    if let Instruction::LocalVariable(LoadOrStore::Store, _, i) | Instruction::IntIncrement(i, _) = insn {
+                        stores[b].insert(*i as usize);
+                    }
+                }
+                low = low.min(frame.stack.len());
+            }
+            stores[b].extend((low..frame.stack.len()).map(|k| max_locals + k));
+            for e in &graph.blocks[b].successors {
+                let incoming = match e.kind {
+                    EdgeKind::Exception(_) => Frame { locals: start.locals.clone(), stack: vec![LocalType::Reference] },
+                    _ if handler(e.target) => {
+                        return Err(Error::Invalid("exception handler", "it is also reached without an exception".into()))
+                    }
+                    _ => frame.clone()
+                };
+                match &mut frames[e.target] {
+                    Some(f) => if !merge(f, &incoming)? {
+                        continue
+                    },
+                    f => *f = Some(incoming)
+                }
+                work.push(e.target);
+            }
+        }
+
+        // Blocks of the function: an entry, then the reachable blocks of the graph in order.
+        let order = (0..graph.blocks.len()).filter(|b| frames[*b].is_some()).collect::<Vec<_>>();
+        let mut block_of = vec![usize::MAX; graph.blocks.len()];
+        for (i, b) in order.iter().enumerate() {
+            block_of[*b] = i + 1;
+        }
+        let mut successors = vec![vec![1]];
+        successors.extend(order.iter().map(|b| graph.blocks[*b].successors.iter().map(|e| block_of[e.target]).collect::<Vec<_>>()));
+        let idom = dominators(&successors);
+        let frontiers = dominance_frontiers(&successors, &idom);
+        let frame_of = |block: usize| if block == 0 { &entry } else { frames[order[block - 1]].as_ref().unwrap() };
+        let typed = |block: usize, var: usize| {
+            let frame = frame_of(block);
+            if var < max_locals { frame.locals[var] } else { frame.stack.get(var - max_locals).copied() }
+        };
+
+        // Phi nodes on the iterated dominance frontiers of the stores to each variable, and for every variable of handlers.
+        let variables = max_locals + order.iter().map(|b| frames[*b].as_ref().unwrap().stack.len()).max().unwrap_or(0);
+        let mut function = Function { blocks: vec![], parameters: vec![], types: vec![] };
+        let mut phis: Vec<Vec<usize>> = vec![vec![]; successors.len()];
+        let is_handler = (0..successors.len()).map(|n| n > 0 && handler(order[n - 1])).collect::<Vec<_>>();
+        for var in 0..variables {
+            let mut placed = vec![false; successors.len()];
+            let mut work = (1..successors.len()).filter(|n| stores[order[n - 1]].contains(&var)).collect::<Vec<_>>();
+            if var < index as usize {
+                work.push(0);
+            }
+            if var < max_locals {
+                work.extend((1..successors.len()).filter(|n| is_handler[*n] && typed(*n, var).is_some()));
+            }
+            while let Some(n) = work.pop() {
+                for &d in &frontiers[n] {
+                    if !placed[d] && typed(d, var).is_some() && !(is_handler[d] && var >= max_locals) {
+                        placed[d] = true;
+                        work.push(d);
+                    }
+                }
+            }
+            for (n, placed) in placed.iter().enumerate() {
+                if *placed || is_handler[n] && var < max_locals && typed(n, var).is_some() {
+                    phis[n].push(var);
+                }
+            }
+        }
+        for n in 0..successors.len() {
+            let block = Block {
+                phis: phis[n].iter().map(|var| Phi { value: function.new_value(typed(n, *var).unwrap()), incoming: vec![] }).collect(),
+                exception: if is_handler[n] { Some(function.new_value(LocalType::Reference)) } else { None },
+                statements: vec![],
+                terminator: Terminator::Jump(1),
+                handlers: vec![]
+            };
+            function.blocks.push(block);
+        }
+
+        // Renaming along the dominator tree.
+        let mut children = vec![vec![]; successors.len()];
+        for (n, d) in idom.iter().enumerate() {
+            if let Some(d) = d {
+                children[*d].push(n);
+            }
+        }
+        let mut state = vec![None; variables];
+        index = 0;
+        for p in parameters {
+            let value = function.new_value(*p);
+            function.parameters.push(value);
+            state[index as usize] = Some(value);
+            index += slots(*p);
+        }
+        let mut work = vec![(0, state)];
+        while let Some((n, mut state)) = work.pop() {
+            for (var, phi) in phis[n].iter().zip(&function.blocks[n].phis) {
+                state[*var] = Some(phi.value);
+            }
+            let start = state.clone();
+            if n > 0 {
+                let b = order[n - 1];
+                let types = frame_of(n);
+                let mut frame = Frame {
+                    locals: (0..max_locals).map(|i| types.locals[i].and(state[i])).collect(),
+                    stack: match function.blocks[n].exception {
+                        Some(e) => vec![e],
+                        None => (0..types.stack.len()).map(|k| state[max_locals + k]).collect::<Option<_>>()
+                            .ok_or(Error::Invalid("stack", "a value of the stack is not defined".into()))?
+                    }
+                };
+                let mut statements = vec![];
+                let mut last = None;
+                let types = RefCell::new(std::mem::take(&mut function.types));
+                let ty = |v: &Value| types.borrow()[v.0 as usize];
+                for insn in &code.code[graph.blocks[b].range.clone()] {
+                    if is_executed(insn) {
+                        last = Some(insn);
+                    }
+                    if matches!(insn, Instruction::Jump(..) | Instruction::TableSwitch { .. } | Instruction::LookupSwitch { .. } |
+                        Instruction::Return(_) | Instruction::Throw) {
+                        break
+                    }
+                    frame.step(insn, &ty, &mut |insn, operands| {
+                        let value = result_type(insn).map(|t| {
+                            let mut types = types.borrow_mut();
+                            types.push(t);
+                            Value(types.len() as u32 - 1)
+                        });
+                        statements.push(Statement { value, instruction: insn.clone(), operands });
+                        value
+                    })?;
+                }
+                function.types = types.into_inner();
+                let ty = |v: &Value| function.types[v.0 as usize];
+                let target = |l: &Label| graph.block_of(*l).map(|b| block_of[b]).unwrap();
+                let next = || graph.blocks[b].successors.iter().find(|e| e.kind == EdgeKind::FallThrough).map(|e| block_of[e.target])
+                    .ok_or(Error::Invalid("code", "execution falls off the end of the code".into()));
+                let terminator = match last {
+                    Some(Instruction::Jump(JumpCondition::Always, l)) => Terminator::Jump(target(l)),
+                    Some(Instruction::Jump(condition, l)) => {
+                        let (pop, _) = last.unwrap().stack_effect().unwrap();
+                        Terminator::Branch { condition: *condition, operands: frame.take(pop, &ty)?, taken: target(l), otherwise: next()? }
+                    }
+                    Some(Instruction::TableSwitch { default, low, offsets }) => Terminator::Switch {
+                        value: frame.take(1, &ty)?[0],
+                        default: target(default),
+                        cases: offsets.iter().enumerate().map(|(i, l)| (low + i as i32, target(l))).collect()
+                    },
+                    Some(Instruction::LookupSwitch { default, table }) => Terminator::Switch {
+                        value: frame.take(1, &ty)?[0],
+                        default: target(default),
+                        cases: table.iter().map(|(k, l)| (*k, target(l))).collect()
+                    },
+                    Some(Instruction::Return(None)) => Terminator::Return(None),
+                    Some(Instruction::Return(Some(lt))) => Terminator::Return(Some(frame.take(slots(*lt), &ty)?[0])),
+                    Some(Instruction::Throw) => Terminator::Throw(frame.take(1, &ty)?[0]),
+                    _ => Terminator::Jump(next()?)
+                };
+                let block = &mut function.blocks[n];
+                block.statements = statements;
+                block.terminator = terminator;
+                block.handlers = graph.blocks[b].successors.iter().filter_map(|e| match e.kind {
+                    EdgeKind::Exception(i) => Some(Handler { catch: code.catches[i].catch.clone(), target: block_of[e.target] }),
+                    _ => None
+                }).collect();
+                for (i, local) in frame.locals.into_iter().enumerate() {
+                    state[i] = local;
+                }
+                for (k, var) in state.iter_mut().enumerate().skip(max_locals) {
+                    *var = frame.stack.get(k - max_locals).copied();
+                }
+            }
+            let edges = function.blocks[n].terminator.successors().into_iter().map(|s| (s, &state))
+                .chain(function.blocks[n].handlers.iter().map(|h| (h.target, &start))).collect::<Vec<_>>();
+            for (s, values) in edges {
+                for (i, var) in phis[s].iter().enumerate() {
+                    let value = values[*var].ok_or_else(|| Error::Invalid("phi", format!("variable {} is not defined on the edge from block {} to {}", var, n, s).into()))?;
+                    let phi = &mut function.blocks[s].phis[i];
+                    if !phi.incoming.iter().any(|(p, _)| *p == n) {
+                        phi.incoming.push((n, value));
+                    }
+                }
+            }
+            for c in &children[n] {
+                work.push((*c, state.clone()));
+            }
+        }
+        function.prune_phis();
+        Ok(function)
+    }
+}
+
+impl Function {
+    /// Creates a value of this type, to be defined by a statement or phi node.
+    pub fn new_value(&mut self, ty: LocalType) -> Value {
+        self.types.push(ty);
+        Value(self.types.len() as u32 - 1)
+    }
+
+    /// The type of a value.
+    #[inline]
+    pub fn type_of(&self, value: Value) -> LocalType {
+        self.types[value.0 as usize]
+    }
+
+    /// The blocks with an edge to each block, exception edges included.
+    pub fn predecessors(&self) -> Vec<Vec<usize>> {
+        let mut predecessors = vec![vec![]; self.blocks.len()];
+        for (b, block) in self.blocks.iter().enumerate() {
+            for s in block.terminator.successors().into_iter().chain(block.handlers.iter().map(|h| h.target)) {
+                if !predecessors[s].contains(&b) {
+                    predecessors[s].push(b);
+                }
+            }
+        }
+        predecessors
+    }
+
+    /// The values used by statements, terminators and the phi nodes of other values.
+    pub fn uses(&self) -> HashSet<Value> {
+        let mut uses = HashSet::new();
+        for block in &self.blocks {
+            for phi in &block.phis {
+                uses.extend(phi.incoming.iter().map(|(_, v)| *v).filter(|v| *v != phi.value));
+            }
+            for s in &block.statements {
+                uses.extend(s.operands.iter().copied());
+            }
+            uses.extend(block.terminator.operands());
+        }
+        uses
+    }
+
+    /// Replaces every use of a value by another.
+    pub fn replace_uses(&mut self, from: Value, to: Value) {
+        let replace = |v: &mut Value| if *v == from {
+            *v = to;
+        };
+        for block in &mut self.blocks {
+            for phi in &mut block.phis {
+                phi.incoming.iter_mut().for_each(|(_, v)| replace(v));
+            }
+            for s in &mut block.statements {
+                s.operands.iter_mut().for_each(replace);
+            }
+            block.terminator.operands_mut().into_iter().for_each(replace);
+        }
+    }
+
+    /// Removes the phi nodes that merge a single value other than their own, replacing them by that value, and those
+    /// whose value is not used.
+    pub fn prune_phis(&mut self) {
+        loop {
+            let mut changed = false;
+            for b in 0..self.blocks.len() {
+                let mut i = 0;
+                while i < self.blocks[b].phis.len() {
+                    let phi = &self.blocks[b].phis[i];
+                    let mut others = phi.incoming.iter().map(|(_, v)| *v).filter(|v| *v != phi.value);
+                    if let Some(v) = others.next() {
+                        if others.all(|o| o == v) {
+                            let value = self.blocks[b].phis.remove(i).value;
+                            self.replace_uses(value, v);
+                            changed = true;
+                            continue
+                        }
+                    }
+                    i += 1;
+                }
+            }
+            let uses = self.uses();
+            for block in &mut self.blocks {
+                let count = block.phis.len();
+                block.phis.retain(|p| uses.contains(&p.value));
+                changed |= block.phis.len() != count;
+            }
+            if !changed {
+                break
+            }
+        }
+    }
+
+    /// Lowers this function to instructions.
+    ///
+    /// The parameters stay in the local variables the method receives them in, and the other values are allocated to
+    /// local variables so that two values never share one while they are both live. Phi nodes become copies at the end
+    /// of their predecessors, on new blocks for the edges of conditional jumps and switches, and at the start of the
+    /// blocks an exception handler covers for the phi nodes of the handler. Line numbers are kept, but local variable
+    /// tables are not generated.
+    ///
+    /// This fails if the value of a phi node of a handler is live at the start of a block the handler covers.
+    pub fn lower(&self) -> Result<Code> {
+        let n = self.blocks.len();
+        let used = self.uses();
+        let incoming = |phi: &Phi, b: usize| phi.incoming.iter().find(|(p, _)| *p == b).map(|(_, v)| *v);
+        let entry = |b: usize| -> Vec<Value> {
+            let block = &self.blocks[b];
+            block.phis.iter().map(|p| p.value).chain(block.exception).filter(|v| used.contains(v)).collect()
+        };
+
+        // Liveness. The values live at the start of a block do not include those it defines there.
+        let mut live_in = vec![HashSet::new(); n];
+        let mut live_out = vec![HashSet::new(); n];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in (0..n).rev() {
+                let block = &self.blocks[b];
+                let mut out = HashSet::new();
+                for s in block.terminator.successors() {
+                    out.extend(live_in[s].iter().copied());
+                    out.extend(self.blocks[s].phis.iter().filter_map(|p| incoming(p, b)));
+                }
+                let mut live = out.clone();
+                live.extend(block.terminator.operands());
+                for s in block.statements.iter().rev() {
+                    if let Some(v) = s.value {
+                        live.remove(&v);
+                    }
+                    live.extend(s.operands.iter().copied());
+                }
+                for h in &block.handlers {
+                    live.extend(live_in[h.target].iter().copied());
+                    live.extend(self.blocks[h.target].phis.iter().filter_map(|p| incoming(p, b)));
+                }
+                for v in block.phis.iter().map(|p| p.value).chain(block.exception) {
+                    live.remove(&v);
+                }
+                if live != live_in[b] || out != live_out[b] {
+                    live_in[b] = live;
+                    live_out[b] = out;
+                    changed = true;
+                }
+            }
+        }
+
+        // Interference.
+        let mut interference: HashMap<Value, HashSet<Value>> = HashMap::new();
+        let mut interfere = |a: Value, b: Value| if a != b {
+            interference.entry(a).or_default().insert(b);
+            interference.entry(b).or_default().insert(a);
+        };
+        for (b, block) in self.blocks.iter().enumerate() {
+            let mut live = live_out[b].clone();
+            live.extend(block.terminator.operands());
+            for s in block.statements.iter().rev() {
+                if let Some(v) = s.value {
+                    if used.contains(&v) {
+                        live.iter().for_each(|u| interfere(v, *u));
+                    }
+                    live.remove(&v);
+                }
+                live.extend(s.operands.iter().copied());
+            }
+            let defs = entry(b);
+            for d in &defs {
+                live.iter().chain(&defs).for_each(|u| interfere(*d, *u));
+            }
+            let mut start = live_in[b].clone();
+            start.extend(defs.iter().filter(|d| live.contains(d)));
+            start.extend(block.handlers.iter().flat_map(|h| &self.blocks[h.target].phis).filter_map(|p| incoming(p, b)));
+            let copies = block.handlers.iter().flat_map(|h| &self.blocks[h.target].phis)
+                .filter(|p| used.contains(&p.value) && incoming(p, b) != Some(p.value)).map(|p| p.value).collect::<Vec<_>>();
+            for p in &copies {
+                if start.contains(p) {
+                    return Err(Error::Invalid("phi", format!("{} is live at the start of a block its handler covers", p).into()))
+                }
+                start.iter().chain(&copies).for_each(|u| interfere(*p, *u));
+            }
+            // Verifiers merge every local variable written in the range of a handler into the frame of the handler, so
+            // the values it receives cannot share a local variable with one written in a block it covers.
+            let received = block.handlers.iter().flat_map(|h| live_in[h.target].iter().chain(self.blocks[h.target].phis.iter().map(|p| &p.value)))
+                .filter(|v| used.contains(v)).copied().collect::<HashSet<_>>();
+            if !received.is_empty() {
+                let written = block.statements.iter().filter_map(|s| s.value).chain(defs.iter().copied())
+                    .chain(block.terminator.successors().into_iter().flat_map(entry)).filter(|v| used.contains(v));
+                for w in written {
+                    received.iter().for_each(|r| interfere(w, *r));
+                }
+            }
+        }
+
+        // Allocation, preferring the local variable of the values a phi node merges.
+        let mut slot = HashMap::new();
+        let mut max_locals = 0;
+        for p in &self.parameters {
+            slot.insert(*p, max_locals);
+            max_locals += slots(self.type_of(*p));
+        }
+        let mut hints: HashMap<Value, Vec<Value>> = HashMap::new();
+        for phi in self.blocks.iter().flat_map(|b| &b.phis) {
+            for (_, v) in &phi.incoming {
+                hints.entry(phi.value).or_default().push(*v);
+                hints.entry(*v).or_default().push(phi.value);
+            }
+        }
+        let mut values = used.iter().copied().filter(|v| !slot.contains_key(v)).collect::<Vec<_>>();
+        values.sort();
+        for v in values {
+            let size = slots(self.type_of(v)) as u32;
+            let taken = interference.get(&v).into_iter().flatten().filter_map(|u| slot.get(u).map(|s| (*s as u32, slots(self.type_of(*u)) as u32)))
+                .collect::<Vec<_>>();
+            let fits = |s: u32| s + size <= u16::MAX as u32 && taken.iter().all(|(t, len)| s + size <= *t || t + len <= s);
+            let hinted = hints.get(&v).into_iter().flatten().filter_map(|h| slot.get(h)).map(|s| *s as u32).find(|s| fits(*s));
+            let s = hinted.or_else(|| (0..u16::MAX as u32).find(|s| fits(*s)))
+                .ok_or(Error::Limit("local variables", u16::MAX as u64 + 1, u16::MAX as u64))?;
+            slot.insert(v, s as u16);
+            max_locals = max_locals.max((s + size) as u16);
+        }
+
+        // Instructions.
+        let load = |v: Value| Instruction::LocalVariable(LoadOrStore::Load, self.type_of(v), slot[&v]);
+        let store = |v: Value| Instruction::LocalVariable(LoadOrStore::Store, self.type_of(v), slot[&v]);
+        let copy = |pairs: Vec<(Value, Value)>| -> Vec<Instruction> {
+            let pairs = pairs.into_iter().filter(|(from, to)| slot[from] != slot[to]).collect::<Vec<_>>();
+            pairs.iter().map(|(from, _)| load(*from)).chain(pairs.iter().rev().map(|(_, to)| store(*to))).collect()
+        };
+        let phi_copies = |from: usize, to: usize| copy(self.blocks[to].phis.iter().filter(|p| used.contains(&p.value))
+            .filter_map(|p| incoming(p, from).map(|v| (v, p.value))).collect());
+        let mut code = vec![];
+        let mut catches = vec![];
+        let mut labels = n as u32;
+        let mut new_label = || {
+            labels += 1;
+            Label(labels - 1)
+        };
+        let mut trampolines = vec![];
+        let mut open: Option<(Label, usize, &[Handler])> = None;
+        for (b, block) in self.blocks.iter().enumerate() {
+            if let Some((start, position, handlers)) = open {
+                if handlers != &block.handlers[..] {
+                    if code[position..].iter().any(is_executed) {
+                        let end = new_label();
+                        code.push(Instruction::Label(end));
+                        catches.extend(handlers.iter().map(|h| Catch { start, end, handler: Label(h.target as u32), catch: h.catch.clone() }));
+                    }
+                    open = None;
+                }
+            }
+            code.push(Instruction::Label(Label(b as u32)));
+            match block.exception {
+                Some(e) if used.contains(&e) => code.push(store(e)),
+                Some(_) => code.push(Instruction::Pop1),
+                None => {}
+            }
+            for h in &block.handlers {
+                code.extend(phi_copies(b, h.target));
+            }
+            // The range opens after the copies, which cannot throw, so that the handler never sees them half done.
+            if open.is_none() && !block.handlers.is_empty() {
+                let start = new_label();
+                code.push(Instruction::Label(start));
+                open = Some((start, code.len(), &block.handlers));
+            }
+            for s in &block.statements {
+                code.extend(s.operands.iter().map(|v| load(*v)));
+                code.push(s.instruction.clone());
+                match s.value {
+                    Some(v) if used.contains(&v) => code.push(store(v)),
+                    Some(v) if slots(self.type_of(v)) == 2 => code.push(Instruction::Pop2),
+                    Some(_) => code.push(Instruction::Pop1),
+                    None => {}
+                }
+            }
+            let mut target = |to: usize| {
+                let copies = phi_copies(b, to);
+                if copies.is_empty() {
+                    Label(to as u32)
+                } else {
+                    let label = new_label();
+                    trampolines.push((label, copies, to));
+                    label
+                }
+            };
+            match &block.terminator {
+                Terminator::Jump(to) => {
+                    code.extend(phi_copies(b, *to));
+                    if *to != b + 1 {
+                        code.push(Instruction::Jump(JumpCondition::Always, Label(*to as u32)));
+                    }
+                }
+                Terminator::Branch { condition, operands, taken, otherwise } => {
+                    code.extend(operands.iter().map(|v| load(*v)));
+                    code.push(Instruction::Jump(*condition, target(*taken)));
+                    code.extend(phi_copies(b, *otherwise));
+                    if *otherwise != b + 1 {
+                        code.push(Instruction::Jump(JumpCondition::Always, Label(*otherwise as u32)));
+                    }
+                }
+                Terminator::Switch { value, default, cases } => {
+                    code.push(load(*value));
+                    let mut targets = HashMap::new();
+                    let mut label = |to: usize| *targets.entry(to).or_insert_with(|| target(to));
+                    let default = label(*default);
+                    let mut cases = cases.iter().map(|(k, to)| (*k, label(*to))).collect::<Vec<_>>();
+                    cases.sort_by_key(|(k, _)| *k);
+                    let contiguous = cases.windows(2).all(|w| w[1].0 == w[0].0.wrapping_add(1));
+                    code.push(match cases.first() {
+                        Some((low, _)) if contiguous => Instruction::TableSwitch { default, low: *low, offsets: cases.iter().map(|(_, l)| *l).collect() },
+                        _ => Instruction::LookupSwitch { default, table: cases.into_iter().collect() }
+                    });
+                }
+                Terminator::Return(value) => {
+                    code.extend(value.map(load));
+                    code.push(Instruction::Return(value.map(|v| self.type_of(v))));
+                }
+                Terminator::Throw(value) => {
+                    code.push(load(*value));
+                    code.push(Instruction::Throw);
+                }
+            }
+        }
+        if let Some((start, position, handlers)) = open {
+            if code[position..].iter().any(is_executed) {
+                let end = new_label();
+                code.push(Instruction::Label(end));
+                catches.extend(handlers.iter().map(|h| Catch { start, end, handler: Label(h.target as u32), catch: h.catch.clone() }));
+            }
+        }
+        for (label, copies, to) in trampolines {
+            code.push(Instruction::Label(label));
+            code.extend(copies);
+            code.push(Instruction::Jump(JumpCondition::Always, Label(to as u32)));
+        }
+        let mut code = Code { max_stack: 0, max_locals, code, catches, attrs: vec![] };
+        let heights = stack_heights(&code)?;
+        for (insn, height) in code.code.iter().zip(heights) {
+            if let Some(h) = height {
+                let (pop, push) = insn.stack_effect().unwrap_or((0, 0));
+                code.max_stack = code.max_stack.max(h).max(h - pop + push);
+            }
+        }
+        Ok(code)
+    }
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let list = |values: &[Value]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ");
+        writeln!(f, "parameters {}", list(&self.parameters))?;
+        for (b, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "b{}:", b)?;
+            for phi in &block.phis {
+                let incoming = phi.incoming.iter().map(|(p, v)| format!("b{}: {}", p, v)).collect::<Vec<_>>().join(", ");
+                writeln!(f, "  {} = phi {}", phi.value, incoming)?;
+            }
+            if let Some(e) = block.exception {
+                writeln!(f, "  {} = exception", e)?;
+            }
+            for s in &block.statements {
+                write!(f, "  ")?;
+                if let Some(v) = s.value {
+                    write!(f, "{} = ", v)?;
+                }
+                write!(f, "{:?}", s.instruction)?;
+                if !s.operands.is_empty() {
+                    write!(f, " {}", list(&s.operands))?;
+                }
+                writeln!(f)?;
+            }
+            match &block.terminator {
+                Terminator::Jump(to) => writeln!(f, "  jump b{}", to)?,
+                Terminator::Branch { condition, operands, taken, otherwise } =>
+                    writeln!(f, "  branch {:?} {} b{} else b{}", condition, list(operands), taken, otherwise)?,
+                Terminator::Switch { value, default, cases } => {
+                    let cases = cases.iter().map(|(k, to)| format!("{}: b{}", k, to)).collect::<Vec<_>>().join(", ");
+                    writeln!(f, "  switch {} [{}] default b{}", value, cases, default)?
+                }
+                Terminator::Return(Some(v)) => writeln!(f, "  return {}", v)?,
+                Terminator::Return(None) => writeln!(f, "  return")?,
+                Terminator::Throw(v) => writeln!(f, "  throw {}", v)?
+            }
+            for h in &block.handlers {
+                writeln!(f, "  catch {} b{}", h.catch.as_deref().unwrap_or("any"), h.target)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Removal of debugging information from classes, similar to what ProGuard does to release builds.
+//!
+//! Every category of debugging information is selected through [`StripFlags`], and the [`Stripper`] applies them to a [`Class`].
+
+use crate::prelude::*;
+use crate::Class;
+
+bitflags! {
+    /// Categories of debugging information that a [`Stripper`] removes.
+    pub struct StripFlags: u16 {
+        /// `LineNumber` instructions, which become the `LineNumberTable` attribute.
+        const LINE_NUMBERS           = 0b0000_0000_0000_0001;
+        /// Local variable names and types of method bodies.
+        const LOCAL_VARIABLES        = 0b0000_0000_0000_0010;
+        /// The `SourceFile` attribute of the class.
+        const SOURCE_FILE            = 0b0000_0000_0000_0100;
+        /// The `SourceDebugExtension` attribute of the class.
+        const SOURCE_DEBUG_EXTENSION = 0b0000_0000_0000_1000;
+        /// Names and flags of method parameters.
+        const METHOD_PARAMETERS      = 0b0000_0000_0001_0000;
+        /// Annotations that are not visible at runtime.
+        const INVISIBLE_ANNOTATIONS  = 0b0000_0000_0010_0000;
+        /// Generic signatures of classes, fields, methods and local variables.
+        const SIGNATURES             = 0b0000_0000_0100_0000;
+    }
+}
+
+/// Categories that require method bodies to be decoded.
+const CODE_FLAGS: StripFlags = StripFlags::from_bits_truncate(
+    StripFlags::LINE_NUMBERS.bits() | StripFlags::LOCAL_VARIABLES.bits() | StripFlags::INVISIBLE_ANNOTATIONS.bits() | StripFlags::SIGNATURES.bits()
+);
+
+/// The size of a class before and after being stripped.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct StripReport {
+    /// The size of the class file in bytes before stripping.
+    pub before: usize,
+    /// The size of the class file in bytes after stripping.
+    pub after: usize
+}
+
+impl StripReport {
+    /// The amount of bytes saved by stripping.
+    #[inline]
+    pub fn saved(&self) -> usize {
+        self.before.saturating_sub(self.after)
+    }
+}
+
+/// Strips the selected categories of debugging information from classes.
+#[derive(Clone, Debug)]
+pub struct Stripper {
+    flags: StripFlags,
+    source_file: Option<Cow<'static, str>>
+}
+
+impl Default for Stripper {
+    fn default() -> Self {
+        Stripper::new(StripFlags::all())
+    }
+}
+
+impl Stripper {
+    /// Creates a new stripper removing the given categories.
+    pub fn new(flags: StripFlags) -> Self {
+        Stripper { flags, source_file: None }
+    }
+
+    /// Replaces the value of the `SourceFile` attribute with `name` instead of removing it.
+    ///
+    /// This has no effect if [`StripFlags::SOURCE_FILE`] is selected, because the attribute is removed anyway.
+    pub fn rename_source_file<S: Into<Cow<'static, str>>>(mut self, name: S) -> Self {
+        self.source_file = Some(name.into());
+        self
+    }
+
+    /// Strips the class and reports its size before and after.
+    ///
+    /// The sizes are measured by writing the class, so this fails when the class cannot be written.
+    pub fn strip(&self, class: &mut Class) -> Result<StripReport> {
+        let before = class_size(class)?;
+        self.strip_in_place(class)?;
+        Ok(StripReport { before, after: class_size(class)? })
+    }
+
+    /// Strips the class without measuring the savings.
+    ///
+    /// This fails when a lazily decoded method body has to be stripped but cannot be decoded.
+    pub fn strip_in_place(&self, class: &mut Class) -> Result<()> {
+        let flags = self.flags;
+        class.attributes.retain(|a| match a {
+            ClassAttribute::Signature(_) => !flags.contains(StripFlags::SIGNATURES),
+            ClassAttribute::SourceFile(_) => !flags.contains(StripFlags::SOURCE_FILE),
+            ClassAttribute::SourceDebugExtension(_) => !flags.contains(StripFlags::SOURCE_DEBUG_EXTENSION),
+            ClassAttribute::RuntimeInvisibleAnnotations(_) => !flags.contains(StripFlags::INVISIBLE_ANNOTATIONS),
+            ClassAttribute::Raw(r) => !flags.contains(StripFlags::INVISIBLE_ANNOTATIONS) || !is_invisible_annotation(&r.name),
+            _ => true
+        });
+        if let Some(ref name) = self.source_file {
+            for a in &mut class.attributes {
+                if let ClassAttribute::SourceFile(s) = a {
+                    *s = name.clone();
+                }
+            }
+        }
+        for f in &mut class.fields {
+            f.attrs.retain(|a| match a {
+                FieldAttribute::Signature(_) => !flags.contains(StripFlags::SIGNATURES),
+                FieldAttribute::RuntimeInvisibleAnnotations(_) | FieldAttribute::RuntimeInvisibleTypeAnnotations(_) => !flags.contains(StripFlags::INVISIBLE_ANNOTATIONS),
+                _ => true
+            });
+        }
+        for m in &mut class.methods {
+            m.attributes.retain(|a| match a {
+                MethodAttribute::Signature(_) => !flags.contains(StripFlags::SIGNATURES),
+                MethodAttribute::MethodParameters(_) => !flags.contains(StripFlags::METHOD_PARAMETERS),
+                MethodAttribute::RuntimeInvisibleAnnotations(_) | MethodAttribute::RuntimeInvisibleTypeAnnotations(_) |
+                MethodAttribute::RuntimeInvisibleParameterAnnotations(_) => !flags.contains(StripFlags::INVISIBLE_ANNOTATIONS),
+                _ => true
+            });
+            for a in &mut m.attributes {
+                match a {
+                    MethodAttribute::Code(c) => self.strip_code(c),
+                    MethodAttribute::LazyCode(c) if flags.intersects(CODE_FLAGS) => self.strip_code(c.get_mut()?),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn strip_code(&self, code: &mut Code) {
+        let flags = self.flags;
+        if flags.contains(StripFlags::LINE_NUMBERS) {
+            code.code.retain(|i| !matches!(i, Instruction::LineNumber(_)));
+        }
+        code.attrs.retain(|a| match a {
+            CodeAttribute::LocalVariables(_) => !flags.contains(StripFlags::LOCAL_VARIABLES),
+            CodeAttribute::InvisibleTypeAnnotations(_) => !flags.contains(StripFlags::INVISIBLE_ANNOTATIONS),
+            _ => true
+        });
+        if flags.contains(StripFlags::SIGNATURES) {
+            for a in &mut code.attrs {
+                if let CodeAttribute::LocalVariables(vars) = a {
+                    for v in vars.iter_mut() {
+                        v.signature = None;
+                    }
+                    // a variable without descriptor nor signature would not be written at all
+                    vars.retain(|v| v.descriptor.is_some());
+                }
+            }
+            code.attrs.retain(|a| !matches!(a, CodeAttribute::LocalVariables(v) if v.is_empty()));
+        }
+    }
+}
+
+fn is_invisible_annotation(name: &str) -> bool {
+    name == "RuntimeInvisibleAnnotations" || name == "RuntimeInvisibleTypeAnnotations"
+}
+
+fn class_size(class: &Class) -> Result<usize> {
+    let mut buf = vec![];
+    class.write_to(&mut buf)?;
+    Ok(buf.len())
+}
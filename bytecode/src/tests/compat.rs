@@ -0,0 +1,138 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::Class;
+use crate::prelude::*;
+use crate::compat::{check, Incompatibility, Visibility};
+use super::{class, field, member, method, void};
+
+#[test]
+fn compatible_changes_are_ignored() {
+    let public = MethodFlags::ACC_PUBLIC;
+    let old = vec![
+        class("a/Base", "java/lang/Object", &[], vec![]),
+        Class {
+            fields: vec![field(FieldFlags::ACC_PRIVATE, "hidden")],
+            ..class("a/Api", "a/Base", &[], vec![
+                method(public, "moved", void(), vec![]),
+                method(MethodFlags::ACC_PROTECTED, "widened", void(), vec![]),
+                method(public | MethodFlags::ACC_FINAL, "unfinal", void(), vec![])
+            ])
+        },
+        Class { access: ClassFlags::empty(), ..class("a/Internal", "java/lang/Object", &[], vec![method(public, "gone", void(), vec![])]) }
+    ];
+    let new = vec![
+        class("a/Base", "java/lang/Object", &[], vec![method(public, "moved", void(), vec![])]),
+        class("a/Api", "a/Base", &[], vec![
+            method(public, "widened", void(), vec![]),
+            method(public, "unfinal", void(), vec![]),
+            method(public, "added", void(), vec![])
+        ])
+    ];
+    assert_eq!(check(&old, &new), vec![]);
+}
+
+#[test]
+fn incompatible_changes() {
+    let public = MethodFlags::ACC_PUBLIC;
+    let itf = ClassFlags::ACC_PUBLIC | ClassFlags::ACC_INTERFACE | ClassFlags::ACC_ABSTRACT;
+    let old = vec![
+        class("a/Base", "java/lang/Object", &[], vec![]),
+        Class {
+            fields: vec![
+                field(FieldFlags::ACC_PUBLIC, "count"),
+                field(FieldFlags::ACC_PUBLIC | FieldFlags::ACC_STATIC, "shared")
+            ],
+            ..class("a/Api", "a/Base", &[], vec![
+                method(public, "removed", void(), vec![]),
+                method(public, "changed", Type::method([Type::Int], None), vec![]),
+                method(public, "narrowed", void(), vec![]),
+                method(public, "finalized", void(), vec![])
+            ])
+        },
+        Class { access: itf, ..class("a/Listener", "java/lang/Object", &[], vec![]) },
+        class("a/Gone", "java/lang/Object", &[], vec![])
+    ];
+    let new = vec![
+        class("a/Base", "java/lang/Object", &[], vec![]),
+        Class {
+            access: ClassFlags::ACC_PUBLIC | ClassFlags::ACC_FINAL,
+            fields: vec![
+                field(FieldFlags::ACC_PUBLIC | FieldFlags::ACC_FINAL, "count"),
+                field(FieldFlags::ACC_PUBLIC, "shared")
+            ],
+            ..class("a/Api", "java/lang/Object", &[], vec![
+                method(public, "changed", Type::method([Type::Long], None), vec![]),
+                method(MethodFlags::ACC_PROTECTED, "narrowed", void(), vec![]),
+                method(public | MethodFlags::ACC_FINAL, "finalized", void(), vec![])
+            ])
+        },
+        Class {
+            access: itf,
+            ..class("a/Listener", "java/lang/Object", &[], vec![
+                method(public | MethodFlags::ACC_ABSTRACT, "onEvent", void(), vec![]),
+                method(public, "withDefault", void(), vec![])
+            ])
+        }
+    ];
+    assert_eq!(check(&old, &new), vec![
+        Incompatibility::ClassFinalAdded("a/Api".into()),
+        Incompatibility::SupertypeRemoved("a/Api".into(), "a/Base".into()),
+        Incompatibility::FinalAdded(member("a/Api", "count", Type::Int)),
+        Incompatibility::StaticChanged(member("a/Api", "shared", Type::Int), false),
+        Incompatibility::MemberRemoved(member("a/Api", "removed", void())),
+        Incompatibility::DescriptorChanged(member("a/Api", "changed", Type::method([Type::Int], None)), Type::method([Type::Long], None)),
+        Incompatibility::VisibilityNarrowed(member("a/Api", "narrowed", void()), Visibility::Public, Visibility::Protected),
+        Incompatibility::FinalAdded(member("a/Api", "finalized", void())),
+        Incompatibility::AbstractMethodAdded(MemberRef { itfs: true, ..member("a/Listener", "onEvent", void()) }),
+        Incompatibility::ClassRemoved("a/Gone".into())
+    ]);
+    assert_eq!(check(&old, &new)[5].to_string(), "a/Api.changed (I)V: descriptor changed to (J)V");
+}
+
+#[test]
+fn final_static_members() {
+    let public_static = MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC;
+    let util = |field_access, method_access| Class {
+        fields: vec![field(field_access, "limit")],
+        ..class("a/Util", "java/lang/Object", &[], vec![method(method_access, "run", void(), vec![])])
+    };
+    let old = vec![util(FieldFlags::ACC_PUBLIC | FieldFlags::ACC_STATIC, public_static)];
+    let new = vec![util(FieldFlags::ACC_PUBLIC | FieldFlags::ACC_STATIC | FieldFlags::ACC_FINAL, public_static | MethodFlags::ACC_FINAL)];
+    assert_eq!(check(&old, &new), vec![Incompatibility::FinalAdded(member("a/Util", "limit", Type::Int))]);
+}
+
+#[test]
+fn abstract_added() {
+    let public = MethodFlags::ACC_PUBLIC;
+    let shape = |access, method_access| Class {
+        access,
+        ..class("a/Shape", "java/lang/Object", &[], vec![
+            method(public, "<init>", void(), vec![]),
+            method(method_access, "area", void(), vec![])
+        ])
+    };
+    let old = vec![shape(ClassFlags::ACC_PUBLIC, public)];
+    let new = vec![shape(ClassFlags::ACC_PUBLIC | ClassFlags::ACC_ABSTRACT, public | MethodFlags::ACC_ABSTRACT)];
+    let found = check(&old, &new);
+    assert_eq!(found, vec![
+        Incompatibility::ClassAbstractAdded("a/Shape".into()),
+        Incompatibility::AbstractAdded(member("a/Shape", "area", void()))
+    ]);
+    assert_eq!(found[1].to_string(), "a/Shape.area ()V: made abstract");
+    // an abstract class stays abstract, and its abstract methods may get a body.
+    assert_eq!(check(&new, &old), vec![]);
+}
@@ -0,0 +1,99 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::Class;
+use crate::prelude::*;
+use std::io::Cursor;
+
+#[test]
+fn string_concat_roundtrip() {
+    let parts = vec![
+        ConcatPart::Literal("x = ".into()),
+        ConcatPart::Argument(Type::Int),
+        ConcatPart::Literal(", tag \u{1}".into()),
+        ConcatPart::Argument(Type::reference("java/lang/Object")),
+        ConcatPart::Constant(Constant::I64(10).into()),
+    ];
+    let d = Dynamic::string_concat(parts.clone());
+    assert!(d.is_string_concat());
+    assert_eq!(d.descriptor, Type::method([Type::Int, Type::reference("java/lang/Object")], Some(Type::reference("java/lang/String"))));
+    assert_eq!(d.bsm().arguments[0], OrDynamic::Static(Constant::string("x = \u{1}\u{2}\u{1}\u{2}")));
+
+    let mut expected = parts;
+    expected[2] = ConcatPart::Constant(Constant::string(", tag \u{1}").into());
+    assert_eq!(d.string_concat_parts().unwrap(), expected);
+}
+
+#[test]
+fn string_concat_mismatched_recipe() {
+    let mut d = Dynamic::string_concat(vec![ConcatPart::Argument(Type::Int)]);
+    d.descriptor = Type::method([], Some(Type::reference("java/lang/String")));
+    assert!(d.string_concat_parts().is_err());
+}
+
+#[test]
+fn string_concat_class_roundtrip() {
+    let parts = vec![ConcatPart::Literal("n = ".into()), ConcatPart::Argument(Type::Int)];
+    let concat = Dynamic::string_concat(parts.clone());
+    // the bootstrap method is written without a BootstrapMethods attribute in the class.
+    let class = Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC | ClassFlags::ACC_SUPER,
+        name: "Concat".into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec![],
+        fields: vec![],
+        methods: vec![Method {
+            access: MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC,
+            name: "show".into(),
+            descriptor: Type::method([Type::Int], Some(Type::reference("java/lang/String"))),
+            attributes: vec![MethodAttribute::Code(Code {
+                max_stack: 1,
+                max_locals: 1,
+                code: vec![
+                    Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+                    Instruction::InvokeDynamic(concat),
+                    Instruction::Return(Some(LocalType::Reference))
+                ],
+                catches: vec![],
+                attrs: vec![]
+            })]
+        }],
+        attributes: vec![]
+    };
+    let mut buf = vec![];
+    class.write_to(&mut buf).unwrap();
+    let read = Class::read_from(&mut Cursor::new(buf)).unwrap();
+    let d = match &read.methods[0].attributes[0] {
+        MethodAttribute::Code(c) => match &c.code[1] {
+            Instruction::InvokeDynamic(d) => d,
+            i => panic!("expected invokedynamic, got {:?}", i)
+        },
+        a => panic!("expected code, got {:?}", a)
+    };
+    assert_eq!(d.string_concat_parts().unwrap(), parts);
+    assert!(matches!(&read.attributes[..], [ClassAttribute::BootstrapMethods(b)] if b.len() == 1));
+}
+
+#[test]
+fn string_concat_leftover_constants() {
+    let d = Dynamic::string_concat(vec![ConcatPart::Constant(Constant::I32(1).into())]);
+    let mut bsm = d.bsm().clone();
+    bsm.arguments.push(Constant::I32(2).into());
+    let d = Dynamic::new(bsm, "makeConcatWithConstants", d.descriptor.clone());
+    assert!(d.string_concat_parts().is_err());
+}
@@ -0,0 +1,75 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::Class;
+use crate::prelude::*;
+use crate::error::{ErrorBase, PathSegment};
+use super::find;
+
+fn class(code: Vec<Instruction>) -> Vec<u8> {
+    let mut buf = vec![];
+    Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC,
+        name: "Broken".into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec![],
+        fields: vec![],
+        methods: vec![Method {
+            access: MethodFlags::ACC_STATIC,
+            name: "run".into(),
+            descriptor: Type::method([], None),
+            attributes: vec![MethodAttribute::Code(Code {
+                max_stack: 0,
+                max_locals: 0,
+                code,
+                catches: vec![],
+                attrs: vec![]
+            })]
+        }],
+        attributes: vec![]
+    }.write_to(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn error_locates_instruction() {
+    let mut bytes = class(vec![Instruction::NoOp, Instruction::NoOp, Instruction::Return(None)]);
+    let pos = find(&bytes, &[0, 0, 0, 3, 0, 0, 0xB1]) + 5;
+    bytes[pos] = 0xCB; // not an opcode
+    let e = Class::read_from(&mut bytes.as_slice()).unwrap_err();
+    let ctx = e.context().unwrap();
+    assert_eq!(ctx.offset, pos as u64);
+    assert_eq!(ctx.path, vec![
+        PathSegment::Class("Broken".into()),
+        PathSegment::Member("run".into(), "()V".into()),
+        PathSegment::Attribute("Code".into()),
+        PathSegment::Insn(1)
+    ]);
+    assert_eq!(ctx.to_string(), format!("offset 0x{:X} in Broken.run()V/Code/insn 1", pos));
+    assert!(!matches!(e.root(), ErrorBase::Context(..)));
+}
+
+#[test]
+fn error_locates_constant_pool() {
+    let bytes = class(vec![Instruction::Return(None)]);
+    let e = Class::read_from(&mut &bytes[..12]).unwrap_err();
+    let ctx = e.context().unwrap();
+    // the first entry is cut off at the end of the input
+    assert_eq!(ctx.offset, 12);
+    assert!(ctx.path.is_empty());
+}
@@ -0,0 +1,124 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::prelude::*;
+use crate::coverage::{instrument_class, ProbeKind, FIELD};
+use super::{class, code, label, member_ref, method_with, void};
+
+fn unary() -> Type {
+    Type::method([Type::Int], Some(Type::Int))
+}
+
+fn probe(id: i32) -> Vec<Instruction> {
+    vec![
+        Instruction::Field(GetOrPut::Get, MemberType::Static, member_ref("Covered", FIELD, Type::array(1, Type::Boolean))),
+        Instruction::Push(OrDynamic::Static(Constant::I32(id))),
+        Instruction::Push(OrDynamic::Static(Constant::I32(1))),
+        Instruction::Array(LoadOrStore::Store, ArrayType::ByteOrBool)
+    ]
+}
+
+#[test]
+fn blocks_and_branches() {
+    use Instruction::*;
+    let load = LocalVariable(LoadOrStore::Load, LocalType::Int, 0);
+    let mut class = class("Covered", "java/lang/Object", &[], vec![method_with(MethodFlags::ACC_STATIC, "sign", unary(), code(1, 1, vec![
+        LineNumber(3),
+        load.clone(),
+        Jump(JumpCondition::IntegerLessThanZero, label(0)),
+        LineNumber(4),
+        Push(OrDynamic::Static(Constant::I32(1))),
+        Return(Some(LocalType::Int)),
+        Label(label(0)),
+        LineNumber(5),
+        Push(OrDynamic::Static(Constant::I32(-1))),
+        Return(Some(LocalType::Int))
+    ]))]);
+    let probes = instrument_class(&mut class).unwrap();
+    let kinds = probes.probes.iter().map(|p| (p.line, p.kind.clone())).collect::<Vec<_>>();
+    assert_eq!(kinds, vec![
+        (Some(3), ProbeKind::Block),
+        (Some(3), ProbeKind::Jump { taken: false }),
+        (Some(4), ProbeKind::Block),
+        (Some(5), ProbeKind::Block),
+        (Some(3), ProbeKind::Jump { taken: true })
+    ]);
+    let code = match &class.methods[0].attributes[0] {
+        MethodAttribute::Code(c) => c,
+        _ => unreachable!()
+    };
+    let expected = vec![LineNumber(3)].into_iter()
+        .chain(probe(0))
+        .chain(vec![load, Jump(JumpCondition::IntegerLessThanZero, label(1))])
+        .chain(probe(1))
+        .chain(vec![LineNumber(4)])
+        .chain(probe(2))
+        .chain(vec![Push(OrDynamic::Static(Constant::I32(1))), Return(Some(LocalType::Int)), Label(label(0)), LineNumber(5)])
+        .chain(probe(3))
+        .chain(vec![Push(OrDynamic::Static(Constant::I32(-1))), Return(Some(LocalType::Int)), Label(label(1))])
+        .chain(probe(4))
+        .chain(vec![Jump(JumpCondition::Always, label(0))])
+        .collect::<Vec<_>>();
+    assert_eq!(code.code, expected);
+    assert_eq!(code.max_stack, 4);
+
+    // the array is created by a new static initializer.
+    assert_eq!(class.fields[0].name, FIELD);
+    let clinit = &class.methods[1];
+    assert_eq!(clinit.name, "<clinit>");
+    match &clinit.attributes[0] {
+        MethodAttribute::Code(c) => assert_eq!(c.code[..2], [
+            Push(OrDynamic::Static(Constant::I32(5))),
+            NewArray(OrDynamic::Static(Type::Boolean), 1)
+        ]),
+        _ => unreachable!()
+    }
+    assert_eq!(probes.to_string().lines().nth(4), Some("Covered\t4\tsign(I)I\t3\ttaken"));
+    assert!(instrument_class(&mut class).is_err());
+}
+
+#[test]
+fn switch_targets() {
+    use Instruction::*;
+    let ret = |i| vec![Label(label(i)), Push(OrDynamic::Static(Constant::I32(i as i32))), Return(Some(LocalType::Int))];
+    let mut insns = vec![
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        LookupSwitch { default: label(1), table: vec![(7, label(0)), (9, label(1)), (12, label(0))].into_iter().collect() }
+    ];
+    insns.extend(ret(0));
+    insns.extend(ret(1));
+    let mut class = class("Covered", "java/lang/Object", &[], vec![method_with(MethodFlags::ACC_STATIC, "pick", unary(), code(1, 1, insns))]);
+    class.methods.push(method_with(MethodFlags::ACC_STATIC, "<clinit>", void(), code(0, 0, vec![Return(None)])));
+    let probes = instrument_class(&mut class).unwrap();
+    let kinds = probes.probes.iter().map(|p| p.kind.to_string()).collect::<Vec<_>>();
+    assert_eq!(kinds, vec!["block", "block", "block", "case 7,12", "case 9,default", "block"]);
+    match &class.methods[0].attributes[0] {
+        MethodAttribute::Code(c) => assert_eq!(c.code[5], LookupSwitch {
+            default: label(3),
+            table: vec![(7, label(2)), (9, label(3)), (12, label(2))].into_iter().collect()
+        }),
+        _ => unreachable!()
+    }
+    // the existing static initializer creates the array first, then sets its own probe.
+    match &class.methods[1].attributes[0] {
+        MethodAttribute::Code(c) => {
+            assert_eq!(c.code[0], Push(OrDynamic::Static(Constant::I32(6))));
+            assert_eq!(c.code[3..7], probe(5)[..]);
+            assert_eq!(c.max_stack, 3);
+        }
+        _ => unreachable!()
+    }
+}
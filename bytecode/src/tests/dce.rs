@@ -0,0 +1,117 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::prelude::*;
+use crate::dce::{eliminate, DeadCode};
+use crate::flow::{ControlFlowGraph, Edge, EdgeKind};
+use super::{code, int, label};
+
+#[test]
+fn graph_edges() {
+    use Instruction::*;
+    let mut code = code(2, 1, vec![
+        Label(label(0)),
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        TableSwitch { default: label(2), low: 0, offsets: vec![label(1), label(2)] },
+        Label(label(1)),
+        Label(label(3)),
+        int(1),
+        Jump(JumpCondition::IntegerEqualsZero, label(2)),
+        Return(None),
+        Label(label(2)),
+        Return(None),
+        Label(label(4)),
+        Throw
+    ]);
+    code.catches.push(Catch { start: label(3), end: label(2), handler: label(4), catch: None });
+    let graph = ControlFlowGraph::new(&code).unwrap();
+    let ranges = graph.blocks.iter().map(|b| b.range.clone()).collect::<Vec<_>>();
+    assert_eq!(ranges, vec![0..3, 3..7, 7..8, 8..10, 10..12]);
+    assert_eq!(graph.block_of(label(3)), Some(1));
+    let edge = |target, kind| Edge { target, kind };
+    assert_eq!(graph.blocks[0].successors, vec![edge(3, EdgeKind::Jump), edge(1, EdgeKind::Jump)]);
+    assert_eq!(graph.blocks[1].successors, vec![edge(3, EdgeKind::Jump), edge(2, EdgeKind::FallThrough), edge(4, EdgeKind::Exception(0))]);
+    assert_eq!(graph.blocks[2].successors, vec![edge(4, EdgeKind::Exception(0))]);
+    assert_eq!(graph.blocks[3].predecessors, vec![0, 1]);
+    assert_eq!(graph.reachable(), vec![true; 5]);
+
+    let code = Code { code: vec![Jump(JumpCondition::Always, label(9))], ..code };
+    assert!(ControlFlowGraph::new(&code).is_err());
+}
+
+#[test]
+fn eliminate_unreachable() {
+    use Instruction::*;
+    let mut code = code(2, 1, vec![
+        Label(label(0)),
+        LineNumber(1),
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Jump(JumpCondition::IntegerEqualsZero, label(1)),
+        int(1),
+        Return(Some(LocalType::Int)),
+        LineNumber(2),
+        Label(label(2)),
+        int(2),
+        Pop1,
+        Label(label(3)),
+        Return(Some(LocalType::Int)),
+        Label(label(4)),
+        Throw,
+        Label(label(1)),
+        LineNumber(3),
+        LineNumber(4),
+        int(0),
+        Label(label(5)),
+        Return(Some(LocalType::Int)),
+        Label(label(6)),
+        Pop1,
+        int(-1),
+        Return(Some(LocalType::Int))
+    ]);
+    code.catches = vec![
+        Catch { start: label(2), end: label(3), handler: label(4), catch: None },
+        Catch { start: label(1), end: label(5), handler: label(6), catch: Some("java/lang/Exception".into()) }
+    ];
+    code.attrs.push(CodeAttribute::LocalVariables(vec![crate::code::LocalVariable {
+        start: label(2),
+        end: label(4),
+        name: "dead".into(),
+        descriptor: Some(Type::Int),
+        signature: None,
+        index: 0
+    }]));
+    let removed = eliminate(&mut code).unwrap();
+    assert_eq!(removed, DeadCode { instructions: 4, catches: 1, local_variables: 1, labels: 4, line_numbers: 2 });
+    assert_eq!(code.code, vec![
+        LineNumber(1),
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Jump(JumpCondition::IntegerEqualsZero, label(1)),
+        int(1),
+        Return(Some(LocalType::Int)),
+        Label(label(1)),
+        LineNumber(4),
+        int(0),
+        Label(label(5)),
+        Return(Some(LocalType::Int)),
+        Label(label(6)),
+        Pop1,
+        int(-1),
+        Return(Some(LocalType::Int))
+    ]);
+    assert_eq!(code.catches.len(), 1);
+    assert!(code.attrs.is_empty());
+    assert!(eliminate(&mut code).unwrap().is_empty());
+}
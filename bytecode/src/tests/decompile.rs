@@ -0,0 +1,232 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::prelude::*;
+use crate::decompile::{decompile, decompile_method, Expr};
+use super::{code, int, label, load, member_ref, method, store};
+
+/// Names the locals after `names`, over the whole code, which must start with label 100 and end with label 101.
+fn names(code: &mut Code, names: &[(&'static str, Type)]) {
+    code.attrs.push(CodeAttribute::LocalVariables(names.iter().enumerate().map(|(i, (name, ty))| crate::code::LocalVariable {
+        start: label(100),
+        end: label(101),
+        name: (*name).into(),
+        descriptor: Some(ty.clone()),
+        signature: None,
+        index: i as u16
+    }).collect()));
+}
+
+#[test]
+fn loop_with_local_names() {
+    use Instruction::*;
+    // `static int sum(int n) { int s = 0; for (int i = 0; i < n; i++) s += i; return s; }`
+    let mut sum = code(4, 4, vec![
+        Label(label(100)),
+        int(0), store(LocalType::Int, 1),
+        int(0), store(LocalType::Int, 2),
+        Label(label(0)),
+        load(LocalType::Int, 2), load(LocalType::Int, 0),
+        Jump(JumpCondition::IntegerGreaterThanOrEquals, label(1)),
+        load(LocalType::Int, 1), load(LocalType::Int, 2), IntOperation(IntType::Int, crate::code::IntOperation::Add), store(LocalType::Int, 1),
+        IntIncrement(2, 1),
+        Jump(JumpCondition::Always, label(0)),
+        Label(label(1)),
+        load(LocalType::Int, 1),
+        Return(Some(LocalType::Int)),
+        Label(label(101))
+    ]);
+    assert_eq!(decompile(&sum).unwrap().to_string(), "\
+local1 = 0;
+local2 = 0;
+while (local2 < local0) {
+    local1 = local1 + local2;
+    local2++;
+}
+return local1;
+");
+    names(&mut sum, &[("n", Type::Int), ("s", Type::Int), ("i", Type::Int)]);
+    assert_eq!(decompile(&sum).unwrap().to_string(), "\
+s = 0;
+i = 0;
+while (i < n) {
+    s = s + i;
+    i++;
+}
+return s;
+");
+}
+
+#[test]
+fn joined_conditions_and_else() {
+    use Instruction::*;
+    // `static int f(int a, int b, boolean c) { int x; if (a > b && c) x = a; else x = b; return x; }`
+    let mut f = code(4, 4, vec![
+        Label(label(100)),
+        load(LocalType::Int, 0), load(LocalType::Int, 1),
+        Jump(JumpCondition::IntegerLessThanOrEquals, label(0)),
+        load(LocalType::Int, 2),
+        Jump(JumpCondition::IntegerEqualsZero, label(0)),
+        load(LocalType::Int, 0), store(LocalType::Int, 3),
+        Jump(JumpCondition::Always, label(1)),
+        Label(label(0)),
+        load(LocalType::Int, 1), store(LocalType::Int, 3),
+        Label(label(1)),
+        load(LocalType::Int, 3),
+        Return(Some(LocalType::Int)),
+        Label(label(101))
+    ]);
+    names(&mut f, &[("a", Type::Int), ("b", Type::Int), ("c", Type::Boolean), ("x", Type::Int)]);
+    assert_eq!(decompile(&f).unwrap().to_string(), "\
+if (a > b && c) {
+    x = a;
+} else {
+    x = b;
+}
+return x;
+");
+}
+
+#[test]
+fn try_catch() {
+    assert_eq!(decompile(&super::try_catch()).unwrap().to_string(), "\
+local2 = 1;
+try {
+    local2 = local0 / local1;
+    local2 = 2;
+} catch (java.lang.ArithmeticException local3) {
+    return local2;
+}
+return local2;
+");
+}
+
+#[test]
+fn switch_with_shared_cases() {
+    use Instruction::*;
+    // `switch (a) { case 1: return 10; case 2: case 3: x = 20; break; default: x = 0; } return x;`
+    let f = code(4, 4, vec![
+        load(LocalType::Int, 0),
+        TableSwitch { default: label(3), low: 1, offsets: vec![label(1), label(2), label(2)] },
+        Label(label(1)),
+        int(10), Return(Some(LocalType::Int)),
+        Label(label(2)),
+        int(20), store(LocalType::Int, 1),
+        Jump(JumpCondition::Always, label(4)),
+        Label(label(3)),
+        int(0), store(LocalType::Int, 1),
+        Label(label(4)),
+        load(LocalType::Int, 1),
+        Return(Some(LocalType::Int))
+    ]);
+    assert_eq!(decompile(&f).unwrap().to_string(), "\
+switch (local0) {
+    case 1:
+        return 10;
+    case 2:
+    case 3:
+        local1 = 20;
+        break;
+    default:
+        local1 = 0;
+}
+return local1;
+");
+}
+
+#[test]
+fn unstructured_flow() {
+    use Instruction::*;
+    // A cycle entered in two places, which no loop statement can express.
+    let f = code(4, 4, vec![
+        load(LocalType::Int, 0),
+        Jump(JumpCondition::IntegerEqualsZero, label(1)),
+        Label(label(0)),
+        IntIncrement(1, 1),
+        Label(label(1)),
+        IntIncrement(1, -2),
+        load(LocalType::Int, 1), int(10),
+        Jump(JumpCondition::IntegerLessThan, label(0)),
+        load(LocalType::Int, 1),
+        Return(Some(LocalType::Int))
+    ]);
+    assert_eq!(decompile(&f).unwrap().to_string(), "\
+if (local0 == 0) goto L2;
+L1:
+local1++;
+L2:
+local1 -= 2;
+if (local1 < 10) goto L1;
+return local1;
+");
+
+    let jsr = code(4, 4, vec![Jsr(label(0)), Return(None), Label(label(0)), store(LocalType::Reference, 0), Ret(0)]);
+    assert!(decompile(&jsr).is_err());
+}
+
+#[test]
+fn swapped_operands() {
+    use Instruction::*;
+    let f = code(4, 4, vec![
+        load(LocalType::Int, 0), load(LocalType::Int, 1),
+        Swap,
+        IntOperation(IntType::Int, crate::code::IntOperation::Subtract),
+        Return(Some(LocalType::Int))
+    ]);
+    assert_eq!(decompile(&f).unwrap().to_string(), "return local1 - local0;\n");
+}
+
+#[test]
+fn method_bodies() {
+    use Instruction::*;
+    // `Owner() { super(); this.text = new StringBuilder().append("a\"b").append(-(x + 1) * 2L); }`
+    let init = method(MethodFlags::empty(), "<init>", Type::method([], None), vec![
+        load(LocalType::Reference, 0),
+        InvokeSpecial(member_ref("java/lang/Object", "<init>", Type::method([], None))),
+        load(LocalType::Reference, 0),
+        New(OrDynamic::Static("java/lang/StringBuilder".into())),
+        Dup,
+        InvokeSpecial(member_ref("java/lang/StringBuilder", "<init>", Type::method([], None))),
+        Push(OrDynamic::Static(Constant::String("a\"b".into()))),
+        InvokeExact(MemberType::Virtual, member_ref("java/lang/StringBuilder", "append", Type::method([Type::Ref("java/lang/String".into())], Some(Type::Ref("java/lang/StringBuilder".into()))))),
+        Field(GetOrPut::Get, MemberType::Static, member_ref("Owner", "x", Type::Int)),
+        int(1),
+        IntOperation(IntType::Int, crate::code::IntOperation::Add),
+        IntOperation(IntType::Int, crate::code::IntOperation::Negate),
+        ConvertInt(BitType::Long),
+        Push(OrDynamic::Static(Constant::I64(2))),
+        IntOperation(IntType::Long, crate::code::IntOperation::Multiply),
+        InvokeExact(MemberType::Virtual, member_ref("java/lang/StringBuilder", "append", Type::method([Type::Long], Some(Type::Ref("java/lang/StringBuilder".into()))))),
+        Field(GetOrPut::Put, MemberType::Virtual, member_ref("Owner", "text", Type::Ref("java/lang/StringBuilder".into()))),
+        Return(None)
+    ]);
+    assert_eq!(decompile_method("Owner", &init).unwrap().to_string(), "\
+super();
+this.text = new java.lang.StringBuilder().append(\"a\\\"b\").append((long) -(Owner.x + 1) * 2L);
+return;
+");
+    // `static boolean positive(int a) { if (a > 0) return true; return false; }`
+    let positive = method(MethodFlags::ACC_STATIC, "positive", Type::method([Type::Int], Some(Type::Boolean)), vec![
+        load(LocalType::Int, 0),
+        Jump(JumpCondition::IntegerLessThanOrEqualsZero, label(0)),
+        int(1), Return(Some(LocalType::Int)),
+        Label(label(0)),
+        int(0), Return(Some(LocalType::Int))
+    ]);
+    assert_eq!(decompile_method("Owner", &positive).unwrap().to_string(), "if (local0 > 0) return true;\nreturn false;\n");
+    assert_eq!(Expr::Binary(Box::new(Expr::Local("a".into())), "-", Box::new(Expr::Binary(Box::new(Expr::Local("b".into())), "-", Box::new(Expr::Local("c".into()))))).to_string(), "a - (b - c)");
+    assert!(decompile_method("Owner", &Method { attributes: vec![], ..positive }).is_err());
+}
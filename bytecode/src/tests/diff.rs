@@ -0,0 +1,189 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::Class;
+use crate::prelude::*;
+use crate::diff::{diff, Change, Differ, InsnEdit, MethodChange, FieldChange};
+use super::{class, field, method};
+
+fn unary() -> Type {
+    Type::method([Type::Int], None)
+}
+
+fn looping(label: Label, line: u16) -> Vec<Instruction> {
+    vec![
+        Instruction::LineNumber(line),
+        Instruction::Label(label),
+        Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Instruction::Jump(JumpCondition::IntegerEqualsZero, label),
+        Instruction::Return(None)
+    ]
+}
+
+#[test]
+fn labels_and_order_ignored() {
+    let a = |label| method(MethodFlags::ACC_STATIC, "a", unary(), looping(label, 1));
+    let b = method(MethodFlags::ACC_STATIC, "b", unary(), vec![Instruction::Return(None)]);
+    let old = class("Diffed", "java/lang/Object", &[], vec![a(Label(3)), b.clone()]);
+    let new = class("Diffed", "java/lang/Object", &[], vec![b, a(Label(0))]);
+    assert!(diff(&old, &new).unwrap().is_empty());
+
+    // the same bytes read lazily compare equal too.
+    let mut buf = vec![];
+    old.write_to(&mut buf).unwrap();
+    let eager = Class::read_from(&mut buf.as_slice()).unwrap();
+    let lazy = Class::read_lazy(&mut buf.as_slice()).unwrap();
+    assert!(diff(&eager, &lazy).unwrap().is_empty());
+}
+
+#[test]
+fn structured_changes() {
+    let old = Class {
+        fields: vec![field(FieldFlags::ACC_PRIVATE, "count")],
+        ..class("Diffed", "java/lang/Object", &[], vec![method(MethodFlags::ACC_STATIC, "a", unary(), looping(Label(0), 1))])
+    };
+    let mut changed = looping(Label(9), 1);
+    changed[4] = Instruction::PushNull;
+    changed.push(Instruction::Throw);
+    let new = Class {
+        fields: vec![field(FieldFlags::ACC_PUBLIC, "count")],
+        ..class("Diffed", "java/lang/Object", &[], vec![
+            method(MethodFlags::ACC_STATIC, "a", unary(), changed),
+            method(MethodFlags::ACC_STATIC, "b", unary(), vec![Instruction::Return(None)])
+        ])
+    };
+
+    let d = diff(&old, &new).unwrap();
+    assert_eq!(d.changes.len(), 3);
+    assert_eq!(d.changes[0], Change::FieldChanged("count".into(), Type::Int, vec![FieldChange::Access(FieldFlags::ACC_PRIVATE, FieldFlags::ACC_PUBLIC)]));
+    assert_eq!(d.changes[1], Change::MethodChanged("a".into(), unary(), vec![MethodChange::Instructions(vec![
+        InsnEdit::Removed(4, Instruction::Return(None)),
+        InsnEdit::Added(4, Instruction::PushNull),
+        InsnEdit::Added(5, Instruction::Throw)
+    ])]));
+    assert!(matches!(&d.changes[2], Change::MethodAdded(m) if m.name == "b"));
+    assert_eq!(d.to_string(), "\
+~ field count I
+    access: ACC_PRIVATE -> ACC_PUBLIC
+~ method a(I)V
+    - 4: Return(None)
+    + 4: PushNull
+    + 5: Throw
++ method b(I)V
+");
+}
+
+#[test]
+fn bootstrap_methods_compared_by_contents() {
+    let bsm = |literal: &'static str| Dynamic::string_concat(vec![ConcatPart::Literal(literal.into()), ConcatPart::Argument(Type::Int)]).bsm().clone();
+    let with = |bsms: Vec<BootstrapMethod>| Class {
+        attributes: vec![ClassAttribute::BootstrapMethods(bsms)],
+        ..class("Diffed", "java/lang/Object", &[], vec![])
+    };
+    // the order of bootstrap methods follows the constant pool of the written class.
+    assert!(diff(&with(vec![bsm("a"), bsm("b")]), &with(vec![bsm("b"), bsm("a")])).unwrap().is_empty());
+    let d = diff(&with(vec![bsm("a"), bsm("b")]), &with(vec![bsm("b"), bsm("c")])).unwrap();
+    assert_eq!(d.changes, vec![
+        Change::AttributeRemoved(ClassAttribute::BootstrapMethods(vec![bsm("a")])),
+        Change::AttributeAdded(ClassAttribute::BootstrapMethods(vec![bsm("c")]))
+    ]);
+}
+
+#[test]
+fn line_numbers_ignored_on_request() {
+    let old = class("Diffed", "java/lang/Object", &[], vec![method(MethodFlags::ACC_STATIC, "a", unary(), looping(Label(0), 1))]);
+    let new = class("Diffed", "java/lang/Object", &[], vec![method(MethodFlags::ACC_STATIC, "a", unary(), looping(Label(0), 5))]);
+    assert_eq!(diff(&old, &new).unwrap().changes.len(), 1);
+    assert!(Differ::new().ignore_line_numbers().diff(&old, &new).unwrap().is_empty());
+}
+
+#[test]
+fn branches_read_back_equal() {
+    let far = vec![Instruction::NoOp; 40000];
+    let mut code = vec![
+        Instruction::Label(Label(0)),
+        Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Instruction::TableSwitch { default: Label(1), low: 1, offsets: vec![Label(0), Label(2)] },
+        Instruction::Label(Label(2)),
+        Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Instruction::LookupSwitch { default: Label(1), table: vec![(7, Label(0)), (-3, Label(2))].into_iter().collect() },
+        Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Instruction::Jump(JumpCondition::IntegerEqualsZero, Label(3)),
+    ];
+    code.extend(far.iter().cloned());
+    code.extend(vec![
+        Instruction::Label(Label(3)),
+        Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Instruction::Jump(JumpCondition::IntegerEqualsZero, Label(0)),
+        Instruction::Jump(JumpCondition::Always, Label(2)),
+        Instruction::Label(Label(1)),
+        Instruction::Return(None)
+    ]);
+    let round_trip = |class: &Class| {
+        let mut buf = vec![];
+        class.write_to(&mut buf).unwrap();
+        Class::read_from(&mut buf.as_slice()).unwrap()
+    };
+    let old = class("Diffed", "java/lang/Object", &[], vec![method(MethodFlags::ACC_STATIC, "a", unary(), code)]);
+    let new = round_trip(&old);
+    // both far conditional jumps are rewritten as an inverted jump over a goto_w, adding a goto and a label each
+    let d = diff(&old, &new).unwrap();
+    match &d.changes[..] {
+        [Change::MethodChanged(_, _, changes)] => match &changes[..] {
+            [MethodChange::Instructions(edits)] => {
+                let added = edits.iter().filter(|e| matches!(e, InsnEdit::Added(..))).count();
+                let removed = edits.iter().filter(|e| matches!(e, InsnEdit::Removed(..))).count();
+                assert_eq!(added - removed, 4, "{}", d);
+            }
+            _ => panic!("{}", d)
+        },
+        _ => panic!("{}", d)
+    }
+    let d = diff(&new, &round_trip(&new)).unwrap();
+    assert!(d.is_empty(), "{}", d);
+}
+
+#[test]
+fn large_unrelated_bodies() {
+    let edits = |old: Vec<Instruction>, new: Vec<Instruction>| {
+        let class = |code| class("Diffed", "java/lang/Object", &[], vec![method(MethodFlags::ACC_STATIC, "a", unary(), code)]);
+        match diff(&class(old), &class(new)).unwrap().changes.pop() {
+            Some(Change::MethodChanged(_, _, mut c)) => match c.pop() {
+                Some(MethodChange::Instructions(edits)) => edits,
+                c => panic!("{:?}", c)
+            },
+            c => panic!("{:?}", c)
+        }
+    };
+    // the example of Myers' paper, ABCABBA to CBABAC, takes five edits.
+    let letter = |c: char| Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, c as u16);
+    let old = "ABCABBA".chars().map(letter).collect::<Vec<_>>();
+    let new = "CBABAC".chars().map(letter).collect::<Vec<_>>();
+    let e = edits(old.clone(), new.clone());
+    assert_eq!(e.len(), 5);
+    let kept = old.iter().enumerate().filter(|(i, _)| !e.contains(&InsnEdit::Removed(*i, old[*i].clone()))).map(|(_, i)| i).collect::<Vec<_>>();
+    let common = new.iter().enumerate().filter(|(i, _)| !e.contains(&InsnEdit::Added(*i, new[*i].clone()))).map(|(_, i)| i).collect::<Vec<_>>();
+    assert_eq!(kept, common);
+
+    // every instruction differs, which needs as many steps as instructions but only memory for one of them.
+    let old = (0..10000).map(|i| Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, i)).collect::<Vec<_>>();
+    let new = (0..10000).map(|i| Instruction::LocalVariable(LoadOrStore::Store, LocalType::Long, i)).collect::<Vec<_>>();
+    let e = edits(old, new);
+    let removed = e.iter().filter_map(|e| if let InsnEdit::Removed(i, _) = e { Some(*i) } else { None }).collect::<Vec<_>>();
+    let added = e.iter().filter_map(|e| if let InsnEdit::Added(i, _) = e { Some(*i) } else { None }).collect::<Vec<_>>();
+    assert_eq!(removed, (0..10000).collect::<Vec<_>>());
+    assert_eq!(added, (0..10000).collect::<Vec<_>>());
+}
@@ -0,0 +1,227 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::Class;
+use crate::prelude::*;
+use crate::flow::stack_heights;
+use crate::inline::{inline_call, Inliner};
+use crate::interp::{Completion, Interpreter, Sandboxed, Value};
+use crate::error::ErrorBase;
+use super::{class, code, field, int, label, member, member_ref, method_with};
+
+fn code_mut(m: &mut Method) -> &mut Code {
+    match &mut m.attributes[0] {
+        MethodAttribute::Code(c) => c,
+        _ => unreachable!()
+    }
+}
+
+/// `static int abs(int a) { if (a < 0) return -a; return a; }` and `static int f(int a, int b) { return b - abs(a); }`.
+fn arithmetic() -> Class {
+    use Instruction::*;
+    let int = |op| IntOperation(IntType::Int, op);
+    let abs = method_with(MethodFlags::ACC_PRIVATE | MethodFlags::ACC_STATIC, "abs", Type::method([Type::Int], Some(Type::Int)), code(1, 1, vec![
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Jump(JumpCondition::IntegerGreaterThanOrEqualsZero, label(0)),
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        int(crate::code::IntOperation::Negate),
+        Return(Some(LocalType::Int)),
+        Label(label(0)),
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Return(Some(LocalType::Int))
+    ]));
+    let f = method_with(MethodFlags::ACC_STATIC, "f", Type::method([Type::Int, Type::Int], Some(Type::Int)), code(2, 2, vec![
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 1),
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Label(label(0)),
+        InvokeExact(MemberType::Static, member_ref("Owner", "abs", Type::method([Type::Int], Some(Type::Int)))),
+        int(crate::code::IntOperation::Subtract),
+        Return(Some(LocalType::Int))
+    ]));
+    class("Owner", "java/lang/Object", &[], vec![abs, f])
+}
+
+#[test]
+fn inline_static_preserves_results() {
+    let original = arithmetic();
+    let mut inlined = original.clone();
+    let mut inliner = Inliner::new();
+    inliner.add_class(&original).unwrap();
+    assert_eq!(inliner.inline_class(&mut inlined).unwrap(), 1);
+    let code = code_mut(&mut inlined.methods[1]).clone();
+    assert!(!code.code.iter().any(|i| matches!(i, Instruction::InvokeExact(..))));
+    assert_eq!((code.max_locals, code.max_stack), (3, 3));
+    assert_eq!(code.code[3], Instruction::LocalVariable(LoadOrStore::Store, LocalType::Int, 2));
+    assert!(stack_heights(&code).unwrap().iter().all(Option::is_some));
+
+    let descriptor = Type::method([Type::Int, Type::Int], Some(Type::Int));
+    for (a, b) in [(3, 10), (-3, 10), (0, 0), (i32::MIN, 1)] {
+        let run = |class: &Class| {
+            let mut interp = Interpreter::new(Sandboxed, 100);
+            interp.add_class(class).unwrap();
+            interp.invoke("Owner", "f", &descriptor, &[Value::Int(a), Value::Int(b)]).unwrap()
+        };
+        assert_eq!(run(&inlined), run(&original));
+        assert_eq!(run(&inlined), Completion::Returned(Some(Value::Int(b.wrapping_sub(a.wrapping_abs())))));
+    }
+}
+
+#[test]
+fn inline_synchronized_accessor() {
+    use Instruction::*;
+    let x = member_ref("Owner", "x", Type::Long);
+    let mut get = method_with(MethodFlags::ACC_PRIVATE | MethodFlags::ACC_SYNCHRONIZED, "getX", Type::method([], Some(Type::Long)), code(2, 1, vec![
+        Label(label(0)),
+        LineNumber(20),
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+        Field(GetOrPut::Get, MemberType::Virtual, x.clone()),
+        Return(Some(LocalType::Long)),
+        Label(label(1))
+    ]));
+    code_mut(&mut get).attrs.push(CodeAttribute::LocalVariables(vec![crate::code::LocalVariable {
+        start: label(0),
+        end: label(1),
+        name: "this".into(),
+        descriptor: Some(Type::reference("Owner")),
+        signature: None,
+        index: 0
+    }]));
+    let mut caller = method_with(MethodFlags::ACC_PUBLIC, "twice", Type::method([], Some(Type::Long)), code(2, 2, vec![
+        LineNumber(5),
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+        InvokeSpecial(member_ref("Owner", "getX", Type::method([], Some(Type::Long)))),
+        Return(Some(LocalType::Long))
+    ]));
+    let code = code_mut(&mut caller);
+    inline_call(code, 2, &get).unwrap();
+    let lock = LocalVariable(LoadOrStore::Load, LocalType::Reference, 3);
+    assert_eq!(code.code, vec![
+        LineNumber(5),
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+        LocalVariable(LoadOrStore::Store, LocalType::Reference, 2),
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 2),
+        InvokeExact(MemberType::Virtual, member_ref("java/lang/Object", "getClass", Type::method([], Some(Type::reference("java/lang/Class"))))),
+        Pop1,
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 2),
+        Dup,
+        LocalVariable(LoadOrStore::Store, LocalType::Reference, 3),
+        Monitor(MonitorOperation::Enter),
+        Label(label(1)),
+        Label(label(4)),
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 2),
+        Field(GetOrPut::Get, MemberType::Virtual, x),
+        lock.clone(),
+        Monitor(MonitorOperation::Exit),
+        Jump(JumpCondition::Always, label(0)),
+        Label(label(5)),
+        Label(label(2)),
+        Label(label(3)),
+        lock,
+        Monitor(MonitorOperation::Exit),
+        Throw,
+        Label(label(0)),
+        Return(Some(LocalType::Long))
+    ]);
+    assert_eq!(code.catches, vec![Catch { start: label(1), end: label(2), handler: label(3), catch: None }]);
+    match &code.attrs[0] {
+        CodeAttribute::LocalVariables(v) => assert_eq!((v[0].start, v[0].end, v[0].index), (label(4), label(5), 2)),
+        _ => unreachable!()
+    }
+    assert_eq!((code.max_locals, code.max_stack), (4, 4));
+    assert_eq!(stack_heights(code).unwrap()[14], Some(2));
+}
+
+#[test]
+fn refused_calls() {
+    use Instruction::*;
+    let descriptor = Type::method([], None);
+    // a callee returning with a value left on the stack.
+    let dirty = method_with(MethodFlags::ACC_STATIC, "dirty", descriptor.clone(), code(1, 0, vec![PushNull, Return(None)]));
+    let mut caller = code(0, 0, vec![InvokeExact(MemberType::Static, member_ref("Owner", "dirty", descriptor.clone())), Return(None)]);
+    assert!(inline_call(&mut caller, 0, &dirty).is_err());
+    assert!(inline_call(&mut caller, 1, &dirty).is_err());
+    // an instance method called statically.
+    let instance = method_with(MethodFlags::ACC_PRIVATE, "dirty", descriptor, code(0, 1, vec![Return(None)]));
+    assert!(inline_call(&mut caller, 0, &instance).is_err());
+
+    // a callee with a handler, called with a value of the caller below its argument.
+    let mut guarded = method_with(MethodFlags::ACC_STATIC, "guarded", Type::method([Type::Int], None), code(1, 1, vec![
+        Label(label(0)),
+        Return(None),
+        Label(label(1)),
+        Pop1,
+        Return(None)
+    ]));
+    code_mut(&mut guarded).catches.push(Catch { start: label(0), end: label(1), handler: label(1), catch: None });
+    let call = InvokeExact(MemberType::Static, member_ref("Owner", "guarded", Type::method([Type::Int], None)));
+    let mut caller = Code { max_stack: 2, code: vec![Push(OrDynamic::Static(Constant::I32(1))), Dup, call.clone(), Pop1, Return(None)], ..caller };
+    assert!(inline_call(&mut caller, 2, &guarded).is_err());
+    let mut inliner = Inliner::new();
+    inliner.add_class(&class("Owner", "java/lang/Object", &[], vec![guarded.clone()])).unwrap();
+    assert_eq!(inliner.inline("Owner", &mut caller).unwrap(), 0);
+    // without the extra value, the handler finds the stack as it would in the callee.
+    caller.code = vec![Push(OrDynamic::Static(Constant::I32(1))), call.clone(), Return(None)];
+    assert_eq!(inliner.inline("Owner", &mut caller).unwrap(), 1);
+
+    // the caller has no room left for the local variables of the callee.
+    let mut full = code(1, u16::MAX, vec![int(1), call.clone(), Return(None)]);
+    assert!(matches!(*inline_call(&mut full, 1, &guarded).unwrap_err().root(), ErrorBase::Limit("local variables", 65536, 65535)));
+
+    let unbalanced = Code { code: vec![PushNull, Jump(JumpCondition::IsNull, label(0)), PushNull, Label(label(0)), Return(None)], ..caller };
+    assert!(stack_heights(&unbalanced).is_err());
+}
+
+#[test]
+fn inaccessible_callees() {
+    use Instruction::*;
+    let int = Type::method([], Some(Type::Int));
+    let call = |name| InvokeExact(MemberType::Static, member_ref("a/Owner", name, int.clone()));
+    let secret = member("a/Owner", "secret", Type::Int);
+    let owner = Class {
+        fields: vec![field(FieldFlags::ACC_PRIVATE | FieldFlags::ACC_STATIC, "secret")],
+        ..class("a/Owner", "java/lang/Object", &[], vec![
+            method_with(MethodFlags::ACC_STATIC, "peek", int.clone(), code(1, 0, vec![Field(GetOrPut::Get, MemberType::Static, OrDynamic::Static(secret)), Return(Some(LocalType::Int))])),
+            method_with(MethodFlags::ACC_STATIC, "one", int.clone(), code(1, 0, vec![Push(OrDynamic::Static(Constant::I32(1))), Return(Some(LocalType::Int))]))
+        ])
+    };
+    let caller = Class {
+        attributes: vec![ClassAttribute::NestHost("a/Owner".into())],
+        ..class("a/Owner$Inner", "java/lang/Object", &[], vec![method_with(MethodFlags::ACC_STATIC, "f", int.clone(), code(2, 0, vec![
+            call("peek"),
+            call("one"),
+            IntOperation(IntType::Int, crate::code::IntOperation::Add),
+            Return(Some(LocalType::Int))
+        ]))])
+    };
+    // the calls would initialize the owner, which the inlined bodies do not.
+    let mut inliner = Inliner::new();
+    inliner.add_class(&owner).unwrap();
+    assert_eq!(inliner.inline_class(&mut caller.clone()).unwrap(), 0);
+    let mut inliner = Inliner::new().other_classes(true);
+    inliner.add_class(&owner).unwrap();
+    // the private field of the owner is only accessible to its nestmates since Java 11.
+    assert_eq!(inliner.inline_class(&mut caller.clone()).unwrap(), 1);
+    let modern = JavaVersion { minor: 0, major: MajorVersion::J11 };
+    let mut inliner = Inliner::new().other_classes(true);
+    inliner.add_class(&Class { version: modern, ..owner }).unwrap();
+    assert_eq!(inliner.inline_class(&mut Class { version: modern, ..caller.clone() }).unwrap(), 2);
+    // another class of the package is no nestmate.
+    assert_eq!(inliner.inline_class(&mut Class { version: modern, name: "a/Other".into(), attributes: vec![], ..caller.clone() }).unwrap(), 1);
+    // nor does the inliner know the classes of another package to be accessible.
+    let mut other = Class { version: modern, name: "b/Other".into(), attributes: vec![], ..caller };
+    assert_eq!(inliner.inline_class(&mut other).unwrap(), 1);
+    assert_eq!(code_mut(&mut other.methods[0]).code[0], call("peek"));
+}
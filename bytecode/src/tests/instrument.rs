@@ -0,0 +1,161 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use std::cell::Cell;
+
+use crate::prelude::*;
+use crate::instrument::{instrument, Probes};
+use crate::interp::{Completion, Interpreter, Value};
+use super::{code, member_ref, method_with};
+
+#[test]
+fn entry_and_exits() {
+    use Instruction::*;
+    let nano_time = || InvokeExact(MemberType::Static, member_ref("java/lang/System", "nanoTime", Type::method([], Some(Type::Long))));
+    let report = InvokeExact(MemberType::Static, member_ref("Profiler", "report", Type::method([Type::Long], None)));
+    let mut m = method_with(MethodFlags::ACC_STATIC, "max", Type::method([Type::Long, Type::Int], Some(Type::Int)), code(2, 3, vec![
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 2),
+        Jump(JumpCondition::IntegerLessThanZero, crate::code::Label(0)),
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 2),
+        Return(Some(LocalType::Int)),
+        Label(crate::code::Label(0)),
+        Push(OrDynamic::Static(Constant::I32(0))),
+        Return(Some(LocalType::Int))
+    ]));
+    let mut boxed = vec![];
+    instrument(&mut m, |cx| {
+        assert_eq!((cx.parameter_slot(1), cx.load_this()), (2, None));
+        boxed = cx.box_parameters();
+        let start = cx.new_local(&Type::Long);
+        let skip = cx.new_label();
+        let exit = vec![
+            LocalVariable(LoadOrStore::Load, LocalType::Long, start),
+            Jump(JumpCondition::Always, skip),
+            Label(skip),
+            nano_time(),
+            IntOperation(IntType::Long, crate::code::IntOperation::Subtract),
+            report.clone()
+        ];
+        Probes {
+            entry: vec![nano_time(), LocalVariable(LoadOrStore::Store, LocalType::Long, start)],
+            exceptional_exit: exit.clone(),
+            exit
+        }
+    }).unwrap();
+    assert_eq!(boxed.len(), 2 + 2 * 5);
+    assert_eq!(boxed[5], InvokeExact(MemberType::Static, member_ref("java/lang/Long", "valueOf", Type::method([Type::Long], Some(Type::reference("java/lang/Long"))))));
+
+    let code = m.code().unwrap().unwrap();
+    let label = |l| Label(crate::code::Label(l));
+    let exit = |skip| vec![
+        LocalVariable(LoadOrStore::Load, LocalType::Long, 3),
+        Jump(JumpCondition::Always, crate::code::Label(skip)),
+        Label(crate::code::Label(skip)),
+        nano_time(),
+        IntOperation(IntType::Long, crate::code::IntOperation::Subtract),
+        report.clone()
+    ];
+    let expected = vec![nano_time(), LocalVariable(LoadOrStore::Store, LocalType::Long, 3), label(2)].into_iter()
+        .chain(vec![LocalVariable(LoadOrStore::Load, LocalType::Int, 2), Jump(JumpCondition::IntegerLessThanZero, crate::code::Label(0)), LocalVariable(LoadOrStore::Load, LocalType::Int, 2), label(3)])
+        .chain(exit(4))
+        .chain(vec![Return(Some(LocalType::Int)), label(5), label(0), Push(OrDynamic::Static(Constant::I32(0))), label(6)])
+        .chain(exit(7))
+        .chain(vec![Return(Some(LocalType::Int)), label(8), label(9), label(10)])
+        .chain(exit(1))
+        .chain(vec![Throw])
+        .collect::<Vec<_>>();
+    assert_eq!(code.code, expected);
+    // the handler does not cover the exit probes.
+    let catch = |start, end| Catch { start: crate::code::Label(start), end: crate::code::Label(end), handler: crate::code::Label(10), catch: None };
+    assert_eq!(code.catches, vec![catch(2, 3), catch(5, 6)]);
+    // the exit probe needs four slots above the returned value.
+    assert_eq!((code.max_stack, code.max_locals), (6, 5));
+}
+
+#[test]
+fn throwing_exit_probe() {
+    use Instruction::*;
+    // throws when the argument is not zero.
+    let mut m = method_with(MethodFlags::ACC_STATIC, "check", Type::method([Type::Int], Some(Type::Int)), code(1, 1, vec![
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Jump(JumpCondition::IntegerEqualsZero, crate::code::Label(0)),
+        PushNull,
+        Throw,
+        Label(crate::code::Label(0)),
+        Push(OrDynamic::Static(Constant::I32(1))),
+        Return(Some(LocalType::Int))
+    ]));
+    instrument(&mut m, |_| Probes {
+        entry: vec![],
+        exit: vec![PushNull, Throw],
+        exceptional_exit: vec![InvokeExact(MemberType::Static, member_ref("Tracer", "fail", Type::method([], None)))]
+    }).unwrap();
+    let run = |arg| {
+        let failed = Cell::new(0);
+        let mut interp = Interpreter::new(|_: &MemberRef, _: &[Value]| {
+            failed.set(failed.get() + 1);
+            Ok(None)
+        }, 100);
+        let completion = interp.run(m.code().unwrap().unwrap(), &[Value::Int(arg)]).unwrap();
+        (completion, failed.get())
+    };
+    assert_eq!(run(1), (Completion::Threw(Value::Exception("java/lang/NullPointerException".into())), 1));
+    // the exception of the exit probe does not run the exceptional exit probe.
+    assert_eq!(run(0), (Completion::NullPointer, 0));
+}
+
+#[test]
+fn constructors() {
+    use Instruction::*;
+    let init = |owner| InvokeSpecial(member_ref(owner, "<init>", Type::method([], None)));
+    let mut m = method_with(MethodFlags::ACC_PUBLIC, "<init>", Type::method([Type::Int], None), code(2, 2, vec![
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+        New(OrDynamic::Static("Arg".into())),
+        Dup,
+        init("Arg"),
+        InvokeSpecial(member_ref("Base", "<init>", Type::method([Type::reference("Arg")], None))),
+        Return(None)
+    ]));
+    instrument(&mut m, |cx| {
+        assert!(cx.is_constructor());
+        let mut entry = cx.load_this().into_iter().collect::<Vec<_>>();
+        entry.push(cx.load_parameter(0));
+        entry.push(InvokeExact(MemberType::Static, member_ref("Tracer", "enter", Type::method([Type::reference("java/lang/Object"), Type::Int], None))));
+        Probes { entry, ..Probes::default() }
+    }).unwrap();
+    let body = m.code().unwrap().unwrap();
+    assert_eq!(body.code[5..8], [
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 1),
+        InvokeExact(MemberType::Static, member_ref("Tracer", "enter", Type::method([Type::reference("java/lang/Object"), Type::Int], None)))
+    ]);
+    assert!(body.catches.is_empty());
+    assert_eq!((body.max_stack, body.max_locals), (4, 2));
+
+    let mut m = method_with(MethodFlags::ACC_PUBLIC, "<init>", Type::method([], None), code(1, 1, vec![Return(None)]));
+    assert!(instrument(&mut m, |_| Probes::default()).is_err());
+}
+
+#[test]
+fn stack_effects() {
+    use Instruction::*;
+    assert_eq!(Dup2X1.stack_effect(), Some((3, 5)));
+    assert_eq!(Jump(JumpCondition::IntegerLessThan, crate::code::Label(0)).stack_effect(), Some((2, 0)));
+    assert_eq!(IntOperation(IntType::Long, crate::code::IntOperation::ShiftLeft).stack_effect(), Some((3, 2)));
+    assert_eq!(Field(GetOrPut::Put, MemberType::Virtual, member_ref("A", "d", Type::Double)).stack_effect(), Some((3, 0)));
+    assert_eq!(InvokeInterface(member_ref("A", "f", Type::method([Type::Long, Type::Int], Some(Type::Double))), 4).stack_effect(), Some((4, 2)));
+    assert_eq!(NewArray(OrDynamic::Static(Type::array(3, Type::Int)), 3).stack_effect(), Some((3, 1)));
+}
@@ -0,0 +1,237 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::Class;
+use crate::prelude::*;
+use crate::error::ErrorBase;
+use crate::interp::{Completion, Interpreter, Sandboxed, Value, string_methods};
+use super::{code, member_ref};
+
+fn static_method(name: &'static str, descriptor: Type, max_locals: u16, code: Vec<Instruction>) -> Method {
+    Method {
+        access: MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC,
+        name: name.into(),
+        descriptor,
+        attributes: vec![MethodAttribute::Code(self::code(8, max_locals, code))]
+    }
+}
+
+fn string() -> Type {
+    Type::reference("java/lang/String")
+}
+
+/// `decrypt(s)` returns `new String(chars)` where each char of `s` was xored with `key()`, as string obfuscators do.
+fn obfuscated() -> Class {
+    use Instruction::*;
+    let (start, end) = (crate::code::Label(0), crate::code::Label(1));
+    let decrypt = static_method("decrypt", Type::method([string()], Some(string())), 3, vec![
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+        InvokeExact(MemberType::Virtual, member_ref("java/lang/String", "toCharArray", Type::method([], Some(Type::array(1, Type::Char))))),
+        LocalVariable(LoadOrStore::Store, LocalType::Reference, 1),
+        Push(OrDynamic::Static(Constant::I32(0))),
+        LocalVariable(LoadOrStore::Store, LocalType::Int, 2),
+        Label(start),
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 2),
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 1),
+        ArrayLength,
+        Jump(JumpCondition::IntegerGreaterThanOrEquals, end),
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 1),
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 2),
+        Dup2,
+        Array(LoadOrStore::Load, ArrayType::Char),
+        InvokeExact(MemberType::Static, member_ref("a/Strings", "key", Type::method([], Some(Type::Int)))),
+        IntOperation(IntType::Int, crate::code::IntOperation::ExclusiveOr),
+        Array(LoadOrStore::Store, ArrayType::Char),
+        IntIncrement(2, 1),
+        Jump(JumpCondition::Always, start),
+        Label(end),
+        New(OrDynamic::Static("java/lang/String".into())),
+        Dup,
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 1),
+        InvokeSpecial(member_ref("java/lang/String", "<init>", Type::method([Type::array(1, Type::Char)], None))),
+        Return(Some(LocalType::Reference))
+    ]);
+    let key = static_method("key", Type::method([], Some(Type::Int)), 0, vec![
+        Field(GetOrPut::Get, MemberType::Static, member_ref("a/Strings", "KEY", Type::Int)),
+        Push(OrDynamic::Static(Constant::I32(0x1000))),
+        IntOperation(IntType::Int, crate::code::IntOperation::Or),
+        Return(Some(LocalType::Int))
+    ]);
+    Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC,
+        name: "a/Strings".into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec![],
+        fields: vec![crate::member::Field {
+            access: FieldFlags::ACC_STATIC | FieldFlags::ACC_FINAL,
+            name: "KEY".into(),
+            descriptor: Type::Int,
+            attrs: vec![FieldAttribute::ConstantValue(Constant::I32(0x2a))]
+        }],
+        methods: vec![decrypt, key],
+        attributes: vec![]
+    }
+}
+
+#[test]
+fn decrypt_strings() {
+    let class = obfuscated();
+    let encrypted: String = "Hello, world".chars().map(|c| std::char::from_u32(c as u32 ^ 0x102a).unwrap()).collect();
+    let mut interp = Interpreter::new(|m: &MemberRef, args: &[Value]| string_methods(m, args).unwrap(), 10_000);
+    interp.add_class(&class).unwrap();
+    let descriptor = Type::method([string()], Some(string()));
+    let res = interp.invoke("a/Strings", "decrypt", &descriptor, &[encrypted.as_str().into()]).unwrap();
+    assert_eq!(res, Completion::Returned(Some("Hello, world".into())));
+    assert!(interp.fuel() < 10_000);
+
+    // running out of fuel in the loop is an error rather than a completion.
+    let mut interp = Interpreter::new(|m: &MemberRef, args: &[Value]| string_methods(m, args).unwrap(), 50);
+    interp.add_class(&class).unwrap();
+    let err = interp.invoke("a/Strings", "decrypt", &descriptor, &[encrypted.as_str().into()]).unwrap_err();
+    assert!(matches!(err.root(), ErrorBase::Limit("interpreted instructions", 51, 50)), "{}", err);
+
+    // calls out of the sandbox go through the hook.
+    let mut interp = Interpreter::new(Sandboxed, 10_000);
+    interp.add_class(&class).unwrap();
+    assert!(interp.invoke("a/Strings", "decrypt", &descriptor, &["x".into()]).is_err());
+}
+
+#[test]
+fn exceptional_completions() {
+    use Instruction::*;
+    let div = |ty: IntType, zero: Constant| code(8, 4, vec![
+        Push(OrDynamic::Static(Constant::I32(1))),
+        if let IntType::Long = ty { Conversion(NumberType::Int, NumberType::Long) } else { NoOp },
+        Push(OrDynamic::Static(zero)),
+        IntOperation(ty, crate::code::IntOperation::Remainder),
+        Return(Some(LocalType::Int))
+    ]);
+    let mut interp = Interpreter::new(Sandboxed, 100);
+    assert_eq!(interp.run(&div(IntType::Int, Constant::I32(0)), &[]).unwrap(), Completion::DivisionByZero);
+    assert_eq!(interp.run(&div(IntType::Long, Constant::I64(0)), &[]).unwrap(), Completion::DivisionByZero);
+
+    // i32::MIN / -1 wraps around instead of failing.
+    let overflow = code(8, 4, vec![
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+        Push(OrDynamic::Static(Constant::I32(-1))),
+        IntOperation(IntType::Int, crate::code::IntOperation::Divide),
+        Return(Some(LocalType::Int))
+    ]);
+    assert_eq!(interp.run(&overflow, &[Value::Int(i32::MIN)]).unwrap(), Completion::Returned(Some(Value::Int(i32::MIN))));
+
+    let throw = code(8, 4, vec![
+        LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+        Throw
+    ]);
+    assert_eq!(interp.run(&throw, &["boom".into()]).unwrap(), Completion::Threw("boom".into()));
+    assert_eq!(interp.run(&throw, &[Value::Null]).unwrap(), Completion::NullPointer);
+
+    let index = code(8, 4, vec![
+        Push(OrDynamic::Static(Constant::I32(2))),
+        NewArray(OrDynamic::Static(Type::Byte), 1),
+        Push(OrDynamic::Static(Constant::I32(2))),
+        Array(LoadOrStore::Load, ArrayType::ByteOrBool),
+        Return(Some(LocalType::Int))
+    ]);
+    assert_eq!(interp.run(&index, &[]).unwrap(), Completion::IndexOutOfBounds(2));
+
+    // wide locals take two slots, so the int argument is in slot 2.
+    let wide = code(8, 4, vec![
+        LocalVariable(LoadOrStore::Load, LocalType::Long, 0),
+        LocalVariable(LoadOrStore::Load, LocalType::Int, 2),
+        IntOperation(IntType::Long, crate::code::IntOperation::ShiftLeft),
+        Return(Some(LocalType::Long))
+    ]);
+    assert_eq!(interp.run(&wide, &[Value::Long(3), Value::Int(65)]).unwrap(), Completion::Returned(Some(Value::Long(6))));
+}
+
+#[test]
+fn allocation_limit() {
+    use Instruction::*;
+    let allocate = |counts: &[i32]| {
+        let mut insns = counts.iter().map(|c| Push(OrDynamic::Static(Constant::I32(*c)))).collect::<Vec<_>>();
+        insns.push(NewArray(OrDynamic::Static(Type::array(counts.len() as u8, Type::Int)), counts.len() as u8));
+        insns.push(Return(Some(LocalType::Reference)));
+        code(8, 4, insns)
+    };
+    let mut interp = Interpreter::new(Sandboxed, 100);
+    let err = interp.run(&code(8, 4, vec![
+        Push(OrDynamic::Static(Constant::I32(i32::MAX))),
+        NewArray(OrDynamic::Static(Type::Int), 1),
+        Return(Some(LocalType::Reference))
+    ]), &[]).unwrap_err();
+    assert!(matches!(err.root(), ErrorBase::Limit("array elements", 0x7fff_ffff, 0x100_0000)), "{}", err);
+    assert!(interp.run(&allocate(&[1 << 16, 1 << 16]), &[]).is_err());
+
+    // the limit is on the elements allocated in total.
+    let mut interp = Interpreter::new(Sandboxed, 100).max_elements(30);
+    assert!(matches!(interp.run(&allocate(&[2, 10]), &[]).unwrap(), Completion::Returned(Some(Value::Array(_)))));
+    assert!(interp.run(&allocate(&[2, 4]), &[]).is_err());
+}
+
+#[test]
+fn exception_handlers() {
+    use Instruction::*;
+    let (start, end, handler) = (crate::code::Label(0), crate::code::Label(1), crate::code::Label(2));
+    let guarded = |body: Vec<Instruction>, catch: Option<&'static str>| {
+        let mut insns = vec![Label(start)];
+        insns.extend(body);
+        insns.extend(vec![Label(end), Return(Some(LocalType::Reference)), Label(handler), Return(Some(LocalType::Reference))]);
+        Code { catches: vec![crate::code::Catch { start, end, handler, catch: catch.map(Into::into) }], ..code(8, 4, insns) }
+    };
+    let divide = vec![
+        Push(OrDynamic::Static(Constant::I32(1))),
+        Push(OrDynamic::Static(Constant::I32(0))),
+        IntOperation(IntType::Int, crate::code::IntOperation::Divide),
+        Pop1
+    ];
+    let arithmetic = Value::Exception("java/lang/ArithmeticException".into());
+    let mut interp = Interpreter::new(Sandboxed, 100);
+    for catch in [None, Some("java/lang/ArithmeticException"), Some("java/lang/RuntimeException"), Some("java/lang/Throwable")] {
+        assert_eq!(interp.run(&guarded(divide.clone(), catch), &[]).unwrap(), Completion::Returned(Some(arithmetic.clone())));
+    }
+    assert_eq!(interp.run(&guarded(divide, Some("java/lang/Error")), &[]).unwrap(), Completion::DivisionByZero);
+
+    // exceptions thrown by interpreted callees are caught by the caller.
+    let class = Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC,
+        name: "a/Fail".into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec![],
+        fields: vec![],
+        methods: vec![static_method("fail", Type::method([], None), 0, vec![Push(OrDynamic::Static(Constant::string("boom"))), Throw])],
+        attributes: vec![]
+    };
+    interp.add_class(&class).unwrap();
+    let call = vec![PushNull, InvokeExact(MemberType::Static, member_ref("a/Fail", "fail", Type::method([], None)))];
+    assert_eq!(interp.run(&guarded(call.clone(), None), &[]).unwrap(), Completion::Returned(Some("boom".into())));
+    // whether thrown values are instances of a class is up to the hook.
+    assert_eq!(interp.run(&guarded(call.clone(), Some("java/lang/IllegalStateException")), &[]).unwrap(), Completion::Threw("boom".into()));
+    struct Strings;
+    impl crate::interp::Hook for Strings {
+        fn invoke(&mut self, method: &MemberRef, args: &[Value]) -> crate::Result<Option<Value>> {
+            Sandboxed.invoke(method, args)
+        }
+        fn is_instance(&mut self, value: &Value, class: &str) -> crate::Result<bool> {
+            Ok(matches!(value, Value::String(_)) && class == "java/lang/IllegalStateException")
+        }
+    }
+    let mut interp = Interpreter::new(Strings, 100);
+    interp.add_class(&class).unwrap();
+    assert_eq!(interp.run(&guarded(call, Some("java/lang/IllegalStateException")), &[]).unwrap(), Completion::Returned(Some("boom".into())));
+}
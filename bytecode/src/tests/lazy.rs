@@ -0,0 +1,132 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::Class;
+use crate::prelude::*;
+use std::io::Cursor;
+
+fn sample() -> Vec<u8> {
+    let concat = Dynamic::string_concat(vec![ConcatPart::Literal("n = ".into()), ConcatPart::Argument(Type::Int)]);
+    let bsm = concat.bsm().clone();
+    let class = Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC | ClassFlags::ACC_SUPER,
+        name: "Lazy".into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec![],
+        fields: vec![],
+        methods: vec![Method {
+            access: MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC,
+            name: "show".into(),
+            descriptor: Type::method([Type::Int], Some(Type::reference("java/lang/String"))),
+            attributes: vec![MethodAttribute::Code(Code {
+                max_stack: 1,
+                max_locals: 1,
+                code: vec![
+                    Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+                    Instruction::InvokeDynamic(concat),
+                    Instruction::Return(Some(LocalType::Reference))
+                ],
+                catches: vec![],
+                attrs: vec![]
+            })]
+        }],
+        attributes: vec![ClassAttribute::BootstrapMethods(vec![bsm])]
+    };
+    let mut buf = vec![];
+    class.write_to(&mut buf).unwrap();
+    buf
+}
+
+fn lazy_code(class: &Class) -> &LazyCode {
+    match &class.methods[0].attributes[0] {
+        MethodAttribute::LazyCode(c) => c,
+        a => panic!("expected lazy code, got {:?}", a)
+    }
+}
+
+fn eager_code(class: &Class) -> &Code {
+    match &class.methods[0].attributes[0] {
+        MethodAttribute::Code(c) => c,
+        a => panic!("expected code, got {:?}", a)
+    }
+}
+
+#[test]
+fn lazy_decodes_on_access() {
+    let bytes = sample();
+    let eager = Class::read_from(&mut Cursor::new(&bytes)).unwrap();
+    let lazy = Class::read_lazy(&mut Cursor::new(&bytes)).unwrap();
+    let code = lazy_code(&lazy);
+    assert!(!code.is_decoded());
+    assert_eq!(*code.get().unwrap(), *eager_code(&eager));
+    assert!(code.is_decoded());
+}
+
+#[test]
+fn lazy_write_copies_bytes() {
+    let bytes = sample();
+    let lazy = Class::read_lazy(&mut Cursor::new(&bytes)).unwrap();
+    let mut written = vec![];
+    lazy.write_to(&mut written).unwrap();
+    let again = Class::read_lazy(&mut Cursor::new(&written)).unwrap();
+    assert_eq!(lazy_code(&again).raw(), lazy_code(&lazy).raw());
+    let eager = Class::read_from(&mut Cursor::new(&written)).unwrap();
+    assert_eq!(*eager_code(&eager), *lazy_code(&lazy).get().unwrap());
+}
+
+#[test]
+fn lazy_write_modified() {
+    let bytes = sample();
+    let mut lazy = Class::read_lazy(&mut Cursor::new(&bytes)).unwrap();
+    if let MethodAttribute::LazyCode(c) = &mut lazy.methods[0].attributes[0] {
+        c.get_mut().unwrap().max_stack = 2;
+    }
+    let mut written = vec![];
+    lazy.write_to(&mut written).unwrap();
+    let eager = Class::read_from(&mut Cursor::new(&written)).unwrap();
+    let code = eager_code(&eager);
+    assert_eq!(code.max_stack, 2);
+    assert_eq!(code.code, lazy_code(&lazy).get().unwrap().code);
+}
+
+#[test]
+fn method_code() {
+    let bytes = sample();
+    let eager = Class::read_from(&mut Cursor::new(&bytes)).unwrap();
+    let mut lazy = Class::read_lazy(&mut Cursor::new(&bytes)).unwrap();
+    assert_eq!(lazy.methods[0].code().unwrap(), eager.methods[0].code().unwrap());
+    assert!(!lazy_code(&lazy).is_modified());
+    lazy.methods[0].code_mut().unwrap().unwrap().max_stack = 2;
+    assert!(lazy_code(&lazy).is_modified());
+    lazy.methods[0].attributes.clear();
+    assert_eq!(lazy.methods[0].code().unwrap(), None);
+}
+
+#[test]
+fn lazy_round_trips_are_stable() {
+    let bytes = sample();
+    let lazy = Class::read_lazy(&mut Cursor::new(&bytes)).unwrap();
+    let mut written = vec![];
+    lazy.write_to(&mut written).unwrap();
+    let again = Class::read_lazy(&mut Cursor::new(&written)).unwrap();
+    let mut rewritten = vec![];
+    again.write_to(&mut rewritten).unwrap();
+    assert_eq!(written, rewritten);
+    // the entries the writer looks up are found in the original pool.
+    assert_eq!(written, bytes);
+}
@@ -0,0 +1,160 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::Class;
+use crate::prelude::*;
+use crate::error::PathSegment;
+use crate::view::{ClassView, ViewEntry};
+use super::find;
+
+fn class(attributes: Vec<MethodAttribute>) -> Vec<u8> {
+    let mut buf = vec![];
+    Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC,
+        name: "Broken".into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec![],
+        fields: vec![],
+        methods: vec![Method {
+            access: MethodFlags::ACC_STATIC,
+            name: "run".into(),
+            descriptor: Type::method([], None),
+            attributes
+        }],
+        attributes: vec![]
+    }.write_to(&mut buf).unwrap();
+    buf
+}
+
+/// Points the signature of the method outside of the pool.
+fn break_signature(bytes: &mut [u8]) {
+    let idx = ClassView::parse(bytes).unwrap().pool().iter().find_map(|(i, e)| match e {
+        ViewEntry::UTF8(s) if s == "Signature" => Some(i),
+        _ => None
+    }).unwrap();
+    let pos = find(bytes, &[(idx >> 8) as u8, idx as u8, 0, 0, 0, 2]) + 6;
+    bytes[pos] = 0xFF;
+    bytes[pos + 1] = 0xFF;
+}
+
+#[test]
+fn malformed_attribute_kept_raw() {
+    let mut bytes = class(vec![MethodAttribute::Signature("()V".parse().unwrap())]);
+    break_signature(&mut bytes);
+
+    assert!(Class::read_from(&mut bytes.as_slice()).is_err());
+    let (class, warnings) = Class::read_lenient(&mut bytes.as_slice()).unwrap();
+    assert!(matches!(&class.methods[0].attributes[..], [MethodAttribute::Raw(r)] if *r.inner == [0xFF, 0xFF][..]));
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].context().unwrap().path, vec![
+        PathSegment::Class("Broken".into()),
+        PathSegment::Member("run".into(), "()V".into()),
+        PathSegment::Attribute("Signature".into())
+    ]);
+}
+
+#[test]
+fn unknown_and_malformed_attributes_round_trip() {
+    let mut bytes = class(vec![
+        MethodAttribute::Raw(RawAttribute::new("Custom", vec![1, 2, 3])),
+        MethodAttribute::Signature("()V".parse().unwrap())
+    ]);
+    break_signature(&mut bytes);
+    let (class, _) = Class::read_lenient(&mut bytes.as_slice()).unwrap();
+    let mut written = vec![];
+    class.write_to(&mut written).unwrap();
+    // unknown attributes are dropped, while malformed ones are written back as they were read.
+    let (reread, warnings) = Class::read_lenient(&mut written.as_slice()).unwrap();
+    assert!(matches!(&reread.methods[0].attributes[..], [MethodAttribute::Raw(r)] if r.name == "Signature" && *r.inner == [0xFF, 0xFF][..]));
+    assert_eq!(warnings.len(), 1);
+    let mut rewritten = vec![];
+    reread.write_to(&mut rewritten).unwrap();
+    assert_eq!(rewritten, written);
+}
+
+#[test]
+fn line_number_outside_code_dropped() {
+    let mut bytes = class(vec![MethodAttribute::Code(Code {
+        max_stack: 0,
+        max_locals: 0,
+        code: vec![Instruction::LineNumber(0x1234), Instruction::Return(None)],
+        catches: vec![],
+        attrs: vec![]
+    })]);
+    let pos = find(&bytes, &[0, 1, 0, 0, 0x12, 0x34]) + 2;
+    bytes[pos + 1] = 7; // past the end of the code
+
+    assert!(Class::read_from(&mut bytes.as_slice()).is_err());
+    let (class, warnings) = Class::read_lenient(&mut bytes.as_slice()).unwrap();
+    match &class.methods[0].attributes[..] {
+        [MethodAttribute::Code(c)] => assert_eq!(c.code, vec![Instruction::Return(None)]),
+        a => panic!("unexpected attributes {:?}", a)
+    }
+    assert_eq!(warnings.len(), 1);
+}
+
+#[test]
+fn trailing_data_warned() {
+    let mut bytes = class(vec![]);
+    let strict = Class::read_from(&mut bytes.as_slice()).unwrap();
+    bytes.extend_from_slice(&[1, 2, 3]);
+    let (class, warnings) = Class::read_lenient(&mut bytes.as_slice()).unwrap();
+    assert_eq!(format!("{:?}", class), format!("{:?}", strict));
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].context().unwrap().offset, bytes.len() as u64 - 3);
+}
+
+#[test]
+fn malformed_bootstrap_methods_fail() {
+    let concat = Dynamic::string_concat(vec![ConcatPart::Argument(Type::Int)]);
+    let mut bytes = vec![];
+    Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC,
+        name: "Broken".into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec![],
+        fields: vec![],
+        methods: vec![Method {
+            access: MethodFlags::ACC_STATIC,
+            name: "show".into(),
+            descriptor: Type::method([Type::Int], Some(Type::reference("java/lang/String"))),
+            attributes: vec![MethodAttribute::Code(Code {
+                max_stack: 1,
+                max_locals: 1,
+                code: vec![
+                    Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+                    Instruction::InvokeDynamic(concat.clone()),
+                    Instruction::Return(Some(LocalType::Reference))
+                ],
+                catches: vec![],
+                attrs: vec![]
+            })]
+        }],
+        attributes: vec![ClassAttribute::BootstrapMethods(vec![concat.bsm().clone()])]
+    }.write_to(&mut bytes).unwrap();
+    let idx = ClassView::parse(&bytes).unwrap().pool().iter().find_map(|(i, e)| match e {
+        ViewEntry::UTF8(s) if s == "BootstrapMethods" => Some(i),
+        _ => None
+    }).unwrap();
+    let pos = find(&bytes, &[(idx >> 8) as u8, idx as u8, 0, 0, 0]) + 8;
+    bytes[pos] = 0xFF; // the method handle now points outside of the pool
+    bytes[pos + 1] = 0xFF;
+
+    assert!(Class::read_from(&mut bytes.as_slice()).is_err());
+    assert!(Class::read_lenient(&mut bytes.as_slice()).is_err());
+}
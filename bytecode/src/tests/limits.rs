@@ -0,0 +1,114 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::Class;
+use crate::prelude::*;
+use crate::code::{RawFrame, VerificationType};
+use crate::cp::VecCp;
+use crate::error::ErrorBase;
+use super::{class, member, method, void};
+
+fn limit(class: &Class) -> (&'static str, u64, u64) {
+    limit_of(class.write_to(&mut vec![]).unwrap_err())
+}
+
+fn limit_of(e: Error) -> (&'static str, u64, u64) {
+    match *e.root() {
+        ErrorBase::Limit(what, actual, max) => (what, actual, max),
+        ref e => panic!("unexpected error {}", e)
+    }
+}
+
+#[test]
+fn constant_pool_size() {
+    let fields = (0..35000).map(|i| Field {
+        access: FieldFlags::ACC_STATIC,
+        name: format!("f{}", i).into(),
+        descriptor: Type::reference(format!("T{}", i)),
+        attrs: vec![]
+    }).collect();
+    let (what, actual, max) = limit(&Class { fields, ..class("Limits", "java/lang/Object", &[], vec![]) });
+    assert_eq!(what, "constant pool size");
+    assert!(actual > max);
+    assert_eq!(max, 65535);
+}
+
+#[test]
+fn counts() {
+    let mut c = class("Limits", "java/lang/Object", &[], vec![]);
+    c.interfaces = vec!["java/lang/Runnable".into(); 70000];
+    assert_eq!(limit(&c), ("interface count", 70000, 65535));
+}
+
+#[test]
+fn utf8_length() {
+    let mut c = class("Limits", "java/lang/Object", &[], vec![]);
+    c.name = "a".repeat(70000).into();
+    assert_eq!(limit(&c), ("UTF8 length", 70000, 65535));
+}
+
+#[test]
+fn code_length() {
+    let c = class("Limits", "java/lang/Object", &[], vec![method(MethodFlags::ACC_STATIC, "run", void(), vec![Instruction::NoOp; 70000])]);
+    assert_eq!(limit(&c), ("code length", 70000, 65535));
+}
+
+#[test]
+fn parameter_slots() {
+    let c = class("Limits", "java/lang/Object", &[], vec![method(MethodFlags::ACC_STATIC, "run", Type::method(vec![Type::Long; 128], None), vec![Instruction::Return(None)])]);
+    assert_eq!(limit(&c), ("parameter slots", 256, 255));
+}
+
+#[test]
+fn array_dimensions() {
+    let new_array = |t, dim| class("Limits", "java/lang/Object", &[], vec![method(MethodFlags::ACC_STATIC, "run", void(), vec![
+        Instruction::Push(OrDynamic::Static(Constant::I32(1))),
+        Instruction::NewArray(OrDynamic::Static(t), dim),
+        Instruction::Return(None)
+    ])]);
+    assert_eq!(limit(&new_array(Type::array(255, Type::Int), 1)), ("array dimensions", 256, 255));
+    assert!(new_array(Type::array(2, Type::Int), 3).write_to(&mut vec![]).is_err());
+
+    for (t, dim) in [(Type::Int, 1), (Type::reference("java/lang/String"), 1), (Type::array(2, Type::Int), 2)] {
+        let mut buf = vec![];
+        new_array(t.clone(), dim).write_to(&mut buf).unwrap();
+        let read = Class::read_from(&mut buf.as_slice()).unwrap();
+        match &read.methods[0].attributes[..] {
+            [MethodAttribute::Code(c)] => assert_eq!(c.code[1], Instruction::NewArray(OrDynamic::Static(t), dim)),
+            a => panic!("unexpected attributes {:?}", a)
+        }
+    }
+}
+
+#[test]
+fn bootstrap_method_arguments() {
+    let bsm = BootstrapMethod {
+        handle: MethodHandle { kind: MethodHandleKind::InvokeStatic, member: member("Bootstrap", "bootstrap", void()) },
+        arguments: vec![OrDynamic::Static(Constant::I32(1)); 70000]
+    };
+    let c = class("Limits", "java/lang/Object", &[], vec![method(MethodFlags::ACC_STATIC, "run", void(), vec![
+        Instruction::InvokeDynamic(Dynamic::new(bsm, "run", void())),
+        Instruction::Return(None)
+    ])]);
+    assert_eq!(limit(&c), ("bootstrap method argument count", 70000, 65535));
+}
+
+#[test]
+fn frame_sizes() {
+    let write = |frame: RawFrame| limit_of(frame.write_to(&mut VecCp::new(), &mut vec![]).unwrap_err());
+    assert_eq!(write(RawFrame::Full(0, vec![VerificationType::Top; 70000], vec![])), ("frame local count", 70000, 65535));
+    assert_eq!(write(RawFrame::Full(0, vec![], vec![VerificationType::Int; 70000])), ("frame stack size", 70000, 65535));
+}
@@ -19,6 +19,32 @@ mod mutf8;
 mod full_type;
 mod insn;
 mod exec;
+mod concat;
+mod strip;
+mod view;
+mod lazy;
+mod sync;
+mod context;
+mod lenient;
+mod limits;
+mod validate;
+mod diff;
+mod compat;
+mod remap;
+mod interp;
+mod peephole;
+mod dce;
+mod instrument;
+mod coverage;
+mod inline;
+mod ssa;
+mod decompile;
+mod pattern;
+mod xref;
+mod shrink;
+mod obfuscate;
+#[cfg(feature = "serde")]
+mod serde;
 
 mod code {
 
@@ -35,7 +61,7 @@ mod code {
             self.0.get(idx as usize - 1).cloned()
         }
 
-        fn resolve_later(&mut self, _bsm_idx: u16, _ptr: Rc<LazyBsm>) {
+        fn resolve_later(&mut self, _bsm_idx: u16, _ptr: Arc<LazyBsm>) {
             unreachable!()
         }
 
@@ -61,7 +87,7 @@ mod code {
 
     use lazy_static::lazy_static;
     use std::fs::File;
-    use std::rc::Rc;
+    use std::sync::Arc;
     lazy_static! {
         static ref SAMPLE: Vec<(String, Vec<u8>)> = class_sample::get_sample_name_bytes(2 * 1024);
     }
@@ -112,17 +138,17 @@ mod code {
         assert_eq!(buffer.into_inner(), vec![
             0, 255, // max_stack
             0, 254, // max_locals
-            0, 0, 0, 43, // code_length
+            0, 0, 0, 44, // code_length
             0, // NOP
             1, // null
             87, // pop
             crate::constants::insn::BIPUSH, 123,
-            170, 0, // tableswitch with padding
-            0, 0, 0, 28, // default
+            170, 0, 0, // tableswitch with padding
+            0, 0, 0, 29, // default
             0, 0, 0, 1,
             0, 0, 0, 2,
-            0, 0, 0, 32,
-            0, 0, 0, 36,
+            0, 0, 0, 33,
+            0, 0, 0, 37,
             0, 0, 0, 0,
             0, 0, 1, 176,
             0, 0, 1, 176,
@@ -132,6 +158,52 @@ mod code {
         ])
     }
     #[test]
+    fn new_array_roundtrip() {
+        let code = Code {
+            max_stack: 2,
+            max_locals: 0,
+            code: vec![
+                NewArray(OrDynamic::Static(Type::Int), 1),
+                NewArray(OrDynamic::Static(Type::reference("java/lang/String")), 1),
+                NewArray(OrDynamic::Static(Type::ArrayRef(1, Box::new(Type::Long))), 1),
+                NewArray(OrDynamic::Static(Type::ArrayRef(3, Box::new(Type::reference("java/lang/Object")))), 2),
+                Return(None)
+            ],
+            catches: vec![],
+            attrs: vec![]
+        };
+        // the first entry keeps written indices starting at 1 like in a real constant pool.
+        let mut cp = arr_cp(vec![RawConstantEntry::Int(0)]);
+        let mut buffer = Cursor::new(Vec::new());
+        code.write_to(&mut cp, &mut buffer).unwrap();
+        let bytes = buffer.into_inner();
+        assert_eq!(&bytes[8..10], &[crate::constants::insn::NEWARRAY, 10]);
+        assert_eq!(bytes[10], crate::constants::insn::ANEWARRAY);
+        assert_eq!(bytes[16], crate::constants::insn::MULTIANEWARRAY);
+        let mut cp = arr_cp(cp.0[1..].to_vec());
+        assert_eq!(Code::read_from(&mut cp, &mut Cursor::new(bytes)).unwrap(), code);
+    }
+    #[test]
+    fn jump_writing() {
+        use crate::constants::insn::{GOTO, GOTO_W, IFNE, RETURN};
+        let write = |code: Vec<Instruction>| {
+            let mut buffer = Cursor::new(Vec::new());
+            Code { max_stack: 1, max_locals: 1, code, catches: vec![], attrs: vec![] }.write_to(&mut arr_cp([].as_ref()), &mut buffer).unwrap();
+            buffer.into_inner()[8..].to_vec()
+        };
+        let (start, end) = (Label(0), Label(1));
+        let bytes = write(vec![Lbl(start), NoOp, Jump(JumpCondition::Always, start), Return(None)]);
+        assert_eq!(&bytes[..5], &[0, GOTO, 0xFF, 0xFF, RETURN]);
+
+        let mut code = vec![Lbl(start), Jump(JumpCondition::IntegerEqualsZero, end)];
+        code.extend(std::iter::repeat_n(NoOp, 40000));
+        code.extend(vec![Lbl(end), Jump(JumpCondition::Always, start), Return(None)]);
+        let bytes = write(code);
+        // the negated branch skips the goto_w, which is 3 bytes further than the branch.
+        assert_eq!(&bytes[..8], &[IFNE, 0, 8, GOTO_W, 0, 0, 0x9C, 0x45]);
+        assert_eq!(&bytes[40008..40014], &[GOTO_W, 0xFF, 0xFF, 0x63, 0xB8, RETURN]);
+    }
+    #[test]
     fn code_reading() {
         let mut cp = arr_cp([RawConstantEntry::Int(123)].as_ref());
         let bytes = [
@@ -177,5 +249,150 @@ mod code {
         })
 
     }
+    #[test]
+    fn int_increment_writing() {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut cp = arr_cp([].as_ref());
+        Code {
+            max_stack: 0,
+            max_locals: 301,
+            code: vec![IntIncrement(1, -1), IntIncrement(300, 1), IntIncrement(2, 1000), Return(None)],
+            catches: vec![],
+            attrs: vec![]
+        }.write_to(&mut cp, &mut buffer).unwrap();
+        assert_eq!(&buffer.into_inner()[8..], &[
+            crate::constants::insn::IINC, 1, 0xFF,
+            crate::constants::insn::WIDE, crate::constants::insn::IINC, 1, 44, 0, 1,
+            crate::constants::insn::WIDE, crate::constants::insn::IINC, 0, 2, 3, 232,
+            crate::constants::insn::RETURN,
+            0, 0,
+            0, 0
+        ][..]);
+    }
+
+    #[test]
+    fn static_field_writing() {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut cp = arr_cp([].as_ref());
+        let field = OrDynamic::Static(MemberRef { owner: "A".into(), name: "f".into(), descriptor: Type::Int, itfs: false });
+        Code {
+            max_stack: 1,
+            max_locals: 0,
+            code: vec![Field(GetOrPut::Get, MemberType::Static, field.clone()), Field(GetOrPut::Put, MemberType::Static, field), Return(None)],
+            catches: vec![],
+            attrs: vec![]
+        }.write_to(&mut cp, &mut buffer).unwrap();
+        let bytes = buffer.into_inner();
+        assert_eq!((bytes[8], bytes[11]), (crate::constants::insn::GETSTATIC, crate::constants::insn::PUTSTATIC));
+    }
+}
+
+
+// Builders shared by the tests of the passes working on whole classes.
+
+use crate::Class;
+use crate::prelude::*;
+
+pub(crate) fn void() -> Type {
+    Type::method([], None)
+}
+
+pub(crate) fn member(owner: &'static str, name: &'static str, descriptor: Type) -> MemberRef {
+    MemberRef { owner: owner.into(), name: name.into(), descriptor, itfs: false }
+}
+
+pub(crate) fn member_ref(owner: &'static str, name: &'static str, descriptor: Type) -> OrDynamic<MemberRef> {
+    OrDynamic::Static(member(owner, name, descriptor))
+}
+
+/// A public class, built with struct update syntax by the tests needing other flags, fields or attributes.
+pub(crate) fn class(name: &'static str, super_name: &'static str, interfaces: &[&'static str], methods: Vec<Method>) -> Class {
+    Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC,
+        name: name.into(),
+        super_name: Some(super_name.into()),
+        interfaces: interfaces.iter().map(|&i| i.into()).collect(),
+        fields: vec![],
+        methods,
+        attributes: vec![]
+    }
+}
+
+/// A method with a `Code` attribute unless the code is empty.
+pub(crate) fn method(access: MethodFlags, name: &'static str, descriptor: Type, insns: Vec<Instruction>) -> Method {
+    if insns.is_empty() {
+        Method { access, name: name.into(), descriptor, attributes: vec![] }
+    } else {
+        method_with(access, name, descriptor, code(3, 1, insns))
+    }
+}
+
+/// A method with the given body, for the tests depending on its maximum stack size and locals.
+pub(crate) fn method_with(access: MethodFlags, name: &'static str, descriptor: Type, body: Code) -> Method {
+    Method { access, name: name.into(), descriptor, attributes: vec![MethodAttribute::Code(body)] }
 }
 
+/// A public constructor calling the one of its superclass without arguments.
+pub(crate) fn constructor(super_name: &'static str) -> Method {
+    method(MethodFlags::ACC_PUBLIC, "<init>", void(), vec![
+        load(LocalType::Reference, 0),
+        Instruction::InvokeSpecial(member_ref(super_name, "<init>", void())),
+        Instruction::Return(None)
+    ])
+}
+
+/// A method body without exception handlers or attributes.
+pub(crate) fn code(max_stack: u16, max_locals: u16, code: Vec<Instruction>) -> Code {
+    Code { max_stack, max_locals, code, catches: vec![], attrs: vec![] }
+}
+
+pub(crate) fn field(access: FieldFlags, name: &'static str) -> Field {
+    Field { access, name: name.into(), descriptor: Type::Int, attrs: vec![] }
+}
+
+pub(crate) fn load(ty: LocalType, i: u16) -> Instruction {
+    Instruction::LocalVariable(LoadOrStore::Load, ty, i)
+}
+
+pub(crate) fn store(ty: LocalType, i: u16) -> Instruction {
+    Instruction::LocalVariable(LoadOrStore::Store, ty, i)
+}
+
+/// Pushes an int constant.
+pub(crate) fn int(i: i32) -> Instruction {
+    Instruction::Push(OrDynamic::Static(Constant::I32(i)))
+}
+
+/// A label, for the tests importing the instructions, whose `Label` variant shadows the type.
+pub(crate) fn label(i: u32) -> Label {
+    Label(i)
+}
+
+/// `static int f(int a, int b) { int x = 1; try { x = a / b; x = 2; } catch (ArithmeticException e) { return x; } return x; }`
+pub(crate) fn try_catch() -> Code {
+    use Instruction::*;
+    let mut f = code(2, 4, vec![
+        int(1), store(LocalType::Int, 2),
+        Label(label(0)),
+        load(LocalType::Int, 0), load(LocalType::Int, 1), IntOperation(IntType::Int, crate::code::IntOperation::Divide),
+        store(LocalType::Int, 2),
+        int(2), store(LocalType::Int, 2),
+        Label(label(1)),
+        Jump(JumpCondition::Always, label(3)),
+        Label(label(2)),
+        store(LocalType::Reference, 3),
+        load(LocalType::Int, 2),
+        Return(Some(LocalType::Int)),
+        Label(label(3)),
+        load(LocalType::Int, 2),
+        Return(Some(LocalType::Int))
+    ]);
+    f.catches.push(Catch { start: label(0), end: label(1), handler: label(2), catch: Some("java/lang/ArithmeticException".into()) });
+    f
+}
+
+/// The position of the first occurrence of `needle` in the bytes of a written class.
+pub(crate) fn find(haystack: &[u8], needle: &[u8]) -> usize {
+    haystack.windows(needle.len()).position(|w| w == needle).unwrap()
+}
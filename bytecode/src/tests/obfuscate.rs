@@ -0,0 +1,160 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::Class;
+use crate::attr::InnerClass;
+use crate::obfuscate::Obfuscator;
+use crate::prelude::*;
+use crate::keep::Keep;
+use super::{class, field, member_ref, method, void};
+
+fn inner() -> ClassAttribute {
+    ClassAttribute::InnerClasses(vec![InnerClass {
+        inner_fqname: "a/Sub$Inner".into(),
+        outer_fqname: Some("a/Sub".into()),
+        inner_name: Some("Inner".into()),
+        inner_access: InnerClassFlags::ACC_PRIVATE | InnerClassFlags::ACC_STATIC
+    }])
+}
+
+/// A public class calling a package-private method that a package-private subclass overrides.
+fn library() -> Vec<Class> {
+    use Instruction::*;
+    let mut base = class("a/Base", "java/lang/Object", &[], vec![
+        method(MethodFlags::ACC_PUBLIC, "run", void(), vec![
+            LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+            InvokeExact(MemberType::Virtual, member_ref("a/Base", "work", void())),
+            LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+            Field(GetOrPut::Get, MemberType::Virtual, member_ref("a/Sub", "count", Type::Int)),
+            Pop1,
+            Return(None)
+        ]),
+        method(MethodFlags::empty(), "work", void(), vec![Return(None)])
+    ]);
+    base.fields = vec![field(FieldFlags::ACC_PRIVATE, "count"), field(FieldFlags::ACC_PUBLIC, "a")];
+    let mut sub = class("a/Sub", "a/Base", &[], vec![
+        method(MethodFlags::empty(), "work", void(), vec![
+            New(OrDynamic::Static("a/Sub$Inner".into())),
+            Pop1,
+            Return(None)
+        ]),
+        method(MethodFlags::ACC_PRIVATE, "readObject", void(), vec![Return(None)])
+    ]);
+    sub.access = ClassFlags::empty();
+    sub.fields = vec![field(FieldFlags::ACC_PRIVATE | FieldFlags::ACC_STATIC, "serialVersionUID")];
+    sub.attributes = vec![inner(), ClassAttribute::NestMembers(vec!["a/Sub$Inner".into()])];
+    let mut nested = class("a/Sub$Inner", "java/lang/Object", &[], vec![method(MethodFlags::ACC_PRIVATE, "help", void(), vec![Return(None)])]);
+    nested.access = ClassFlags::empty();
+    nested.attributes = vec![inner(), ClassAttribute::NestHost("a/Sub".into())];
+    vec![base, sub, nested]
+}
+
+fn instructions(class: &Class, method: usize) -> &[Instruction] {
+    match &class.methods[method].attributes[0] {
+        MethodAttribute::Code(c) => &c.code,
+        _ => unreachable!()
+    }
+}
+
+#[test]
+fn renames_with_hierarchy() {
+    let mut classes = library();
+    Obfuscator::new().obfuscate(&mut classes).unwrap();
+    let (base, sub, nested) = (&classes[0], &classes[1], &classes[2]);
+    assert_eq!((base.name.as_ref(), sub.name.as_ref(), nested.name.as_ref()), ("a/Base", "a/a", "a/a$a"));
+    // the overriding method is renamed with the method it overrides, and the public field keeps its name `a`.
+    assert_eq!(base.methods.iter().map(|m| m.name.as_ref()).collect::<Vec<_>>(), vec!["run", "a"]);
+    assert_eq!(sub.methods.iter().map(|m| m.name.as_ref()).collect::<Vec<_>>(), vec!["a", "readObject"]);
+    assert_eq!(base.fields.iter().map(|f| f.name.as_ref()).collect::<Vec<_>>(), vec!["b", "a"]);
+    assert_eq!(sub.fields[0].name, "serialVersionUID");
+    assert_eq!(nested.methods[0].name, "a");
+
+    let run = instructions(base, 0);
+    assert_eq!(run[1], Instruction::InvokeExact(MemberType::Virtual, member_ref("a/Base", "a", void())));
+    // the field is found in the superclass of `a/Sub`.
+    assert_eq!(run[3], Instruction::Field(GetOrPut::Get, MemberType::Virtual, member_ref("a/a", "b", Type::Int)));
+    assert_eq!(instructions(sub, 0)[0], Instruction::New(OrDynamic::Static("a/a$a".into())));
+    match &sub.attributes[..] {
+        [ClassAttribute::InnerClasses(i), ClassAttribute::NestMembers(m)] => {
+            assert_eq!((i[0].inner_fqname.as_ref(), i[0].outer_fqname.as_deref(), i[0].inner_name.as_deref()), ("a/a$a", Some("a/a"), Some("a")));
+            assert_eq!(m, &vec![Cow::Borrowed("a/a$a")]);
+        }
+        a => panic!("{:?}", a)
+    }
+    assert_eq!(nested.attributes[1], ClassAttribute::NestHost("a/a".into()));
+}
+
+#[test]
+fn mapping_file() {
+    let mut classes = library();
+    let mapping = Obfuscator::new().obfuscate(&mut classes).unwrap();
+    assert_eq!(mapping.to_string(), "\
+a.Base -> a.Base:
+    int count -> b
+    void work() -> a
+a.Sub -> a.a:
+    void work() -> a
+a.Sub$Inner -> a.a$a:
+    void help() -> a
+");
+}
+
+#[test]
+fn kept_and_visible_names() {
+    let mut classes = library();
+    // a public override makes the whole family visible.
+    classes[1].methods[0].access = MethodFlags::ACC_PUBLIC;
+    let obfuscator = Obfuscator::new()
+        .keep("a/Sub".parse().unwrap())
+        .keep(Keep::new("a/Base").members("count"));
+    let mapping = obfuscator.obfuscate(&mut classes).unwrap();
+    assert_eq!(mapping.classes.len(), 1);
+    assert_eq!((mapping.classes[0].from.as_ref(), mapping.classes[0].to.as_ref()), ("a/Sub$Inner", "a/Sub$a"));
+    assert_eq!(classes[0].methods[1].name, "work");
+    assert_eq!(classes[0].fields[0].name, "count");
+}
+
+#[test]
+fn visible_nested_class() {
+    let mut classes = library();
+    // a public nested class is named after its package-private outer class.
+    let public = match inner() {
+        ClassAttribute::InnerClasses(mut i) => {
+            i[0].inner_access = InnerClassFlags::ACC_PUBLIC | InnerClassFlags::ACC_STATIC;
+            ClassAttribute::InnerClasses(i)
+        }
+        a => a
+    };
+    classes[1].attributes[0] = public.clone();
+    classes[2].attributes[0] = public;
+    let mapping = Obfuscator::new().obfuscate(&mut classes).unwrap();
+    assert_eq!((classes[1].name.as_ref(), classes[2].name.as_ref()), ("a/Sub", "a/Sub$Inner"));
+    assert_eq!(instructions(&classes[1], 0)[0], Instruction::New(OrDynamic::Static("a/Sub$Inner".into())));
+    assert!(mapping.classes.iter().all(|c| c.from == c.to));
+}
+
+#[test]
+fn native_names() {
+    let mut classes = library();
+    // JNI looks up `Java_a_Sub_work`, so neither the method nor its class may be renamed.
+    classes[1].methods[0] = Method { access: MethodFlags::ACC_NATIVE, name: "work".into(), descriptor: void(), attributes: vec![] };
+    let mapping = Obfuscator::new().obfuscate(&mut classes).unwrap();
+    assert_eq!((classes[1].name.as_ref(), classes[1].methods[0].name.as_ref()), ("a/Sub", "work"));
+    // the native method overrides the package-private method of `a/Base`, which keeps its name too.
+    assert_eq!(classes[0].methods[1].name, "work");
+    assert_eq!(classes[2].name, "a/Sub$a");
+    assert!(mapping.classes.iter().all(|c| c.methods.iter().all(|m| m.from != "work")));
+}
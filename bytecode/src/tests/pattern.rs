@@ -0,0 +1,119 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::prelude::*;
+use crate::pattern::{glob, Capture, ConstantPattern, Matcher, MemberPattern, Pattern};
+use super::{class, code, label, member_ref, method, void};
+
+fn string(s: &'static str) -> Instruction {
+    Instruction::Push(OrDynamic::Static(Constant::String(s.into())))
+}
+
+fn for_name() -> Instruction {
+    Instruction::InvokeExact(MemberType::Static, member_ref("java/lang/Class", "forName", Type::method([Type::reference("java/lang/String")], Some(Type::reference("java/lang/Class")))))
+}
+
+fn load_int() -> Instruction {
+    Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, 0)
+}
+
+#[test]
+fn globs() {
+    assert!(glob("*", ""));
+    assert!(glob("java/lang/*", "java/lang/String"));
+    assert!(glob("*/String", "java/lang/String"));
+    assert!(glob("a*b*c", "abbc"));
+    assert!(glob("exit", "exit"));
+    assert!(!glob("exit", "exit2"));
+    assert!(!glob("a*b", "ab_"));
+    assert!(!glob("ab*ba", "aba"));
+}
+
+#[test]
+fn gaps_and_captures() {
+    use Instruction::*;
+    // `Class.forName("a.B")` with a line number and a label nothing jumps to, then one reached from a jump.
+    let code = code(2, 1, vec![
+        LineNumber(7),
+        string("a.B"),
+        Label(label(0)),
+        for_name(),
+        Pop1,
+        load_int(),
+        Jump(JumpCondition::IntegerEqualsZero, label(1)),
+        string("c.D"),
+        Label(label(1)),
+        LineNumber(9),
+        for_name(),
+        Return(None)
+    ]);
+    let pattern = Pattern::new()
+        .capture("name", Matcher::Push(ConstantPattern::String("*.*".into())))
+        .gap(Some(3))
+        .capture("call", Matcher::Invoke(None, MemberPattern::new("java/lang/Class", "forName")));
+    let found = pattern.find(&code);
+    // the second string is pushed before the jump target, where the other path joins.
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].range, 1..4);
+    assert_eq!(found[0].line, Some(7));
+    assert_eq!(found[0].capture("name"), Some(&Capture::Constant(Constant::String("a.B".into()))));
+    match found[0].capture("call") {
+        Some(Capture::Member(m)) => assert_eq!(m.name, "forName"),
+        c => panic!("{:?}", c)
+    }
+
+    let across = "push \"*\" @name; ...; invoke static java/lang/Class.forName".parse::<Pattern>().unwrap();
+    let found = across.find(&code);
+    assert_eq!(found.iter().map(|o| o.range.clone()).collect::<Vec<_>>(), vec![1..4, 7..11]);
+    assert_eq!(found[1].line, Some(7));
+    assert_eq!(found[1].captures.len(), 1);
+
+    let adjacent = "load 0; jump".parse::<Pattern>().unwrap();
+    assert_eq!(adjacent.find(&code).len(), 1);
+    assert!("load 1; jump".parse::<Pattern>().unwrap().find(&code).is_empty());
+    assert!("pop; return".parse::<Pattern>().is_err());
+}
+
+#[test]
+fn gaps_over_large_body() {
+    // without remembering where the rest of the pattern fails, every start would try every placement of the gaps.
+    let pattern = "load 0; ...; load 0; ...; load 0; ...; load 0; ...; return".parse::<Pattern>().unwrap();
+    let mut body = code(2, 1, vec![load_int(); 1000]);
+    assert!(pattern.find(&body).is_empty());
+    body.code.push(Instruction::Return(None));
+    assert_eq!(pattern.find(&body).iter().map(|o| o.range.clone()).collect::<Vec<_>>(), vec![0..1001]);
+}
+
+#[test]
+fn search_classes() {
+    use Instruction::*;
+    let exit = InvokeExact(MemberType::Static, member_ref("java/lang/System", "exit", Type::method([Type::Int], None)));
+    let method = |name: &'static str, code: Vec<Instruction>| method(MethodFlags::ACC_STATIC, name, void(), code);
+    let classes = [
+        class("A", "java/lang/Object", &[], vec![
+            method("quit", vec![Push(OrDynamic::Static(Constant::I32(1))), exit.clone(), Return(None)]),
+            method("stay", vec![Return(None)])
+        ]),
+        class("B", "java/lang/Object", &[], vec![method("main", vec![Push(OrDynamic::Static(Constant::I32(0))), exit, Push(OrDynamic::Static(Constant::I32(2))), Return(None)])])
+    ];
+    let pattern = "push int @status; invoke static java/lang/System.exit (I)V".parse::<Pattern>().unwrap();
+    let found = pattern.search(&classes).unwrap();
+    assert_eq!(found.iter().map(|m| (&*m.class, &*m.method)).collect::<Vec<_>>(), vec![("A", "quit"), ("B", "main")]);
+    assert_eq!(found[1].occurrence.capture("status"), Some(&Capture::Constant(Constant::I32(0))));
+    assert_eq!(found[1].to_string(), "B.main()V [0..2]\tstatus=0");
+    assert!("push 0; invoke java/lang/System.exit (J)V".parse::<Pattern>().unwrap().search(&classes).unwrap().is_empty());
+    assert_eq!("push 2; return".parse::<Pattern>().unwrap().search(&classes).unwrap().len(), 1);
+}
@@ -0,0 +1,117 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::prelude::*;
+use crate::peephole::{Peephole, PeepholeRules};
+use super::int;
+
+fn local(kind: LoadOrStore, ty: LocalType, index: u16) -> Instruction {
+    Instruction::LocalVariable(kind, ty, index)
+}
+
+/// Code as emitted by a naive compiler frontend, with the labels of an exception handler and of a local variable.
+fn generated() -> Code {
+    use Instruction::*;
+    let (try_start, try_end, handler, scope, unused, next) = (crate::code::Label(0), crate::code::Label(1), crate::code::Label(2), crate::code::Label(3), crate::code::Label(4), crate::code::Label(5));
+    Code {
+        max_stack: 4,
+        max_locals: 3,
+        code: vec![
+            Label(try_start),
+            int(1),
+            Pop1,
+            Label(try_end),
+            local(LoadOrStore::Load, LocalType::Int, 0),
+            ConvertInt(BitType::Long),
+            Conversion(NumberType::Long, NumberType::Int),
+            ConvertInt(BitType::Char),
+            ConvertInt(BitType::Byte),
+            Dup,
+            Pop1,
+            local(LoadOrStore::Store, LocalType::Int, 1),
+            Push(OrDynamic::Static(Constant::I64(7))),
+            Pop2,
+            local(LoadOrStore::Load, LocalType::Int, 1),
+            Label(unused),
+            local(LoadOrStore::Store, LocalType::Int, 1),
+            NoOp,
+            local(LoadOrStore::Load, LocalType::Int, 1),
+            Jump(JumpCondition::IntegerEqualsZero, next),
+            Label(next),
+            Jump(JumpCondition::Always, scope),
+            LineNumber(4),
+            Label(scope),
+            IntIncrement(1, 0),
+            Return(None),
+            Label(handler),
+            Pop1,
+            Return(None)
+        ],
+        catches: vec![Catch { start: try_start, end: try_end, handler, catch: None }],
+        attrs: vec![CodeAttribute::LocalVariables(vec![crate::code::LocalVariable {
+            start: scope,
+            end: handler,
+            name: "x".into(),
+            descriptor: Some(Type::Int),
+            signature: None,
+            index: 1
+        }])]
+    }
+}
+
+#[test]
+fn optimize_generated_code() {
+    use Instruction::*;
+    let mut code = generated();
+    let count = Peephole::default().optimize(&mut code);
+    assert_eq!(code.code, vec![
+        Label(crate::code::Label(0)),
+        // the protected range cannot become empty.
+        NoOp,
+        Label(crate::code::Label(1)),
+        local(LoadOrStore::Load, LocalType::Int, 0),
+        ConvertInt(BitType::Byte),
+        local(LoadOrStore::Store, LocalType::Int, 1),
+        LineNumber(4),
+        Label(crate::code::Label(3)),
+        Return(None),
+        Label(crate::code::Label(2)),
+        Pop1,
+        Return(None)
+    ]);
+    assert_eq!(count, 13);
+    // the result is a fixed point.
+    assert_eq!(Peephole::default().optimize(&mut code), 0);
+}
+
+#[test]
+fn selected_rules_only() {
+    use Instruction::*;
+    let mut code = generated();
+    let count = Peephole::new(PeepholeRules::NO_OPS | PeepholeRules::DUP_POP).optimize(&mut code);
+    assert_eq!(count, 2);
+    assert!(!code.code.contains(&NoOp));
+    assert!(code.code.contains(&Label(crate::code::Label(4))));
+    assert_eq!(code.code.len(), generated().code.len() - 3);
+
+    // a label starting the scope of a local variable keeps the rewrite from applying.
+    let mut code = Code {
+        code: vec![int(1), Label(crate::code::Label(3)), Pop1, Return(None)],
+        catches: vec![],
+        ..generated()
+    };
+    assert_eq!(Peephole::default().optimize(&mut code), 0);
+}
@@ -0,0 +1,172 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use std::collections::HashMap;
+
+use crate::Class;
+use crate::annotation::{Annotation, AnnotationValue};
+use crate::module::{Module, Provide};
+use crate::prelude::*;
+use crate::remap::Remapper;
+use super::{class, field, member_ref, method, void};
+
+fn library() -> Vec<Class> {
+    let mut base = class("a/Base", "java/lang/Object", &[], vec![method(MethodFlags::ACC_PUBLIC, "foo", void(), vec![Instruction::Return(None)])]);
+    base.fields.push(field(FieldFlags::ACC_PUBLIC, "count"));
+    let sub = class("a/Sub", "a/Base", &[], vec![method(MethodFlags::ACC_PUBLIC, "foo", void(), vec![
+        Instruction::LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+        Instruction::InvokeExact(MemberType::Virtual, member_ref("a/Sub", "foo", void())),
+        Instruction::LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+        Instruction::Field(GetOrPut::Get, MemberType::Virtual, member_ref("a/Sub", "count", Type::Int)),
+        Instruction::Pop1,
+        Instruction::New(OrDynamic::Static("a/Base$Inner".into())),
+        Instruction::CheckCast(OrDynamic::Static(ClassType::Array(1, Type::reference("a/Base")))),
+        Instruction::Pop1,
+        Instruction::Return(None)
+    ])]);
+    vec![base, sub]
+}
+
+const MAPPINGS: &str = "# renames the base class and its members
+a/Base b/Root
+a/Base foo ()V bar
+a/Base count total
+";
+
+#[test]
+fn remap_all_follows_hierarchy() {
+    let remapper = Remapper::from_csrg(MAPPINGS).unwrap();
+    let mut classes = library();
+    remapper.remap_all(&mut classes).unwrap();
+    let (base, sub) = (&classes[0], &classes[1]);
+    assert_eq!(base.name, "b/Root");
+    assert_eq!((base.fields[0].name.as_ref(), base.methods[0].name.as_ref()), ("total", "bar"));
+    assert_eq!(sub.super_name.as_deref(), Some("b/Root"));
+    assert_eq!(sub.methods[0].name, "bar");
+    let code = match &sub.methods[0].attributes[0] {
+        MethodAttribute::Code(c) => &c.code,
+        _ => unreachable!()
+    };
+    assert_eq!(code[1], Instruction::InvokeExact(MemberType::Virtual, member_ref("a/Sub", "bar", void())));
+    assert_eq!(code[3], Instruction::Field(GetOrPut::Get, MemberType::Virtual, member_ref("a/Sub", "total", Type::Int)));
+    assert_eq!(code[5], Instruction::New(OrDynamic::Static("b/Root$Inner".into())));
+    assert_eq!(code[6], Instruction::CheckCast(OrDynamic::Static(ClassType::Array(1, Type::reference("b/Root")))));
+}
+
+#[test]
+fn remap_alone_and_invalid_mappings() {
+    let mut remapper = Remapper::new();
+    remapper.map_class("a/Base", "b/Root").map_method("a/Base", "foo", void(), "bar");
+    let mut sub = library().remove(1);
+    remapper.remap(&mut sub).unwrap();
+    // without the hierarchy, the override is not known to be one.
+    assert_eq!(sub.methods[0].name, "foo");
+    assert_eq!(sub.super_name.as_deref(), Some("b/Root"));
+    assert_eq!(remapper.class_name("[[La/Base;").as_deref(), Some("[[Lb/Root;"));
+    assert_eq!(remapper.class_name("a/Other"), None);
+
+    assert!(Remapper::from_csrg("a/Base b/Root extra words here").is_err());
+    assert!(Remapper::from_csrg("a/Base foo notadescriptor bar").is_err());
+}
+
+#[test]
+fn remap_lambda_call_site() {
+    let task = Class {
+        access: ClassFlags::ACC_PUBLIC | ClassFlags::ACC_INTERFACE | ClassFlags::ACC_ABSTRACT,
+        ..class("a/Task", "java/lang/Object", &[], vec![method(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_ABSTRACT, "run", void(), vec![])])
+    };
+    let metafactory = MemberRef {
+        owner: "java/lang/invoke/LambdaMetafactory".into(),
+        name: "metafactory".into(),
+        descriptor: "(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/invoke/MethodType;Ljava/lang/invoke/MethodType;Ljava/lang/invoke/MethodHandle;Ljava/lang/invoke/MethodType;)Ljava/lang/invoke/CallSite;".parse().unwrap(),
+        itfs: false
+    };
+    let implementation = MethodHandle { kind: MethodHandleKind::InvokeStatic, member: MemberRef { owner: "a/Main".into(), name: "lambda$main$0".into(), descriptor: void(), itfs: false } };
+    let bsm = BootstrapMethod {
+        handle: MethodHandle { kind: MethodHandleKind::InvokeStatic, member: metafactory },
+        arguments: vec![
+            OrDynamic::Static(Constant::MethodType(void())),
+            OrDynamic::Static(Constant::MethodHandle(implementation)),
+            OrDynamic::Static(Constant::MethodType(void()))
+        ]
+    };
+    let main = class("a/Main", "java/lang/Object", &[], vec![method(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC, "main", void(), vec![
+        Instruction::InvokeDynamic(Dynamic::new(bsm, "run", Type::method([], Some(Type::reference("a/Task"))))),
+        Instruction::Pop1,
+        Instruction::Return(None)
+    ])]);
+    let mut classes = vec![task, main];
+    let mut remapper = Remapper::new();
+    remapper.map_class("a/Task", "b/Job").map_method("a/Task", "run", void(), "go");
+    remapper.remap_all(&mut classes).unwrap();
+    assert_eq!(classes[0].methods[0].name, "go");
+    let dynamic = match &classes[1].methods[0].attributes[0] {
+        MethodAttribute::Code(c) => match &c.code[0] {
+            Instruction::InvokeDynamic(d) => d,
+            i => panic!("expected invokedynamic, got {:?}", i)
+        },
+        _ => unreachable!()
+    };
+    assert_eq!(dynamic.name, "go");
+    assert_eq!(dynamic.descriptor, Type::method([], Some(Type::reference("b/Job"))));
+}
+
+#[test]
+fn remap_modules_and_annotations() {
+    let mut remapper = Remapper::new();
+    remapper.map_class("a/Service", "b/Api")
+        .map_class("a/Impl", "c/Impl")
+        .map_class("a/Level", "b/Grade")
+        .map_field("a/Level", "HIGH", "TOP")
+        .map_method("a/Marked", "level", Type::method([], Some(Type::reference("a/Level"))), "grade");
+
+    let module = Module {
+        name: "app".into(),
+        flags: ModuleFlags::empty(),
+        version: None,
+        requires: vec![],
+        exports: vec![],
+        opens: vec![],
+        uses: vec!["a/Service".into()],
+        provides: vec![Provide { class: "a/Service".into(), with: vec!["a/Impl".into()] }]
+    };
+    let mut info = Class {
+        access: ClassFlags::ACC_MODULE,
+        super_name: None,
+        attributes: vec![ClassAttribute::Module(module), ClassAttribute::ModulePackages(vec!["a".into()])],
+        ..class("module-info", "java/lang/Object", &[], vec![])
+    };
+    remapper.remap(&mut info).unwrap();
+    match &info.attributes[..] {
+        [ClassAttribute::Module(m), ClassAttribute::ModulePackages(packages)] => {
+            assert_eq!(m.uses, vec!["b/Api"]);
+            assert_eq!(m.provides, vec![Provide { class: "b/Api".into(), with: vec!["c/Impl".into()] }]);
+            assert_eq!(packages, &vec!["a", "b", "c"]);
+        }
+        a => panic!("unexpected attributes {:?}", a)
+    }
+
+    let mut element_values = HashMap::new();
+    element_values.insert("level".into(), AnnotationValue::Enum(Type::reference("a/Level"), "HIGH".into()));
+    let mut annotated = Class {
+        attributes: vec![ClassAttribute::RuntimeVisibleAnnotations(vec![Annotation { annotation_type: Type::reference("a/Marked"), element_values }])],
+        ..class("a/User", "java/lang/Object", &[], vec![])
+    };
+    remapper.remap(&mut annotated).unwrap();
+    let mut element_values = HashMap::new();
+    element_values.insert("grade".into(), AnnotationValue::Enum(Type::reference("b/Grade"), "TOP".into()));
+    assert_eq!(annotated.attributes, vec![ClassAttribute::RuntimeVisibleAnnotations(vec![Annotation { annotation_type: Type::reference("a/Marked"), element_values }])]);
+}
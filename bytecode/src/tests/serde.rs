@@ -0,0 +1,96 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::Class;
+use crate::prelude::*;
+
+fn sample() -> Class {
+    let concat = Dynamic::string_concat(vec![ConcatPart::Literal("n = ".into()), ConcatPart::Argument(Type::Int)]);
+    let bsm = concat.bsm().clone();
+    Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC | ClassFlags::ACC_SUPER,
+        name: "Serialized".into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec![],
+        fields: vec![Field {
+            access: FieldFlags::ACC_PRIVATE | FieldFlags::ACC_STATIC,
+            name: "count".into(),
+            descriptor: Type::Int,
+            attrs: vec![FieldAttribute::ConstantValue(Constant::I32(3))]
+        }],
+        methods: vec![Method {
+            access: MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC,
+            name: "show".into(),
+            descriptor: Type::method([Type::Int], Some(Type::reference("java/lang/String"))),
+            attributes: vec![MethodAttribute::Code(Code {
+                max_stack: 1,
+                max_locals: 1,
+                code: vec![
+                    Instruction::LocalVariable(LoadOrStore::Load, LocalType::Int, 0),
+                    Instruction::InvokeDynamic(concat),
+                    Instruction::Return(Some(LocalType::Reference))
+                ],
+                catches: vec![],
+                attrs: vec![]
+            })]
+        }],
+        attributes: vec![ClassAttribute::BootstrapMethods(vec![bsm])]
+    }
+}
+
+fn bytes(class: &Class) -> Vec<u8> {
+    let mut buf = vec![];
+    class.write_to(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn json_round_trip() {
+    let class = sample();
+    let json = serde_json::to_string(&class).unwrap();
+    let back: Class = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.fields, class.fields);
+    assert_eq!(back.methods, class.methods);
+    assert_eq!(bytes(&back), bytes(&class));
+}
+
+#[test]
+fn missing_bootstrap_method() {
+    let mut value = serde_json::to_value(sample()).unwrap();
+    value["methods"][0]["attributes"][0]["Code"]["code"][1]["InvokeDynamic"]["bsm"] = serde_json::Value::Null;
+    assert!(serde_json::from_value::<Class>(value).is_err());
+}
+
+#[test]
+fn flags_as_bits() {
+    let value = serde_json::to_value(sample()).unwrap();
+    assert_eq!(value["access"], 0x21);
+    assert_eq!(value["methods"][0]["access"], 0x09);
+}
+
+#[test]
+fn lazy_code_as_decoded() {
+    let written = bytes(&sample());
+    let eager = Class::read_from(&mut written.as_slice()).unwrap();
+    let lazy = Class::read_lazy(&mut written.as_slice()).unwrap();
+    let eager_json = serde_json::to_value(&eager).unwrap();
+    let lazy_json = serde_json::to_value(&lazy).unwrap();
+    assert_eq!(lazy_json["methods"][0]["attributes"][0]["LazyCode"], eager_json["methods"][0]["attributes"][0]["Code"]);
+
+    let back: Class = serde_json::from_value(lazy_json).unwrap();
+    assert_eq!(bytes(&back), bytes(&eager));
+}
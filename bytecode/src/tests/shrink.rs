@@ -0,0 +1,179 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use std::collections::HashMap;
+
+use crate::Class;
+use crate::annotation::Annotation;
+use crate::attr::InnerClass;
+use crate::module::{Module, Provide};
+use crate::prelude::*;
+use crate::keep::Keep;
+use crate::shrink::Shrinker;
+use crate::xref::MemberId;
+use super::{class, constructor, field, member_ref, method, void};
+
+fn names(classes: &[Class]) -> Vec<String> {
+    let mut names = classes.iter().flat_map(|c| {
+        std::iter::once(c.name.to_string())
+            .chain(c.methods.iter().map(move |m| format!("{}.{}{}", c.name, m.name, m.descriptor)))
+            .chain(c.fields.iter().map(move |f| format!("{}.{}", c.name, f.name)))
+    }).collect::<Vec<_>>();
+    names.sort_unstable();
+    names
+}
+
+/// A main method starting a task, which the library calls through `Runnable`, and reading a counter.
+fn application() -> Vec<Class> {
+    use Instruction::*;
+    let main = class("a/Main", "java/lang/Object", &[], vec![
+        method(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC, "main", "([Ljava/lang/String;)V".parse().unwrap(), vec![
+            New(OrDynamic::Static("a/Task".into())),
+            Dup,
+            InvokeSpecial(member_ref("a/Task", "<init>", void())),
+            InvokeExact(MemberType::Static, member_ref("java/lang/Thread", "startVirtualThread", "(Ljava/lang/Runnable;)Ljava/lang/Thread;".parse().unwrap())),
+            Pop1,
+            Field(GetOrPut::Get, MemberType::Static, member_ref("a/Counter", "count", Type::Int)),
+            Pop1,
+            Push(OrDynamic::Static(Constant::String("a.Plugin".into()))),
+            InvokeExact(MemberType::Static, member_ref("java/lang/Class", "forName", "(Ljava/lang/String;)Ljava/lang/Class;".parse().unwrap())),
+            Pop1,
+            Return(None)
+        ])
+    ]);
+    let task = class("a/Task", "java/lang/Object", &["java/lang/Runnable"], vec![
+        constructor("java/lang/Object"),
+        method(MethodFlags::ACC_PUBLIC, "run", void(), vec![Return(None)]),
+        method(MethodFlags::ACC_PRIVATE, "unused", void(), vec![Return(None)])
+    ]);
+    let mut counter = class("a/Counter", "java/lang/Object", &[], vec![
+        method(MethodFlags::ACC_STATIC, "<clinit>", void(), vec![Return(None)]),
+        method(MethodFlags::ACC_PUBLIC, "toString", "()Ljava/lang/String;".parse().unwrap(), vec![PushNull, Return(Some(LocalType::Reference))])
+    ]);
+    counter.fields = vec![field(FieldFlags::ACC_STATIC, "count"), field(FieldFlags::ACC_STATIC, "unused")];
+    counter.attributes.push(ClassAttribute::InnerClasses(vec![InnerClass {
+        inner_fqname: "a/Counter$Dead".into(),
+        outer_fqname: Some("a/Counter".into()),
+        inner_name: Some("Dead".into()),
+        inner_access: InnerClassFlags::ACC_STATIC
+    }]));
+    let plugin = class("a/Plugin", "java/lang/Object", &[], vec![constructor("java/lang/Object")]);
+    let dead = class("a/Counter$Dead", "java/lang/Object", &[], vec![constructor("java/lang/Object")]);
+    vec![main, task, counter, plugin, dead]
+}
+
+#[test]
+fn shrink_from_main() {
+    let mut classes = application();
+    let report = Shrinker::new().main_class("a.Main").shrink(&mut classes).unwrap();
+    // `a/Task.run` is called by the library through `java/lang/Runnable`, `a/Counter` is never instantiated.
+    assert_eq!(names(&classes), vec![
+        "a/Counter",
+        "a/Counter.<clinit>()V",
+        "a/Counter.count",
+        "a/Main",
+        "a/Main.main([Ljava/lang/String;)V",
+        "a/Plugin",
+        "a/Plugin.<init>()V",
+        "a/Task",
+        "a/Task.<init>()V",
+        "a/Task.run()V"
+    ]);
+    assert_eq!(report.classes, vec!["a/Counter$Dead"]);
+    assert_eq!(report.fields, vec![MemberId::new("a/Counter", "unused", Type::Int)]);
+    assert_eq!(report.methods.len(), 2);
+    assert!(matches!(&classes[2].attributes[0], ClassAttribute::InnerClasses(i) if i.is_empty()));
+
+    // without hints, the plugin is only loaded by name.
+    let mut classes = application();
+    Shrinker::new().main_class("a/Main").string_hints(false).shrink(&mut classes).unwrap();
+    assert!(classes.iter().all(|c| c.name != "a/Plugin"));
+}
+
+#[test]
+fn keep_rules() {
+    let rule = "a/api/* public".parse::<Keep>().unwrap();
+    assert_eq!(rule, Keep::new("a/api/*").class_flags(ClassFlags::ACC_PUBLIC));
+    let rule = "a/** * @a.Keep static".parse::<Keep>().unwrap();
+    assert_eq!(rule, Keep::new("a/**").members("*").annotated("a/Keep").member_flags(0x0008));
+    assert!("".parse::<Keep>().is_err());
+    assert!("a/B run walk".parse::<Keep>().is_err());
+    assert_eq!("a/** @a/Keep".parse::<Keep>().unwrap(), Keep::new("a/**").annotated("a/Keep"));
+
+    // `*` stays in the package, `**` also matches its subpackages, and an annotation without a member applies to classes.
+    let annotation = Annotation { annotation_type: Type::reference("a/Keep"), element_values: HashMap::new() };
+    let nested = || {
+        let mut classes = application();
+        classes.push(class("a/b/Nested", "java/lang/Object", &[], vec![]));
+        classes[3].attributes.push(ClassAttribute::RuntimeInvisibleAnnotations(vec![annotation.clone()]));
+        classes
+    };
+    let kept = |rule: &str| {
+        let mut classes = nested();
+        Shrinker::new().keep(rule.parse().unwrap()).shrink(&mut classes).unwrap();
+        classes.into_iter().map(|c| c.name.to_string()).collect::<Vec<_>>()
+    };
+    assert!(!kept("a/*").contains(&"a/b/Nested".to_owned()));
+    assert!(kept("a/**").contains(&"a/b/Nested".to_owned()));
+    assert_eq!(kept("a/*/*"), vec!["a/b/Nested"]);
+    assert_eq!(kept("a/** @a/Keep"), vec!["a/Plugin"]);
+    // class annotations are decoded when reading.
+    let mut bytes = vec![];
+    nested()[3].write_to(&mut bytes).unwrap();
+    assert_eq!(Class::read_from(&mut bytes.as_slice()).unwrap().attributes, nested()[3].attributes);
+
+    let mut classes = application();
+    classes[1].methods[2].attributes.push(MethodAttribute::RuntimeVisibleAnnotations(vec![annotation]));
+    let shrinker = Shrinker::new()
+        .keep("a/Task * @a/Keep".parse().unwrap())
+        .keep(Keep::new("a/Counter").members("u*"))
+        .keep("a/Counter$* public".parse().unwrap());
+    shrinker.shrink(&mut classes).unwrap();
+    // `a/Task` is not instantiated and `a/Main` is not a root.
+    assert_eq!(names(&classes), vec![
+        "a/Counter",
+        "a/Counter$Dead",
+        "a/Counter.<clinit>()V",
+        "a/Counter.unused",
+        "a/Task",
+        "a/Task.unused()V"
+    ]);
+}
+
+#[test]
+fn module_providers() {
+    let module = Module {
+        name: "app".into(),
+        flags: ModuleFlags::empty(),
+        version: None,
+        requires: vec![],
+        exports: vec![],
+        opens: vec![],
+        uses: vec![],
+        provides: vec![Provide { class: "a/Service".into(), with: vec!["a/Plugin".into()] }]
+    };
+    let mut info = class("module-info", "java/lang/Object", &[], vec![]);
+    info.access = ClassFlags::ACC_MODULE;
+    info.super_name = None;
+    info.attributes = vec![ClassAttribute::Module(module), ClassAttribute::ModuleMainClass("a/Main".into())];
+    let service = class("a/Service", "java/lang/Object", &[], vec![]);
+    let mut classes = application();
+    classes[3].interfaces.push("a/Service".into());
+    classes.extend([info, service]);
+    let report = Shrinker::new().string_hints(false).shrink(&mut classes).unwrap();
+    assert_eq!(report.classes, vec!["a/Counter$Dead"]);
+    assert!(names(&classes).contains(&"a/Plugin.<init>()V".to_owned()));
+}
@@ -0,0 +1,165 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::prelude::*;
+use crate::flow::{dominance_frontiers, dominators};
+use crate::interp::{Completion, Interpreter, Sandboxed, Value};
+use crate::ssa::{Function, Terminator};
+use super::{code, int, label, load, store, try_catch};
+
+fn is_stack_or_local(insn: &Instruction) -> bool {
+    matches!(insn, Instruction::LocalVariable(..) | Instruction::Dup | Instruction::Dup2 | Instruction::Swap | Instruction::Pop1 | Instruction::Pop2)
+}
+
+fn run(code: &Code, args: &[Value]) -> Completion {
+    Interpreter::new(Sandboxed, 1000).run(code, args).unwrap()
+}
+
+#[test]
+fn dominator_tree() {
+    // 0 -> 1 -> 2 -> 1, 1 -> 3, and 4 unreachable.
+    let successors = vec![vec![1], vec![2, 3], vec![1], vec![], vec![3]];
+    let idom = dominators(&successors);
+    assert_eq!(idom, vec![None, Some(0), Some(1), Some(1), None]);
+    assert_eq!(dominance_frontiers(&successors, &idom), vec![vec![], vec![1], vec![1], vec![], vec![]]);
+}
+
+/// `static int sum(int n) { int s = 0; for (int i = 0; i < n; i++) s += i; return s; }`
+fn sum() -> Code {
+    use Instruction::*;
+    code(2, 3, vec![
+        int(0), store(LocalType::Int, 1),
+        int(0), store(LocalType::Int, 2),
+        Label(label(0)),
+        load(LocalType::Int, 2), load(LocalType::Int, 0),
+        Jump(JumpCondition::IntegerGreaterThanOrEquals, label(1)),
+        load(LocalType::Int, 1), load(LocalType::Int, 2), IntOperation(IntType::Int, crate::code::IntOperation::Add), store(LocalType::Int, 1),
+        IntIncrement(2, 1),
+        Jump(JumpCondition::Always, label(0)),
+        Label(label(1)),
+        load(LocalType::Int, 1),
+        Return(Some(LocalType::Int))
+    ])
+}
+
+#[test]
+fn lift_loop() {
+    let original = sum();
+    let function = Function::lift(&original, &[LocalType::Int]).unwrap();
+    let header = function.blocks.iter().position(|b| matches!(b.terminator, Terminator::Branch { .. })).unwrap();
+    assert_eq!(function.blocks[header].phis.len(), 2);
+    assert!(function.blocks[header].phis.iter().all(|p| p.incoming.len() == 2 && function.type_of(p.value) == LocalType::Int));
+    assert!(function.blocks.iter().flat_map(|b| &b.statements).all(|s| !is_stack_or_local(&s.instruction)));
+    let lowered = function.lower().unwrap();
+    assert!(lowered.max_locals <= 4);
+    for n in [-1, 0, 1, 5, 20] {
+        assert_eq!(run(&lowered, &[Value::Int(n)]), run(&original, &[Value::Int(n)]));
+    }
+    assert_eq!(run(&lowered, &[Value::Int(5)]), Completion::Returned(Some(Value::Int(10))));
+}
+
+#[test]
+fn lift_stack_across_blocks() {
+    use Instruction::*;
+    // `return 1 + (a > b ? a : b)`, with the constant on the stack at the join.
+    let original = code(3, 2, vec![
+        int(1),
+        load(LocalType::Int, 0), load(LocalType::Int, 1),
+        Jump(JumpCondition::IntegerLessThanOrEquals, label(0)),
+        load(LocalType::Int, 0),
+        Jump(JumpCondition::Always, label(1)),
+        Label(label(0)),
+        load(LocalType::Int, 1),
+        Label(label(1)),
+        IntOperation(IntType::Int, crate::code::IntOperation::Add),
+        Return(Some(LocalType::Int))
+    ]);
+    let function = Function::lift(&original, &[LocalType::Int, LocalType::Int]).unwrap();
+    let phis = function.blocks.iter().flat_map(|b| &b.phis).collect::<Vec<_>>();
+    assert_eq!(phis.len(), 1);
+    let mut incoming = phis[0].incoming.iter().map(|(_, v)| *v).collect::<Vec<_>>();
+    incoming.sort();
+    assert_eq!(incoming, function.parameters);
+    let lowered = function.lower().unwrap();
+    for (a, b) in [(1, 2), (2, 1), (-5, -5)] {
+        let args = [Value::Int(a), Value::Int(b)];
+        assert_eq!(run(&lowered, &args), run(&original, &args));
+    }
+}
+
+#[test]
+fn lift_swap() {
+    use Instruction::*;
+    let original = code(2, 2, vec![
+        load(LocalType::Int, 0), load(LocalType::Int, 1),
+        Swap,
+        IntOperation(IntType::Int, crate::code::IntOperation::Subtract),
+        Return(Some(LocalType::Int))
+    ]);
+    let lowered = Function::lift(&original, &[LocalType::Int, LocalType::Int]).unwrap().lower().unwrap();
+    let args = [Value::Int(10), Value::Int(3)];
+    assert_eq!(run(&original, &args), Completion::Returned(Some(Value::Int(-7))));
+    assert_eq!(run(&lowered, &args), run(&original, &args));
+}
+
+#[test]
+fn lift_wide_values() {
+    use Instruction::*;
+    // `static long square(long a, int unused) { long b = a; b = b * b; return b; }` with a `dup2`.
+    let original = code(4, 5, vec![
+        load(LocalType::Long, 0), store(LocalType::Long, 3),
+        load(LocalType::Long, 3), Dup2, IntOperation(IntType::Long, crate::code::IntOperation::Multiply), store(LocalType::Long, 3),
+        load(LocalType::Long, 3),
+        Return(Some(LocalType::Long))
+    ]);
+    let function = Function::lift(&original, &[LocalType::Long, LocalType::Int]).unwrap();
+    assert_eq!(function.blocks.iter().map(|b| b.statements.len()).sum::<usize>(), 1);
+    let lowered = function.lower().unwrap();
+    assert_eq!((lowered.max_locals, lowered.max_stack), (3, 4));
+    let args = [Value::Long(-7), Value::Int(0)];
+    assert_eq!(run(&lowered, &args), Completion::Returned(Some(Value::Long(49))));
+
+    let narrow = code(1, 2, vec![load(LocalType::Long, 0), Return(Some(LocalType::Long))]);
+    assert!(Function::lift(&narrow, &[LocalType::Int, LocalType::Int]).is_err());
+    let jsr = code(1, 1, vec![Jsr(label(0)), Return(None), Label(label(0)), store(LocalType::Reference, 0), Ret(0)]);
+    assert!(Function::lift(&jsr, &[]).is_err());
+}
+
+#[test]
+fn lift_exception_handler() {
+    use Instruction::*;
+    let original = try_catch();
+    let function = Function::lift(&original, &[LocalType::Int, LocalType::Int]).unwrap();
+    let handler = function.blocks.iter().position(|b| b.exception.is_some()).unwrap();
+    let covered = function.blocks.iter().filter(|b| !b.handlers.is_empty()).collect::<Vec<_>>();
+    assert!(covered.iter().all(|b| b.handlers.len() == 1 && b.handlers[0].target == handler));
+    // the division starts a block, so the handler is entered with the values of the start of that block.
+    let divide = IntOperation(IntType::Int, crate::code::IntOperation::Divide);
+    assert!(covered.iter().any(|b| b.statements.first().map(|s| &s.instruction) == Some(&divide)));
+    // the handler returns the constant 1 that `x` holds when the division throws.
+    match function.blocks[handler].terminator {
+        Terminator::Return(Some(v)) => assert_eq!(function.blocks.iter().flat_map(|b| &b.statements).find(|s| s.value == Some(v)).unwrap().instruction, int(1)),
+        _ => panic!("{}", function)
+    }
+
+    let lowered = function.lower().unwrap();
+    assert_eq!(lowered.catches.len(), 1);
+    assert_eq!(lowered.catches[0].catch.as_deref(), Some("java/lang/ArithmeticException"));
+    let args = [Value::Int(7), Value::Int(2)];
+    assert_eq!(run(&lowered, &args), Completion::Returned(Some(Value::Int(2))));
+    let relifted = Function::lift(&lowered, &[LocalType::Int, LocalType::Int]).unwrap();
+    assert_eq!(relifted.blocks.iter().filter(|b| b.exception.is_some()).count(), 1);
+}
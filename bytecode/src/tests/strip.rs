@@ -0,0 +1,121 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::Class;
+use crate::annotation::Annotation;
+use crate::prelude::*;
+use crate::strip::{StripFlags, Stripper};
+use std::collections::HashMap;
+use std::io::Cursor;
+
+fn sample() -> Class {
+    let start = Label(0);
+    let end = Label(1);
+    Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC,
+        name: "Sample".into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec![],
+        fields: vec![],
+        methods: vec![Method {
+            access: MethodFlags::ACC_STATIC | MethodFlags::ACC_PUBLIC,
+            name: "get".into(),
+            descriptor: Type::method([Type::reference("java/lang/Object")], Some(Type::reference("java/lang/Object"))),
+            attributes: vec![
+                MethodAttribute::Signature("(Ljava/lang/Object;)Ljava/lang/Object;".parse().unwrap()),
+                MethodAttribute::Code(Code {
+                    max_stack: 1,
+                    max_locals: 1,
+                    code: vec![
+                        Instruction::Label(start),
+                        Instruction::LineNumber(3),
+                        Instruction::LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+                        Instruction::Return(Some(LocalType::Reference)),
+                        Instruction::Label(end)
+                    ],
+                    catches: vec![],
+                    attrs: vec![CodeAttribute::LocalVariables(vec![LocalVariable {
+                        start,
+                        end,
+                        name: "value".into(),
+                        descriptor: Some(Type::reference("java/lang/Object")),
+                        signature: None,
+                        index: 0
+                    }])]
+                })
+            ]
+        }],
+        attributes: vec![ClassAttribute::SourceFile("Sample.java".into())]
+    }
+}
+
+fn reread(class: &Class) -> Class {
+    let mut buf = vec![];
+    class.write_to(&mut buf).unwrap();
+    Class::read_from(&mut Cursor::new(buf)).unwrap()
+}
+
+fn code(class: &Class) -> &Code {
+    class.methods[0].attributes.iter().find_map(|a| if let MethodAttribute::Code(c) = a { Some(c) } else { None }).unwrap()
+}
+
+#[test]
+fn line_numbers_are_written() {
+    let class = reread(&sample());
+    assert!(code(&class).code.contains(&Instruction::LineNumber(3)));
+}
+
+#[test]
+fn strip_everything() {
+    let mut class = sample();
+    let report = Stripper::default().strip(&mut class).unwrap();
+    assert!(report.saved() > 0);
+    assert!(report.after < report.before);
+    let class = reread(&class);
+    assert!(class.attributes.is_empty());
+    assert_eq!(class.methods[0].attributes.len(), 1);
+    let code = code(&class);
+    assert!(code.attrs.is_empty());
+    assert!(!code.code.iter().any(|i| matches!(i, Instruction::LineNumber(_))));
+}
+
+#[test]
+fn strip_selected_and_rename_source() {
+    let mut class = sample();
+    Stripper::new(StripFlags::LINE_NUMBERS).rename_source_file("SourceFile").strip(&mut class).unwrap();
+    let class = reread(&class);
+    assert_eq!(class.attributes, vec![ClassAttribute::SourceFile("SourceFile".into())]);
+    assert_eq!(class.methods[0].attributes.len(), 2);
+    let code = code(&class);
+    assert_eq!(code.attrs.len(), 1);
+    assert!(!code.code.iter().any(|i| matches!(i, Instruction::LineNumber(_))));
+}
+
+#[test]
+fn strip_class_annotations() {
+    let annotation = |name: &str| Annotation { annotation_type: Type::reference(name.to_owned()), element_values: HashMap::new() };
+    let mut class = sample();
+    class.attributes.push(ClassAttribute::RuntimeVisibleAnnotations(vec![annotation("a/Visible")]));
+    class.attributes.push(ClassAttribute::RuntimeInvisibleAnnotations(vec![annotation("a/Invisible")]));
+    assert_eq!(reread(&class).attributes, class.attributes);
+    Stripper::new(StripFlags::INVISIBLE_ANNOTATIONS).strip(&mut class).unwrap();
+    assert_eq!(reread(&class).attributes, vec![
+        ClassAttribute::SourceFile("Sample.java".into()),
+        ClassAttribute::RuntimeVisibleAnnotations(vec![annotation("a/Visible")])
+    ]);
+}
@@ -0,0 +1,70 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::Class;
+use crate::prelude::*;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn class_is_send_sync() {
+    assert_send_sync::<Class>();
+    assert_send_sync::<Code>();
+    assert_send_sync::<Dynamic>();
+    assert_send_sync::<LazyCode>();
+    assert_send_sync::<crate::Error>();
+}
+
+fn class(name: &'static str) -> Vec<u8> {
+    let mut buf = vec![];
+    Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC,
+        name: name.into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec![],
+        fields: vec![],
+        methods: vec![],
+        attributes: vec![]
+    }.write_to(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn read_all_keeps_order() {
+    let mut buffers = (0..20).map(|i| class(if i % 2 == 0 { "Even" } else { "Odd" })).collect::<Vec<_>>();
+    buffers[7] = vec![0xCA, 0xFE];
+    let classes = Class::read_all(&buffers);
+    assert_eq!(classes.len(), 20);
+    for (i, c) in classes.iter().enumerate() {
+        match c {
+            Ok(c) => assert_eq!(c.name, if i % 2 == 0 { "Even" } else { "Odd" }),
+            Err(_) => assert_eq!(i, 7)
+        }
+    }
+    assert!(classes[7].is_err());
+    assert!(Class::read_all::<Vec<u8>>(&[]).is_empty());
+}
+
+#[test]
+fn bsm_mut_on_clone() {
+    let concat = Dynamic::string_concat(vec![ConcatPart::Argument(Type::Int)]);
+    let mut copy = concat.clone();
+    copy.bsm_mut().arguments.clear();
+    assert!(copy.bsm().arguments.is_empty());
+    assert!(!concat.bsm().arguments.is_empty());
+}
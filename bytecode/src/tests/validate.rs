@@ -0,0 +1,69 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::Class;
+use crate::prelude::*;
+use crate::error::PathSegment;
+use super::{class, field, method, void};
+
+#[test]
+fn valid_class() {
+    let c = Class {
+        access: ClassFlags::ACC_PUBLIC | ClassFlags::ACC_SUPER,
+        ..class("Checked", "java/lang/Object", &[], vec![
+            method(MethodFlags::ACC_PUBLIC, "<init>", void(), vec![Instruction::Return(None)]),
+            method(MethodFlags::ACC_STATIC, "<clinit>", void(), vec![Instruction::Return(None)]),
+            method(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_NATIVE, "run", void(), vec![])
+        ])
+    };
+    assert_eq!(c.validate(), vec![]);
+}
+
+#[test]
+fn all_violations_reported() {
+    let mut c = Class {
+        access: ClassFlags::ACC_PUBLIC | ClassFlags::ACC_INTERFACE,
+        ..class("Checked", "java/lang/Object", &[], vec![
+            method(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_ABSTRACT, "run", void(), vec![Instruction::Return(None)]),
+            method(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC, "<init>", void(), vec![Instruction::Return(None)])
+        ])
+    };
+    c.fields.push(field(FieldFlags::ACC_PUBLIC, "x"));
+    let violations = c.validate();
+    let messages = violations.iter().map(|v| v.message.as_ref()).collect::<Vec<_>>();
+    assert_eq!(messages, vec![
+        "an interface must be abstract",
+        "an interface field must be public, static and final",
+        "an abstract or native method must not have code",
+        "an interface must not have instance initializers",
+        "an instance initializer may only be public, private, protected, varargs, strict and synthetic"
+    ]);
+    assert_eq!(violations[2].path, vec![PathSegment::Class("Checked".into()), PathSegment::Member("run".into(), "()V".into())]);
+    assert_eq!(violations[2].to_string(), "Checked.run()V: an abstract or native method must not have code");
+}
+
+#[test]
+fn version_dependent_rules() {
+    let mut c = Class {
+        access: ClassFlags::ACC_PUBLIC | ClassFlags::ACC_INTERFACE | ClassFlags::ACC_ABSTRACT,
+        ..class("Checked", "java/lang/Object", &[], vec![
+            method(MethodFlags::ACC_PUBLIC, "helper", void(), vec![Instruction::Return(None)])
+        ])
+    };
+    assert_eq!(c.validate(), vec![]);
+    c.version.major = MajorVersion::J7;
+    assert_eq!(c.validate().len(), 1);
+}
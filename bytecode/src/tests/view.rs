@@ -0,0 +1,89 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::Class;
+use crate::prelude::*;
+use crate::view::{ClassView, MemberRefView};
+
+fn sample() -> Vec<u8> {
+    let class = Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC | ClassFlags::ACC_SUPER,
+        name: "pkg/Sample".into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec!["java/lang/Runnable".into()],
+        fields: vec![Field {
+            access: FieldFlags::ACC_PRIVATE,
+            name: "çount".into(),
+            descriptor: Type::Int,
+            attrs: vec![]
+        }],
+        methods: vec![Method {
+            access: MethodFlags::ACC_PUBLIC,
+            name: "run".into(),
+            descriptor: Type::method([], None),
+            attributes: vec![MethodAttribute::Code(Code {
+                max_stack: 1,
+                max_locals: 1,
+                code: vec![
+                    Instruction::Field(GetOrPut::Get, MemberType::Static, MemberRef {
+                        owner: "java/lang/System".into(),
+                        name: "out".into(),
+                        descriptor: Type::reference("java/io/PrintStream"),
+                        itfs: false
+                    }.into()),
+                    Instruction::Pop1,
+                    Instruction::Return(None)
+                ],
+                catches: vec![],
+                attrs: vec![]
+            })]
+        }],
+        attributes: vec![ClassAttribute::SourceFile("Sample.java".into())]
+    };
+    let mut buf = vec![];
+    class.write_to(&mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn view_borrows_ascii() {
+    let bytes = sample();
+    let view = ClassView::parse(&bytes).unwrap();
+    assert!(matches!(view.name, Cow::Borrowed("pkg/Sample")));
+    assert_eq!(view.super_name.as_deref(), Some("java/lang/Object"));
+    assert_eq!(view.interfaces, vec!["java/lang/Runnable"]);
+    assert!(matches!(view.fields[0].name, Cow::Owned(ref s) if s == "çount"));
+    assert_eq!(view.methods[0].name, "run");
+    assert_eq!(view.methods[0].descriptor, "()V");
+    assert_eq!(view.methods[0].attributes[0].name, "Code");
+    assert_eq!(view.attributes[0].name, "SourceFile");
+    assert!(view.class_refs().any(|c| c == "java/lang/System"));
+    assert_eq!(view.member_refs().collect::<Vec<_>>(), vec![MemberRefView {
+        owner: "java/lang/System".into(),
+        name: "out".into(),
+        descriptor: "Ljava/io/PrintStream;".into(),
+        itfs: false
+    }]);
+    assert_eq!(view.to_class().unwrap().name, "pkg/Sample");
+}
+
+#[test]
+fn view_truncated() {
+    let bytes = sample();
+    assert!(ClassView::parse(&bytes[..bytes.len() - 3]).is_err());
+}
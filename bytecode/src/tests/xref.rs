@@ -0,0 +1,160 @@
+/*
+    This file is part of Coffer.
+
+    Coffer is free software: you can redistribute it and/or modify
+    it under the terms of the GNU Lesser General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    Coffer is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU Lesser General Public License
+    along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::Class;
+use crate::dynamic::BootstrapMethod;
+use crate::prelude::*;
+use crate::xref::{Access, CallKind, Index, MemberId};
+use super::{class, constructor, member, member_ref, method, void};
+
+fn id(owner: &'static str, name: &'static str, descriptor: Type) -> MemberId {
+    MemberId::new(owner, name, descriptor)
+}
+
+fn lambda() -> Dynamic {
+    let metafactory = member("java/lang/invoke/LambdaMetafactory", "metafactory", "(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/invoke/MethodType;Ljava/lang/invoke/MethodType;Ljava/lang/invoke/MethodHandle;Ljava/lang/invoke/MethodType;)Ljava/lang/invoke/CallSite;".parse().unwrap());
+    let handle = |kind, member| OrDynamic::Static(Constant::MethodHandle(MethodHandle { kind, member }));
+    let bsm = BootstrapMethod {
+        handle: MethodHandle { kind: MethodHandleKind::InvokeStatic, member: metafactory },
+        arguments: vec![
+            OrDynamic::Static(Constant::MethodType(void())),
+            handle(MethodHandleKind::InvokeStatic, member("a/Main", "lambda$main$0", void())),
+            OrDynamic::Static(Constant::MethodType(void()))
+        ]
+    };
+    Dynamic::new(bsm, "run", Type::method([], Some(Type::reference("a/Task"))))
+}
+
+/// A main method calling a task through its interface, a lambda, and a static field of a class with an initializer.
+fn application() -> Vec<Class> {
+    use Instruction::*;
+    let string_array = Type::ArrayRef(1, Box::new(Type::reference("java/lang/String")));
+    let main = class("a/Main", "java/lang/Object", &[], vec![
+        method(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC, "main", Type::method([string_array], None), vec![
+            New(OrDynamic::Static("a/Impl".into())),
+            Dup,
+            InvokeSpecial(member_ref("a/Impl", "<init>", void())),
+            InvokeInterface(OrDynamic::Static(MemberRef { itfs: true, ..member("a/Task", "run", void()) }), 1),
+            InvokeDynamic(lambda()),
+            Pop1,
+            Field(GetOrPut::Get, MemberType::Static, member_ref("a/Config", "NAME", Type::reference("java/lang/String"))),
+            Pop1,
+            Return(None)
+        ]),
+        method(MethodFlags::ACC_PRIVATE | MethodFlags::ACC_STATIC, "lambda$main$0", void(), vec![
+            Push(OrDynamic::Static(Constant::String("hello".into()))),
+            Pop1,
+            Return(None)
+        ]),
+        method(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC, "unused", void(), vec![
+            InvokeExact(MemberType::Static, member_ref("a/Other", "helper", void())),
+            Return(None)
+        ])
+    ]);
+    let mut task = class("a/Task", "java/lang/Object", &[], vec![method(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_ABSTRACT, "run", void(), vec![])]);
+    task.access |= ClassFlags::ACC_INTERFACE | ClassFlags::ACC_ABSTRACT;
+    let base = class("a/Base", "java/lang/Object", &[], vec![constructor("java/lang/Object"), method(MethodFlags::ACC_PUBLIC, "run", void(), vec![Return(None)])]);
+    let implementation = class("a/Impl", "a/Base", &["a/Task"], vec![constructor("a/Base")]);
+    let other_task = class("a/Quick", "java/lang/Object", &["a/Task"], vec![method(MethodFlags::ACC_PUBLIC, "run", void(), vec![Return(None)])]);
+    let mut config = class("a/Config", "java/lang/Object", &[], vec![
+        method(MethodFlags::ACC_STATIC, "<clinit>", void(), vec![
+            Push(OrDynamic::Static(Constant::String("app".into()))),
+            Field(GetOrPut::Put, MemberType::Static, member_ref("a/Config", "NAME", Type::reference("java/lang/String"))),
+            Return(None)
+        ])
+    ]);
+    config.fields.push(crate::Field {
+        access: FieldFlags::ACC_PUBLIC | FieldFlags::ACC_STATIC,
+        name: "NAME".into(),
+        descriptor: Type::reference("java/lang/String"),
+        attrs: vec![]
+    });
+    config.fields.push(crate::Field {
+        access: FieldFlags::ACC_PUBLIC,
+        name: "items".into(),
+        descriptor: Type::reference("java/util/List"),
+        attrs: vec![FieldAttribute::Signature("Ljava/util/List<La/Item;>;".parse().unwrap())]
+    });
+    let other = class("a/Other", "java/lang/Object", &[], vec![method(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC, "helper", void(), vec![Return(None)])]);
+    vec![main, task, base, implementation, other_task, config, other]
+}
+
+#[test]
+fn calls_and_field_accesses() {
+    let index = Index::build(&application()).unwrap();
+    let main = id("a/Main", "main", "([Ljava/lang/String;)V".parse().unwrap());
+    let callees = index.callees(&main).map(|c| (c.target.to_string(), c.kind, c.site.index)).collect::<Vec<_>>();
+    assert_eq!(callees, vec![
+        ("a/Impl.<init>()V".to_owned(), CallKind::Special, Some(2)),
+        ("a/Task.run()V".to_owned(), CallKind::Interface, Some(3)),
+        ("java/lang/invoke/LambdaMetafactory.metafactory(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/invoke/MethodType;Ljava/lang/invoke/MethodType;Ljava/lang/invoke/MethodHandle;Ljava/lang/invoke/MethodType;)Ljava/lang/invoke/CallSite;".to_owned(), CallKind::Bootstrap, Some(4)),
+        ("a/Main.lambda$main$0()V".to_owned(), CallKind::Handle(MethodHandleKind::InvokeStatic), Some(4))
+    ]);
+    // the constructor of `a/Impl` is written as a call on `a/Impl`, and resolves to the one it declares.
+    let callers = index.callers(&id("a/Impl", "<init>", void())).map(|c| c.site.to_string()).collect::<Vec<_>>();
+    assert_eq!(callers, vec!["a/Main.main([Ljava/lang/String;)V [2]"]);
+    assert_eq!(index.resolve(&id("a/Impl", "run", void())), Some(id("a/Base", "run", void())));
+    assert_eq!(index.resolve(&id("a/Impl", "missing", void())), None);
+    assert_eq!(index.subtypes("a/Task"), vec!["a/Impl", "a/Quick"]);
+
+    let name = id("a/Config", "NAME", Type::reference("java/lang/String"));
+    let readers = index.readers(&name).map(|a| a.site.to_string()).collect::<Vec<_>>();
+    let writers = index.writers(&name).map(|a| a.site.to_string()).collect::<Vec<_>>();
+    assert_eq!(readers, vec!["a/Main.main([Ljava/lang/String;)V [6]"]);
+    assert_eq!(writers, vec!["a/Config.<clinit>()V [1]"]);
+    assert!(index.accesses().iter().all(|a| a.access == Access::Read || a.site.member.as_ref().is_some_and(|m| m.name == "<clinit>")));
+}
+
+#[test]
+fn class_references_and_strings() {
+    let index = Index::build(&application()).unwrap();
+    let task = index.references("a/Task").iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    // the interface call, the type of the lambda, and the interfaces of the implementations.
+    assert_eq!(task, vec!["a/Main.main([Ljava/lang/String;)V [3]", "a/Main.main([Ljava/lang/String;)V [4]", "a/Impl", "a/Quick"]);
+    // from the generic signature of a field only.
+    assert_eq!(index.references("a/Item").iter().map(|s| s.to_string()).collect::<Vec<_>>(), vec!["a/Config.items:Ljava/util/List;"]);
+    assert!(index.references("a/Missing").is_empty());
+    assert!(index.referenced_classes().any(|c| c == "java/lang/String"));
+    assert!(!index.contains("java/lang/String") && index.contains("a/Main"));
+
+    assert_eq!(index.string_uses("hello").iter().map(|s| s.to_string()).collect::<Vec<_>>(), vec!["a/Main.lambda$main$0()V [0]"]);
+    let mut strings = index.strings().collect::<Vec<_>>();
+    strings.sort_unstable();
+    assert_eq!(strings, vec!["app", "hello"]);
+}
+
+#[test]
+fn reachability_from_main() {
+    let index = Index::build(&application()).unwrap();
+    let main = id("a/Main", "main", "([Ljava/lang/String;)V".parse().unwrap());
+    let mut reached = index.reachable([main]).iter().map(|m| m.to_string()).collect::<Vec<_>>();
+    reached.sort_unstable();
+    // `a/Quick.run` is reached through the interface call, although it is never instantiated, and the abstract
+    // `a/Task.run` is the declaration the call resolves to.
+    assert_eq!(reached, vec![
+        "a/Base.<init>()V",
+        "a/Base.run()V",
+        "a/Config.<clinit>()V",
+        "a/Impl.<init>()V",
+        "a/Main.lambda$main$0()V",
+        "a/Main.main([Ljava/lang/String;)V",
+        "a/Quick.run()V",
+        "a/Task.run()V"
+    ]);
+    let from_unused = index.reachable([id("a/Main", "unused", void()), id("a/Missing", "run", void())]);
+    assert_eq!(from_unused.len(), 2);
+    assert!(from_unused.contains(&id("a/Other", "helper", void())));
+}
@@ -22,6 +22,7 @@ use std::fmt::{Display, Formatter};
 
 /// A descriptor, field or method.
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     /// A field descriptor representing the primitive `byte` (8-bit integer).
     Byte,
@@ -147,7 +148,7 @@ impl FromStr for Type {
                     let mut dim: u8 = 1;
                     while let Some('[') = c.as_str().chars().next() {
                         c.next();
-                        dim += 1;
+                        dim = dim.checked_add(1).ok_or_else(|| crate::error::Error::Invalid("array dimensions", st.to_owned().into()))?;
                     }
                     let r = get_type(c, st)?;
                     Type::ArrayRef(dim, Box::new(r))
@@ -0,0 +1,188 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Format checking of access flags and class structure, following the rules of chapter 4 of the JVM specification.
+//!
+//! Coffer accepts any combination of flags when reading and writing, [`Class::validate`] reports those the JVM would reject.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+use crate::prelude::*;
+use crate::Class;
+use crate::error::{DisplayPath, PathSegment};
+
+/// A format checking rule that a class, field or method breaks.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Violation {
+    /// The element that breaks the rule, from the outermost to the innermost. Empty when it is the element that was validated.
+    pub path: Vec<PathSegment>,
+    /// The rule that is broken.
+    pub message: Cow<'static, str>
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{}: {}", DisplayPath(&self.path), self.message)
+        }
+    }
+}
+
+/// Collects violations of the validated element.
+struct Violations(Vec<Violation>);
+
+impl Violations {
+    fn check<M: Into<Cow<'static, str>>>(&mut self, ok: bool, message: M) {
+        if !ok {
+            self.0.push(Violation { path: vec![], message: message.into() })
+        }
+    }
+
+    /// Adds the violations of an inner element, prepending a segment to their paths.
+    fn extend(&mut self, segment: PathSegment, violations: Vec<Violation>) {
+        self.0.extend(violations.into_iter().map(|mut v| {
+            v.path.insert(0, segment.clone());
+            v
+        }))
+    }
+}
+
+#[inline]
+fn major(version: JavaVersion) -> u16 {
+    version.major as u16
+}
+
+/// The amount of the public, private and protected flags that are set.
+fn visibility_count(bits: u16) -> u32 {
+    (bits & 0b111).count_ones()
+}
+
+impl Class {
+    /// Checks the flags and structure of this class, its fields and its methods, for the version of this class.
+    ///
+    /// Every violation is returned, an empty vector means that the class passes format checking.
+    pub fn validate(&self) -> Vec<Violation> {
+        let mut v = Violations(vec![]);
+        let a = self.access;
+        if a.contains(ClassFlags::ACC_MODULE) {
+            v.check(major(self.version) >= 53, "modules require Java 9");
+            v.check(a == ClassFlags::ACC_MODULE, "a module must not have other flags");
+            v.check(self.name == "module-info", "a module must be named module-info");
+            v.check(self.super_name.is_none(), "a module must not have a superclass");
+            v.check(self.interfaces.is_empty() && self.fields.is_empty() && self.methods.is_empty(), "a module must not have interfaces, fields or methods");
+            return v.0
+        }
+        if a.contains(ClassFlags::ACC_INTERFACE) {
+            v.check(a.contains(ClassFlags::ACC_ABSTRACT), "an interface must be abstract");
+            v.check(!a.intersects(ClassFlags::ACC_FINAL | ClassFlags::ACC_SUPER | ClassFlags::ACC_ENUM), "an interface must not be final, super or enum");
+            v.check(self.super_name.as_deref() == Some("java/lang/Object"), "the superclass of an interface must be java/lang/Object");
+        } else {
+            v.check(!a.contains(ClassFlags::ACC_ANNOTATION), "an annotation must be an interface");
+            v.check(!a.contains(ClassFlags::ACC_FINAL | ClassFlags::ACC_ABSTRACT), "a class must not be both final and abstract");
+        }
+        v.check(self.super_name.is_some() || self.name == "java/lang/Object", "only java/lang/Object may have no superclass");
+
+        let mut seen = HashSet::new();
+        for f in &self.fields {
+            let segment = PathSegment::Member(f.name.clone(), f.descriptor.to_string().into());
+            if !seen.insert(segment.clone()) {
+                v.extend(segment.clone(), vec![Violation { path: vec![], message: "duplicate field".into() }]);
+            }
+            v.extend(segment, f.validate(a));
+        }
+        let mut seen = HashSet::new();
+        for m in &self.methods {
+            let segment = PathSegment::Member(m.name.clone(), m.descriptor.to_string().into());
+            if !seen.insert(segment.clone()) {
+                v.extend(segment.clone(), vec![Violation { path: vec![], message: "duplicate method".into() }]);
+            }
+            v.extend(segment, m.validate(self.version, a));
+        }
+        let mut violations = v.0;
+        for violation in &mut violations {
+            violation.path.insert(0, PathSegment::Class(self.name.clone()));
+        }
+        violations
+    }
+}
+
+impl Field {
+    /// Checks the flags and descriptor of this field, declared in a class with the given flags.
+    pub fn validate(&self, owner: ClassFlags) -> Vec<Violation> {
+        let mut v = Violations(vec![]);
+        let a = self.access;
+        v.check(!self.descriptor.is_method(), "the descriptor of a field must not be a method descriptor");
+        v.check(visibility_count(a.bits()) <= 1, "at most one of public, private and protected may be set");
+        v.check(!a.contains(FieldFlags::ACC_FINAL | FieldFlags::ACC_VOLATILE), "a field must not be both final and volatile");
+        if owner.contains(ClassFlags::ACC_INTERFACE) {
+            v.check(a.contains(FieldFlags::ACC_PUBLIC | FieldFlags::ACC_STATIC | FieldFlags::ACC_FINAL), "an interface field must be public, static and final");
+            v.check((a - FieldFlags::ACC_SYNTHETIC - FieldFlags::ACC_PUBLIC - FieldFlags::ACC_STATIC - FieldFlags::ACC_FINAL).is_empty(),
+                    "an interface field may only be public, static, final and synthetic");
+        }
+        v.0
+    }
+}
+
+impl Method {
+    /// Checks the flags, descriptor and presence of code of this method, declared in a class with the given version and flags.
+    pub fn validate(&self, version: JavaVersion, owner: ClassFlags) -> Vec<Violation> {
+        let mut v = Violations(vec![]);
+        let a = self.access;
+        let interface = owner.contains(ClassFlags::ACC_INTERFACE);
+        let void = matches!(&self.descriptor, Type::Method { ret: None, .. });
+        v.check(self.descriptor.is_method(), "the descriptor of a method must be a method descriptor");
+        v.check(visibility_count(a.bits()) <= 1, "at most one of public, private and protected may be set");
+
+        match self.name.as_ref() {
+            "<clinit>" => {
+                v.check(void && matches!(&self.descriptor, Type::Method { parameters, .. } if parameters.is_empty()), "a class initializer must take no parameters and return void");
+                v.check(major(version) < 51 || a.contains(MethodFlags::ACC_STATIC), "a class initializer must be static since Java 7");
+            }
+            "<init>" => {
+                v.check(!interface, "an interface must not have instance initializers");
+                v.check(void, "an instance initializer must return void");
+                let allowed = MethodFlags::ACC_PUBLIC | MethodFlags::ACC_PRIVATE | MethodFlags::ACC_PROTECTED
+                    | MethodFlags::ACC_VARARGS | MethodFlags::ACC_STRICT | MethodFlags::ACC_SYNTHETIC;
+                v.check((a - allowed).is_empty(), "an instance initializer may only be public, private, protected, varargs, strict and synthetic");
+            }
+            _ if interface => {
+                if major(version) < 52 {
+                    v.check(a.contains(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_ABSTRACT), "an interface method must be public and abstract before Java 8");
+                } else {
+                    v.check(a.intersects(MethodFlags::ACC_PUBLIC | MethodFlags::ACC_PRIVATE), "an interface method must be either public or private");
+                }
+                v.check(!a.intersects(MethodFlags::ACC_PROTECTED | MethodFlags::ACC_FINAL | MethodFlags::ACC_SYNCHRONIZED | MethodFlags::ACC_NATIVE),
+                        "an interface method must not be protected, final, synchronized or native");
+            }
+            _ => {}
+        }
+        if a.contains(MethodFlags::ACC_ABSTRACT) {
+            v.check(!a.intersects(MethodFlags::ACC_PRIVATE | MethodFlags::ACC_STATIC | MethodFlags::ACC_FINAL | MethodFlags::ACC_SYNCHRONIZED | MethodFlags::ACC_NATIVE | MethodFlags::ACC_STRICT),
+                    "an abstract method must not be private, static, final, synchronized, native or strict");
+        }
+
+        let code = self.attributes.iter().filter(|a| matches!(a, MethodAttribute::Code(_) | MethodAttribute::LazyCode(_))).count();
+        if a.intersects(MethodFlags::ACC_ABSTRACT | MethodFlags::ACC_NATIVE) {
+            v.check(code == 0, "an abstract or native method must not have code");
+        } else {
+            v.check(code == 1, "a method that is neither abstract nor native must have exactly one code attribute");
+        }
+        v.0
+    }
+}
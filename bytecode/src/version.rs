@@ -20,6 +20,7 @@ use std::fmt::{Display, Formatter, Result};
 
 /// the version of a java class.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, ReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct JavaVersion {
     /// The minor version.
     pub minor: u16,
@@ -36,6 +37,7 @@ impl JavaVersion {
 
 /// Major version of a java class.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, ReadWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[tag_type(u16)]
 pub enum MajorVersion {
     /// Java version 1.0.2/1.1
@@ -0,0 +1,294 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Borrowed, read-only views of class files.
+//!
+//! Reading a [`Class`] allocates every string it contains, which is wasteful when scanning many classes only to look at names and references.
+//! A [`ClassView`] keeps references into the input buffer instead: modified UTF-8 that is plain ASCII is borrowed,
+//! and only the remaining strings are decoded into owned ones. Attributes are kept as raw bytes.
+//!
+//! A view can be turned into a full [`Class`] with [`ClassView::to_class`].
+
+use crate::prelude::*;
+use crate::Class;
+use crate::mod_utf8::modified_utf8_to_cow;
+use std::io::ErrorKind;
+
+/// An entry of a borrowed constant pool.
+#[derive(Clone, Debug)]
+pub enum ViewEntry<'a> {
+    /// A string, borrowed from the buffer when possible.
+    UTF8(Cow<'a, str>),
+    /// Any other entry. This is never [`RawConstantEntry::UTF8`].
+    Raw(RawConstantEntry)
+}
+
+/// A constant pool whose strings are borrowed from the buffer it was read from.
+#[derive(Clone, Debug, Default)]
+pub struct ViewPool<'a> {
+    entries: Vec<Option<ViewEntry<'a>>>
+}
+
+impl<'a> ViewPool<'a> {
+    fn read(rd: &mut &'a [u8]) -> Result<Self> {
+        let count = u16::read_from(rd)? as usize;
+        let mut entries = Vec::with_capacity(count);
+        entries.push(None);
+        while entries.len() < count {
+            if let Some(1) = rd.first() {
+                *rd = &rd[1..];
+                let len = u16::read_from(rd)?;
+                entries.push(Some(ViewEntry::UTF8(modified_utf8_to_cow(take(rd, len as usize)?)?)));
+            } else {
+                let entry = RawConstantEntry::read_from(rd)?;
+                let wide = entry.is_wide();
+                entries.push(Some(ViewEntry::Raw(entry)));
+                if wide {
+                    entries.push(None);
+                }
+            }
+        }
+        Ok(ViewPool { entries })
+    }
+
+    /// Returns the entry at `idx`, if any.
+    #[inline]
+    pub fn get(&self, idx: u16) -> Option<&ViewEntry<'a>> {
+        self.entries.get(idx as usize).and_then(Option::as_ref)
+    }
+
+    /// Returns the raw entry at `idx` if it is not a string.
+    #[inline]
+    pub fn raw(&self, idx: u16) -> Option<&RawConstantEntry> {
+        match self.get(idx) {
+            Some(ViewEntry::Raw(r)) => Some(r),
+            _ => None
+        }
+    }
+
+    /// Returns the string at `idx`.
+    #[inline]
+    pub fn utf8(&self, idx: u16) -> Option<&Cow<'a, str>> {
+        match self.get(idx) {
+            Some(ViewEntry::UTF8(s)) => Some(s),
+            _ => None
+        }
+    }
+
+    /// Returns the name of the class at `idx`.
+    #[inline]
+    pub fn class(&self, idx: u16) -> Option<&Cow<'a, str>> {
+        match self.raw(idx) {
+            Some(RawConstantEntry::Class(n)) => self.utf8(*n),
+            _ => None
+        }
+    }
+
+    /// Returns the name and the descriptor at `idx`.
+    pub fn name_and_type(&self, idx: u16) -> Option<(&Cow<'a, str>, &Cow<'a, str>)> {
+        match self.raw(idx) {
+            Some(RawConstantEntry::NameAndType(n, t)) => Some((self.utf8(*n)?, self.utf8(*t)?)),
+            _ => None
+        }
+    }
+
+    /// Returns the field or method reference at `idx`.
+    pub fn member(&self, idx: u16) -> Option<MemberRefView<'a>> {
+        let (owner, nt, itfs) = match self.raw(idx) {
+            Some(RawConstantEntry::Field(o, nt)) | Some(RawConstantEntry::Method(o, nt)) => (*o, *nt, false),
+            Some(RawConstantEntry::InterfaceMethod(o, nt)) => (*o, *nt, true),
+            _ => return None
+        };
+        let (name, descriptor) = self.name_and_type(nt)?;
+        Some(MemberRefView {
+            owner: self.class(owner)?.clone(),
+            name: name.clone(),
+            descriptor: descriptor.clone(),
+            itfs
+        })
+    }
+
+    /// An iterator over the indices and entries of this pool.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &ViewEntry<'a>)> {
+        self.entries.iter().enumerate().filter_map(|(i, e)| e.as_ref().map(|e| (i as u16, e)))
+    }
+
+    fn utf8_or_err(&self, idx: u16) -> Result<Cow<'a, str>> {
+        self.utf8(idx).cloned().ok_or_else(|| Error::Invalid("constant pool entry index", idx.to_string().into()))
+    }
+
+    fn class_or_err(&self, idx: u16) -> Result<Cow<'a, str>> {
+        self.class(idx).cloned().ok_or_else(|| Error::Invalid("constant pool entry index", idx.to_string().into()))
+    }
+}
+
+/// A borrowed counterpart of [`MemberRef`] with the descriptor left unparsed.
+#[derive(Clone, Eq, PartialEq, Debug, Hash)]
+pub struct MemberRefView<'a> {
+    /// The owner of the member.
+    pub owner: Cow<'a, str>,
+    /// The name of the member.
+    pub name: Cow<'a, str>,
+    /// The descriptor of the member.
+    pub descriptor: Cow<'a, str>,
+    /// Whether the owner is an interface.
+    pub itfs: bool
+}
+
+/// An attribute whose content is left unparsed.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct AttributeView<'a> {
+    /// The name of the attribute.
+    pub name: Cow<'a, str>,
+    /// The content of the attribute, borrowed from the buffer.
+    pub data: &'a [u8]
+}
+
+impl<'a> AttributeView<'a> {
+    fn read(pool: &ViewPool<'a>, rd: &mut &'a [u8]) -> Result<Self> {
+        let name = pool.utf8_or_err(u16::read_from(rd)?)?;
+        let len = u32::read_from(rd)?;
+        Ok(AttributeView { name, data: take(rd, len as usize)? })
+    }
+}
+
+/// A borrowed field or method.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct MemberView<'a, F> {
+    /// The access flags of the member.
+    pub access: F,
+    /// The name of the member.
+    pub name: Cow<'a, str>,
+    /// The descriptor of the member, left unparsed.
+    pub descriptor: Cow<'a, str>,
+    /// The attributes of the member.
+    pub attributes: Vec<AttributeView<'a>>
+}
+
+/// A borrowed field.
+pub type FieldView<'a> = MemberView<'a, FieldFlags>;
+/// A borrowed method.
+pub type MethodView<'a> = MemberView<'a, MethodFlags>;
+
+impl<'a, F: ReadWrite> MemberView<'a, F> {
+    fn read(pool: &ViewPool<'a>, rd: &mut &'a [u8]) -> Result<Self> {
+        let access = F::read_from(rd)?;
+        let name = pool.utf8_or_err(u16::read_from(rd)?)?;
+        let descriptor = pool.utf8_or_err(u16::read_from(rd)?)?;
+        let attributes = read_vec(rd, |rd| AttributeView::read(pool, rd))?;
+        Ok(MemberView { access, name, descriptor, attributes })
+    }
+}
+
+/// A borrowed view of a class file.
+///
+/// Every string of the view is [`Cow::Borrowed`] from the parsed buffer when its modified UTF-8 encoding is plain ASCII,
+/// since both encodings are then the same bytes. Strings with other characters are decoded into [`Cow::Owned`] ones
+/// while parsing, so the view never outlives the buffer but may still allocate for non-ASCII names.
+#[derive(Clone, Debug)]
+pub struct ClassView<'a> {
+    bytes: &'a [u8],
+    pool: ViewPool<'a>,
+    /// The version of the class file.
+    pub version: JavaVersion,
+    /// The access flags of the class.
+    pub access: ClassFlags,
+    /// The internal name of the class.
+    pub name: Cow<'a, str>,
+    /// The internal name of the super class, `None` for `java/lang/Object` and module descriptors.
+    pub super_name: Option<Cow<'a, str>>,
+    /// The internal names of the directly implemented interfaces.
+    pub interfaces: Vec<Cow<'a, str>>,
+    /// The fields declared by the class.
+    pub fields: Vec<FieldView<'a>>,
+    /// The methods declared by the class.
+    pub methods: Vec<MethodView<'a>>,
+    /// The attributes of the class, left unparsed.
+    pub attributes: Vec<AttributeView<'a>>
+}
+
+impl<'a> ClassView<'a> {
+    /// Parses the class file in `bytes` without copying its strings.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        let mut rd = bytes;
+        let rd = &mut rd;
+        match u32::read_from(rd)? {
+            0xCAFEBABE => {}
+            n => return Err(Error::Invalid("class header", n.to_string().into()))
+        }
+        let version = JavaVersion::read_from(rd)?;
+        let pool = ViewPool::read(rd)?;
+        let access = ClassFlags::read_from(rd)?;
+        let name = pool.class_or_err(u16::read_from(rd)?)?;
+        let super_name = match u16::read_from(rd)? {
+            0 => None,
+            idx => Some(pool.class_or_err(idx)?)
+        };
+        let interfaces = read_vec(rd, |rd| pool.class_or_err(u16::read_from(rd)?))?;
+        let fields = read_vec(rd, |rd| MemberView::read(&pool, rd))?;
+        let methods = read_vec(rd, |rd| MemberView::read(&pool, rd))?;
+        let attributes = read_vec(rd, |rd| AttributeView::read(&pool, rd))?;
+        Ok(ClassView { bytes, pool, version, access, name, super_name, interfaces, fields, methods, attributes })
+    }
+
+    /// The constant pool of this class.
+    #[inline]
+    pub fn pool(&self) -> &ViewPool<'a> {
+        &self.pool
+    }
+
+    /// The buffer this view was parsed from.
+    #[inline]
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// An iterator over the names of all classes referenced from the constant pool, including this class.
+    pub fn class_refs<'s>(&'s self) -> impl Iterator<Item = &'s Cow<'a, str>> + 's {
+        self.pool.iter().filter_map(move |(_, e)| match e {
+            ViewEntry::Raw(RawConstantEntry::Class(n)) => self.pool.utf8(*n),
+            _ => None
+        })
+    }
+
+    /// An iterator over all field and method references in the constant pool.
+    pub fn member_refs<'s>(&'s self) -> impl Iterator<Item = MemberRefView<'a>> + 's {
+        self.pool.iter().filter_map(move |(i, _)| self.pool.member(i))
+    }
+
+    /// Reads the full, owned class.
+    pub fn to_class(&self) -> Result<Class> {
+        Class::read_from(&mut &*self.bytes)
+    }
+}
+
+fn take<'a>(rd: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if rd.len() < len {
+        return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into())
+    }
+    let (taken, rest) = rd.split_at(len);
+    *rd = rest;
+    Ok(taken)
+}
+
+fn read_vec<'a, T, F: FnMut(&mut &'a [u8]) -> Result<T>>(rd: &mut &'a [u8], mut f: F) -> Result<Vec<T>> {
+    let len = u16::read_from(rd)?;
+    let mut vec = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        vec.push(f(rd)?);
+    }
+    Ok(vec)
+}
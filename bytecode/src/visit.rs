@@ -0,0 +1,436 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! The walk over the references a class holds, shared by the cross reference index and the remapper.
+//!
+//! [`Visit`] walks a class by reference and [`VisitMut`] by mutable reference. Both are generated from the same walk, which
+//! visits descriptors, generic signatures, instructions, constants, bootstrap methods, annotations and the attributes of
+//! the class, and calls the hooks of the visitor on each reference to a class or a member.
+
+use std::collections::HashMap;
+
+use crate::annotation::{Annotation, AnnotationValue};
+use crate::prelude::*;
+use crate::signature::{ClassTypeSignature, RefTypeSignature, Throws, TypeArgument, TypeParameter, TypeSignature};
+use crate::xref::{Access, CallKind};
+use crate::Class;
+
+/// How a member is referred to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(crate) enum MemberUse {
+    /// A member constant, which is neither called nor accessed.
+    Constant,
+    /// A call of a method.
+    Call(CallKind),
+    /// A read or a write of a field.
+    Access(Access)
+}
+
+impl From<MethodHandleKind> for MemberUse {
+    fn from(kind: MethodHandleKind) -> Self {
+        match kind {
+            MethodHandleKind::GetField | MethodHandleKind::GetStatic => MemberUse::Access(Access::Read),
+            MethodHandleKind::PutField | MethodHandleKind::PutStatic => MemberUse::Access(Access::Write),
+            kind => MemberUse::Call(CallKind::Handle(kind))
+        }
+    }
+}
+
+/// The internal name of the class of a signature, its nested classes being separated by `$`.
+pub(crate) fn signature_name(sig: &ClassTypeSignature) -> String {
+    let mut name = sig.package.iter().map(|p| p.as_ref()).chain(std::iter::once(sig.name.name.as_ref())).collect::<Vec<_>>().join("/");
+    for suffix in &sig.suffix {
+        name.push('$');
+        name.push_str(&suffix.name);
+    }
+    name
+}
+
+macro_rules! visitor {
+    ($(#[$doc:meta])* $name: ident $(, $m: tt)?; $bsm: ident, $code: ident) => {
+        $(#[$doc])*
+        // names are passed as `Cow`s so that they can be kept without being copied.
+        #[allow(clippy::ptr_arg)]
+        pub(crate) trait $name: Sized {
+            /// Called on each reference to a class, by its internal name or by an array descriptor. The name of the
+            /// visited class is not visited.
+            fn name(&mut self, name: &$($m)? Cow<'static, str>);
+
+            /// Called on the class of a generic signature, before its type arguments are visited.
+            fn class_type_signature(&mut self, sig: &$($m)? ClassTypeSignature);
+
+            /// Called on each reference to a member, before its owner and descriptor are visited.
+            fn member(&mut self, _member: &$($m)? MemberRef, _usage: MemberUse) {}
+
+            /// Called on each string constant.
+            fn string(&mut self, _string: &$($m)? Cow<'static, str>) {}
+
+            /// Called on each dynamically computed call site or constant, before its descriptor and bootstrap method are visited.
+            fn dynamic(&mut self, _dynamic: &$($m)? Dynamic) {}
+
+            /// Called on the elements of each annotation, before its type and values are visited.
+            fn annotation(&mut self, _ty: &Type, _values: &$($m)? HashMap<Cow<'static, str>, AnnotationValue>) {}
+
+            /// Called on the name of each enum constant in an annotation, before its type is visited.
+            fn enum_constant(&mut self, _ty: &Type, _name: &$($m)? Cow<'static, str>) {}
+
+            /// Called with the index of the instruction being visited, and with `None` once the instructions are visited.
+            fn at(&mut self, _index: Option<usize>) {}
+
+            /// Called on each field of the visited class, before its descriptor and attributes are visited.
+            fn field(&mut self, _owner: &Cow<'static, str>, _name: &$($m)? Cow<'static, str>, _descriptor: &Type) {}
+
+            /// Called on each method of the visited class, before its descriptor, body and attributes are visited.
+            fn method(&mut self, _owner: &Cow<'static, str>, _name: &$($m)? Cow<'static, str>, _descriptor: &Type) {}
+
+            /// Called once the fields and methods are visited, before the attributes of the class.
+            fn members_visited(&mut self) {}
+
+            /// Called on the method enclosing a local or anonymous class, before its descriptor and class are visited.
+            fn enclosing_method(&mut self, _class: &str, _name: &$($m)? Cow<'static, str>, _descriptor: &Type) {}
+
+            /// Visits an entry of the inner classes attribute.
+            fn inner_class(&mut self, class: &$($m)? InnerClass) {
+                self.name(&$($m)? class.inner_fqname);
+                if let Some(o) = &$($m)? class.outer_fqname {
+                    self.name(o);
+                }
+            }
+
+            /// Visits the bootstrap methods attribute.
+            fn bootstrap_methods(&mut self, bsms: &$($m)? [BootstrapMethod]) {
+                for b in bsms {
+                    self.visit_bsm(b);
+                }
+            }
+
+            /// Called on the packages of a module.
+            fn module_packages(&mut self, _packages: &$($m)? Vec<Cow<'static, str>>) {}
+
+            fn visit_type(&mut self, ty: &$($m)? Type) {
+                match ty {
+                    Type::Ref(name) => self.name(name),
+                    Type::ArrayRef(_, inner) => self.visit_type(inner),
+                    Type::Method { parameters, ret } => {
+                        for p in parameters {
+                            self.visit_type(p);
+                        }
+                        if let Some(r) = ret {
+                            self.visit_type(r);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            fn visit_member(&mut self, member: &$($m)? MemberRef, usage: MemberUse) {
+                self.member(member, usage);
+                self.name(&$($m)? member.owner);
+                self.visit_type(&$($m)? member.descriptor);
+            }
+
+            fn visit_constant(&mut self, constant: &$($m)? Constant) {
+                match constant {
+                    Constant::String(s) => self.string(s),
+                    Constant::Class(name) => self.name(name),
+                    Constant::Member(m) => self.visit_member(m, MemberUse::Constant),
+                    Constant::MethodType(t) => self.visit_type(t),
+                    Constant::MethodHandle(h) => self.visit_member(&$($m)? h.member, h.kind.into()),
+                    _ => {}
+                }
+            }
+
+            fn visit_dynamic(&mut self, dynamic: &$($m)? Dynamic) {
+                self.dynamic(dynamic);
+                self.visit_type(&$($m)? dynamic.descriptor);
+                self.visit_bsm(dynamic.$bsm());
+            }
+
+            fn visit_bsm(&mut self, bsm: &$($m)? BootstrapMethod) {
+                self.visit_member(&$($m)? bsm.handle.member, MemberUse::Call(CallKind::Bootstrap));
+                for arg in &$($m)? bsm.arguments {
+                    self.visit_or_dynamic(arg, Self::visit_constant);
+                }
+            }
+
+            fn visit_or_dynamic<T>(&mut self, value: &$($m)? OrDynamic<T>, f: fn(&mut Self, &$($m)? T)) {
+                match value {
+                    OrDynamic::Dynamic(d) => self.visit_dynamic(d),
+                    OrDynamic::Static(t) => f(self, t)
+                }
+            }
+
+            fn visit_class_type(&mut self, ty: &$($m)? ClassType) {
+                match ty {
+                    ClassType::Object(name) => self.name(name),
+                    ClassType::Array(_, ty) => self.visit_type(ty)
+                }
+            }
+
+            fn visit_annotation_value(&mut self, value: &$($m)? AnnotationValue) {
+                match value {
+                    AnnotationValue::Enum(ty, name) => {
+                        self.enum_constant(ty, name);
+                        self.visit_type(ty);
+                    }
+                    AnnotationValue::Class(Some(ty)) => self.visit_type(ty),
+                    AnnotationValue::Annotation(a) => self.visit_annotation(&$($m)? a.annotation_type, &$($m)? a.element_values),
+                    AnnotationValue::Array(values) => {
+                        for v in values {
+                            self.visit_annotation_value(v);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            fn visit_annotation(&mut self, ty: &$($m)? Type, values: &$($m)? HashMap<Cow<'static, str>, AnnotationValue>) {
+                self.annotation(ty, values);
+                self.visit_type(ty);
+                for (_, v) in values {
+                    self.visit_annotation_value(v);
+                }
+            }
+
+            fn visit_annotations(&mut self, annotations: &$($m)? [Annotation]) {
+                for a in annotations {
+                    self.visit_annotation(&$($m)? a.annotation_type, &$($m)? a.element_values);
+                }
+            }
+
+            fn visit_class_type_signature(&mut self, sig: &$($m)? ClassTypeSignature) {
+                self.class_type_signature(sig);
+                for arg in &$($m)? sig.name.type_arguments {
+                    self.visit_type_argument(arg);
+                }
+                for s in &$($m)? sig.suffix {
+                    for arg in &$($m)? s.type_arguments {
+                        self.visit_type_argument(arg);
+                    }
+                }
+            }
+
+            fn visit_type_argument(&mut self, arg: &$($m)? TypeArgument) {
+                match arg {
+                    TypeArgument::Extends(t) | TypeArgument::Super(t) | TypeArgument::Exact(t) => self.visit_ref_type_signature(t),
+                    TypeArgument::Any => {}
+                }
+            }
+
+            fn visit_ref_type_signature(&mut self, sig: &$($m)? RefTypeSignature) {
+                match sig {
+                    RefTypeSignature::TypeVariable(_) => {}
+                    RefTypeSignature::ArrayRef(_, t) => self.visit_type_signature(t),
+                    RefTypeSignature::ClassType(c) => self.visit_class_type_signature(c)
+                }
+            }
+
+            fn visit_type_signature(&mut self, sig: &$($m)? TypeSignature) {
+                if let TypeSignature::Ref(r) = sig {
+                    self.visit_ref_type_signature(r);
+                }
+            }
+
+            fn visit_type_parameters(&mut self, params: &$($m)? [TypeParameter]) {
+                for p in params {
+                    if let Some(b) = &$($m)? p.class_bound {
+                        self.visit_ref_type_signature(b);
+                    }
+                    for b in &$($m)? p.interface_bounds {
+                        self.visit_ref_type_signature(b);
+                    }
+                }
+            }
+
+            fn visit_code(&mut self, code: &$($m)? Code) {
+                for (i, insn) in (&$($m)? code.code).into_iter().enumerate() {
+                    self.at(Some(i));
+                    match insn {
+                        Instruction::Push(c) => self.visit_or_dynamic(c, Self::visit_constant),
+                        Instruction::CheckCast(t) | Instruction::InstanceOf(t) => self.visit_or_dynamic(t, Self::visit_class_type),
+                        Instruction::NewArray(t, _) => self.visit_or_dynamic(t, Self::visit_type),
+                        Instruction::New(n) => self.visit_or_dynamic(n, Self::name),
+                        Instruction::Field(GetOrPut::Get, _, m) => self.visit_or_dynamic(m, |s, m| s.visit_member(m, MemberUse::Access(Access::Read))),
+                        Instruction::Field(GetOrPut::Put, _, m) => self.visit_or_dynamic(m, |s, m| s.visit_member(m, MemberUse::Access(Access::Write))),
+                        Instruction::InvokeExact(MemberType::Static, m) => self.visit_or_dynamic(m, |s, m| s.visit_member(m, MemberUse::Call(CallKind::Static))),
+                        Instruction::InvokeExact(MemberType::Virtual, m) => self.visit_or_dynamic(m, |s, m| s.visit_member(m, MemberUse::Call(CallKind::Virtual))),
+                        Instruction::InvokeSpecial(m) => self.visit_or_dynamic(m, |s, m| s.visit_member(m, MemberUse::Call(CallKind::Special))),
+                        Instruction::InvokeInterface(m, _) => self.visit_or_dynamic(m, |s, m| s.visit_member(m, MemberUse::Call(CallKind::Interface))),
+                        Instruction::InvokeDynamic(d) => self.visit_dynamic(d),
+                        _ => {}
+                    }
+                }
+                self.at(None);
+                for c in &$($m)? code.catches {
+                    if let Some(name) = &$($m)? c.catch {
+                        self.name(name);
+                    }
+                }
+                for attr in &$($m)? code.attrs {
+                    match attr {
+                        CodeAttribute::VisibleTypeAnnotations(a) | CodeAttribute::InvisibleTypeAnnotations(a) => {
+                            for a in a {
+                                self.visit_annotation(&$($m)? a.annotation_type, &$($m)? a.element_values);
+                            }
+                        }
+                        CodeAttribute::LocalVariables(vars) => {
+                            for v in vars {
+                                if let Some(t) = &$($m)? v.descriptor {
+                                    self.visit_type(t);
+                                }
+                                if let Some(s) = &$($m)? v.signature {
+                                    self.visit_ref_type_signature(&$($m)? s.0);
+                                }
+                            }
+                        }
+                        CodeAttribute::Raw(_) => {}
+                    }
+                }
+            }
+
+            /// Visits the references held by a class, other than its own name.
+            ///
+            /// This fails if a lazily read method body cannot be decoded.
+            fn visit_class(&mut self, class: &$($m)? Class) -> Result<()> {
+                let owner = class.name.clone();
+                for f in &$($m)? class.fields {
+                    self.field(&owner, &$($m)? f.name, &f.descriptor);
+                    self.visit_type(&$($m)? f.descriptor);
+                    for attr in &$($m)? f.attrs {
+                        match attr {
+                            FieldAttribute::Signature(s) => self.visit_ref_type_signature(&$($m)? s.0),
+                            FieldAttribute::ConstantValue(c) => self.visit_constant(c),
+                            FieldAttribute::RuntimeVisibleAnnotations(a) | FieldAttribute::RuntimeInvisibleAnnotations(a) => self.visit_annotations(a),
+                            FieldAttribute::RuntimeVisibleTypeAnnotations(a) | FieldAttribute::RuntimeInvisibleTypeAnnotations(a) => {
+                                for a in a {
+                                    self.visit_annotation(&$($m)? a.annotation_type, &$($m)? a.element_values);
+                                }
+                            }
+                            FieldAttribute::Deprecated | FieldAttribute::Synthetic | FieldAttribute::Raw(_) => {}
+                        }
+                    }
+                }
+                for m in &$($m)? class.methods {
+                    self.method(&owner, &$($m)? m.name, &m.descriptor);
+                    self.visit_type(&$($m)? m.descriptor);
+                    if let Some(code) = m.$code()? {
+                        self.visit_code(code);
+                    }
+                    for attr in &$($m)? m.attributes {
+                        match attr {
+                            // the body was visited above.
+                            MethodAttribute::Code(_) | MethodAttribute::LazyCode(_) => {}
+                            MethodAttribute::Signature(s) => {
+                                self.visit_type_parameters(&$($m)? s.type_parameters);
+                                for p in &$($m)? s.parameters {
+                                    self.visit_type_signature(p);
+                                }
+                                if let Some(r) = &$($m)? s.return_type {
+                                    self.visit_type_signature(r);
+                                }
+                                for t in &$($m)? s.throws {
+                                    if let Throws::Class(c) = t {
+                                        self.visit_class_type_signature(c);
+                                    }
+                                }
+                            }
+                            MethodAttribute::RuntimeVisibleAnnotations(a) | MethodAttribute::RuntimeInvisibleAnnotations(a) => self.visit_annotations(a),
+                            MethodAttribute::RuntimeVisibleTypeAnnotations(a) | MethodAttribute::RuntimeInvisibleTypeAnnotations(a) => {
+                                for a in a {
+                                    self.visit_annotation(&$($m)? a.annotation_type, &$($m)? a.element_values);
+                                }
+                            }
+                            MethodAttribute::RuntimeVisibleParameterAnnotations(p) | MethodAttribute::RuntimeInvisibleParameterAnnotations(p) => {
+                                for p in p {
+                                    self.visit_annotations(p);
+                                }
+                            }
+                            MethodAttribute::Exceptions(e) => {
+                                for e in e {
+                                    self.name(e);
+                                }
+                            }
+                            MethodAttribute::AnnotationDefault(v) => self.visit_annotation_value(v),
+                            MethodAttribute::Deprecated | MethodAttribute::Synthetic | MethodAttribute::MethodParameters(_) | MethodAttribute::Raw(_) => {}
+                        }
+                    }
+                }
+                self.members_visited();
+                for attr in &$($m)? class.attributes {
+                    match attr {
+                        ClassAttribute::Signature(s) => {
+                            self.visit_type_parameters(&$($m)? s.type_parameters);
+                            self.visit_class_type_signature(&$($m)? s.super_class);
+                            for c in &$($m)? s.interfaces {
+                                self.visit_class_type_signature(c);
+                            }
+                        }
+                        ClassAttribute::InnerClasses(inner) => {
+                            for i in inner {
+                                self.inner_class(i);
+                            }
+                        }
+                        ClassAttribute::EnclosingMethod(c, method) => {
+                            if let Some((name, ty)) = method {
+                                self.enclosing_method(c, name, ty);
+                                self.visit_type(ty);
+                            }
+                            self.name(c);
+                        }
+                        ClassAttribute::BootstrapMethods(bsms) => self.bootstrap_methods(bsms),
+                        ClassAttribute::RuntimeVisibleAnnotations(a) | ClassAttribute::RuntimeInvisibleAnnotations(a) => self.visit_annotations(a),
+                        ClassAttribute::ModuleMainClass(c) | ClassAttribute::NestHost(c) => self.name(c),
+                        ClassAttribute::NestMembers(c) => {
+                            for c in c {
+                                self.name(c);
+                            }
+                        }
+                        ClassAttribute::Module(module) => {
+                            for c in &$($m)? module.uses {
+                                self.name(c);
+                            }
+                            for p in &$($m)? module.provides {
+                                self.name(&$($m)? p.class);
+                                for c in &$($m)? p.with {
+                                    self.name(c);
+                                }
+                            }
+                        }
+                        ClassAttribute::ModulePackages(packages) => self.module_packages(packages),
+                        ClassAttribute::Synthetic | ClassAttribute::Deprecated | ClassAttribute::SourceFile(_) | ClassAttribute::SourceDebugExtension(_)
+                        | ClassAttribute::Raw(_) => {}
+                    }
+                }
+                if let Some(s) = &$($m)? class.super_name {
+                    self.name(s);
+                }
+                for i in &$($m)? class.interfaces {
+                    self.name(i);
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+visitor!(
+    /// Visits the references held by a class.
+    Visit; bsm, code
+);
+visitor!(
+    /// Visits the references held by a class, which the hooks may change.
+    VisitMut, mut; bsm_mut, code_mut
+);
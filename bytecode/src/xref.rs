@@ -0,0 +1,464 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Cross references between the classes of a library or an application.
+//!
+//! An [`Index`] is built from many classes and records the methods each method calls, the fields it reads and writes,
+//! the classes referred to from descriptors, signatures, annotations, instructions and attributes, and the string
+//! constants loaded. Calls are recorded from `invoke` instructions, from the bootstrap methods of `invokedynamic`
+//! instructions and the method handles passed to them, such as the implementation of a lambda, and from method handle
+//! constants.
+//!
+//! Calls and field accesses are recorded with the member as it is written in the instruction. Queries resolve them
+//! through the supertypes that are part of the index, so that a call to `b/Sub.run()V` is found as a caller of
+//! `a/Base.run()V` when `b/Sub` inherits it. [`Index::reachable`] follows virtual calls to the overriding methods of
+//! every subtype of the owner as well.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+use crate::prelude::*;
+use crate::signature::ClassTypeSignature;
+use crate::visit::{signature_name, MemberUse, Visit};
+use crate::Class;
+
+/// A field or a method, identified by its owner, name and descriptor.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct MemberId {
+    /// The class declaring or inheriting the member.
+    pub owner: Cow<'static, str>,
+    /// The name of the member.
+    pub name: Cow<'static, str>,
+    /// The descriptor of the member, a method type for methods.
+    pub descriptor: Type
+}
+
+impl MemberId {
+    /// Creates a member identifier.
+    pub fn new<O: Into<Cow<'static, str>>, N: Into<Cow<'static, str>>>(owner: O, name: N, descriptor: Type) -> Self {
+        Self { owner: owner.into(), name: name.into(), descriptor }
+    }
+
+    /// Whether this is a method.
+    pub fn is_method(&self) -> bool {
+        self.descriptor.is_method()
+    }
+
+    fn with_owner(&self, owner: &str) -> Self {
+        Self { owner: Cow::Owned(owner.to_owned()), name: self.name.clone(), descriptor: self.descriptor.clone() }
+    }
+}
+
+impl From<&MemberRef> for MemberId {
+    fn from(member: &MemberRef) -> Self {
+        Self { owner: member.owner.clone(), name: member.name.clone(), descriptor: member.descriptor.clone() }
+    }
+}
+
+impl Display for MemberId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_method() {
+            write!(f, "{}.{}{}", self.owner, self.name, self.descriptor)
+        } else {
+            write!(f, "{}.{}:{}", self.owner, self.name, self.descriptor)
+        }
+    }
+}
+
+/// Where a reference is made.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Site {
+    /// The class holding the reference.
+    pub class: Cow<'static, str>,
+    /// The field or method holding the reference, or `None` if the class itself or one of its attributes holds it.
+    pub member: Option<MemberId>,
+    /// The index of the instruction holding the reference, if it is made from the body of a method.
+    pub index: Option<usize>
+}
+
+impl Display for Site {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.member {
+            Some(m) => m.fmt(f)?,
+            None => f.write_str(&self.class)?
+        }
+        if let Some(i) = self.index {
+            write!(f, " [{}]", i)?;
+        }
+        Ok(())
+    }
+}
+
+/// How a method is called.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum CallKind {
+    /// `invokestatic`.
+    Static,
+    /// `invokevirtual`.
+    Virtual,
+    /// `invokespecial`, calling a constructor, a private method or the method of a supertype.
+    Special,
+    /// `invokeinterface`.
+    Interface,
+    /// The bootstrap method of an `invokedynamic` instruction or a dynamically computed constant.
+    Bootstrap,
+    /// A method handle constant, loaded or passed to a bootstrap method.
+    Handle(MethodHandleKind)
+}
+
+impl CallKind {
+    /// Whether the method that runs is selected from the class of the receiver, and may override the target.
+    pub fn is_dispatched(self) -> bool {
+        matches!(self, CallKind::Virtual | CallKind::Interface
+            | CallKind::Handle(MethodHandleKind::InvokeVirtual) | CallKind::Handle(MethodHandleKind::InvokeInterface))
+    }
+}
+
+/// A call from one method to another.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Call {
+    /// Where the call is made.
+    pub site: Site,
+    /// The method called, as written in the instruction or handle.
+    pub target: MemberId,
+    /// How the method is called.
+    pub kind: CallKind
+}
+
+/// Whether a field is read or written.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Access {
+    Read,
+    Write
+}
+
+/// A read or a write of a field.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FieldAccess {
+    /// Where the field is accessed.
+    pub site: Site,
+    /// The field, as written in the instruction or handle.
+    pub field: MemberId,
+    /// Whether the field is read or written.
+    pub access: Access
+}
+
+/// What the index keeps of a class to resolve members through the hierarchy.
+#[derive(Clone, Debug)]
+struct Declared {
+    supertypes: Vec<Cow<'static, str>>,
+    super_name: Option<Cow<'static, str>>,
+    methods: HashMap<(Cow<'static, str>, Type), MethodFlags>,
+    fields: HashSet<(Cow<'static, str>, Type)>
+}
+
+/// An index of the references between classes.
+#[derive(Clone, Debug, Default)]
+pub struct Index {
+    classes: HashMap<Cow<'static, str>, Declared>,
+    subtypes: HashMap<Cow<'static, str>, Vec<Cow<'static, str>>>,
+    calls: Vec<Call>,
+    callees: HashMap<MemberId, Vec<usize>>,
+    accesses: Vec<FieldAccess>,
+    accessed: HashMap<MemberId, Vec<usize>>,
+    references: HashMap<Cow<'static, str>, Vec<Site>>,
+    strings: HashMap<Cow<'static, str>, Vec<Site>>
+}
+
+impl Index {
+    /// Creates an empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the index of a set of classes.
+    ///
+    /// This fails if a lazily read method body cannot be decoded.
+    pub fn build<'a, I: IntoIterator<Item = &'a Class>>(classes: I) -> Result<Self> {
+        let mut index = Self::new();
+        for class in classes {
+            index.add(class)?;
+        }
+        Ok(index)
+    }
+
+    /// Adds the declarations and references of a class to the index.
+    ///
+    /// This fails if a lazily read method body cannot be decoded.
+    pub fn add(&mut self, class: &Class) -> Result<()> {
+        let supertypes = class.super_name.iter().chain(class.interfaces.iter()).cloned().collect::<Vec<_>>();
+        for s in &supertypes {
+            self.subtypes.entry(s.clone()).or_default().push(class.name.clone());
+        }
+        self.classes.insert(class.name.clone(), Declared {
+            supertypes,
+            super_name: class.super_name.clone(),
+            methods: class.methods.iter().map(|m| ((m.name.clone(), m.descriptor.clone()), m.access)).collect(),
+            fields: class.fields.iter().map(|f| (f.name.clone(), f.descriptor.clone())).collect()
+        });
+        Collector { index: self, site: Site { class: class.name.clone(), member: None, index: None } }.visit_class(class)
+    }
+
+    /// Whether a class is part of the index.
+    pub fn contains(&self, class: &str) -> bool {
+        self.classes.contains_key(class)
+    }
+
+    /// The names of the classes in the index.
+    pub fn classes(&self) -> impl Iterator<Item = &str> {
+        self.classes.keys().map(|c| c.as_ref())
+    }
+
+    /// Whether a class of the index declares a member, without looking at its supertypes.
+    pub fn declares(&self, member: &MemberId) -> bool {
+        self.classes.get(member.owner.as_ref()).is_some_and(|c| {
+            let key = (member.name.clone(), member.descriptor.clone());
+            if member.is_method() { c.methods.contains_key(&key) } else { c.fields.contains(&key) }
+        })
+    }
+
+    /// The access flags of a method declared in the index.
+    pub fn method_flags(&self, method: &MemberId) -> Option<MethodFlags> {
+        self.classes.get(method.owner.as_ref())?.methods.get(&(method.name.clone(), method.descriptor.clone())).copied()
+    }
+
+    /// Finds the declaration of a member in its owner or the supertypes of its owner, the superclasses being searched
+    /// before the interfaces. Returns `None` if it is not declared in the index.
+    pub fn resolve(&self, member: &MemberId) -> Option<MemberId> {
+        let mut seen = HashSet::new();
+        let mut queue = vec![member.owner.as_ref()];
+        while let Some(o) = queue.pop() {
+            if !seen.insert(o) {
+                continue
+            }
+            let candidate = member.with_owner(o);
+            if self.declares(&candidate) {
+                return Some(candidate)
+            }
+            if let Some(c) = self.classes.get(o) {
+                queue.extend(c.supertypes.iter().rev().map(|s| s.as_ref()));
+            }
+        }
+        None
+    }
+
+    /// The classes of the index that extend or implement a class, directly or not.
+    pub fn subtypes(&self, class: &str) -> Vec<&str> {
+        let mut seen = HashSet::new();
+        let mut queue = vec![class];
+        let mut found = vec![];
+        while let Some(c) = queue.pop() {
+            for s in self.subtypes.get(c).into_iter().flatten() {
+                if seen.insert(s.as_ref()) {
+                    found.push(s.as_ref());
+                    queue.push(s);
+                }
+            }
+        }
+        found
+    }
+
+    /// All the calls in the index.
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    /// The calls made by a method.
+    pub fn callees(&self, method: &MemberId) -> impl Iterator<Item = &Call> {
+        self.callees.get(method).into_iter().flatten().map(move |&i| &self.calls[i])
+    }
+
+    /// The calls whose target resolves to a method, not counting calls to the methods it overrides.
+    pub fn callers<'a>(&'a self, method: &'a MemberId) -> impl Iterator<Item = &'a Call> {
+        self.calls.iter().filter(move |c| self.refers_to(&c.target, method))
+    }
+
+    /// The methods a call may run: the method its target resolves to, and for a dispatched call the methods of the
+    /// subtypes of the owner of the target that override it. Only methods declared in the index are returned.
+    pub fn dispatch(&self, call: &Call) -> Vec<MemberId> {
+        let mut found = self.resolve(&call.target).into_iter().collect::<Vec<_>>();
+        if call.kind.is_dispatched() {
+            for s in self.subtypes(&call.target.owner) {
+                if let Some(m) = self.resolve(&call.target.with_owner(s)) {
+                    let overrides = self.method_flags(&m).is_some_and(|f| !f.intersects(MethodFlags::ACC_STATIC | MethodFlags::ACC_PRIVATE));
+                    if overrides && !found.contains(&m) {
+                        found.push(m);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// All the field accesses in the index.
+    pub fn accesses(&self) -> &[FieldAccess] {
+        &self.accesses
+    }
+
+    /// The field accesses made by a method or a field.
+    pub fn accessed(&self, member: &MemberId) -> impl Iterator<Item = &FieldAccess> {
+        self.accessed.get(member).into_iter().flatten().map(move |&i| &self.accesses[i])
+    }
+
+    /// The reads of a field.
+    pub fn readers<'a>(&'a self, field: &'a MemberId) -> impl Iterator<Item = &'a FieldAccess> {
+        self.accesses.iter().filter(move |a| a.access == Access::Read && self.refers_to(&a.field, field))
+    }
+
+    /// The writes of a field.
+    pub fn writers<'a>(&'a self, field: &'a MemberId) -> impl Iterator<Item = &'a FieldAccess> {
+        self.accesses.iter().filter(move |a| a.access == Access::Write && self.refers_to(&a.field, field))
+    }
+
+    /// Where a class is referred to, other than in its own name.
+    pub fn references(&self, class: &str) -> &[Site] {
+        self.references.get(class).map_or(&[], |s| s.as_slice())
+    }
+
+    /// The names of the classes referred to from the index, including those outside of it.
+    pub fn referenced_classes(&self) -> impl Iterator<Item = &str> {
+        self.references.keys().map(|c| c.as_ref())
+    }
+
+    /// Where a string constant is loaded or used as the value of a field.
+    pub fn string_uses(&self, string: &str) -> &[Site] {
+        self.strings.get(string).map_or(&[], |s| s.as_slice())
+    }
+
+    /// The string constants in the index.
+    pub fn strings(&self) -> impl Iterator<Item = &str> {
+        self.strings.keys().map(|s| s.as_ref())
+    }
+
+    /// The methods of the index that can run when the entry points are called.
+    ///
+    /// Calls are followed with [`dispatch`](Self::dispatch), and running a method or accessing a static field runs
+    /// the static initializers of its class and its superclasses. Methods only called from outside the index, such as
+    /// the overrides of library methods, are only reached if they are entry points.
+    pub fn reachable<I: IntoIterator<Item = MemberId>>(&self, entries: I) -> HashSet<MemberId> {
+        let mut reached = HashSet::new();
+        // the methods a call may run only depend on its target and whether it is dispatched.
+        let mut followed = HashSet::new();
+        let mut queue = entries.into_iter().filter(|m| self.declares(m)).collect::<Vec<_>>();
+        while let Some(m) = queue.pop() {
+            if reached.contains(&m) {
+                continue
+            }
+            queue.extend(self.initializers(&m.owner));
+            for call in self.callees(&m) {
+                if followed.insert((&call.target, call.kind.is_dispatched())) {
+                    queue.extend(self.dispatch(call));
+                }
+            }
+            for access in self.accessed(&m) {
+                if let Some(f) = self.resolve(&access.field) {
+                    queue.extend(self.initializers(&f.owner));
+                }
+            }
+            reached.insert(m);
+        }
+        reached
+    }
+
+    /// The static initializers of a class and its superclasses.
+    fn initializers(&self, class: &str) -> Vec<MemberId> {
+        let mut found = vec![];
+        let mut seen = HashSet::new();
+        let mut next = Some(class);
+        while let Some(c) = next.filter(|c| seen.insert(*c)) {
+            let clinit = MemberId::new(c.to_owned(), "<clinit>", Type::method([], None));
+            if self.declares(&clinit) {
+                found.push(clinit);
+            }
+            next = self.classes.get(c).and_then(|d| d.super_name.as_deref());
+        }
+        found
+    }
+
+    fn refers_to(&self, reference: &MemberId, member: &MemberId) -> bool {
+        reference == member || reference.name == member.name && reference.descriptor == member.descriptor
+            && self.resolve(reference).as_ref() == Some(member)
+    }
+}
+
+/// Records the references made from a class into the index.
+struct Collector<'a> {
+    index: &'a mut Index,
+    site: Site
+}
+
+impl Collector<'_> {
+    fn record(&mut self, name: &str) {
+        if name.starts_with('[') {
+            if let Ok(ty) = name.parse::<Type>() {
+                self.visit_type(&ty);
+            }
+            return
+        }
+        self.index.references.entry(Cow::Owned(name.to_owned())).or_default().push(self.site.clone());
+    }
+}
+
+impl Visit for Collector<'_> {
+    fn name(&mut self, name: &Cow<'static, str>) {
+        self.record(name);
+    }
+
+    fn class_type_signature(&mut self, sig: &ClassTypeSignature) {
+        self.record(&signature_name(sig));
+    }
+
+    fn member(&mut self, member: &MemberRef, usage: MemberUse) {
+        match usage {
+            MemberUse::Constant => {}
+            MemberUse::Call(kind) => {
+                if let Some(caller) = self.site.member.clone().filter(|m| m.is_method()) {
+                    self.index.callees.entry(caller).or_default().push(self.index.calls.len());
+                }
+                self.index.calls.push(Call { site: self.site.clone(), target: member.into(), kind });
+            }
+            MemberUse::Access(access) => {
+                if let Some(m) = self.site.member.clone() {
+                    self.index.accessed.entry(m).or_default().push(self.index.accesses.len());
+                }
+                self.index.accesses.push(FieldAccess { site: self.site.clone(), field: member.into(), access });
+            }
+        }
+    }
+
+    fn string(&mut self, string: &Cow<'static, str>) {
+        self.index.strings.entry(string.clone()).or_default().push(self.site.clone());
+    }
+
+    fn at(&mut self, index: Option<usize>) {
+        self.site.index = index;
+    }
+
+    fn field(&mut self, owner: &Cow<'static, str>, name: &Cow<'static, str>, descriptor: &Type) {
+        self.site.member = Some(MemberId::new(owner.clone(), name.clone(), descriptor.clone()));
+    }
+
+    fn method(&mut self, owner: &Cow<'static, str>, name: &Cow<'static, str>, descriptor: &Type) {
+        self.site.member = Some(MemberId::new(owner.clone(), name.clone(), descriptor.clone()));
+    }
+
+    fn members_visited(&mut self) {
+        self.site.member = None;
+    }
+
+    // bootstrap methods are recorded from the instructions that use them.
+    fn bootstrap_methods(&mut self, _bsms: &[BootstrapMethod]) {}
+}
@@ -0,0 +1,204 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! A listing of classes similar to the output of `javap`.
+
+use std::fmt::{Display, Formatter, Result};
+
+use coffer::prelude::*;
+use coffer::Class;
+
+/// Lists the declarations of a class, and the bodies of its methods if `code` is set.
+pub struct Dump<'a> {
+    pub class: &'a Class,
+    pub code: bool
+}
+
+/// The name of a type as written in Java source.
+fn java_type(ty: &Type) -> String {
+    match ty {
+        Type::Byte => "byte".into(),
+        Type::Char => "char".into(),
+        Type::Double => "double".into(),
+        Type::Float => "float".into(),
+        Type::Int => "int".into(),
+        Type::Long => "long".into(),
+        Type::Boolean => "boolean".into(),
+        Type::Short => "short".into(),
+        Type::Ref(name) => name.replace('/', "."),
+        Type::ArrayRef(dim, ty) => format!("{}{}", java_type(ty), "[]".repeat(*dim as usize)),
+        Type::Method { .. } => ty.to_string()
+    }
+}
+
+/// Java modifiers of the flags that have one, in the order javac writes them.
+fn modifiers(bits: u16, keywords: &[(u16, &str)]) -> String {
+    keywords.iter().filter(|(b, _)| bits & b != 0).map(|(_, k)| format!("{} ", k)).collect()
+}
+
+const PUBLIC: u16 = 0x0001;
+const PRIVATE: u16 = 0x0002;
+const PROTECTED: u16 = 0x0004;
+const STATIC: u16 = 0x0008;
+const FINAL: u16 = 0x0010;
+
+impl Display for Dump<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let class = self.class;
+        let access = class.access;
+        let kind = if access.contains(ClassFlags::ACC_ANNOTATION) {
+            "@interface"
+        } else if access.contains(ClassFlags::ACC_INTERFACE) {
+            "interface"
+        } else if access.contains(ClassFlags::ACC_ENUM) {
+            "enum"
+        } else if access.contains(ClassFlags::ACC_MODULE) {
+            "module"
+        } else {
+            "class"
+        };
+        let keywords: &[(u16, &str)] = if access.contains(ClassFlags::ACC_INTERFACE) {
+            &[(PUBLIC, "public")]
+        } else {
+            &[(PUBLIC, "public"), (FINAL, "final"), (ClassFlags::ACC_ABSTRACT.bits(), "abstract")]
+        };
+        for a in &class.attributes {
+            if let ClassAttribute::SourceFile(s) = a {
+                writeln!(f, "Compiled from \"{}\"", s)?;
+            }
+        }
+        write!(f, "{}{} {}", modifiers(access.bits(), keywords), kind, class.name.replace('/', "."))?;
+        if let Some(s) = &class.super_name {
+            if s != "java/lang/Object" {
+                write!(f, " extends {}", s.replace('/', "."))?;
+            }
+        }
+        if !class.interfaces.is_empty() {
+            let interfaces = class.interfaces.iter().map(|i| i.replace('/', ".")).collect::<Vec<_>>().join(", ");
+            let keyword = if access.contains(ClassFlags::ACC_INTERFACE) { "extends" } else { "implements" };
+            write!(f, " {} {}", keyword, interfaces)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  version: {}", class.version)?;
+        writeln!(f, "  flags: (0x{:04x}) {:?}", access.bits(), access)?;
+        writeln!(f, "{{")?;
+        for field in &class.fields {
+            let keywords = [(PUBLIC, "public"), (PRIVATE, "private"), (PROTECTED, "protected"), (STATIC, "static"), (FINAL, "final"),
+                (FieldFlags::ACC_VOLATILE.bits(), "volatile"), (FieldFlags::ACC_TRANSIENT.bits(), "transient")];
+            writeln!(f, "  {}{} {};", modifiers(field.access.bits(), &keywords), java_type(&field.descriptor), field.name)?;
+            writeln!(f, "    descriptor: {}", field.descriptor)?;
+            writeln!(f, "    flags: (0x{:04x}) {:?}", field.access.bits(), field.access)?;
+            writeln!(f)?;
+        }
+        for method in &class.methods {
+            let keywords = [(PUBLIC, "public"), (PRIVATE, "private"), (PROTECTED, "protected"), (STATIC, "static"), (FINAL, "final"),
+                (MethodFlags::ACC_SYNCHRONIZED.bits(), "synchronized"), (MethodFlags::ACC_NATIVE.bits(), "native"),
+                (MethodFlags::ACC_ABSTRACT.bits(), "abstract")];
+            write!(f, "  {}", modifiers(method.access.bits(), &keywords))?;
+            if let Type::Method { parameters, ret } = &method.descriptor {
+                let parameters = parameters.iter().map(java_type).collect::<Vec<_>>().join(", ");
+                match method.name.as_ref() {
+                    "<clinit>" => write!(f, "{{}}")?,
+                    "<init>" => write!(f, "{}({})", class.name.replace('/', "."), parameters)?,
+                    name => write!(f, "{} {}({})", ret.as_deref().map_or_else(|| "void".into(), java_type), name, parameters)?
+                }
+            }
+            writeln!(f, ";")?;
+            writeln!(f, "    descriptor: {}", method.descriptor)?;
+            writeln!(f, "    flags: (0x{:04x}) {:?}", method.access.bits(), method.access)?;
+            for a in &method.attributes {
+                match a {
+                    MethodAttribute::Code(code) if self.code => write_code(f, code)?,
+                    MethodAttribute::Exceptions(e) => {
+                        writeln!(f, "    throws {}", e.iter().map(|e| e.replace('/', ".")).collect::<Vec<_>>().join(", "))?
+                    }
+                    _ => {}
+                }
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+fn write_code(f: &mut Formatter<'_>, code: &Code) -> Result {
+    writeln!(f, "    Code:")?;
+    writeln!(f, "      stack={}, locals={}", code.max_stack, code.max_locals)?;
+    for insn in &code.code {
+        match insn {
+            Instruction::Label(l) => writeln!(f, "     L{}:", l.0)?,
+            Instruction::LineNumber(n) => writeln!(f, "        // line {}", n)?,
+            insn => {
+                write!(f, "        ")?;
+                write_insn(f, insn)?;
+                writeln!(f)?
+            }
+        }
+    }
+    if !code.catches.is_empty() {
+        writeln!(f, "    Exception table:")?;
+        for c in &code.catches {
+            let ty = c.catch.as_deref().map_or_else(|| "any".into(), |c| c.replace('/', "."));
+            writeln!(f, "      L{} to L{} target L{} type {}", c.start.0, c.end.0, c.handler.0, ty)?;
+        }
+    }
+    Ok(())
+}
+
+fn member(m: &OrDynamic<MemberRef>) -> String {
+    match m {
+        OrDynamic::Static(m) => format!("{}.{}:{}", m.owner, m.name, m.descriptor),
+        OrDynamic::Dynamic(d) => format!("dynamic {}:{}", d.name, d.descriptor)
+    }
+}
+
+fn constant(c: &OrDynamic<Constant>) -> String {
+    match c {
+        OrDynamic::Static(Constant::String(s)) => format!("{:?}", s),
+        OrDynamic::Static(Constant::Class(c)) => format!("class {}", c),
+        OrDynamic::Static(Constant::Member(m)) => member(&OrDynamic::Static(m.clone())),
+        OrDynamic::Static(Constant::MethodType(t)) => format!("methodtype {}", t),
+        OrDynamic::Static(Constant::MethodHandle(h)) => format!("methodhandle {:?} {}", h.kind, member(&OrDynamic::Static(h.member.clone()))),
+        OrDynamic::Static(c) => format!("{:?}", c),
+        OrDynamic::Dynamic(d) => format!("dynamic {}:{}", d.name, d.descriptor)
+    }
+}
+
+/// Writes the instructions that refer to the constant pool in a short form, and the others as they are debugged.
+fn write_insn(f: &mut Formatter<'_>, insn: &Instruction) -> Result {
+    match insn {
+        Instruction::Push(c) => write!(f, "push {}", constant(c)),
+        Instruction::Field(op, kind, m) => {
+            let op = match (op, kind) {
+                (GetOrPut::Get, MemberType::Static) => "getstatic",
+                (GetOrPut::Put, MemberType::Static) => "putstatic",
+                (GetOrPut::Get, MemberType::Virtual) => "getfield",
+                (GetOrPut::Put, MemberType::Virtual) => "putfield"
+            };
+            write!(f, "{} {}", op, member(m))
+        }
+        Instruction::InvokeExact(MemberType::Static, m) => write!(f, "invokestatic {}", member(m)),
+        Instruction::InvokeExact(MemberType::Virtual, m) => write!(f, "invokevirtual {}", member(m)),
+        Instruction::InvokeSpecial(m) => write!(f, "invokespecial {}", member(m)),
+        Instruction::InvokeInterface(m, _) => write!(f, "invokeinterface {}", member(m)),
+        Instruction::InvokeDynamic(d) => write!(f, "invokedynamic {}:{} bootstrap {}", d.name, d.descriptor, member(&OrDynamic::Static(d.bsm().handle.member.clone()))),
+        Instruction::New(OrDynamic::Static(c)) => write!(f, "new {}", c),
+        Instruction::CheckCast(OrDynamic::Static(c)) => write!(f, "checkcast {}", Cow::from(c.clone())),
+        Instruction::InstanceOf(OrDynamic::Static(c)) => write!(f, "instanceof {}", Cow::from(c.clone())),
+        Instruction::Jump(cond, l) => write!(f, "jump {:?} L{}", cond, l.0),
+        insn => write!(f, "{:?}", insn)
+    }
+}
@@ -0,0 +1,119 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Reading and writing of class files and jars.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use coffer::prelude::*;
+use coffer::Class;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::Result;
+
+/// A file of the input, with its path in the jar or its file name.
+pub struct Entry {
+    pub name: String,
+    pub bytes: Vec<u8>
+}
+
+impl Entry {
+    pub fn is_class(&self) -> bool {
+        self.name.ends_with(".class")
+    }
+
+    /// Reads the class in this entry, prefixing errors with the name of the entry.
+    pub fn read(&self) -> Result<Class> {
+        Class::read_from(&mut self.bytes.as_slice()).map_err(|e| format!("{}: {}", self.name, e).into())
+    }
+}
+
+/// A single class file, or the entries of a jar.
+pub struct Input {
+    pub entries: Vec<Entry>,
+    pub jar: bool
+}
+
+fn is_jar(path: &Path) -> bool {
+    matches!(path.extension().and_then(|e| e.to_str()), Some("jar") | Some("zip"))
+}
+
+impl Input {
+    /// Opens a class file, or a jar if the path ends with `.jar` or `.zip`.
+    pub fn open(path: &Path) -> Result<Self> {
+        if !is_jar(path) {
+            let name = path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned());
+            return Ok(Input { entries: vec![Entry { name, bytes: std::fs::read(path)? }], jar: false })
+        }
+        let mut archive = ZipArchive::new(File::open(path)?)?;
+        let mut entries = Vec::with_capacity(archive.len());
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            if file.is_dir() {
+                continue
+            }
+            // the size in the header is not trusted, the entry may be much smaller.
+            let mut bytes = vec![];
+            file.read_to_end(&mut bytes)?;
+            entries.push(Entry { name: file.name().to_owned(), bytes });
+        }
+        Ok(Input { entries, jar: true })
+    }
+
+    /// The entries holding classes. A single class file is always considered a class.
+    pub fn classes(&self) -> impl Iterator<Item = &Entry> {
+        let jar = self.jar;
+        self.entries.iter().filter(move |e| !jar || e.is_class())
+    }
+
+    /// The `Main-Class` of the manifest of a jar.
+    pub fn main_class(&self) -> Option<String> {
+        let manifest = self.entries.iter().find(|e| e.name == "META-INF/MANIFEST.MF")?;
+        String::from_utf8_lossy(&manifest.bytes).lines().find_map(|l| l.strip_prefix("Main-Class:")).map(|c| c.trim().to_owned())
+    }
+
+    /// The service providers listed in `META-INF/services`, by binary name.
+    pub fn providers(&self) -> Vec<String> {
+        self.entries.iter().filter(|e| e.name.starts_with("META-INF/services/"))
+            .flat_map(|e| String::from_utf8_lossy(&e.bytes).lines().map(|l| l.split('#').next().unwrap_or_default().trim().to_owned()).collect::<Vec<_>>())
+            .filter(|p| !p.is_empty())
+            .collect()
+    }
+
+    /// Writes the entries to a class file, or to a jar if this was read from one.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if !self.jar {
+            return Ok(std::fs::write(path, &self.entries[0].bytes)?)
+        }
+        let mut zip = ZipWriter::new(File::create(path)?);
+        for e in &self.entries {
+            zip.start_file(e.name.as_str(), FileOptions::default())?;
+            zip.write_all(&e.bytes)?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+/// Writes a class to bytes.
+pub fn to_bytes(class: &Class) -> coffer::Result<Vec<u8>> {
+    let mut buf = vec![];
+    class.write_to(&mut buf)?;
+    Ok(buf)
+}
@@ -0,0 +1,321 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! The `coffer` command-line tool, to inspect and transform classes and jars from a shell.
+
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use coffer::coverage;
+use coffer::prelude::*;
+use coffer::keep::Keep;
+use coffer::remap::Remapper;
+use coffer::shrink::Shrinker;
+use coffer::strip::{StripFlags, Stripper};
+use coffer::Class;
+
+use crate::input::{to_bytes, Input};
+
+mod dump;
+mod input;
+mod stats;
+#[cfg(test)]
+mod tests;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Parser)]
+#[command(name = "coffer", version, about = "Inspects and transforms Java classes and jars")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command
+}
+
+/// Every command takes a class file, or a jar if the path ends with `.jar` or `.zip`.
+#[derive(Subcommand)]
+enum Command {
+    /// Prints the classes in a listing similar to javap.
+    Dump {
+        input: PathBuf,
+        /// Prints the instructions of methods as well.
+        #[arg(short = 'c', long)]
+        code: bool
+    },
+    /// Prints the classes as JSON.
+    Json {
+        input: PathBuf,
+        /// Prints everything on one line.
+        #[arg(long)]
+        compact: bool
+    },
+    /// Removes debugging information.
+    Strip {
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Debugging information to keep.
+        #[arg(long, value_enum)]
+        keep: Vec<Category>
+    },
+    /// Renames classes and members with mappings in the compact SRG format.
+    Remap {
+        input: PathBuf,
+        mappings: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf
+    },
+    /// Checks that classes can be read and follow the rules of the class file format.
+    Verify {
+        input: PathBuf
+    },
+    /// Counts constant pool entries and the sizes of attributes.
+    Stats {
+        input: PathBuf
+    },
+    /// Inserts coverage probes and writes the probe map as tab-separated lines.
+    Coverage {
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Where to write the probe map.
+        #[arg(long)]
+        probes: PathBuf
+    },
+    /// Removes the classes and members that cannot be reached from the main classes, keep rules and service providers.
+    Shrink {
+        input: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+        /// A class whose `main` method is kept, in addition to the `Main-Class` of the manifest.
+        #[arg(long)]
+        main: Vec<String>,
+        /// A keep rule, as `CLASS [MEMBER] [@ANNOTATION] [FLAG ...]`.
+        #[arg(long)]
+        keep: Vec<String>
+    },
+    /// Reads classes, writes them back and compares the bytes.
+    Roundtrip {
+        input: PathBuf,
+        /// Leaves method bodies undecoded, which copies them as they are.
+        #[arg(long)]
+        lazy: bool
+    }
+}
+
+/// A category of debugging information, see [`StripFlags`].
+#[derive(Copy, Clone, ValueEnum)]
+enum Category {
+    LineNumbers,
+    LocalVariables,
+    SourceFile,
+    SourceDebugExtension,
+    MethodParameters,
+    InvisibleAnnotations,
+    Signatures
+}
+
+impl From<Category> for StripFlags {
+    fn from(c: Category) -> Self {
+        match c {
+            Category::LineNumbers => StripFlags::LINE_NUMBERS,
+            Category::LocalVariables => StripFlags::LOCAL_VARIABLES,
+            Category::SourceFile => StripFlags::SOURCE_FILE,
+            Category::SourceDebugExtension => StripFlags::SOURCE_DEBUG_EXTENSION,
+            Category::MethodParameters => StripFlags::METHOD_PARAMETERS,
+            Category::InvisibleAnnotations => StripFlags::INVISIBLE_ANNOTATIONS,
+            Category::Signatures => StripFlags::SIGNATURES
+        }
+    }
+}
+
+fn main() {
+    match run(Cli::parse().command) {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            exit(2)
+        }
+    }
+}
+
+/// Runs a command, returning `false` if it found problems in the input.
+fn run(command: Command) -> Result<bool> {
+    match command {
+        Command::Dump { input, code } => {
+            let input = Input::open(&input)?;
+            for e in input.classes() {
+                print!("{}", dump::Dump { class: &e.read()?, code });
+            }
+        }
+        Command::Json { input, compact } => {
+            let input = Input::open(&input)?;
+            let classes = input.classes().map(|e| e.read()).collect::<Result<Vec<_>>>()?;
+            let out = std::io::stdout();
+            let out = out.lock();
+            match (input.jar, compact) {
+                (true, true) => serde_json::to_writer(out, &classes)?,
+                (true, false) => serde_json::to_writer_pretty(out, &classes)?,
+                (false, true) => serde_json::to_writer(out, &classes[0])?,
+                (false, false) => serde_json::to_writer_pretty(out, &classes[0])?
+            }
+            println!();
+        }
+        Command::Strip { input, output, keep } => {
+            let mut input = Input::open(&input)?;
+            let stripper = Stripper::new(keep.into_iter().fold(StripFlags::all(), |f, c| f - c.into()));
+            let (mut before, mut after) = (0, 0);
+            let jar = input.jar;
+            for e in input.entries.iter_mut().filter(|e| !jar || e.is_class()) {
+                let mut class = e.read()?;
+                let report = stripper.strip(&mut class).map_err(|err| format!("{}: {}", e.name, err))?;
+                before += report.before;
+                after += report.after;
+                e.bytes = to_bytes(&class)?;
+            }
+            input.write(&output)?;
+            println!("{} bytes -> {} bytes, saved {}", before, after, before.saturating_sub(after));
+        }
+        Command::Remap { input, mappings, output } => {
+            let mut input = Input::open(&input)?;
+            let remapper = Remapper::from_csrg(&std::fs::read_to_string(mappings)?)?;
+            let jar = input.jar;
+            let (indices, mut classes): (Vec<_>, Vec<_>) = input.entries.iter().enumerate()
+                .filter(|(_, e)| !jar || e.is_class())
+                .map(|(i, e)| e.read().map(|c| (i, c)))
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .unzip();
+            remapper.remap_all(&mut classes)?;
+            for (i, class) in indices.into_iter().zip(&classes) {
+                let e = &mut input.entries[i];
+                e.bytes = to_bytes(class)?;
+                if jar {
+                    // classes are stored under their internal name in jars.
+                    e.name = format!("{}.class", class.name);
+                }
+            }
+            input.write(&output)?;
+        }
+        Command::Verify { input } => {
+            let input = Input::open(&input)?;
+            let mut ok = true;
+            for e in input.classes() {
+                match Class::read_lenient(&mut e.bytes.as_slice()) {
+                    Ok((class, warnings)) => {
+                        for w in warnings {
+                            println!("{}: {}", e.name, w);
+                        }
+                        for v in class.validate() {
+                            println!("{}: {}", e.name, v);
+                            ok = false;
+                        }
+                    }
+                    Err(err) => {
+                        println!("{}: {}", e.name, err);
+                        ok = false;
+                    }
+                }
+            }
+            return Ok(ok)
+        }
+        Command::Stats { input } => {
+            let input = Input::open(&input)?;
+            let mut stats = stats::Stats::default();
+            for e in input.classes() {
+                stats.add(&e.bytes).map_err(|err| format!("{}: {}", e.name, err))?;
+            }
+            print!("{}", stats);
+        }
+        Command::Coverage { input, output, probes } => {
+            let mut input = Input::open(&input)?;
+            let jar = input.jar;
+            let mut map = String::new();
+            let mut count = 0;
+            for e in input.entries.iter_mut().filter(|e| !jar || e.is_class()) {
+                let mut class = e.read()?;
+                let class_probes = coverage::instrument_class(&mut class).map_err(|err| format!("{}: {}", e.name, err))?;
+                count += class_probes.probes.len();
+                map.push_str(&class_probes.to_string());
+                e.bytes = to_bytes(&class)?;
+            }
+            input.write(&output)?;
+            std::fs::write(probes, map)?;
+            println!("{} probes", count);
+        }
+        Command::Shrink { input, output, main, keep } => {
+            let mut input = Input::open(&input)?;
+            let mut shrinker = main.into_iter().chain(input.main_class()).fold(Shrinker::new(), |s, m| s.main_class(m));
+            shrinker = input.providers().into_iter().fold(shrinker, |s, p| s.provider(p));
+            for rule in keep {
+                shrinker = shrinker.keep(rule.parse::<Keep>()?);
+            }
+            let jar = input.jar;
+            let (indices, mut classes): (Vec<_>, Vec<_>) = input.entries.iter().enumerate()
+                .filter(|(_, e)| !jar || e.is_class())
+                .map(|(i, e)| e.read().map(|c| (i, c)))
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .unzip();
+            let names = classes.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+            let report = shrinker.shrink(&mut classes)?;
+            // the classes that are kept are in their original order.
+            let mut kept = classes.iter().peekable();
+            let mut removed = vec![];
+            for (i, name) in indices.into_iter().zip(names) {
+                match kept.next_if(|c| c.name == name) {
+                    Some(class) => input.entries[i].bytes = to_bytes(class)?,
+                    None => removed.push(i)
+                }
+            }
+            let mut i = 0;
+            input.entries.retain(|_| {
+                i += 1;
+                !removed.contains(&(i - 1))
+            });
+            if input.entries.is_empty() {
+                return Err("nothing can be reached from the roots".into())
+            }
+            input.write(&output)?;
+            println!("removed {} classes, {} methods and {} fields", report.classes.len(), report.methods.len(), report.fields.len());
+        }
+        Command::Roundtrip { input, lazy } => {
+            let input = Input::open(&input)?;
+            let (mut same, mut different, mut failed) = (0, 0, 0);
+            for e in input.classes() {
+                let read = if lazy { Class::read_lazy(&mut e.bytes.as_slice()) } else { Class::read_from(&mut e.bytes.as_slice()) };
+                // what is written must be readable again, or it cannot be compared to the input.
+                let written = read.and_then(|c| to_bytes(&c)).and_then(|b| Class::read_from(&mut b.as_slice()).map(|_| b));
+                match written {
+                    Ok(bytes) if bytes == e.bytes => same += 1,
+                    Ok(bytes) => {
+                        let at = bytes.iter().zip(&e.bytes).position(|(a, b)| a != b).unwrap_or_else(|| bytes.len().min(e.bytes.len()));
+                        println!("{}: differs at offset {} ({} bytes -> {} bytes)", e.name, at, e.bytes.len(), bytes.len());
+                        different += 1;
+                    }
+                    Err(err) => {
+                        println!("{}: {}", e.name, err);
+                        failed += 1;
+                    }
+                }
+            }
+            println!("{} identical, {} different, {} failed", same, different, failed);
+            return Ok(different == 0 && failed == 0)
+        }
+    }
+    Ok(true)
+}
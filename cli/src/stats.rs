@@ -0,0 +1,125 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Counts of constant pool entries and sizes of attributes.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt::{Display, Formatter};
+
+use coffer::prelude::*;
+use coffer::view::{AttributeView, ClassView, ViewEntry};
+
+/// Totals over the classes that were added.
+#[derive(Default)]
+pub struct Stats {
+    classes: usize,
+    bytes: usize,
+    entries: BTreeMap<&'static str, usize>,
+    /// The count and total size, with headers, of attributes by name.
+    attributes: BTreeMap<String, (usize, usize)>
+}
+
+fn kind(entry: &ViewEntry) -> &'static str {
+    match entry {
+        ViewEntry::UTF8(_) => "Utf8",
+        ViewEntry::Raw(raw) => match raw {
+            RawConstantEntry::UTF8(_) => "Utf8",
+            RawConstantEntry::Int(_) => "Integer",
+            RawConstantEntry::Float(_) => "Float",
+            RawConstantEntry::Long(_) => "Long",
+            RawConstantEntry::Double(_) => "Double",
+            RawConstantEntry::Class(_) => "Class",
+            RawConstantEntry::String(_) => "String",
+            RawConstantEntry::Field(..) => "Fieldref",
+            RawConstantEntry::Method(..) => "Methodref",
+            RawConstantEntry::InterfaceMethod(..) => "InterfaceMethodref",
+            RawConstantEntry::NameAndType(..) => "NameAndType",
+            RawConstantEntry::MethodHandle(..) => "MethodHandle",
+            RawConstantEntry::MethodType(_) => "MethodType",
+            RawConstantEntry::Dynamic(..) => "Dynamic",
+            RawConstantEntry::InvokeDynamic(..) => "InvokeDynamic",
+            RawConstantEntry::Module(_) => "Module",
+            RawConstantEntry::Package(_) => "Package"
+        }
+    }
+}
+
+impl Stats {
+    /// Adds the class file in `bytes`.
+    pub fn add(&mut self, bytes: &[u8]) -> coffer::Result<()> {
+        let view = ClassView::parse(bytes)?;
+        self.classes += 1;
+        self.bytes += bytes.len();
+        for (_, e) in view.pool().iter() {
+            *self.entries.entry(kind(e)).or_default() += 1;
+        }
+        let attributes = view.attributes.iter()
+            .chain(view.fields.iter().flat_map(|f| &f.attributes))
+            .chain(view.methods.iter().flat_map(|m| &m.attributes));
+        for a in attributes {
+            self.attribute(a.name.to_string(), a.data.len());
+            if a.name == "Code" {
+                self.code_attributes(&view, a)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn attribute(&mut self, name: String, len: usize) {
+        let (count, size) = self.attributes.entry(name).or_default();
+        *count += 1;
+        // the name index and the length come before the content.
+        *size += len + 6;
+    }
+
+    /// Adds the attributes nested in a `Code` attribute, named after it.
+    fn code_attributes(&mut self, view: &ClassView, code: &AttributeView) -> coffer::Result<()> {
+        let truncated = || Error::Invalid("Code attribute", "truncated".into());
+        let data = code.data;
+        let u16_at = |i: usize| data.get(i..i + 2).map(|b| u16::from_be_bytes(b.try_into().unwrap()) as usize).ok_or_else(truncated);
+        let code_len = data.get(4..8).map(|b| u32::from_be_bytes(b.try_into().unwrap()) as usize).ok_or_else(truncated)?;
+        let mut pos = 8 + code_len;
+        pos += 2 + u16_at(pos)? * 8;
+        let count = u16_at(pos)?;
+        pos += 2;
+        for _ in 0..count {
+            let name = view.pool().utf8(u16_at(pos)? as u16).ok_or_else(truncated)?;
+            let len = data.get(pos + 2..pos + 6).map(|b| u32::from_be_bytes(b.try_into().unwrap()) as usize).ok_or_else(truncated)?;
+            if data.len() - (pos + 6) < len {
+                return Err(truncated())
+            }
+            self.attribute(format!("Code.{}", name), len);
+            pos += 6 + len;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} classes, {} bytes", self.classes, self.bytes)?;
+        writeln!(f, "constant pool entries: {}", self.entries.values().sum::<usize>())?;
+        for (kind, count) in &self.entries {
+            writeln!(f, "  {:<28} {:>8}", kind, count)?;
+        }
+        writeln!(f, "attributes:                        count    bytes")?;
+        for (name, (count, size)) in &self.attributes {
+            writeln!(f, "  {:<28} {:>8} {:>8}", name, count, size)?;
+        }
+        Ok(())
+    }
+}
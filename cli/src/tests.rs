@@ -0,0 +1,215 @@
+/*
+ *     This file is part of Coffer.
+ *
+ *     Coffer is free software: you can redistribute it and/or modify
+ *     it under the terms of the GNU Lesser General Public License as published by
+ *     the Free Software Foundation, either version 3 of the License, or
+ *     (at your option) any later version.
+ *
+ *     Coffer is distributed in the hope that it will be useful,
+ *     but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *     MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *     GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU Lesser General Public License
+ *     along with Coffer. (LICENSE.md)  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use coffer::prelude::*;
+use coffer::Class;
+use coffer::view::ClassView;
+use tempfile::TempDir;
+
+use crate::dump::Dump;
+use crate::input::{to_bytes, Entry, Input};
+use crate::stats::Stats;
+use crate::{run, Command};
+
+fn sample() -> Class {
+    Class {
+        version: JavaVersion::J8,
+        access: ClassFlags::ACC_PUBLIC | ClassFlags::ACC_SUPER,
+        name: "a/Main".into(),
+        super_name: Some("java/lang/Object".into()),
+        interfaces: vec![],
+        fields: vec![Field { access: FieldFlags::ACC_PRIVATE | FieldFlags::ACC_STATIC, name: "count".into(), descriptor: Type::Int, attrs: vec![] }],
+        methods: vec![Method {
+            access: MethodFlags::ACC_PUBLIC | MethodFlags::ACC_STATIC,
+            name: "main".into(),
+            descriptor: Type::method([Type::ArrayRef(1, Box::new(Type::reference("java/lang/String")))], None),
+            attributes: vec![MethodAttribute::Code(Code {
+                max_stack: 1,
+                max_locals: 1,
+                code: vec![
+                    Instruction::Label(Label(0)),
+                    Instruction::LineNumber(3),
+                    Instruction::Push(OrDynamic::Static(Constant::string("hi"))),
+                    Instruction::Pop1,
+                    Instruction::Return(None)
+                ],
+                catches: vec![],
+                attrs: vec![]
+            })]
+        }],
+        attributes: vec![ClassAttribute::SourceFile("Main.java".into())]
+    }
+}
+
+/// A jar with the sample class and a manifest.
+fn jar(dir: &TempDir) -> std::path::PathBuf {
+    let path = dir.path().join("app.jar");
+    Input {
+        entries: vec![
+            Entry { name: "META-INF/MANIFEST.MF".into(), bytes: b"Manifest-Version: 1.0\n".to_vec() },
+            Entry { name: "a/Main.class".into(), bytes: to_bytes(&sample()).unwrap() }
+        ],
+        jar: true
+    }.write(&path).unwrap();
+    path
+}
+
+#[test]
+fn stats_of_code_attributes() {
+    let bytes = to_bytes(&sample()).unwrap();
+    let mut stats = Stats::default();
+    stats.add(&bytes).unwrap();
+    let out = stats.to_string();
+    assert!(out.starts_with(&format!("1 classes, {} bytes\n", bytes.len())));
+    assert!(out.lines().any(|l| l.split_whitespace().eq(["Code.LineNumberTable", "1", "12"])));
+
+    // a nested attribute claiming more bytes than the Code attribute holds.
+    let code = ClassView::parse(&bytes).unwrap().methods[0].attributes[0].data.to_vec();
+    let start = bytes.windows(code.len()).position(|w| w == code.as_slice()).unwrap();
+    let code_len = u32::from_be_bytes([code[4], code[5], code[6], code[7]]) as usize;
+    // the max values, the code, the empty exception table, the attribute count and the name of the nested attribute.
+    let nested_len = start + 8 + code_len + 2 + 2 + 2;
+    let mut truncated = bytes.clone();
+    truncated[nested_len + 3] += 100;
+    assert!(Stats::default().add(&truncated).is_err());
+}
+
+#[test]
+fn jar_roundtrip() {
+    let dir = TempDir::new().unwrap();
+    let path = jar(&dir);
+    let input = Input::open(&path).unwrap();
+    assert!(input.jar);
+    assert_eq!(input.entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(), vec!["META-INF/MANIFEST.MF", "a/Main.class"]);
+    assert_eq!(input.classes().map(|e| e.read().unwrap().name).collect::<Vec<_>>(), vec!["a/Main"]);
+
+    let copy = dir.path().join("copy.jar");
+    input.write(&copy).unwrap();
+    let reopened = Input::open(&copy).unwrap();
+    assert!(input.entries.iter().zip(&reopened.entries).all(|(a, b)| a.name == b.name && a.bytes == b.bytes));
+
+    assert!(run(Command::Roundtrip { input: path, lazy: false }).unwrap());
+}
+
+#[test]
+fn verify_results() {
+    let dir = TempDir::new().unwrap();
+    assert!(run(Command::Verify { input: jar(&dir) }).unwrap());
+
+    let mut bytes = to_bytes(&sample()).unwrap();
+    bytes.truncate(bytes.len() / 2);
+    let broken = dir.path().join("Broken.class");
+    std::fs::write(&broken, &bytes).unwrap();
+    assert!(!run(Command::Verify { input: broken.clone() }).unwrap());
+    assert!(!run(Command::Roundtrip { input: broken, lazy: false }).unwrap());
+
+    assert!(run(Command::Verify { input: dir.path().join("Missing.class") }).is_err());
+}
+
+#[test]
+fn dump_listing() {
+    let class = sample();
+    let code = "    Code:
+      stack=1, locals=1
+     L0:
+        // line 3
+        push \"hi\"
+        Pop1
+        Return(None)
+";
+    let listing = "Compiled from \"Main.java\"
+public class a.Main
+  version: Java SE 8 minor version 0
+  flags: (0x0021) ACC_PUBLIC | ACC_SUPER
+{
+  private static int count;
+    descriptor: I
+    flags: (0x000a) ACC_PRIVATE | ACC_STATIC
+
+  public static void main(java.lang.String[]);
+    descriptor: ([Ljava/lang/String;)V
+    flags: (0x0009) ACC_PUBLIC | ACC_STATIC
+CODE
+}
+";
+    assert_eq!(Dump { class: &class, code: false }.to_string(), listing.replace("CODE\n", "\n"));
+    assert_eq!(Dump { class: &class, code: true }.to_string(), listing.replace("CODE\n", &format!("{}\n", code)));
+}
+
+#[test]
+fn shrink_jar() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("app.jar");
+    let class = |name: &str| Class { name: name.to_owned().into(), fields: vec![], ..sample() };
+    let mut plugin = class("a/Plugin");
+    plugin.methods = vec![Method {
+        access: MethodFlags::ACC_PUBLIC,
+        name: "<init>".into(),
+        descriptor: Type::method([], None),
+        attributes: vec![MethodAttribute::Code(Code {
+            max_stack: 1,
+            max_locals: 1,
+            code: vec![
+                Instruction::LocalVariable(LoadOrStore::Load, LocalType::Reference, 0),
+                Instruction::InvokeSpecial(OrDynamic::Static(MemberRef { owner: "java/lang/Object".into(), name: "<init>".into(), descriptor: Type::method([], None), itfs: false })),
+                Instruction::Return(None)
+            ],
+            catches: vec![],
+            attrs: vec![]
+        })]
+    }];
+    Input {
+        entries: vec![
+            Entry { name: "META-INF/MANIFEST.MF".into(), bytes: b"Manifest-Version: 1.0\nMain-Class: a.Main\n".to_vec() },
+            Entry { name: "META-INF/services/a.Api".into(), bytes: b"# plugins\na.Plugin # the default one\n".to_vec() },
+            Entry { name: "a/Main.class".into(), bytes: to_bytes(&sample()).unwrap() },
+            Entry { name: "a/Dead.class".into(), bytes: to_bytes(&class("a/Dead")).unwrap() },
+            Entry { name: "a/Plugin.class".into(), bytes: to_bytes(&plugin).unwrap() }
+        ],
+        jar: true
+    }.write(&path).unwrap();
+
+    let output = dir.path().join("shrunk.jar");
+    assert!(run(Command::Shrink { input: path, output: output.clone(), main: vec![], keep: vec![] }).unwrap());
+    let shrunk = Input::open(&output).unwrap();
+    assert_eq!(shrunk.entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(),
+        vec!["META-INF/MANIFEST.MF", "META-INF/services/a.Api", "a/Main.class", "a/Plugin.class"]);
+    // the unused field of the main class is removed as well.
+    assert!(shrunk.entries[2].read().unwrap().fields.is_empty());
+
+    let dead = dir.path().join("Dead.class");
+    std::fs::write(&dead, to_bytes(&class("a/Dead")).unwrap()).unwrap();
+    assert!(run(Command::Shrink { input: dead.clone(), output: dir.path().join("out.class"), main: vec![], keep: vec![] }).is_err());
+    assert!(run(Command::Shrink { input: dead, output: dir.path().join("out.class"), main: vec![], keep: vec!["a/*".into()] }).unwrap());
+}
+
+#[test]
+fn non_standard_attributes() {
+    let dir = TempDir::new().unwrap();
+    let mut class = sample();
+    class.attributes.push(ClassAttribute::Raw(RawAttribute::new("Custom", vec![1, 2])));
+    class.fields[0].attrs.push(FieldAttribute::Raw(RawAttribute::new("Custom", vec![3])));
+    class.methods[0].attributes.push(MethodAttribute::Raw(RawAttribute::new("Custom", vec![])));
+    let path = dir.path().join("Main.class");
+    std::fs::write(&path, to_bytes(&class).unwrap()).unwrap();
+    // the reader does not know the attributes and drops them, but what it writes can be read back.
+    assert!(!run(Command::Roundtrip { input: path.clone(), lazy: false }).unwrap());
+    let stripped = dir.path().join("Stripped.class");
+    assert!(run(Command::Strip { input: path, output: stripped.clone(), keep: vec![] }).unwrap());
+    assert!(run(Command::Verify { input: stripped.clone() }).unwrap());
+    assert!(run(Command::Roundtrip { input: stripped, lazy: false }).unwrap());
+}